@@ -4,6 +4,7 @@ use crate::resource::{Resource, ResourceStorage};
 
 use anyhow::Ok;
 use kalosm::language::*;
+use kalosm::{ModelFamily, ModelSpec};
 use kalosm_common::ModelLoadingProgress;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -114,81 +115,45 @@ impl LlmBuilder {
 }
 
 impl main::types::ModelType {
-    fn llm_builder(&self) -> LlmBuilder {
+    /// The id of the preset in the kalosm model registry.
+    fn preset_id(&self) -> &'static str {
         match self {
-            main::types::ModelType::MistralSeven => Llama::builder()
-                .with_source(LlamaSource::mistral_7b())
-                .into(),
-            main::types::ModelType::MistralSevenInstruct => Llama::builder()
-                .with_source(LlamaSource::mistral_7b_instruct())
-                .into(),
-            main::types::ModelType::MistralSevenInstructTwo => Llama::builder()
-                .with_source(LlamaSource::mistral_7b_instruct_2())
-                .into(),
-            main::types::ModelType::ZephyrSevenAlpha => Llama::builder()
-                .with_source(LlamaSource::zephyr_7b_alpha())
-                .into(),
-            main::types::ModelType::ZephyrSevenBeta => Llama::builder()
-                .with_source(LlamaSource::zephyr_7b_beta())
-                .into(),
-            main::types::ModelType::OpenChatSeven => Llama::builder()
-                .with_source(LlamaSource::open_chat_7b())
-                .into(),
-            main::types::ModelType::StarlingSevenAlpha => Llama::builder()
-                .with_source(LlamaSource::starling_7b_alpha())
-                .into(),
-            main::types::ModelType::TinyLlamaChat => Llama::builder()
-                .with_source(LlamaSource::tiny_llama_1_1b_chat())
-                .into(),
-            main::types::ModelType::TinyLlama => Llama::builder()
-                .with_source(LlamaSource::tiny_llama_1_1b())
-                .into(),
-            main::types::ModelType::LlamaSeven => {
-                Llama::builder().with_source(LlamaSource::llama_7b()).into()
-            }
-            main::types::ModelType::LlamaThirteen => Llama::builder()
-                .with_source(LlamaSource::llama_13b())
-                .into(),
-            main::types::ModelType::LlamaSeventy => Llama::builder()
-                .with_source(LlamaSource::llama_70b())
-                .into(),
-            main::types::ModelType::LlamaSevenChat => Llama::builder()
-                .with_source(LlamaSource::llama_7b_chat())
-                .into(),
-            main::types::ModelType::LlamaThirteenChat => Llama::builder()
-                .with_source(LlamaSource::llama_13b_chat())
-                .into(),
-            main::types::ModelType::LlamaSeventyChat => Llama::builder()
-                .with_source(LlamaSource::llama_70b_chat())
-                .into(),
-            main::types::ModelType::LlamaSevenCode => Llama::builder()
-                .with_source(LlamaSource::llama_7b_code())
-                .into(),
-            main::types::ModelType::LlamaThirteenCode => Llama::builder()
-                .with_source(LlamaSource::llama_13b_code())
-                .into(),
-            main::types::ModelType::LlamaThirtyFourCode => Llama::builder()
-                .with_source(LlamaSource::llama_34b_code())
-                .into(),
-            main::types::ModelType::SolarTen => Llama::builder()
-                .with_source(LlamaSource::solar_10_7b())
-                .into(),
-            main::types::ModelType::SolarTenInstruct => Llama::builder()
-                .with_source(LlamaSource::solar_10_7b_instruct())
-                .into(),
-            main::types::ModelType::PhiOne => Phi::builder().with_source(PhiSource::v1()).into(),
-            main::types::ModelType::PhiOnePointFive => {
-                Phi::builder().with_source(PhiSource::v1_5()).into()
-            }
-            main::types::ModelType::PhiTwo => Phi::builder().with_source(PhiSource::v2()).into(),
-            main::types::ModelType::PuffinPhiTwo => Phi::builder()
-                .with_source(PhiSource::puffin_phi_v2())
-                .into(),
-            main::types::ModelType::DolphinPhiTwo => Phi::builder()
-                .with_source(PhiSource::dolphin_phi_v2())
-                .into(),
+            main::types::ModelType::MistralSeven => "mistral-7b",
+            main::types::ModelType::MistralSevenInstruct => "mistral-7b-instruct",
+            main::types::ModelType::MistralSevenInstructTwo => "mistral-7b-instruct-2",
+            main::types::ModelType::ZephyrSevenAlpha => "zephyr-7b-alpha",
+            main::types::ModelType::ZephyrSevenBeta => "zephyr-7b-beta",
+            main::types::ModelType::OpenChatSeven => "open-chat-7b",
+            main::types::ModelType::StarlingSevenAlpha => "starling-7b-alpha",
+            main::types::ModelType::TinyLlamaChat => "tiny-llama-1-1b-chat",
+            main::types::ModelType::TinyLlama => "tiny-llama-1-1b",
+            main::types::ModelType::LlamaSeven => "llama-7b",
+            main::types::ModelType::LlamaThirteen => "llama-13b",
+            main::types::ModelType::LlamaSeventy => "llama-70b",
+            main::types::ModelType::LlamaSevenChat => "llama-7b-chat",
+            main::types::ModelType::LlamaThirteenChat => "llama-13b-chat",
+            main::types::ModelType::LlamaSeventyChat => "llama-70b-chat",
+            main::types::ModelType::LlamaSevenCode => "llama-7b-code",
+            main::types::ModelType::LlamaThirteenCode => "llama-13b-code",
+            main::types::ModelType::LlamaThirtyFourCode => "llama-34b-code",
+            main::types::ModelType::SolarTen => "solar-10-7b",
+            main::types::ModelType::SolarTenInstruct => "solar-10-7b-instruct",
+            main::types::ModelType::PhiOne => "phi-v1",
+            main::types::ModelType::PhiOnePointFive => "phi-v1-5",
+            main::types::ModelType::PhiTwo => "phi-v2",
+            main::types::ModelType::PuffinPhiTwo => "puffin-phi-v2",
+            main::types::ModelType::DolphinPhiTwo => "dolphin-phi-v2",
         }
     }
+
+    fn llm_builder(&self) -> LlmBuilder {
+        let spec = ModelSpec::preset(self.preset_id());
+        let builder = match spec.family() {
+            Ok(ModelFamily::Phi) => spec.phi_builder().map(LlmBuilder::from),
+            _ => spec.llama_builder().map(LlmBuilder::from),
+        };
+        builder.expect("every model type has a text generation preset in the registry")
+    }
 }
 
 impl main::types::ModelType {
@@ -0,0 +1,166 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+use crate::cache::{relative_cache_path, sha256_file};
+use crate::{Cache, FileSource};
+
+/// The name of the manifest file at the root of every bundle
+const MANIFEST: &str = "kalosm-bundle.json";
+
+#[derive(Default, Serialize, Deserialize)]
+struct BundleManifest {
+    files: Vec<BundleEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleEntry {
+    /// The source the file was downloaded from
+    source: FileSource,
+    /// The path of the file relative to the bundle directory
+    path: PathBuf,
+    /// The SHA-256 hash of the file
+    sha256: String,
+    /// The size of the file in bytes
+    size: u64,
+}
+
+impl BundleManifest {
+    async fn read(bundle: &Path) -> anyhow::Result<Self> {
+        let manifest = bundle.join(MANIFEST);
+        let json = tokio::fs::read_to_string(&manifest)
+            .await
+            .with_context(|| {
+                format!("Failed to read the bundle manifest {}", manifest.display())
+            })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    async fn write(&self, bundle: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(bundle.join(MANIFEST), json).await?;
+        Ok(())
+    }
+}
+
+impl Cache {
+    /// Copy files into a bundle directory that can be moved to a machine without internet access and loaded with [`Cache::import_bundle`]. Files that are not in the cache yet are downloaded first.
+    ///
+    /// Exporting into a directory that already contains a bundle adds the files to the existing bundle.
+    pub async fn export_bundle(
+        &self,
+        sources: &[FileSource],
+        bundle: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let bundle = bundle.as_ref();
+        tokio::fs::create_dir_all(bundle).await?;
+        let mut manifest = if bundle.join(MANIFEST).exists() {
+            BundleManifest::read(bundle).await?
+        } else {
+            BundleManifest::default()
+        };
+
+        for source in sources {
+            let Some(path) = relative_cache_path(source)? else {
+                bail!("{source} is a local file. Only downloaded files can be bundled");
+            };
+            let cached = self.get(source, |_| {}).await?;
+
+            let destination = bundle.join(&path);
+            if let Some(parent) = destination.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(&cached, &destination).await?;
+            let sha256 = sha256_file(&destination).await?;
+            let size = tokio::fs::metadata(&destination).await?.len();
+
+            manifest.files.retain(|entry| entry.path != path);
+            manifest.files.push(BundleEntry {
+                source: source.clone(),
+                path,
+                sha256,
+                size,
+            });
+        }
+
+        manifest.write(bundle).await
+    }
+
+    /// Copy every file in a bundle created with [`Cache::export_bundle`] into the cache. Each file is checked against the hash recorded in the bundle before it is copied.
+    ///
+    /// Returns the sources of the imported files.
+    pub async fn import_bundle(&self, bundle: impl AsRef<Path>) -> anyhow::Result<Vec<FileSource>> {
+        let bundle = bundle.as_ref();
+        let manifest = BundleManifest::read(bundle).await?;
+
+        let mut imported = Vec::with_capacity(manifest.files.len());
+        for entry in manifest.files {
+            let Some(cache_path) = relative_cache_path(&entry.source)? else {
+                bail!("The bundle contains the local file {}", entry.source);
+            };
+            // The manifest is read from disk, so make sure it can't point outside of the bundle
+            if !entry
+                .path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                bail!("The bundle entry for {} has an invalid path", entry.source);
+            }
+            let destination = self.location().join(cache_path);
+
+            let from = bundle.join(&entry.path);
+            let found = sha256_file(&from)
+                .await
+                .with_context(|| format!("Failed to read {} from the bundle", from.display()))?;
+            let pinned = entry.source.sha256();
+            let expected = pinned.as_deref().unwrap_or(&entry.sha256);
+            if !found.eq_ignore_ascii_case(expected) || !found.eq_ignore_ascii_case(&entry.sha256) {
+                bail!(
+                    "{} in the bundle is corrupted: expected the SHA-256 hash {expected}, but found {found}",
+                    from.display()
+                );
+            }
+
+            if let Some(parent) = destination.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(&from, &destination).await?;
+            self.record_use(&entry.source).await;
+            imported.push(entry.source);
+        }
+
+        Ok(imported)
+    }
+}
+
+#[tokio::test]
+async fn bundles_round_trip() {
+    let root = std::env::temp_dir().join(format!("kalosm-bundle-test-{}", std::process::id()));
+    let source_cache = Cache::new(root.join("source")).with_offline(true);
+    let target_cache = Cache::new(root.join("target")).with_offline(true);
+    let bundle = root.join("bundle");
+
+    let source = FileSource::huggingface("kalosm/test", "main", "weights.bin");
+    let cached = source_cache.path(&source).unwrap();
+    tokio::fs::create_dir_all(cached.parent().unwrap())
+        .await
+        .unwrap();
+    tokio::fs::write(&cached, b"weights").await.unwrap();
+
+    source_cache
+        .export_bundle(std::slice::from_ref(&source), &bundle)
+        .await
+        .unwrap();
+    assert!(!target_cache.exists(&source));
+    let imported = target_cache.import_bundle(&bundle).await.unwrap();
+    assert_eq!(imported, vec![source.clone()]);
+    assert!(target_cache.exists(&source));
+
+    // Corrupted files are rejected
+    tokio::fs::write(bundle.join("huggingface/kalosm/test/main/weights.bin"), b"corrupted")
+        .await
+        .unwrap();
+    assert!(target_cache.import_bundle(&bundle).await.is_err());
+
+    tokio::fs::remove_dir_all(root).await.unwrap();
+}
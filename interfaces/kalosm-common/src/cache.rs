@@ -1,14 +1,20 @@
 use anyhow::bail;
-use hf_hub::{Repo, RepoType};
+use futures_util::StreamExt;
 use httpdate::parse_http_date;
-use reqwest::header::{HeaderValue, CONTENT_LENGTH, LAST_MODIFIED, RANGE};
-use reqwest::{Response, StatusCode, Url};
-use std::path::PathBuf;
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, LAST_MODIFIED, RANGE};
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 
-use crate::FileSource;
+use crate::backend::UrlBackend;
+use crate::{DownloadBackend, FileSource, HttpBackend};
 
 #[derive(Debug)]
 /// The progress starting a model
@@ -106,11 +112,52 @@ impl ModelLoadingProgress {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The environment variables that turn on offline mode for every cache. Any value other than an empty string, `0` or `false` enables offline mode.
+const OFFLINE_ENV_VARS: [&str; 2] = ["KALOSM_OFFLINE", "HF_HUB_OFFLINE"];
+
+/// The Hugging Face endpoint used if the `HF_ENDPOINT` environment variable is not set
+const DEFAULT_HUGGINGFACE_ENDPOINT: &str = "https://huggingface.co";
+
+#[derive(Clone)]
 pub struct Cache {
     location: PathBuf,
     /// The huggingface token to use (defaults to the token set with `huggingface-cli login`)
     huggingface_token: Option<String>,
+    /// The base URL of the Hugging Face hub or a mirror of it
+    huggingface_endpoint: String,
+    /// If the cache is offline, it never makes network requests and only returns files that are already downloaded
+    offline: bool,
+    /// Backends for [`FileSource::Remote`] sources by name
+    backends: HashMap<String, Arc<dyn DownloadBackend>>,
+    /// The number of chunks that are downloaded at the same time
+    parallel_downloads: usize,
+    /// The size of each chunk in bytes
+    chunk_size: u64,
+    /// The number of times a failed request is retried
+    download_retries: u32,
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("location", &self.location)
+            .field("huggingface_endpoint", &self.huggingface_endpoint)
+            .field("offline", &self.offline)
+            .field("backends", &self.backends.keys().collect::<Vec<_>>())
+            .field("parallel_downloads", &self.parallel_downloads)
+            .field("chunk_size", &self.chunk_size)
+            .field("download_retries", &self.download_retries)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A file that is downloaded through a backend
+struct RemoteFile {
+    backend: Arc<dyn DownloadBackend>,
+    /// The path of the file in the backend
+    path: String,
+    /// The hash pinned in the source
+    sha256: Option<String>,
 }
 
 impl Cache {
@@ -119,114 +166,432 @@ impl Cache {
         Self {
             location,
             huggingface_token: None,
+            huggingface_endpoint: std::env::var("HF_ENDPOINT")
+                .unwrap_or_else(|_| DEFAULT_HUGGINGFACE_ENDPOINT.to_string()),
+            offline: offline_from_env(),
+            backends: HashMap::new(),
+            parallel_downloads: 4,
+            chunk_size: 16 * 1024 * 1024,
+            download_retries: 3,
         }
     }
 
+    /// Create a cache in the default location that never accesses the network. Files that are not already downloaded will fail to load instead of being downloaded.
+    pub fn offline() -> Self {
+        Self::default().with_offline(true)
+    }
+
+    /// Set whether the cache is allowed to access the network (defaults to online unless the `KALOSM_OFFLINE` or `HF_HUB_OFFLINE` environment variable is set)
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Check if the cache is in offline mode
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Get the location of the cache
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
     /// Set the Hugging Face token to use for downloading (defaults to the token set with `huggingface-cli login`, and then the environment variable `HF_TOKEN`)
     pub fn with_huggingface_token(mut self, token: Option<String>) -> Self {
         self.huggingface_token = token;
         self
     }
 
-    /// Check if the file exists locally (if it is a local file or if it has been downloaded)
-    pub fn exists(&self, source: &FileSource) -> bool {
-        match source {
+    /// Set the base URL Hugging Face files are downloaded from. This can point to an internal mirror of the hub (defaults to the environment variable `HF_ENDPOINT`, and then `https://huggingface.co`)
+    pub fn with_huggingface_endpoint(mut self, endpoint: impl ToString) -> Self {
+        self.huggingface_endpoint = endpoint.to_string();
+        self
+    }
+
+    /// Register a backend that [`FileSource::Remote`] sources can download from with the name `name`
+    ///
+    /// ```rust, no_run
+    /// # use kalosm_common::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let cache = Cache::default().with_backend("s3", S3Backend::new("https://s3.us-east-1.amazonaws.com")?);
+    /// let path = cache
+    ///     .get(&FileSource::remote("s3", "my-models/model.gguf"), |_| {})
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_backend(mut self, name: impl ToString, backend: impl DownloadBackend) -> Self {
+        self.backends.insert(name.to_string(), Arc::new(backend));
+        self
+    }
+
+    /// Set the number of chunks of a file that are downloaded at the same time (defaults to 4)
+    pub fn with_parallel_downloads(mut self, parallel_downloads: usize) -> Self {
+        self.parallel_downloads = parallel_downloads.max(1);
+        self
+    }
+
+    /// Set the size of each chunk that is downloaded with a separate range request in bytes (defaults to 16 MiB)
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Set the number of times a failed request is retried before the download fails (defaults to 3)
+    pub fn with_download_retries(mut self, download_retries: u32) -> Self {
+        self.download_retries = download_retries;
+        self
+    }
+
+    /// The path a file is stored at. Local files are never copied into the cache, so this is the path of the file itself.
+    pub(crate) fn path(&self, source: &FileSource) -> anyhow::Result<PathBuf> {
+        if let FileSource::Local(path) = source {
+            return Ok(path.clone());
+        }
+        let path = relative_cache_path(source)?
+            .expect("every source except local files is stored in the cache");
+        Ok(self.location.join(path))
+    }
+
+    fn remote(&self, source: &FileSource) -> anyhow::Result<RemoteFile> {
+        let (backend, path): (Arc<dyn DownloadBackend>, _) = match source {
             FileSource::HuggingFace {
                 model_id,
                 revision,
                 file,
-                ..
             } => {
-                let path = self.location.join(model_id).join(revision);
-                let complete_download = path.join(file);
-                complete_download.exists()
+                let token = self.huggingface_token.clone().or_else(huggingface_token);
+                let backend = HttpBackend::new(&self.huggingface_endpoint)?.with_token(token);
+                // Revisions may be branch names with slashes which need to be escaped to stay in one path segment
+                let path = format!("{model_id}/resolve/{}/{file}", revision.replace('/', "%2F"));
+                (Arc::new(backend), path)
+            }
+            FileSource::Url { url, .. } => (Arc::new(UrlBackend), url.clone()),
+            FileSource::Remote { backend, path, .. } => {
+                let Some(backend) = self.backends.get(backend) else {
+                    bail!("No download backend named {backend:?} is registered on the cache for {source}");
+                };
+                (backend.clone(), path.clone())
             }
-            FileSource::Local(path) => path.exists(),
+            FileSource::Local(_) => bail!("{source} is a local file"),
+        };
+        Ok(RemoteFile {
+            backend,
+            path,
+            sha256: source.sha256(),
+        })
+    }
+
+    /// Older versions of kalosm stored Hugging Face files directly in the cache location instead of the `huggingface` folder
+    fn legacy_path(&self, source: &FileSource) -> Option<PathBuf> {
+        match source {
+            FileSource::HuggingFace {
+                model_id,
+                revision,
+                file,
+            } => Some(self.location.join(model_id).join(revision).join(file)),
+            _ => None,
         }
     }
 
+    /// Move a file downloaded by an older version of kalosm to the path it is stored at now
+    pub(crate) async fn migrate_legacy_file(
+        &self,
+        source: &FileSource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        if path.exists() {
+            return Ok(());
+        }
+        if let Some(legacy) = self.legacy_path(source).filter(|legacy| legacy.is_file()) {
+            tracing::trace!("Moving {} to {}", legacy.display(), path.display());
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(legacy, path).await?;
+        }
+        Ok(())
+    }
+
+    /// Check if the file exists locally (if it is a local file or if it has been downloaded)
+    pub fn exists(&self, source: &FileSource) -> bool {
+        self.path(source).is_ok_and(|path| path.exists())
+            || self
+                .legacy_path(source)
+                .is_some_and(|legacy| legacy.is_file())
+    }
+
     /// Get the file from the cache, downloading it if necessary
+    ///
+    /// Large files are downloaded in chunks with several range requests at the same time. Failed requests are retried, and interrupted downloads resume from the last chunk that was written. New downloads are checked against the SHA-256 hash pinned in the source, or the hash Hugging Face reports for LFS files if no hash is pinned. If the cache is offline, this only returns files that are already downloaded.
     pub async fn get(
         &self,
         source: &FileSource,
         progress: impl FnMut(f32),
     ) -> anyhow::Result<PathBuf> {
-        match source {
-            FileSource::HuggingFace {
-                model_id,
-                revision,
-                file,
-            } => {
-                let token = self.huggingface_token.clone().or_else(huggingface_token);
+        let path = self.fetch(source, progress).await?;
+        self.record_use(source).await;
+        Ok(path)
+    }
+
+    async fn fetch(
+        &self,
+        source: &FileSource,
+        progress: impl FnMut(f32),
+    ) -> anyhow::Result<PathBuf> {
+        if let FileSource::Local(path) = source {
+            return Ok(path.clone());
+        }
+        let complete_download = self.path(source)?;
+        self.migrate_legacy_file(source, &complete_download).await?;
 
-                let path = self.location.join(model_id).join(revision);
-                let complete_download = path.join(file);
+        if self.offline {
+            if complete_download.exists() {
+                return Ok(complete_download);
+            }
+            bail!(
+                "{source} is not in the cache at {} and downloads are disabled in offline mode",
+                complete_download.display()
+            );
+        }
 
-                let api = hf_hub::api::sync::Api::new()?;
-                let repo = Repo::with_revision(
-                    model_id.to_string(),
-                    RepoType::Model,
-                    revision.to_string(),
-                );
-                let api = api.repo(repo);
-                let url = api.url(file);
-                let url = Url::from_str(&url)?;
-                let client = reqwest::Client::new();
-                let response = client
-                    .head(url.clone())
-                    .with_authorization_header(token.clone())
-                    .send()
-                    .await;
-
-                if complete_download.exists() {
-                    let metadata = tokio::fs::metadata(&complete_download).await?;
-                    let file_last_modified = metadata.modified()?;
-                    // If the server says the file hasn't been modified since we downloaded it, we can use the local file
-                    if let Some(last_updated) = response
-                        .as_ref()
-                        .ok()
-                        .and_then(|response| response.headers().get(LAST_MODIFIED))
-                        .and_then(|last_updated| last_updated.to_str().ok())
-                        .and_then(|s| parse_http_date(s).ok())
+        let remote = self.remote(source)?;
+        let client = Client::new();
+        let response = match remote.backend.request(&client, Method::HEAD, &remote.path) {
+            Ok(request) => request.send().await.map_err(anyhow::Error::from),
+            Err(err) => Err(err),
+        };
+
+        if complete_download.exists() {
+            let metadata = tokio::fs::metadata(&complete_download).await?;
+            let file_last_modified = metadata.modified()?;
+            // If the server says the file hasn't been modified since we downloaded it, we can use the local file
+            if let Some(last_updated) = response
+                .as_ref()
+                .ok()
+                .and_then(|response| response.headers().get(LAST_MODIFIED))
+                .and_then(|last_updated| last_updated.to_str().ok())
+                .and_then(|s| parse_http_date(s).ok())
+            {
+                if last_updated <= file_last_modified {
+                    return Ok(complete_download);
+                }
+            } else {
+                // Or if we are offline, we can use the local file
+                return Ok(complete_download);
+            }
+        }
+        let mut incomplete_download = complete_download.clone().into_os_string();
+        incomplete_download.push(".partial");
+        let incomplete_download = PathBuf::from(incomplete_download);
+
+        let head = response?.error_for_status()?;
+        let expected_sha256 = match remote.sha256 {
+            Some(sha256) => Some(sha256),
+            None => match linked_sha256(remote.backend.as_ref(), &remote.path).await {
+                Ok(sha256) => sha256,
+                Err(err) => {
+                    tracing::warn!("Failed to look up the SHA-256 hash of {source}, the download will not be verified: {err}");
+                    None
+                }
+            },
+        };
+
+        tracing::trace!("Downloading into {:?}", incomplete_download);
+
+        self.download_into(
+            remote.backend.as_ref(),
+            &remote.path,
+            &incomplete_download,
+            &head,
+            progress,
+        )
+        .await?;
+
+        if let Some(expected) = expected_sha256 {
+            let found = sha256_file(&incomplete_download).await?;
+            if !found.eq_ignore_ascii_case(&expected) {
+                // Remove the partial file so the next attempt starts from scratch instead of resuming the corrupted download
+                tokio::fs::remove_file(&incomplete_download).await?;
+                bail!("The download of {source} is corrupted: expected the SHA-256 hash {expected}, but found {found}");
+            }
+        }
+
+        // Rename the file to remove the .partial extension
+        tokio::fs::rename(&incomplete_download, &complete_download).await?;
+
+        Ok(complete_download)
+    }
+
+    /// Get the size of the file in bytes without downloading it. Files that are already downloaded are read from the cache.
+    pub async fn file_size(&self, source: &FileSource) -> anyhow::Result<u64> {
+        for path in std::iter::once(self.path(source)?).chain(self.legacy_path(source)) {
+            if let Ok(metadata) = tokio::fs::metadata(path).await {
+                return Ok(metadata.len());
+            }
+        }
+        if let FileSource::Local(_) = source {
+            bail!("The local file {source} doesn't exist");
+        }
+        if self.offline {
+            bail!(
+                "Can't look up the size of {source} in offline mode because it is not in the cache"
+            );
+        }
+        let remote = self.remote(source)?;
+
+        let response = remote
+            .backend
+            .request(&Client::new(), Method::HEAD, &remote.path)?
+            .send()
+            .await?
+            .error_for_status()?;
+        let Some(length) = content_length(&response) else {
+            bail!("the response for {source} doesn't include the content length")
+        };
+        Ok(length)
+    }
+
+    async fn download_into(
+        &self,
+        backend: &dyn DownloadBackend,
+        path: &str,
+        file: &Path,
+        head: &Response,
+        mut progress: impl FnMut(f32),
+    ) -> anyhow::Result<()> {
+        let length = content_length(head);
+        let accepts_ranges = head
+            .headers()
+            .get(ACCEPT_RANGES)
+            .is_some_and(|ranges| ranges.as_bytes() == b"bytes");
+
+        let (mut start, mut output_file) = if let Ok(metadata) = tokio::fs::metadata(file).await {
+            let output_file = OpenOptions::new().append(true).open(file).await?;
+            (metadata.len(), output_file)
+        } else {
+            tokio::fs::create_dir_all(file.parent().unwrap()).await?;
+            (0, File::create(file).await?)
+        };
+
+        // If the partial file is longer than the file on the server, the file changed since the download started
+        if length.is_some_and(|length| start > length) {
+            output_file.set_len(0).await?;
+            start = 0;
+        }
+
+        if let Some(length) = length {
+            progress(start as f32 / length as f32);
+        }
+
+        if Some(start) == length {
+            tracing::trace!("File {} already downloaded", file.display());
+            progress(1.0);
+            return Ok(());
+        }
+
+        let client = Client::new();
+        match length {
+            Some(length) if accepts_ranges => {
+                // Chunks are downloaded concurrently, but written in order so the length of the partial file is always the amount downloaded
+                let ranges = (start..length)
+                    .step_by(self.chunk_size as usize)
+                    .map(|chunk_start| chunk_start..(chunk_start + self.chunk_size).min(length));
+                let mut chunks = futures_util::stream::iter(ranges)
+                    .map(|range| self.download_range(&client, backend, path, range))
+                    .buffered(self.parallel_downloads);
+
+                let mut current_progress = start;
+                while let Some(chunk) = chunks.next().await {
+                    let chunk = chunk?;
+                    output_file.write_all(&chunk).await?;
+                    tracing::trace!("wrote chunk of size {}", chunk.len());
+                    current_progress += chunk.len() as u64;
+                    progress(current_progress as f32 / length as f32);
+                }
+            }
+            _ => {
+                let mut attempt = 0;
+                loop {
+                    match stream_into(
+                        &client,
+                        backend,
+                        path,
+                        &mut output_file,
+                        length,
+                        &mut progress,
+                    )
+                    .await
                     {
-                        if last_updated <= file_last_modified {
-                            return Ok(complete_download);
+                        Ok(()) => break,
+                        Err(err) if attempt < self.download_retries => {
+                            attempt += 1;
+                            tracing::warn!("Download of {path} failed, retrying: {err}");
+                            tokio::time::sleep(retry_delay(attempt)).await;
                         }
-                    } else {
-                        // Or if we are offline, we can use the local file
-                        return Ok(complete_download);
+                        Err(err) => return Err(err),
                     }
                 }
-                let incomplete_download = path.join(format!("{}.partial", file));
-
-                tracing::trace!("Downloading into {:?}", incomplete_download);
+            }
+        }
+        output_file.flush().await?;
 
-                download_into(
-                    url,
-                    &incomplete_download,
-                    response?,
-                    client,
-                    token,
-                    progress,
-                )
-                .await?;
+        tracing::trace!("Download of {} complete", file.display());
+        progress(1.0);
 
-                // Rename the file to remove the .partial extension
-                tokio::fs::rename(&incomplete_download, &complete_download).await?;
+        Ok(())
+    }
 
-                Ok(complete_download)
+    /// Download one range of a file, retrying if the request fails
+    async fn download_range(
+        &self,
+        client: &Client,
+        backend: &dyn DownloadBackend,
+        path: &str,
+        range: Range<u64>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let result = async {
+                let response = backend
+                    .request(client, Method::GET, path)?
+                    .header(RANGE, format!("bytes={}-{}", range.start, range.end - 1))
+                    .send()
+                    .await?;
+                let status = response.status();
+                if status != StatusCode::PARTIAL_CONTENT {
+                    bail!("Unexpected status code for the range {range:?}: {status:?}");
+                }
+                let bytes = response.bytes().await?;
+                if bytes.len() as u64 != range.end - range.start {
+                    bail!(
+                        "Expected {} bytes for the range {range:?}, but got {}",
+                        range.end - range.start,
+                        bytes.len()
+                    );
+                }
+                anyhow::Ok(bytes.to_vec())
+            }
+            .await;
+
+            match result {
+                Ok(bytes) => return Ok(bytes),
+                Err(err) if attempt < self.download_retries => {
+                    attempt += 1;
+                    tracing::warn!("Download of {range:?} from {path} failed, retrying: {err}");
+                    tokio::time::sleep(retry_delay(attempt)).await;
+                }
+                Err(err) => return Err(err),
             }
-            FileSource::Local(path) => Ok(path.clone()),
         }
     }
 }
 
 impl Default for Cache {
     fn default() -> Self {
-        Self {
-            location: dirs::data_dir().unwrap().join("kalosm").join("cache"),
-            huggingface_token: None,
-        }
+        Self::new(dirs::data_dir().unwrap().join("kalosm").join("cache"))
     }
 }
 
@@ -236,58 +601,87 @@ impl FileSource {
         let cache = Cache::default();
         cache.get(self, progress).await
     }
-}
-
-async fn download_into(
-    url: Url,
-    file: &PathBuf,
-    head: Response,
-    client: reqwest::Client,
-    token: Option<String>,
-    mut progress: impl FnMut(f32),
-) -> anyhow::Result<()> {
-    let length = head
-        .headers()
-        .get(CONTENT_LENGTH)
-        .ok_or("response doesn't include the content length")
-        .unwrap();
-    let length = length.to_str().ok().and_then(|s| u64::from_str(s).ok());
-
-    let (start, mut output_file) = if let Ok(metadata) = tokio::fs::metadata(file).await {
-        let start = metadata.len();
-        let output_file = OpenOptions::new().append(true).open(file).await.unwrap();
-        (start, output_file)
-    } else {
-        tokio::fs::create_dir_all(file.parent().unwrap()).await?;
-        (0, File::create(file).await.unwrap())
-    };
 
-    if let Some(length) = length {
-        progress(start as f32 / length as f32);
+    /// Get the size of the file in bytes without downloading it
+    pub async fn file_size(&self) -> anyhow::Result<u64> {
+        let cache = Cache::default();
+        cache.file_size(self).await
     }
+}
 
-    if Some(start) == length {
-        tracing::trace!("File {} already downloaded", file.display());
-        progress(1.0);
-        return Ok(());
+/// The path a downloaded file is stored at relative to the cache location, or `None` for local files
+///
+/// Each kind of source is stored in its own top level folder so a Hugging Face organization can't collide with a URL host or a backend name.
+pub(crate) fn relative_cache_path(source: &FileSource) -> anyhow::Result<Option<PathBuf>> {
+    let path = match source {
+        FileSource::HuggingFace {
+            model_id,
+            revision,
+            file,
+        } => Path::new("huggingface")
+            .join(model_id)
+            .join(revision)
+            .join(file),
+        FileSource::Url { url, .. } => {
+            let parsed = Url::parse(url)?;
+            let Some(host) = parsed.host_str() else {
+                bail!("The URL {url} doesn't have a host");
+            };
+            // URLs that only differ in their query or path are different files, so they are stored under a hash of the whole URL
+            let hash = Sha256::digest(url.as_bytes())
+                .iter()
+                .take(16)
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            let file_name = parsed
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("download");
+            Path::new("url").join(host).join(hash).join(file_name)
+        }
+        FileSource::Remote { backend, path, .. } => Path::new("remote")
+            .join(backend)
+            .join(path.trim_start_matches('/')),
+        FileSource::Local(_) => return Ok(None),
+    };
+    // Sources can come from config files, so make sure they can't point outside of the cache
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("{source} can't be stored in the cache because it has an invalid path");
     }
+    Ok(Some(path))
+}
 
-    let range = length
-        .and_then(|length| HeaderValue::from_str(&format!("bytes={}-{}", start, length - 1)).ok());
-
-    tracing::trace!("Fetching range {:?}", range);
-    let mut request = client.get(url).with_authorization_header(token);
-    if let Some(range) = range {
-        request = request.header(RANGE, range);
+/// Stream the rest of a file into the output file, starting from the end of the output file
+async fn stream_into(
+    client: &Client,
+    backend: &dyn DownloadBackend,
+    path: &str,
+    output_file: &mut File,
+    length: Option<u64>,
+    progress: &mut impl FnMut(f32),
+) -> anyhow::Result<()> {
+    let start = output_file.metadata().await?.len();
+    let mut request = backend.request(client, Method::GET, path)?;
+    if start > 0 {
+        request = request.header(RANGE, format!("bytes={start}-"));
     }
+    tracing::trace!("Fetching from byte {}", start);
     let mut response = request.send().await?;
 
-    let status = response.status();
-    if !(status == StatusCode::OK || status == StatusCode::PARTIAL_CONTENT) {
-        bail!("Unexpected status code: {:?}", status);
-    }
-
     let mut current_progress = start;
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => {}
+        // The server ignored the range and sent the whole file
+        StatusCode::OK => {
+            output_file.set_len(0).await?;
+            current_progress = 0;
+        }
+        status => bail!("Unexpected status code: {:?}", status),
+    }
 
     while let Some(chunk) = response.chunk().await? {
         output_file.write_all(&chunk).await?;
@@ -298,47 +692,159 @@ async fn download_into(
         }
     }
 
-    tracing::trace!("Download of {} complete", file.display());
-    progress(1.0);
-
     Ok(())
 }
 
-trait RequestBuilderExt {
-    fn with_authorization_header(self, token: Option<String>) -> Self;
+fn content_length(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| u64::from_str(length).ok())
 }
 
-impl RequestBuilderExt for reqwest::RequestBuilder {
-    fn with_authorization_header(self, token: Option<String>) -> Self {
-        if let Some(token) = token {
-            self.header(reqwest::header::AUTHORIZATION, format!("Bearer {token}"))
-        } else {
-            self
+/// Exponential backoff starting at half a second
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(attempt.saturating_sub(1).min(6)))
+}
+
+fn offline_from_env() -> bool {
+    OFFLINE_ENV_VARS.iter().any(|var| {
+        std::env::var(var)
+            .is_ok_and(|value| !matches!(value.trim().to_lowercase().as_str(), "" | "0" | "false"))
+    })
+}
+
+/// Serve `data` over HTTP on a local port. Range requests are only supported if `accept_ranges` is true. Returns the address of the server.
+#[cfg(test)]
+async fn serve_file(data: Vec<u8>, accept_ranges: bool) -> std::net::SocketAddr {
+    use tokio::io::AsyncReadExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let data = Arc::new(data);
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            let data = data.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let range = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .filter(|_| accept_ranges)
+                    .map(|(start, end)| {
+                        start.parse::<usize>().unwrap()..end.parse::<usize>().unwrap() + 1
+                    });
+                let (status, body) = match range {
+                    Some(range) => ("206 Partial Content", &data[range]),
+                    None => ("200 OK", &data[..]),
+                };
+                let accept_ranges = if accept_ranges { "bytes" } else { "none" };
+                let head = format!(
+                    "HTTP/1.1 {status}\r\ncontent-length: {}\r\naccept-ranges: {accept_ranges}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                if !request.starts_with("head") {
+                    let _ = stream.write_all(body).await;
+                }
+            });
         }
-    }
+    });
+    address
 }
 
 #[tokio::test]
 async fn downloads_work() {
-    let url = "https://httpbin.org/range/102400?duration=2";
-    let file = PathBuf::from("download.bin");
-    let progress = |p| {
-        println!("Progress: {}", p);
+    let data = (0..102400).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    for accept_ranges in [true, false] {
+        let url = format!(
+            "http://{}/range/102400?duration=2",
+            serve_file(data.clone(), accept_ranges).await
+        );
+        let file = std::env::temp_dir().join(format!(
+            "kalosm-download-{}-{accept_ranges}.bin",
+            std::process::id()
+        ));
+        let progress = |p| {
+            println!("Progress: {}", p);
+        };
+        let client = reqwest::Client::new();
+        let response = client.head(&url).send().await.unwrap();
+        Cache::default()
+            .with_chunk_size(10240)
+            .download_into(&UrlBackend, &url, &file, &response, progress)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&file).await.unwrap(), data);
+        tokio::fs::remove_file(file).await.unwrap();
+    }
+}
+
+#[test]
+fn sources_are_stored_in_separate_folders() {
+    let path = |source: FileSource| relative_cache_path(&source).unwrap().unwrap();
+    assert_eq!(
+        path(FileSource::huggingface("url", "main", "model.gguf")),
+        Path::new("huggingface/url/main/model.gguf")
+    );
+    assert!(path(FileSource::url("https://example.com/model.gguf")).starts_with("url/example.com"));
+    assert_eq!(
+        path(FileSource::remote("url", "example.com/model.gguf")),
+        Path::new("remote/url/example.com/model.gguf")
+    );
+
+    // URLs that only differ in their query are different files
+    let first = path(FileSource::url("https://example.com/model.gguf?version=1"));
+    let second = path(FileSource::url("https://example.com/model.gguf?version=2"));
+    assert_ne!(first, second);
+    assert!(first.ends_with("model.gguf"));
+}
+
+/// Get the SHA-256 hash of a Hugging Face LFS file without downloading it.
+///
+/// The hub reports the hash in the `x-linked-etag` header of the redirect to the file storage, so this request can't follow redirects. Other servers and files that are not stored with LFS don't report a SHA-256 hash, so this returns `None` for them.
+async fn linked_sha256(
+    backend: &dyn DownloadBackend,
+    path: &str,
+) -> anyhow::Result<Option<String>> {
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    let response = backend.request(&client, Method::HEAD, path)?.send().await?;
+    let Some(etag) = response.headers().get("x-linked-etag") else {
+        return Ok(None);
     };
-    let client = reqwest::Client::new();
-    let response = client.head(url).send().await.unwrap();
-    download_into(
-        Url::from_str(url).unwrap(),
-        &file,
-        response,
-        client,
-        None,
-        progress,
+    let hash = etag.to_str()?.trim_start_matches("W/").trim_matches('"');
+    Ok(
+        (hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| hash.to_lowercase()),
     )
-    .await
-    .unwrap();
-    assert!(file.exists());
-    tokio::fs::remove_file(file).await.unwrap();
+}
+
+/// Hash a file with SHA-256 and return the hash as a lowercase hex string
+pub(crate) async fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use sha2::{Digest, Sha256};
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        anyhow::Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
 }
 
 fn huggingface_token() -> Option<String> {
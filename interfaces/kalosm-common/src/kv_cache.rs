@@ -1,36 +1,272 @@
-use candle_core::Tensor;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use candle_core::quantized::ggml_file::qtensor_from_ggml;
+use candle_core::quantized::{GgmlDType, QTensor};
+use candle_core::{DType, Tensor};
+
+/// The number of tokens that are kept at full precision before they are quantized in a quantized [`KvCache`].
+const QUANTIZED_CHUNK_SIZE: usize = 256;
+
+/// The precision a [`KvCache`] stores keys and values with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KvCacheQuantization {
+    /// Store keys and values at the precision of the model.
+    #[default]
+    None,
+    /// Store keys and values with 8 bits per value in blocks of 32 values. The quantized values use roughly a quarter of the memory of f32 values.
+    Q8_0,
+    /// Store keys and values with 4 bits per value in blocks of 32 values. The quantized values use roughly an eighth of the memory of f32 values.
+    Q4_0,
+}
+
+impl KvCacheQuantization {
+    fn ggml_dtype(&self) -> Option<GgmlDType> {
+        match self {
+            KvCacheQuantization::None => None,
+            KvCacheQuantization::Q8_0 => Some(GgmlDType::Q8_0),
+            KvCacheQuantization::Q4_0 => Some(GgmlDType::Q4_0),
+        }
+    }
+}
+
+/// Keys and values that have been moved out of the full precision cache and quantized.
+#[derive(Debug, Clone)]
+struct QuantizedChunks {
+    dtype: GgmlDType,
+    keys: Vec<Arc<QTensor>>,
+    values: Vec<Arc<QTensor>>,
+    seq_len: usize,
+    /// The type the chunks are dequantized to
+    float_dtype: DType,
+}
+
+impl QuantizedChunks {
+    fn new(dtype: GgmlDType) -> Self {
+        Self {
+            dtype,
+            keys: Vec::new(),
+            values: Vec::new(),
+            seq_len: 0,
+            float_dtype: DType::F32,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+        self.seq_len = 0;
+    }
+
+    /// Quantize a chunk of keys and values and add it to the end of the quantized chunks.
+    fn push(&mut self, k: &Tensor, v: &Tensor, concat_dim: usize) -> candle_core::Result<()> {
+        self.float_dtype = k.dtype();
+        self.keys.push(Arc::new(QTensor::quantize(k, self.dtype)?));
+        self.values
+            .push(Arc::new(QTensor::quantize(v, self.dtype)?));
+        self.seq_len += k.dim(concat_dim)?;
+        Ok(())
+    }
+
+    /// Dequantize the chunks and join them with the tokens that are still stored at full precision.
+    ///
+    /// The dequantized chunks are not kept, so only the layer that is currently running attention holds its keys and values at full precision.
+    fn join(
+        &self,
+        chunks: &[Arc<QTensor>],
+        tail: Option<Tensor>,
+        concat_dim: usize,
+    ) -> candle_core::Result<Option<Tensor>> {
+        let Some(first) = chunks.first() else {
+            return Ok(tail);
+        };
+        let device = first.device();
+        let mut tensors = chunks
+            .iter()
+            .map(|chunk| chunk.dequantize(&device)?.to_dtype(self.float_dtype))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        tensors.extend(tail);
+        Tensor::cat(&tensors, concat_dim).map(Some)
+    }
+
+    fn save(
+        chunks: &[Arc<QTensor>],
+        prefix: &str,
+        map: &mut HashMap<String, Tensor>,
+    ) -> candle_core::Result<()> {
+        for (i, chunk) in chunks.iter().enumerate() {
+            let data = chunk.data()?;
+            let device = chunk.device();
+            let shape = chunk.shape().dims().iter().map(|dim| *dim as u32);
+            map.insert(
+                format!("{prefix}.chunks.{i}"),
+                Tensor::from_slice(&data, data.len(), &device)?,
+            );
+            map.insert(
+                format!("{prefix}.chunks.{i}.shape"),
+                Tensor::from_iter(shape, &device)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn load(
+        dtype: GgmlDType,
+        prefix: &str,
+        map: &HashMap<String, Tensor>,
+    ) -> candle_core::Result<Vec<Arc<QTensor>>> {
+        let mut chunks = Vec::new();
+        while let (Some(data), Some(shape)) = (
+            map.get(&format!("{prefix}.chunks.{}", chunks.len())),
+            map.get(&format!("{prefix}.chunks.{}.shape", chunks.len())),
+        ) {
+            let dims = shape
+                .to_vec1::<u32>()?
+                .into_iter()
+                .map(|dim| dim as usize)
+                .collect();
+            let chunk = qtensor_from_ggml(dtype, &data.to_vec1::<u8>()?, dims, data.device())?;
+            chunks.push(Arc::new(chunk));
+        }
+        Ok(chunks)
+    }
+}
 
 /// A growable kv cache. This cache wraps candles [`KvCache`] with exponentially larger allocations as the sequence length increases.
+///
+/// The cache can optionally quantize keys and values to save memory. Quantized caches keep the most recent tokens at full precision and quantize them in chunks. The chunks are dequantized again every time keys and values are appended, so the cache itself never holds a full precision copy of the quantized tokens.
 #[derive(Debug, Clone)]
 pub struct KvCache {
     cache: candle_nn::kv_cache::KvCache,
     concat_dim: usize,
     max_seq_len: usize,
+    quantized: Option<QuantizedChunks>,
 }
 
 impl KvCache {
     /// Create a new cache with the given max sequence length.
     pub fn new(concat_dim: usize, max_seq_len: usize) -> Self {
+        Self::new_quantized(concat_dim, max_seq_len, KvCacheQuantization::None)
+    }
+
+    /// Create a new cache with the given max sequence length that stores keys and values with the given quantization. The last dimension of the keys and values must be a multiple of 32 for quantized caches.
+    pub fn new_quantized(
+        concat_dim: usize,
+        max_seq_len: usize,
+        quantization: KvCacheQuantization,
+    ) -> Self {
         Self {
             cache: candle_nn::kv_cache::KvCache::new(concat_dim, 8),
             concat_dim,
             max_seq_len,
+            quantized: quantization.ggml_dtype().map(QuantizedChunks::new),
+        }
+    }
+
+    /// Get the quantization of the cache.
+    pub fn quantization(&self) -> KvCacheQuantization {
+        match self.quantized.as_ref().map(|quantized| quantized.dtype) {
+            Some(GgmlDType::Q8_0) => KvCacheQuantization::Q8_0,
+            Some(GgmlDType::Q4_0) => KvCacheQuantization::Q4_0,
+            _ => KvCacheQuantization::None,
         }
     }
 
-    /// Get the raw cache.
+    /// Get the raw cache. For quantized caches, this only contains the most recent tokens that have not been quantized yet.
     pub fn cache(&self) -> &candle_nn::kv_cache::KvCache {
         &self.cache
     }
 
-    /// Get the raw cache mutably.
+    /// Get the raw cache mutably. For quantized caches, this only contains the most recent tokens that have not been quantized yet.
     pub fn cache_mut(&mut self) -> &mut candle_nn::kv_cache::KvCache {
         &mut self.cache
     }
 
+    /// Get the number of tokens in the cache.
+    pub fn current_seq_len(&self) -> usize {
+        let quantized_len = self.quantized.as_ref().map_or(0, |q| q.seq_len);
+        quantized_len + self.cache.current_seq_len()
+    }
+
+    /// Get all of the keys in the cache at full precision.
+    pub fn k(&self) -> candle_core::Result<Option<Tensor>> {
+        match &self.quantized {
+            Some(quantized) => quantized.join(&quantized.keys, self.cache.k()?, self.concat_dim),
+            None => self.cache.k(),
+        }
+    }
+
+    /// Get all of the values in the cache at full precision.
+    pub fn v(&self) -> candle_core::Result<Option<Tensor>> {
+        match &self.quantized {
+            Some(quantized) => quantized.join(&quantized.values, self.cache.v()?, self.concat_dim),
+            None => self.cache.v(),
+        }
+    }
+
     /// Reset the cache.
     pub fn reset(&mut self) {
-        self.cache.reset()
+        self.cache.reset();
+        if let Some(quantized) = &mut self.quantized {
+            quantized.clear();
+        }
+    }
+
+    /// Add the keys and values in the cache to a tensor map with names that start with `prefix`. This can be used to save the cache to disk.
+    ///
+    /// Quantized caches save the quantized bytes of each chunk, so they take the same space on disk as in memory.
+    pub fn save_tensors(
+        &self,
+        prefix: &str,
+        map: &mut HashMap<String, Tensor>,
+    ) -> candle_core::Result<()> {
+        if let Some(quantized) = &self.quantized {
+            QuantizedChunks::save(&quantized.keys, &format!("{prefix}.key"), map)?;
+            QuantizedChunks::save(&quantized.values, &format!("{prefix}.value"), map)?;
+        }
+        if let (Some(k), Some(v)) = (self.cache.k()?, self.cache.v()?) {
+            map.insert(format!("{prefix}.key"), k);
+            map.insert(format!("{prefix}.value"), v);
+        }
+        Ok(())
+    }
+
+    /// Load keys and values saved with [`KvCache::save_tensors`] into an empty cache with the same quantization.
+    ///
+    /// Full precision keys and values are quantized again if the cache is quantized, so caches saved without quantization can still be loaded into a quantized cache.
+    pub fn load_tensors(
+        &mut self,
+        prefix: &str,
+        map: &HashMap<String, Tensor>,
+    ) -> candle_core::Result<()> {
+        self.reset();
+        if let Some(quantized) = &mut self.quantized {
+            let keys = QuantizedChunks::load(quantized.dtype, &format!("{prefix}.key"), map)?;
+            let values = QuantizedChunks::load(quantized.dtype, &format!("{prefix}.value"), map)?;
+            if keys.len() != values.len() {
+                candle_core::bail!(
+                    "the saved kv cache has {} key chunks but {} value chunks",
+                    keys.len(),
+                    values.len()
+                );
+            }
+            quantized.seq_len = keys
+                .iter()
+                .map(|chunk| chunk.shape().dims()[self.concat_dim])
+                .sum();
+            quantized.keys = keys;
+            quantized.values = values;
+        }
+        if let (Some(k), Some(v)) = (
+            map.get(&format!("{prefix}.key")),
+            map.get(&format!("{prefix}.value")),
+        ) {
+            if let Some(quantized) = &mut self.quantized {
+                quantized.float_dtype = k.dtype();
+            }
+            self.append(k, v)?;
+        }
+        Ok(())
     }
 
     /// Append a new key/value pair to the cache.
@@ -41,6 +277,15 @@ impl KvCache {
         // The key and value token length must be the same.
         debug_assert_eq!(seq_len, v.dim(self.concat_dim)?);
 
+        // Quantized caches move tokens out of the inner cache, so the inner cache can't enforce the max sequence length
+        if self.quantized.is_some() && self.current_seq_len() + seq_len > self.max_seq_len {
+            candle_core::bail!(
+                "cannot append {seq_len} tokens to a kv cache with {} tokens, the max sequence length is {}",
+                self.current_seq_len(),
+                self.max_seq_len
+            );
+        }
+
         let current_allocated_size = self.cache.k_cache().max_seq_len();
         let size_required_for_append = self.cache.current_seq_len() + seq_len;
 
@@ -63,6 +308,192 @@ impl KvCache {
             self.cache = new_cache;
         }
 
-        self.cache.append(&k, &v)
+        let (k, v) = self.cache.append(&k, &v)?;
+
+        let Some(quantized) = &mut self.quantized else {
+            return Ok((k, v));
+        };
+
+        // Once enough tokens are stored at full precision, move them into a quantized chunk
+        let tail = if self.cache.current_seq_len() >= QUANTIZED_CHUNK_SIZE {
+            quantized.push(&k, &v, self.concat_dim)?;
+            // The full precision cache only needs to hold one chunk at a time
+            self.cache = candle_nn::kv_cache::KvCache::new(self.concat_dim, QUANTIZED_CHUNK_SIZE);
+            None
+        } else {
+            Some((k, v))
+        };
+        let (tail_k, tail_v) = tail.unzip();
+        let k = quantized.join(&quantized.keys, tail_k, self.concat_dim)?;
+        let v = quantized.join(&quantized.values, tail_v, self.concat_dim)?;
+        match (k, v) {
+            (Some(k), Some(v)) => Ok((k, v)),
+            _ => candle_core::bail!("the kv cache is empty after appending"),
+        }
+    }
+}
+
+#[test]
+fn quantized_kv_cache() {
+    let device = candle_core::Device::Cpu;
+    let mut cache = KvCache::new_quantized(2, 1024, KvCacheQuantization::Q8_0);
+    let mut expected_keys = Vec::new();
+    for step in 0..10 {
+        let tokens = if step == 0 { 300 } else { 1 };
+        let k = Tensor::randn(0f32, 1., (1, 2, tokens, 64), &device).unwrap();
+        let v = Tensor::randn(0f32, 1., (1, 2, tokens, 64), &device).unwrap();
+        expected_keys.push(k.clone());
+        let (all_k, all_v) = cache.append(&k, &v).unwrap();
+        assert_eq!(all_k.dims(), all_v.dims());
+        assert_eq!(all_k.dim(2).unwrap(), cache.current_seq_len());
+    }
+    assert_eq!(cache.current_seq_len(), 309);
+    assert_eq!(cache.cache().current_seq_len(), 9);
+
+    let expected_keys = Tensor::cat(&expected_keys, 2).unwrap();
+    let error = (cache.k().unwrap().unwrap() - expected_keys)
+        .unwrap()
+        .abs()
+        .unwrap()
+        .max_keepdim(3)
+        .unwrap()
+        .flatten_all()
+        .unwrap()
+        .max(0)
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap();
+    assert!(error < 0.05);
+
+    let clone = cache.clone();
+    let difference = (clone.k().unwrap().unwrap() - cache.k().unwrap().unwrap())
+        .unwrap()
+        .abs()
+        .unwrap()
+        .flatten_all()
+        .unwrap()
+        .max(0)
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap();
+    assert_eq!(difference, 0.);
+
+    cache.reset();
+    assert_eq!(cache.current_seq_len(), 0);
+    assert!(cache.k().unwrap().is_none());
+}
+
+#[test]
+fn quantized_kv_cache_full_precision_size_is_bounded() {
+    let device = candle_core::Device::Cpu;
+    let max_seq_len = 4096;
+    let mut cache = KvCache::new_quantized(2, max_seq_len, KvCacheQuantization::Q4_0);
+    for step in 0..1000 {
+        let tokens = if step == 0 { 100 } else { 1 };
+        let k = Tensor::zeros((1, 2, tokens, 32), DType::F32, &device).unwrap();
+        let (all_k, _) = cache.append(&k, &k).unwrap();
+        assert_eq!(all_k.dim(2).unwrap(), cache.current_seq_len());
+        // Tokens are only kept at full precision until there are enough to fill a chunk
+        assert!(cache.cache().k_cache().max_seq_len() <= QUANTIZED_CHUNK_SIZE);
+        assert!(cache.cache().current_seq_len() < QUANTIZED_CHUNK_SIZE);
+    }
+    assert_eq!(cache.current_seq_len(), 1099);
+}
+
+#[test]
+fn quantized_kv_cache_max_seq_len() {
+    let device = candle_core::Device::Cpu;
+    let mut cache = KvCache::new_quantized(2, 300, KvCacheQuantization::Q8_0);
+    let k = Tensor::zeros((1, 2, 260, 32), DType::F32, &device).unwrap();
+    cache.append(&k, &k).unwrap();
+    let k = Tensor::zeros((1, 2, 40, 32), DType::F32, &device).unwrap();
+    cache.append(&k, &k).unwrap();
+    let k = Tensor::zeros((1, 2, 1, 32), DType::F32, &device).unwrap();
+    assert!(cache.append(&k, &k).is_err());
+    assert_eq!(cache.current_seq_len(), 300);
+}
+
+#[cfg(test)]
+impl KvCache {
+    /// The number of bytes the cache keeps allocated
+    fn size_in_bytes(&self) -> usize {
+        let full_precision = [self.cache.k_cache(), self.cache.v_cache()]
+            .iter()
+            .filter_map(|cache| cache.all_data().as_ref())
+            .map(|data| data.elem_count() * data.dtype().size_in_bytes())
+            .sum::<usize>();
+        let quantized = self.quantized.iter().flat_map(|quantized| {
+            quantized
+                .keys
+                .iter()
+                .chain(&quantized.values)
+                .map(|chunk| chunk.storage_size_in_bytes())
+        });
+        let quantized = quantized.sum::<usize>();
+        full_precision + quantized
+    }
+}
+
+#[test]
+fn quantized_kv_cache_uses_less_memory() {
+    let device = candle_core::Device::Cpu;
+    let mut caches = [
+        KvCacheQuantization::None,
+        KvCacheQuantization::Q8_0,
+        KvCacheQuantization::Q4_0,
+    ]
+    .map(|quantization| KvCache::new_quantized(2, 4096, quantization));
+    for step in 0..100 {
+        let tokens = if step == 0 { 1000 } else { 1 };
+        let k = Tensor::randn(0f32, 1., (1, 4, tokens, 64), &device).unwrap();
+        for cache in &mut caches {
+            cache.append(&k, &k).unwrap();
+        }
+    }
+    let [full, q8, q4] = caches.map(|cache| cache.size_in_bytes());
+    assert!(q8 < full / 2, "{q8} >= {full} / 2");
+    assert!(q4 < q8, "{q4} >= {q8}");
+}
+
+#[test]
+fn quantized_kv_cache_saves_quantized_chunks() {
+    let device = candle_core::Device::Cpu;
+    let mut cache = KvCache::new_quantized(2, 1024, KvCacheQuantization::Q8_0);
+    for tokens in [300, 5, 5] {
+        let k = Tensor::randn(0f32, 1., (1, 2, tokens, 64), &device).unwrap();
+        let v = Tensor::randn(0f32, 1., (1, 2, tokens, 64), &device).unwrap();
+        cache.append(&k, &v).unwrap();
+    }
+
+    let mut map = HashMap::new();
+    cache.save_tensors("cache", &mut map).unwrap();
+    // The quantized chunk is saved as raw bytes next to the tokens that are still at full precision
+    assert_eq!(map["cache.key.chunks.0"].dtype(), DType::U8);
+    assert_eq!(map["cache.key"].dim(2).unwrap(), 10);
+    let saved_bytes = map
+        .values()
+        .map(|tensor| tensor.elem_count() * tensor.dtype().size_in_bytes())
+        .sum::<usize>();
+    let full_precision_bytes = 2 * 310 * 2 * 64 * 4;
+    assert!(saved_bytes < full_precision_bytes / 2);
+
+    let mut loaded = KvCache::new_quantized(2, 1024, KvCacheQuantization::Q8_0);
+    loaded.load_tensors("cache", &map).unwrap();
+    assert_eq!(loaded.current_seq_len(), 310);
+    for (loaded, original) in [
+        (loaded.k().unwrap().unwrap(), cache.k().unwrap().unwrap()),
+        (loaded.v().unwrap().unwrap(), cache.v().unwrap().unwrap()),
+    ] {
+        let difference = (loaded - original)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .max(0)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert_eq!(difference, 0.);
     }
 }
@@ -1,13 +1,27 @@
-use std::{fmt::Display, future::Future, path::PathBuf, pin::Pin, sync::OnceLock};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{OnceLock, RwLock},
+};
 
 use candle_core::{backend::BackendStorage, utils::*, Device, Storage, Tensor, WithDType};
 
+mod backend;
+pub use backend::*;
+mod bundle;
 mod cache;
 pub use cache::*;
 mod kv_cache;
 pub use kv_cache::*;
 mod mask;
 pub use mask::*;
+mod prune;
+pub use prune::*;
+mod thread_pool;
+pub use thread_pool::*;
 
 /// Create a candle device that uses any available accelerator.
 pub fn accelerated_device_if_available() -> candle_core::Result<Device> {
@@ -34,8 +48,17 @@ pub fn accelerated_device_if_available() -> candle_core::Result<Device> {
     Ok(device)
 }
 
-/// A source for a file, either from Hugging Face or a local path
-#[derive(Clone, Debug)]
+/// A source for a file: Hugging Face, a URL, a custom [`DownloadBackend`] or a local path
+///
+/// Sources can be read from config files. In TOML, the variants are written as:
+/// - `{ huggingface = { model_id = "...", revision = "main", file = "..." } }`
+/// - `{ url = { url = "https://..." } }`
+/// - `{ remote = { backend = "s3", path = "bucket/key" } }`
+/// - `{ local = "path/to/file" }`
+///
+/// Downloaded files may also pin the expected hash with `sha256 = "..."`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(from = "FileSourceConfig", into = "FileSourceConfig")]
 pub enum FileSource {
     /// A file from Hugging Face
     HuggingFace {
@@ -46,10 +69,117 @@ pub enum FileSource {
         /// The file to use
         file: String,
     },
+    /// A file from a plain HTTP(S) URL
+    Url {
+        /// The URL of the file
+        url: String,
+        /// The SHA-256 hash the downloaded file must match
+        sha256: Option<String>,
+    },
+    /// A file from a [`DownloadBackend`] registered on the cache with [`Cache::with_backend`]
+    Remote {
+        /// The name the backend is registered under
+        backend: String,
+        /// The path of the file in the backend
+        path: String,
+        /// The SHA-256 hash the downloaded file must match
+        sha256: Option<String>,
+    },
     /// A local file
     Local(PathBuf),
 }
 
+/// SHA-256 hashes pinned for Hugging Face files with [`FileSource::with_sha256`]. They are kept outside of [`FileSource::HuggingFace`] so the variant keeps the same fields.
+fn huggingface_sha256() -> &'static RwLock<HashMap<FileSource, String>> {
+    static HASHES: OnceLock<RwLock<HashMap<FileSource, String>>> = OnceLock::new();
+    HASHES.get_or_init(Default::default)
+}
+
+/// The config file representation of a [`FileSource`]. Hugging Face files can pin their hash here even though [`FileSource::HuggingFace`] doesn't store it.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FileSourceConfig {
+    #[serde(rename = "huggingface")]
+    HuggingFace {
+        model_id: String,
+        revision: String,
+        file: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    Url {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    Remote {
+        backend: String,
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sha256: Option<String>,
+    },
+    Local(PathBuf),
+}
+
+impl From<FileSourceConfig> for FileSource {
+    fn from(config: FileSourceConfig) -> Self {
+        match config {
+            FileSourceConfig::HuggingFace {
+                model_id,
+                revision,
+                file,
+                sha256,
+            } => {
+                let source = FileSource::huggingface(model_id, revision, file);
+                match sha256 {
+                    Some(sha256) => source.with_sha256(sha256),
+                    None => source,
+                }
+            }
+            FileSourceConfig::Url { url, sha256 } => FileSource::Url { url, sha256 },
+            FileSourceConfig::Remote {
+                backend,
+                path,
+                sha256,
+            } => FileSource::Remote {
+                backend,
+                path,
+                sha256,
+            },
+            FileSourceConfig::Local(path) => FileSource::Local(path),
+        }
+    }
+}
+
+impl From<FileSource> for FileSourceConfig {
+    fn from(source: FileSource) -> Self {
+        let sha256 = source.sha256();
+        match source {
+            FileSource::HuggingFace {
+                model_id,
+                revision,
+                file,
+            } => FileSourceConfig::HuggingFace {
+                model_id,
+                revision,
+                file,
+                sha256,
+            },
+            FileSource::Url { url, sha256 } => FileSourceConfig::Url { url, sha256 },
+            FileSource::Remote {
+                backend,
+                path,
+                sha256,
+            } => FileSourceConfig::Remote {
+                backend,
+                path,
+                sha256,
+            },
+            FileSource::Local(path) => FileSourceConfig::Local(path),
+        }
+    }
+}
+
 impl Display for FileSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -58,6 +188,8 @@ impl Display for FileSource {
                 revision,
                 file,
             } => write!(f, "hf://{}/{}/{}", model_id, revision, file),
+            FileSource::Url { url, .. } => write!(f, "{url}"),
+            FileSource::Remote { backend, path, .. } => write!(f, "{backend}://{path}"),
             FileSource::Local(path) => write!(f, "{}", path.display()),
         }
     }
@@ -77,6 +209,50 @@ impl FileSource {
         }
     }
 
+    /// Create a new source for a file from a URL
+    pub fn url(url: impl ToString) -> Self {
+        Self::Url {
+            url: url.to_string(),
+            sha256: None,
+        }
+    }
+
+    /// Create a new source for a file from a [`DownloadBackend`] registered on the cache under the name `backend`
+    pub fn remote(backend: impl ToString, path: impl ToString) -> Self {
+        Self::Remote {
+            backend: backend.to_string(),
+            path: path.to_string(),
+            sha256: None,
+        }
+    }
+
+    /// Pin the SHA-256 hash of a downloaded file. Downloads that don't match the hash are rejected. This has no effect on local files.
+    ///
+    /// Hashes for Hugging Face files are pinned for every equal source in the process.
+    pub fn with_sha256(mut self, hash: impl ToString) -> Self {
+        let hash = hash.to_string().to_lowercase();
+        match self {
+            Self::HuggingFace { .. } => {
+                let mut hashes = huggingface_sha256().write().unwrap();
+                hashes.insert(self.clone(), hash);
+            }
+            Self::Url { ref mut sha256, .. } | Self::Remote { ref mut sha256, .. } => {
+                *sha256 = Some(hash)
+            }
+            Self::Local(_) => {}
+        }
+        self
+    }
+
+    /// Get the SHA-256 hash pinned for the file, if any
+    pub fn sha256(&self) -> Option<String> {
+        match self {
+            Self::HuggingFace { .. } => huggingface_sha256().read().unwrap().get(self).cloned(),
+            Self::Url { sha256, .. } | Self::Remote { sha256, .. } => sha256.clone(),
+            Self::Local(_) => None,
+        }
+    }
+
     /// Create a new source for a local file
     pub fn local(path: PathBuf) -> Self {
         Self::Local(path)
@@ -132,3 +308,32 @@ pub fn copy_tensor_into_vec<T: WithDType>(
         Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?, layout),
     }
 }
+
+#[test]
+fn pinned_hashes_round_trip() {
+    let source = FileSource::huggingface("kalosm/pinned", "main", "weights.bin").with_sha256("ABC");
+    assert_eq!(
+        source,
+        FileSource::HuggingFace {
+            model_id: "kalosm/pinned".to_string(),
+            revision: "main".to_string(),
+            file: "weights.bin".to_string(),
+        }
+    );
+    assert_eq!(source.sha256().as_deref(), Some("abc"));
+    assert_eq!(
+        FileSource::huggingface("kalosm/pinned", "main", "other.bin").sha256(),
+        None
+    );
+
+    let json = serde_json::to_string(&source).unwrap();
+    assert!(json.contains("\"sha256\":\"abc\""));
+    let json = json.replace("kalosm/pinned", "kalosm/from-config");
+    let from_config: FileSource = serde_json::from_str(&json).unwrap();
+    assert_eq!(from_config.sha256().as_deref(), Some("abc"));
+
+    let url = FileSource::url("https://example.com/weights.bin").with_sha256("DEF");
+    let json = serde_json::to_string(&url).unwrap();
+    assert_eq!(serde_json::from_str::<FileSource>(&json).unwrap(), url);
+    assert_eq!(url.sha256().as_deref(), Some("def"));
+}
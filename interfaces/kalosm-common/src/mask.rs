@@ -49,6 +49,32 @@ impl MaskCache {
             on_true: mask.on_true,
         })
     }
+
+    /// Get a causal mask that also hides every key more than `window - 1` positions before the query (used by sliding window attention)
+    pub fn get_sliding_window_mask(
+        &self,
+        seq_len: usize,
+        seqlen_offset: usize,
+        window: usize,
+        device: &Device,
+    ) -> Result<AttentionMask> {
+        // If every key is inside the window, the sliding window mask is the same as the causal mask
+        if seqlen_offset + seq_len <= window {
+            return self.get_mask(seq_len, seqlen_offset, device);
+        }
+        let kv_len = seqlen_offset + seq_len;
+        let mask: Vec<_> = (0..seq_len)
+            .flat_map(|i| {
+                let position = seqlen_offset + i;
+                (0..kv_len).map(move |j| u8::from(j > position || position - j >= window))
+            })
+            .collect();
+        let mask = Tensor::from_slice(&mask, (seq_len, kv_len), device)?;
+        Ok(AttentionMask {
+            mask: mask.unsqueeze(0)?.unsqueeze(0)?,
+            on_true: OnceCell::new(),
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -74,3 +100,24 @@ impl AttentionMask {
         Ok(())
     }
 }
+
+#[test]
+fn sliding_window_mask() -> Result<()> {
+    let cache = MaskCache::default();
+    let mask = |seq_len, offset, window| -> Result<Vec<Vec<u8>>> {
+        cache
+            .get_sliding_window_mask(seq_len, offset, window, &Device::Cpu)?
+            .mask
+            .squeeze(0)?
+            .squeeze(0)?
+            .to_vec2()
+    };
+    // Everything fits in the window, so only future tokens are masked
+    assert_eq!(mask(2, 1, 4)?, [[0, 0, 1], [0, 0, 0]]);
+    assert_eq!(
+        mask(4, 0, 2)?,
+        [[0, 1, 1, 1], [0, 0, 1, 1], [1, 0, 0, 1], [1, 1, 0, 0]]
+    );
+    assert_eq!(mask(1, 4, 3)?, [[1, 1, 0, 0, 0]]);
+    Ok(())
+}
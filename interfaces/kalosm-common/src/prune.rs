@@ -0,0 +1,511 @@
+use fs4::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::relative_cache_path;
+use crate::{Cache, FileSource};
+
+/// The name of the index file at the root of the cache that tracks when files were last used and which files are pinned
+const INDEX: &str = ".kalosm-cache.json";
+
+/// The file every process locks while it updates the index so concurrent updates don't overwrite each other
+const INDEX_LOCK: &str = ".kalosm-cache.json.lock";
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndex {
+    /// Files by their path relative to the cache location
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedFile {
+    source: FileSource,
+    /// Seconds since the unix epoch
+    last_used: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+impl CacheIndex {
+    fn read(location: &Path) -> Self {
+        std::fs::read_to_string(location.join(INDEX))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, location: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(location)?;
+        // Write to a temporary file first so other processes never read a half written index
+        static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+        let temporary = location.join(format!(
+            "{INDEX}.{}.{}.tmp",
+            std::process::id(),
+            NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(temporary, location.join(INDEX))?;
+        Ok(())
+    }
+}
+
+/// A file in the cache
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    path: PathBuf,
+    source: Option<FileSource>,
+    size: u64,
+    last_used: SystemTime,
+    pinned: bool,
+}
+
+impl CacheEntry {
+    /// Get the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the source the file was downloaded from. This is `None` for files downloaded by an older version of kalosm that have not been used since.
+    pub fn source(&self) -> Option<&FileSource> {
+        self.source.as_ref()
+    }
+
+    /// Get the Hugging Face model id if the file was downloaded from Hugging Face
+    pub fn model_id(&self) -> Option<&str> {
+        match &self.source {
+            Some(FileSource::HuggingFace { model_id, .. }) => Some(model_id),
+            _ => None,
+        }
+    }
+
+    /// Get the Hugging Face revision if the file was downloaded from Hugging Face
+    pub fn revision(&self) -> Option<&str> {
+        match &self.source {
+            Some(FileSource::HuggingFace { revision, .. }) => Some(revision),
+            _ => None,
+        }
+    }
+
+    /// Get the name of the file in the Hugging Face repo if the file was downloaded from Hugging Face
+    pub fn file(&self) -> Option<&str> {
+        match &self.source {
+            Some(FileSource::HuggingFace { file, .. }) => Some(file),
+            _ => None,
+        }
+    }
+
+    /// Get the size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Get the last time the file was loaded from the cache. Files that have not been loaded since they were downloaded report the download time.
+    pub fn last_used(&self) -> SystemTime {
+        self.last_used
+    }
+
+    /// Check if the file is pinned. Pinned files are never removed by [`Cache::prune`].
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+}
+
+/// The rules [`Cache::prune`] uses to decide which files to remove. Pinned files are always kept.
+///
+/// ```rust, no_run
+/// # use kalosm_common::*;
+/// # use std::time::Duration;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// // Keep the cache under 20 GB, and remove files that haven't been used in a month
+/// let policy = PrunePolicy::new()
+///     .with_max_size(20 * 1024 * 1024 * 1024)
+///     .with_max_age(Duration::from_secs(60 * 60 * 24 * 30))
+///     .with_remove_stale_revisions(true);
+/// let removed = Cache::default().prune(&policy).await?;
+/// println!("Removed {} files", removed.len());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrunePolicy {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    remove_stale_revisions: bool,
+    dry_run: bool,
+}
+
+impl PrunePolicy {
+    /// Create a new policy that doesn't remove anything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the least recently used files until the cache is at most `max_size` bytes (defaults to no limit)
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Remove files that have not been used for longer than `max_age` (defaults to no limit)
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Remove every revision of a Hugging Face model except the most recently used one (defaults to false)
+    pub fn with_remove_stale_revisions(mut self, remove_stale_revisions: bool) -> Self {
+        self.remove_stale_revisions = remove_stale_revisions;
+        self
+    }
+
+    /// Only report the files that would be removed without removing them (defaults to false)
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Pick the entries to remove
+    fn select(&self, entries: Vec<CacheEntry>, now: SystemTime) -> Vec<CacheEntry> {
+        let (pinned, mut entries): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|entry| entry.pinned);
+        let mut removed = Vec::new();
+
+        if let Some(max_age) = self.max_age {
+            let (old, recent): (Vec<_>, Vec<_>) = entries.into_iter().partition(|entry| {
+                now.duration_since(entry.last_used)
+                    .is_ok_and(|age| age > max_age)
+            });
+            removed.extend(old);
+            entries = recent;
+        }
+
+        if self.remove_stale_revisions {
+            let mut latest_revision: HashMap<&str, (&str, SystemTime)> = HashMap::new();
+            for entry in pinned.iter().chain(&entries) {
+                if let (Some(model_id), Some(revision)) = (entry.model_id(), entry.revision()) {
+                    let latest = latest_revision
+                        .entry(model_id)
+                        .or_insert((revision, entry.last_used));
+                    if entry.last_used > latest.1 {
+                        *latest = (revision, entry.last_used);
+                    }
+                }
+            }
+            let latest_revision: HashMap<String, String> = latest_revision
+                .into_iter()
+                .map(|(model_id, (revision, _))| (model_id.to_string(), revision.to_string()))
+                .collect();
+            let (stale, current): (Vec<_>, Vec<_>) =
+                entries
+                    .into_iter()
+                    .partition(|entry| match (entry.model_id(), entry.revision()) {
+                        (Some(model_id), Some(revision)) => {
+                            latest_revision.get(model_id).map(String::as_str) != Some(revision)
+                        }
+                        _ => false,
+                    });
+            removed.extend(stale);
+            entries = current;
+        }
+
+        if let Some(max_size) = self.max_size {
+            let mut size: u64 = pinned.iter().chain(&entries).map(|entry| entry.size).sum();
+            // Remove the least recently used files first
+            entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+            while size > max_size {
+                let Some(entry) = entries.pop() else {
+                    break;
+                };
+                size -= entry.size;
+                removed.push(entry);
+            }
+        }
+
+        removed
+    }
+}
+
+impl Cache {
+    /// List every file in the cache. Partial downloads are not included.
+    pub async fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        let location = self.location().to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            // The index is replaced atomically, so it can be read without holding the lock
+            let index = CacheIndex::read(&location);
+            let mut entries = Vec::new();
+            let mut directories = vec![location.clone()];
+            while let Some(directory) = directories.pop() {
+                let Ok(read_dir) = std::fs::read_dir(&directory) else {
+                    continue;
+                };
+                for item in read_dir {
+                    let item = item?;
+                    let path = item.path();
+                    let metadata = item.metadata()?;
+                    if metadata.is_dir() {
+                        directories.push(path);
+                        continue;
+                    }
+                    let Ok(relative) = path.strip_prefix(&location) else {
+                        continue;
+                    };
+                    let relative = relative.to_path_buf();
+                    let name = relative.to_string_lossy();
+                    if name.starts_with(INDEX) || name.ends_with(".partial") {
+                        continue;
+                    }
+                    let indexed = index.files.get(&relative);
+                    entries.push(CacheEntry {
+                        source: indexed
+                            .map(|file| file.source.clone())
+                            .or_else(|| infer_source(&relative)),
+                        size: metadata.len(),
+                        last_used: match indexed {
+                            Some(file) => UNIX_EPOCH + Duration::from_secs(file.last_used),
+                            None => metadata.modified().unwrap_or(UNIX_EPOCH),
+                        },
+                        pinned: indexed.is_some_and(|file| file.pinned),
+                        path,
+                    });
+                }
+            }
+            anyhow::Ok(entries)
+        })
+        .await?
+    }
+
+    /// Get the total size of every file in the cache in bytes
+    pub async fn size(&self) -> anyhow::Result<u64> {
+        Ok(self.entries().await?.iter().map(CacheEntry::size).sum())
+    }
+
+    /// Remove files from the cache according to a policy. Returns the entries that were removed.
+    pub async fn prune(&self, policy: &PrunePolicy) -> anyhow::Result<Vec<CacheEntry>> {
+        let removed = policy.select(self.entries().await?, SystemTime::now());
+        if !policy.dry_run {
+            for entry in &removed {
+                self.remove_file(&entry.path).await?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove a downloaded file from the cache. Removing a local file source does nothing.
+    pub async fn remove(&self, source: &FileSource) -> anyhow::Result<()> {
+        if let FileSource::Local(_) = source {
+            return Ok(());
+        }
+        self.migrate_legacy_file(source, &self.path(source)?)
+            .await?;
+        let path = self.path(source)?;
+        if path.exists() {
+            self.remove_file(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Pin a file so [`Cache::prune`] never removes it. The file doesn't need to be downloaded yet.
+    pub fn pin(&self, source: &FileSource) -> anyhow::Result<()> {
+        self.set_pinned(source, true)
+    }
+
+    /// Unpin a file that was pinned with [`Cache::pin`]
+    pub fn unpin(&self, source: &FileSource) -> anyhow::Result<()> {
+        self.set_pinned(source, false)
+    }
+
+    fn set_pinned(&self, source: &FileSource, pinned: bool) -> anyhow::Result<()> {
+        let Some(relative) = relative_cache_path(source)? else {
+            return Ok(());
+        };
+        self.update_index(|index| {
+            index
+                .files
+                .entry(relative)
+                .or_insert_with(|| IndexedFile {
+                    source: source.clone(),
+                    last_used: unix_seconds(SystemTime::now()),
+                    pinned,
+                })
+                .pinned = pinned;
+        })
+    }
+
+    /// Record that a file was loaded from the cache
+    pub(crate) async fn record_use(&self, source: &FileSource) {
+        let Ok(Some(relative)) = relative_cache_path(source) else {
+            return;
+        };
+        let now = unix_seconds(SystemTime::now());
+        let indexed_source = source.clone();
+        let result = self
+            .update_index_in_background(move |index| {
+                index
+                    .files
+                    .entry(relative)
+                    .and_modify(|file| {
+                        file.source = indexed_source.clone();
+                        file.last_used = now;
+                    })
+                    .or_insert_with(|| IndexedFile {
+                        source: indexed_source,
+                        last_used: now,
+                        pinned: false,
+                    });
+            })
+            .await;
+        // Tracking usage is best effort. A read only cache should still be able to load models
+        if let Err(err) = result {
+            tracing::warn!("Failed to record the use of {source} in the cache index: {err}");
+        }
+    }
+
+    fn update_index(&self, update: impl FnOnce(&mut CacheIndex)) -> anyhow::Result<()> {
+        update_index(self.location(), update)
+    }
+
+    /// Update the index on the blocking thread pool so async callers don't wait on the file lock
+    async fn update_index_in_background(
+        &self,
+        update: impl FnOnce(&mut CacheIndex) + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let location = self.location().to_path_buf();
+        tokio::task::spawn_blocking(move || update_index(&location, update)).await?
+    }
+
+    /// Remove a file in the cache, its entry in the index and any directories that are empty afterwards
+    async fn remove_file(&self, path: &Path) -> anyhow::Result<()> {
+        tokio::fs::remove_file(path).await?;
+        if let Ok(relative) = path.strip_prefix(self.location()) {
+            let relative = relative.to_path_buf();
+            self.update_index_in_background(move |index| {
+                // Keep pins for files that are removed manually so they are kept when they are downloaded again
+                if index.files.get(&relative).is_some_and(|file| !file.pinned) {
+                    index.files.remove(&relative);
+                }
+            })
+            .await?;
+        }
+        let mut directory = path.parent();
+        while let Some(current) = directory {
+            if current == self.location() || tokio::fs::remove_dir(current).await.is_err() {
+                break;
+            }
+            directory = current.parent();
+        }
+        Ok(())
+    }
+}
+
+/// Read, update and write the index while holding an exclusive lock on the index that is shared with other processes
+fn update_index(location: &Path, update: impl FnOnce(&mut CacheIndex)) -> anyhow::Result<()> {
+    std::fs::create_dir_all(location)?;
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(location.join(INDEX_LOCK))?;
+    // The lock is released when the file is closed
+    lock.lock_exclusive()?;
+    let mut index = CacheIndex::read(location);
+    update(&mut index);
+    index.write(location)
+}
+
+/// Guess the source of a file that isn't in the index from its path. Hugging Face files are stored as `huggingface/org/model/revision/file` (or `org/model/revision/file` by older versions of kalosm),
+/// and remote files as `remote/backend/path`. URLs are stored under a hash, so their source can't be recovered from the path.
+fn infer_source(relative: &Path) -> Option<FileSource> {
+    let components = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    match components.as_slice() {
+        ["url", ..] => None,
+        ["remote", backend, path @ ..] if !path.is_empty() => {
+            Some(FileSource::remote(backend, path.join("/")))
+        }
+        ["huggingface", organization, model, revision, file @ ..]
+        | [organization, model, revision, file @ ..]
+            if !file.is_empty() =>
+        {
+            Some(FileSource::huggingface(
+                format!("{organization}/{model}"),
+                revision,
+                file.join("/"),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[tokio::test]
+async fn prune_removes_least_recently_used_files() {
+    let location = std::env::temp_dir().join(format!("kalosm-prune-test-{}", std::process::id()));
+    let cache = Cache::new(location.clone()).with_offline(true);
+
+    let old_revision = FileSource::huggingface("kalosm/model", "old", "model.gguf");
+    let new_revision = FileSource::huggingface("kalosm/model", "main", "model.gguf");
+    let tokenizer = FileSource::huggingface("kalosm/model", "main", "tokenizer.json");
+    let pinned = FileSource::huggingface("kalosm/pinned", "main", "model.gguf");
+    for (last_used, (source, size)) in [
+        (&old_revision, 400),
+        (&pinned, 300),
+        (&new_revision, 200),
+        (&tokenizer, 100),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let path = cache.path(source).unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, vec![0; size]).unwrap();
+        // Loading the file records it in the index
+        cache.get(source, |_| {}).await.unwrap();
+        let relative = relative_cache_path(source).unwrap().unwrap();
+        cache
+            .update_index(|index| {
+                index.files.get_mut(&relative).unwrap().last_used = last_used as u64 * 1000;
+            })
+            .unwrap();
+    }
+    cache.pin(&pinned).unwrap();
+    assert_eq!(cache.size().await.unwrap(), 1000);
+
+    let stale = cache
+        .prune(
+            &PrunePolicy::new()
+                .with_remove_stale_revisions(true)
+                .with_dry_run(true),
+        )
+        .await
+        .unwrap();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].revision(), Some("old"));
+    assert_eq!(cache.size().await.unwrap(), 1000);
+
+    // The pinned file is kept even though it is the least recently used file left after the old revision
+    let removed = cache
+        .prune(&PrunePolicy::new().with_max_size(500))
+        .await
+        .unwrap();
+    let removed: Vec<_> = removed
+        .iter()
+        .map(|entry| entry.source().cloned())
+        .collect();
+    assert_eq!(removed, vec![Some(old_revision), Some(new_revision)]);
+    assert!(cache.exists(&pinned));
+    assert!(cache.exists(&tokenizer));
+    assert_eq!(cache.size().await.unwrap(), 400);
+
+    std::fs::remove_dir_all(location).unwrap();
+}
@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+pub use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// The threads a model uses for inference on the CPU. This includes candle's matrix multiplications and the parallel work in structured generation.
+#[derive(Debug, Clone, Default)]
+pub enum InferenceThreads {
+    /// Use rayon's global thread pool. Every model that uses the global pool shares the same threads.
+    #[default]
+    Global,
+    /// Create a dedicated thread pool with this many threads for the model.
+    ///
+    /// Candle splits matrix multiplications into one chunk for each thread in `RAYON_NUM_THREADS`, or one chunk for each CPU if it is not set. Kalosm never changes the environment of the process, so if you want candle to split work into as many chunks as the pool has threads, set `RAYON_NUM_THREADS` before your program starts any threads.
+    Count(usize),
+    /// Use an existing thread pool. This is useful to share a fixed set of threads between a few models.
+    Pool(Arc<ThreadPool>),
+}
+
+impl InferenceThreads {
+    /// Build the thread pool the model runs inference with.
+    pub fn build(&self, name: &str) -> anyhow::Result<InferenceThreadPool> {
+        let pool = match self {
+            InferenceThreads::Global => None,
+            InferenceThreads::Count(threads) => {
+                let name = name.to_string();
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(*threads)
+                    .thread_name(move |index| format!("{name}-{index}"))
+                    .build()?;
+                Some(Arc::new(pool))
+            }
+            InferenceThreads::Pool(pool) => Some(pool.clone()),
+        };
+        Ok(InferenceThreadPool { pool })
+    }
+}
+
+/// A thread pool a model runs inference with. Created with [`InferenceThreads::build`].
+#[derive(Debug, Clone, Default)]
+pub struct InferenceThreadPool {
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl InferenceThreadPool {
+    /// Get the number of threads in the pool.
+    pub fn current_num_threads(&self) -> usize {
+        match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        }
+    }
+
+    /// Run a closure in the thread pool. Any parallel work the closure starts runs on the threads in the pool.
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
+
+#[test]
+fn install_uses_the_dedicated_pool() {
+    let pool = InferenceThreads::Count(3).build("test").unwrap();
+    assert_eq!(pool.current_num_threads(), 3);
+    let name = pool.install(|| std::thread::current().name().map(ToString::to_string));
+    assert!(name.unwrap().starts_with("test-"));
+    assert_eq!(pool.install(rayon::current_num_threads), 3);
+
+    let global = InferenceThreads::Global.build("test").unwrap();
+    assert_eq!(
+        global.install(rayon::current_num_threads),
+        rayon::current_num_threads()
+    );
+}
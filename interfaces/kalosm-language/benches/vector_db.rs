@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kalosm_language::prelude::*;
+use rand::{Rng, SeedableRng};
+
+criterion_group!(benches, ingestion);
+criterion_main!(benches);
+
+const DIMENSIONS: usize = 384;
+
+fn random_embeddings(count: usize) -> Vec<Embedding<UnknownVectorSpace>> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    (0..count)
+        .map(|_| Embedding::from((0..DIMENSIONS).map(|_| rng.gen_range(-1.0..1.0))))
+        .collect()
+}
+
+/// Add embeddings one at a time, then run a single search so every strategy ends with a fully built index
+fn ingest_one_at_a_time(
+    embeddings: &[Embedding<UnknownVectorSpace>],
+    build_strategy: IndexBuildStrategy,
+) {
+    let db = VectorDB::new().unwrap().with_build_strategy(build_strategy);
+    for embedding in embeddings {
+        db.add_embedding(embedding.clone()).unwrap();
+    }
+    db.get_closest(embeddings[0].clone(), 1).unwrap();
+}
+
+fn ingestion(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vector db ingestion");
+    group.sample_size(10);
+
+    for count in [100, 1000] {
+        let embeddings = random_embeddings(count);
+        group.throughput(Throughput::Elements(count as u64));
+
+        for (name, build_strategy) in [
+            ("immediate", IndexBuildStrategy::Immediate),
+            ("manual", IndexBuildStrategy::Manual),
+            (
+                "background",
+                IndexBuildStrategy::Background {
+                    max_pending_changes: 256,
+                    idle_time: Duration::from_millis(100),
+                },
+            ),
+        ] {
+            group.bench_with_input(
+                BenchmarkId::new(name, count),
+                &embeddings,
+                |b, embeddings| b.iter(|| ingest_one_at_a_time(embeddings, build_strategy)),
+            );
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("batch", count),
+            &embeddings,
+            |b, embeddings| {
+                b.iter(|| {
+                    let db = VectorDB::new().unwrap();
+                    db.add_embeddings(embeddings.iter().cloned()).unwrap();
+                    db.get_closest(embeddings[0].clone(), 1).unwrap();
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
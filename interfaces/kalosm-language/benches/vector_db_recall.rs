@@ -0,0 +1,72 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kalosm_language::prelude::*;
+use rand::{Rng, SeedableRng};
+
+criterion_group!(benches, recall);
+criterion_main!(benches);
+
+const DIMENSIONS: usize = 384;
+const QUERIES: usize = 100;
+const K: usize = 10;
+
+fn random_embeddings(count: usize, seed: u64) -> Vec<Embedding<UnknownVectorSpace>> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| Embedding::from((0..DIMENSIONS).map(|_| rng.gen_range(-1.0..1.0))))
+        .collect()
+}
+
+fn database<D: VectorDistance>(
+    embeddings: &[Embedding<UnknownVectorSpace>],
+    search_mode: SearchMode,
+) -> VectorDB<UnknownVectorSpace, D> {
+    let db = VectorDB::new_with_distance()
+        .unwrap()
+        .with_search_mode(search_mode);
+    db.add_embeddings(embeddings.iter().cloned()).unwrap();
+    db
+}
+
+/// Measure the fraction of the exact top K results the approximate index finds, and how long each search takes
+fn recall_for<D: VectorDistance>(c: &mut Criterion, distance: &str) {
+    let mut group = c.benchmark_group(format!("vector db search ({distance})"));
+    group.sample_size(10);
+    let queries = random_embeddings(QUERIES, 1);
+
+    for count in [1000, 10000] {
+        let embeddings = random_embeddings(count, 0);
+        let approximate = database::<D>(&embeddings, SearchMode::Approximate);
+        let exact = database::<D>(&embeddings, SearchMode::Exact);
+
+        let mut found = 0;
+        for query in &queries {
+            let expected = exact.get_closest(query.clone(), K).unwrap();
+            let results = approximate.get_closest(query.clone(), K).unwrap();
+            found += results
+                .iter()
+                .filter(|result| expected.iter().any(|e| e.value == result.value))
+                .count();
+        }
+        let recall = found as f64 / (QUERIES * K) as f64;
+        println!("{distance} recall@{K} with {count} embeddings: {recall:.3}");
+
+        for (name, db) in [("approximate", &approximate), ("exact", &exact)] {
+            group.bench_with_input(BenchmarkId::new(name, count), &queries, |b, queries| {
+                b.iter(|| {
+                    for query in queries {
+                        db.get_closest(query.clone(), K).unwrap();
+                    }
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn recall(c: &mut Criterion) {
+    recall_for::<Angular>(c, "angular");
+    recall_for::<Euclidean>(c, "euclidean");
+    recall_for::<Manhattan>(c, "manhattan");
+    recall_for::<DotProduct>(c, "dot product");
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A full-text index that ranks documents with [BM25](https://en.wikipedia.org/wiki/Okapi_BM25).
+///
+/// Lexical search finds exact terms like error codes, product SKUs or names that embedding search often misses. It can be combined with vector search with [`FusionStrategy`](super::FusionStrategy).
+///
+/// ```rust
+/// # use kalosm_language::prelude::*;
+/// let mut index = Bm25Index::new();
+/// index.insert(0, "The server returned error E1042 after the update");
+/// index.insert(1, "The server is running normally");
+///
+/// let results = index.search("E1042", 10);
+/// assert_eq!(results[0].key, 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bm25Index<K> {
+    k1: f32,
+    b: f32,
+    documents: HashMap<K, IndexedDocument>,
+    /// The documents each term appears in and how many times it appears in each document
+    postings: HashMap<String, HashMap<K, usize>>,
+    total_length: usize,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedDocument {
+    length: usize,
+    /// The unique terms in the document
+    terms: Vec<String>,
+}
+
+impl<K> Default for Bm25Index<K> {
+    fn default() -> Self {
+        Self {
+            k1: 1.2,
+            b: 0.75,
+            documents: HashMap::new(),
+            postings: HashMap::new(),
+            total_length: 0,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Bm25Index<K> {
+    /// Create a new empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how quickly repeated terms stop increasing the score of a document (defaults to 1.2)
+    pub fn with_k1(mut self, k1: f32) -> Self {
+        self.k1 = k1;
+        self
+    }
+
+    /// Set how much longer documents are penalized, from 0 (no penalty) to 1 (fully normalized by length) (defaults to 0.75)
+    pub fn with_b(mut self, b: f32) -> Self {
+        self.b = b;
+        self
+    }
+
+    /// Add text to the index. If the key is already in the index, the old text is replaced.
+    pub fn insert(&mut self, key: K, text: &str) {
+        self.remove(&key);
+
+        let mut term_frequency = HashMap::new();
+        let mut length = 0;
+        for term in tokenize(text) {
+            *term_frequency.entry(term).or_insert(0) += 1;
+            length += 1;
+        }
+        let mut terms = Vec::with_capacity(term_frequency.len());
+        for (term, frequency) in term_frequency {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(key.clone(), frequency);
+            terms.push(term);
+        }
+        self.total_length += length;
+        self.documents
+            .insert(key, IndexedDocument { length, terms });
+    }
+
+    /// Remove text from the index. Returns true if the key was in the index.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(document) = self.documents.remove(key) else {
+            return false;
+        };
+        self.total_length -= document.length;
+        for term in document.terms {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.remove(key);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        true
+    }
+
+    /// Remove every document with a key that doesn't match the predicate.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        let removed = self
+            .documents
+            .keys()
+            .filter(|key| !keep(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+
+    /// Remove every document from the index.
+    pub fn clear(&mut self) {
+        self.documents.clear();
+        self.postings.clear();
+        self.total_length = 0;
+    }
+
+    /// Get the number of documents in the index.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Check if the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Find the top k documents that best match the query, sorted from the highest score to the lowest. Documents that don't contain any of the terms in the query are never returned.
+    ///
+    /// Only the documents that contain a term in the query are scored, so searches stay fast as the index grows.
+    pub fn search(&self, query: &str, k: usize) -> Vec<Bm25SearchResult<K>> {
+        if self.documents.is_empty() {
+            return Vec::new();
+        }
+        let document_count = self.documents.len() as f32;
+        let average_length = self.total_length as f32 / document_count;
+
+        let mut terms = tokenize(query).collect::<Vec<_>>();
+        terms.sort();
+        terms.dedup();
+        let mut scores: HashMap<&K, f32> = HashMap::new();
+        for term in terms {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let document_frequency = postings.len() as f32;
+            let idf =
+                ((document_count - document_frequency + 0.5) / (document_frequency + 0.5)).ln_1p();
+            for (key, frequency) in postings {
+                let length = self.documents[key].length as f32;
+                let length_norm = 1. - self.b + self.b * length / average_length;
+                let frequency = *frequency as f32;
+                let saturation = frequency + self.k1 * length_norm;
+                *scores.entry(key).or_default() += idf * frequency * (self.k1 + 1.) / saturation;
+            }
+        }
+
+        let mut results = scores
+            .into_iter()
+            .filter(|(_, score)| *score > 0.)
+            .map(|(key, score)| Bm25SearchResult {
+                key: key.clone(),
+                score,
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(k);
+        results
+    }
+}
+
+/// A result from a [`Bm25Index`] search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bm25SearchResult<K> {
+    /// The key of the document
+    pub key: K,
+    /// The BM25 score of the document. Higher scores are better matches.
+    pub score: f32,
+}
+
+/// Split text into lowercase alphanumeric terms
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[test]
+fn bm25_ranks_exact_terms() {
+    let mut index = Bm25Index::new();
+    index.insert("sku", "Replacement filter SKU-4411 for the X200 purifier");
+    index.insert("manual", "How to replace the filter in your purifier");
+    index.insert("other", "Shipping and returns");
+
+    let results = index.search("sku-4411", 10);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].key, "sku");
+
+    let results = index.search("replace filter", 10);
+    assert_eq!(results[0].key, "manual");
+    assert_eq!(results.len(), 2);
+
+    assert!(index.remove(&"manual"));
+    assert!(!index.remove(&"manual"));
+    let results = index.search("replace filter", 10);
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].key, "sku");
+
+    // Inserting a key again replaces the old text
+    index.insert("sku", "Replacement cartridge SKU-5000");
+    assert!(index.search("filter", 10).is_empty());
+    assert_eq!(index.search("sku-5000", 10)[0].key, "sku");
+    assert_eq!(index.len(), 2);
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// How results from lexical search (like [`Bm25Index`](super::Bm25Index)) and vector search are combined into one ranking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FusionStrategy {
+    /// Score each result by the sum of `1 / (k + rank)` across the rankings it appears in. Only the order of each ranking is used, so scores from different searches never need to be compared.
+    ReciprocalRank {
+        /// Dampens the advantage of the top ranked results. 60 is a good default.
+        k: f32,
+    },
+    /// Normalize the scores from each search to the range 0 to 1 and add them together with a weight.
+    Weighted {
+        /// The weight of the lexical score from 0 to 1. The vector score is weighted by `1 - lexical_weight`.
+        lexical_weight: f32,
+    },
+}
+
+impl Default for FusionStrategy {
+    fn default() -> Self {
+        Self::ReciprocalRank { k: 60. }
+    }
+}
+
+/// A result after fusing lexical and vector search results with a [`FusionStrategy`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FusedSearchResult<K> {
+    /// The key of the result
+    pub key: K,
+    /// The combined score of the result. Higher scores are better matches.
+    pub score: f32,
+    /// The score of the result in the lexical search, if the lexical search found it
+    pub lexical_score: Option<f32>,
+    /// The distance of the result in the vector search, if the vector search found it
+    pub distance: Option<f32>,
+}
+
+impl FusionStrategy {
+    /// Fuse lexical results (with higher scores being better) and vector results (with lower distances being better) into the top k results. Both lists must be sorted from the best match to the worst match.
+    pub fn fuse<K: Clone + Eq + Hash>(
+        &self,
+        lexical: impl IntoIterator<Item = (K, f32)>,
+        vector: impl IntoIterator<Item = (K, f32)>,
+        k: usize,
+    ) -> Vec<FusedSearchResult<K>> {
+        let lexical = lexical.into_iter().collect::<Vec<_>>();
+        let vector = vector.into_iter().collect::<Vec<_>>();
+
+        let mut results: HashMap<K, FusedSearchResult<K>> = HashMap::new();
+        match *self {
+            Self::ReciprocalRank { k } => {
+                for (rank, (key, score)) in lexical.iter().enumerate() {
+                    let result = entry(&mut results, key);
+                    result.score += 1. / (k + rank as f32 + 1.);
+                    result.lexical_score = Some(*score);
+                }
+                for (rank, (key, distance)) in vector.iter().enumerate() {
+                    let result = entry(&mut results, key);
+                    result.score += 1. / (k + rank as f32 + 1.);
+                    result.distance = Some(*distance);
+                }
+            }
+            Self::Weighted { lexical_weight } => {
+                let lexical_weight = lexical_weight.clamp(0., 1.);
+                let max_score = lexical.iter().map(|(_, score)| *score).fold(0., f32::max);
+                for (key, score) in &lexical {
+                    let result = entry(&mut results, key);
+                    if max_score > 0. {
+                        result.score += lexical_weight * score / max_score;
+                    }
+                    result.lexical_score = Some(*score);
+                }
+                let (min_distance, max_distance) = vector.iter().fold(
+                    (f32::INFINITY, f32::NEG_INFINITY),
+                    |(min, max), (_, distance)| (min.min(*distance), max.max(*distance)),
+                );
+                let range = max_distance - min_distance;
+                for (key, distance) in &vector {
+                    let result = entry(&mut results, key);
+                    // The closest result scores 1 and the furthest scores 0
+                    let similarity = if range > 0. {
+                        (max_distance - distance) / range
+                    } else {
+                        1.
+                    };
+                    result.score += (1. - lexical_weight) * similarity;
+                    result.distance = Some(*distance);
+                }
+            }
+        }
+
+        let mut results = results.into_values().collect::<Vec<_>>();
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(k);
+        results
+    }
+}
+
+fn entry<'a, K: Clone + Eq + Hash>(
+    results: &'a mut HashMap<K, FusedSearchResult<K>>,
+    key: &K,
+) -> &'a mut FusedSearchResult<K> {
+    results
+        .entry(key.clone())
+        .or_insert_with(|| FusedSearchResult {
+            key: key.clone(),
+            score: 0.,
+            lexical_score: None,
+            distance: None,
+        })
+}
+
+#[test]
+fn fusion_combines_rankings() {
+    let lexical = [("sku", 8.), ("manual", 2.)];
+    let vector = [("manual", 0.2), ("faq", 0.4), ("sku", 0.9)];
+
+    let fused = FusionStrategy::default().fuse(lexical, vector, 10);
+    let keys = fused.iter().map(|result| result.key).collect::<Vec<_>>();
+    // Results found by both searches rank above results found by one
+    assert_eq!(keys, ["manual", "sku", "faq"]);
+    assert_eq!(fused[0].lexical_score, Some(2.));
+    assert_eq!(fused[0].distance, Some(0.2));
+
+    let fused = FusionStrategy::Weighted { lexical_weight: 1. }.fuse(lexical, vector, 2);
+    assert_eq!(fused.len(), 2);
+    assert_eq!(fused[0].key, "sku");
+    assert_eq!(fused[0].score, 1.);
+
+    let fused = FusionStrategy::Weighted { lexical_weight: 0. }.fuse(lexical, vector, 1);
+    assert_eq!(fused[0].key, "manual");
+    assert_eq!(fused[0].distance, Some(0.2));
+}
@@ -1,6 +1,11 @@
 //! The index module contains different types of search indexes that can be used to search for [`crate::context::Document`]s created from [`crate::context::IntoDocument`] or [`crate::context::IntoDocuments`]
 
+mod bm25;
+pub use bm25::*;
+mod fusion;
+pub use fusion::*;
 mod postprocessing;
+pub use postprocessing::*;
 mod preprocessing;
 pub use preprocessing::*;
 
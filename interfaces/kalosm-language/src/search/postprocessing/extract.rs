@@ -0,0 +1,122 @@
+use std::ops::Range;
+
+use kalosm_language_model::{Embedder, EmbedderExt};
+
+use crate::prelude::SentenceChunker;
+
+use super::{Postprocessor, SearchCandidate};
+
+/// A [`Postprocessor`] that shortens each candidate to the sentences that are most similar to the query.
+///
+/// Chunks are often much longer than the part that answers the query. Extracting the relevant sentences keeps more results in the context window of a model. The order of the candidates and their scores are not changed.
+pub struct SentenceExtractor<E> {
+    embedder: E,
+    max_sentences: usize,
+    window: usize,
+}
+
+impl<E: Embedder> SentenceExtractor<E> {
+    /// Create a new sentence extractor that compares sentences to the query with an embedder.
+    pub fn new(embedder: E) -> Self {
+        Self {
+            embedder,
+            max_sentences: 3,
+            window: 0,
+        }
+    }
+
+    /// Set the maximum number of sentences to keep from each candidate (defaults to 3)
+    pub fn with_max_sentences(mut self, max_sentences: usize) -> Self {
+        self.max_sentences = max_sentences;
+        self
+    }
+
+    /// Set the number of neighboring sentences to keep on each side of a matching sentence for context (defaults to 0)
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+}
+
+impl<E: Embedder> Postprocessor for SentenceExtractor<E> {
+    async fn process<T: Send>(
+        &self,
+        query: &str,
+        mut candidates: Vec<SearchCandidate<T>>,
+    ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+        // The sentence chunker is not Send, so it is dropped before the embeddings are awaited
+        let sentences = {
+            let chunker = SentenceChunker::default();
+            candidates
+                .iter()
+                .map(|candidate| chunker.split_sentences(&candidate.text))
+                .collect::<Vec<_>>()
+        };
+        let texts = candidates
+            .iter()
+            .zip(&sentences)
+            .flat_map(|(candidate, ranges)| {
+                ranges
+                    .iter()
+                    .map(|range| candidate.text[range.clone()].to_string())
+            })
+            .collect::<Vec<_>>();
+        if texts.is_empty() {
+            return Ok(candidates);
+        }
+
+        let query = self.embedder.embed_query(query).await?;
+        let embeddings = self.embedder.embed_vec(texts).await?;
+        let mut similarities = embeddings
+            .iter()
+            .map(|embedding| embedding.cosine_similarity(&query));
+
+        for (candidate, ranges) in candidates.iter_mut().zip(sentences) {
+            let similarities = similarities.by_ref().take(ranges.len()).collect::<Vec<_>>();
+            let kept = select_sentences(&similarities, self.max_sentences, self.window);
+            if kept.len() == ranges.len() {
+                continue;
+            }
+            candidate.text = join_sentences(&candidate.text, &ranges, &kept);
+        }
+
+        Ok(candidates)
+    }
+}
+
+/// Pick the indexes of the most similar sentences and their neighbors in their original order
+fn select_sentences(similarities: &[f32], max_sentences: usize, window: usize) -> Vec<usize> {
+    let mut ranked = (0..similarities.len()).collect::<Vec<_>>();
+    ranked.sort_by(|&a, &b| similarities[b].total_cmp(&similarities[a]));
+    ranked.truncate(max_sentences);
+
+    let mut kept = vec![false; similarities.len()];
+    for index in ranked {
+        let start = index.saturating_sub(window);
+        let end = (index + window + 1).min(similarities.len());
+        kept[start..end].fill(true);
+    }
+    kept.iter()
+        .enumerate()
+        .filter_map(|(index, kept)| kept.then_some(index))
+        .collect()
+}
+
+fn join_sentences(text: &str, ranges: &[Range<usize>], kept: &[usize]) -> String {
+    kept.iter()
+        .map(|&index| text[ranges[index].clone()].trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn sentence_selection_keeps_order() {
+    let similarities = [0.1, 0.9, 0.2, 0.8, 0.0];
+    assert_eq!(select_sentences(&similarities, 2, 0), [1, 3]);
+    assert_eq!(select_sentences(&similarities, 1, 1), [0, 1, 2]);
+    assert_eq!(select_sentences(&similarities, 10, 0), [0, 1, 2, 3, 4]);
+
+    let text = "First. Second. Third.";
+    let ranges = [0..7, 7..15, 15..21];
+    assert_eq!(join_sentences(text, &ranges, &[0, 2]), "First. Third.");
+}
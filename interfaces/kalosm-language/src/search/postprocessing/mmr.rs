@@ -0,0 +1,115 @@
+use kalosm_language_model::{Embedder, EmbedderExt};
+
+use super::{Postprocessor, SearchCandidate};
+
+/// A [`Postprocessor`] that picks candidates with [maximal marginal relevance](https://www.cs.cmu.edu/~jgc/publication/The_Use_MMR_Diversity_Based_LTMIR_1998.pdf).
+///
+/// Search results often contain several chunks that say the same thing. MMR picks candidates one at a time, trading off how similar each candidate is to the query against how similar it is to the candidates that were already picked.
+pub struct MaximalMarginalRelevance<E> {
+    embedder: E,
+    lambda: f32,
+    top_k: Option<usize>,
+}
+
+impl<E: Embedder> MaximalMarginalRelevance<E> {
+    /// Create a new MMR postprocessor that compares candidates with an embedder.
+    pub fn new(embedder: E) -> Self {
+        Self {
+            embedder,
+            lambda: 0.5,
+            top_k: None,
+        }
+    }
+
+    /// Set the tradeoff between relevance and diversity from 0 (only diversity) to 1 (only relevance) (defaults to 0.5)
+    pub fn with_lambda(mut self, lambda: f32) -> Self {
+        self.lambda = lambda.clamp(0., 1.);
+        self
+    }
+
+    /// Set the number of candidates to pick (defaults to every candidate)
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+}
+
+impl<E: Embedder> Postprocessor for MaximalMarginalRelevance<E> {
+    async fn process<T: Send>(
+        &self,
+        query: &str,
+        candidates: Vec<SearchCandidate<T>>,
+    ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let query = self.embedder.embed_query(query).await?;
+        let texts = candidates
+            .iter()
+            .map(|candidate| candidate.text.clone())
+            .collect::<Vec<_>>();
+        let embeddings = self.embedder.embed_vec(texts).await?;
+
+        let relevance = embeddings
+            .iter()
+            .map(|embedding| embedding.cosine_similarity(&query))
+            .collect::<Vec<_>>();
+        let top_k = self.top_k.unwrap_or(candidates.len());
+        let order = select(&relevance, self.lambda, top_k, |a, b| {
+            embeddings[a].cosine_similarity(&embeddings[b])
+        });
+
+        let mut candidates = candidates.into_iter().map(Some).collect::<Vec<_>>();
+        Ok(order
+            .into_iter()
+            .filter_map(|index| {
+                let mut candidate = candidates[index].take()?;
+                candidate.score = relevance[index];
+                Some(candidate)
+            })
+            .collect())
+    }
+}
+
+/// Greedily pick the indexes of the top k items by maximal marginal relevance
+fn select(
+    relevance: &[f32],
+    lambda: f32,
+    top_k: usize,
+    similarity: impl Fn(usize, usize) -> f32,
+) -> Vec<usize> {
+    let mut remaining = (0..relevance.len()).collect::<Vec<_>>();
+    let mut selected: Vec<usize> = Vec::with_capacity(top_k.min(relevance.len()));
+    while selected.len() < top_k && !remaining.is_empty() {
+        let marginal_relevance = |&index: &usize| {
+            let redundancy = selected
+                .iter()
+                .map(|&other| similarity(index, other))
+                .fold(f32::NEG_INFINITY, f32::max);
+            let redundancy = if selected.is_empty() { 0. } else { redundancy };
+            lambda * relevance[index] - (1. - lambda) * redundancy
+        };
+        let (position, _) = remaining
+            .iter()
+            .map(marginal_relevance)
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("remaining is not empty");
+        selected.push(remaining.swap_remove(position));
+    }
+    selected
+}
+
+#[test]
+fn mmr_prefers_diverse_candidates() {
+    // Candidates 0 and 1 are duplicates, candidate 2 is a little less relevant but different
+    let relevance = [0.9, 0.89, 0.7];
+    let similarity = |a: usize, b: usize| match (a.min(b), a.max(b)) {
+        (0, 1) => 1.,
+        _ => 0.1,
+    };
+
+    assert_eq!(select(&relevance, 1., 2, similarity), [0, 1]);
+    assert_eq!(select(&relevance, 0.5, 2, similarity), [0, 2]);
+    assert_eq!(select(&relevance, 0.5, 10, similarity), [0, 2, 1]);
+}
@@ -1,4 +1,169 @@
-// Context extraction strategies:
-// 1. Dump all sentences
-// 2. Dump all sentences that mention an entity
-// 3. Extract relevant sentences with an llm
+//! Postprocessing steps that refine search results after retrieval.
+//!
+//! Retrieval with embeddings or [`Bm25Index`](super::Bm25Index) is fast, but it can return results that are only loosely related to the query. Postprocessors run over the top results of a search to rerank, filter, diversify, or shorten them before they are passed to a model.
+
+use std::future::Future;
+
+mod extract;
+pub use extract::*;
+mod mmr;
+pub use mmr::*;
+mod relevance;
+pub use relevance::*;
+mod rerank;
+pub use rerank::*;
+
+/// A search result passed through a [`Postprocessor`]. Results from a document table search like `select_nearest` can be converted into candidates with [`Into`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchCandidate<T> {
+    /// The text of the result
+    pub text: String,
+    /// The score of the result. Higher scores are better matches.
+    pub score: f32,
+    /// The original search result
+    pub value: T,
+}
+
+impl<T> SearchCandidate<T> {
+    /// Create a new search candidate.
+    pub fn new(text: impl ToString, score: f32, value: T) -> Self {
+        Self {
+            text: text.to_string(),
+            score,
+            value,
+        }
+    }
+}
+
+/// A step that refines a list of search results for a query. Postprocessors can be chained with [`Postprocessor::then`].
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let bert = Bert::new_for_search().await?;
+/// let pipeline = CrossEncoderReranker::new(BertCrossEncoder::new().await?)
+///     .with_top_k(10)
+///     .then(MaximalMarginalRelevance::new(bert.clone()).with_top_k(5))
+///     .then(SentenceExtractor::new(bert));
+///
+/// let candidates = vec![
+///     SearchCandidate::new("Kalosm is a library for local AI in Rust.", 1.0, ()),
+///     SearchCandidate::new("The quick brown fox jumps over the lazy dog.", 0.5, ()),
+/// ];
+/// let results = pipeline.process("What is Kalosm?", candidates).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Postprocessor {
+    /// Process the candidates for a query. The returned candidates are sorted from the best match to the worst match.
+    fn process<T: Send>(
+        &self,
+        query: &str,
+        candidates: Vec<SearchCandidate<T>>,
+    ) -> impl Future<Output = anyhow::Result<Vec<SearchCandidate<T>>>> + Send;
+
+    /// Run another postprocessor on the output of this postprocessor.
+    fn then<P: Postprocessor>(self, next: P) -> PostprocessorChain<Self, P>
+    where
+        Self: Sized,
+    {
+        PostprocessorChain { first: self, next }
+    }
+}
+
+/// Two [`Postprocessor`]s run one after another. Created with [`Postprocessor::then`].
+pub struct PostprocessorChain<A, B> {
+    first: A,
+    next: B,
+}
+
+impl<A: Postprocessor + Sync, B: Postprocessor + Sync> Postprocessor for PostprocessorChain<A, B> {
+    async fn process<T: Send>(
+        &self,
+        query: &str,
+        candidates: Vec<SearchCandidate<T>>,
+    ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+        let candidates = self.first.process(query, candidates).await?;
+        self.next.process(query, candidates).await
+    }
+}
+
+/// Sort candidates from the highest score to the lowest and keep the top k
+fn sort_and_truncate<T>(candidates: &mut Vec<SearchCandidate<T>>, top_k: Option<usize>) {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    if let Some(top_k) = top_k {
+        candidates.truncate(top_k);
+    }
+}
+
+#[tokio::test]
+async fn postprocessors_run_in_order() -> anyhow::Result<()> {
+    /// Adds a bonus to candidates that contain a word
+    struct Boost(&'static str);
+
+    impl Postprocessor for Boost {
+        async fn process<T: Send>(
+            &self,
+            _: &str,
+            mut candidates: Vec<SearchCandidate<T>>,
+        ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+            for candidate in &mut candidates {
+                if candidate.text.contains(self.0) {
+                    candidate.score += 1.;
+                }
+            }
+            sort_and_truncate(&mut candidates, None);
+            Ok(candidates)
+        }
+    }
+
+    /// Keeps the top k candidates
+    struct TopK(usize);
+
+    impl Postprocessor for TopK {
+        async fn process<T: Send>(
+            &self,
+            _: &str,
+            mut candidates: Vec<SearchCandidate<T>>,
+        ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+            sort_and_truncate(&mut candidates, Some(self.0));
+            Ok(candidates)
+        }
+    }
+
+    let candidates = || {
+        vec![
+            SearchCandidate::new("apples", 0.9, 0),
+            SearchCandidate::new("bananas", 0.5, 1),
+            SearchCandidate::new("cherries", 0.1, 2),
+        ]
+    };
+
+    // Boosting before truncating can promote a low scoring candidate
+    let results = Boost("cherries")
+        .then(TopK(2))
+        .process("fruit", candidates())
+        .await?;
+    let order: Vec<_> = results.iter().map(|candidate| candidate.value).collect();
+    assert_eq!(order, [2, 0]);
+
+    // Truncating first removes it before it can be boosted
+    let results = TopK(2)
+        .then(Boost("cherries"))
+        .process("fruit", candidates())
+        .await?;
+    let order: Vec<_> = results.iter().map(|candidate| candidate.value).collect();
+    assert_eq!(order, [0, 1]);
+
+    // Chains can be chained again
+    let results = TopK(2)
+        .then(Boost("bananas"))
+        .then(TopK(1))
+        .process("fruit", candidates())
+        .await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].value, 1);
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use futures_util::future::try_join_all;
+use kalosm_language_model::{Model, SyncModel};
+use kalosm_sample::{LiteralParser, ParserExt};
+
+use crate::prelude::{IndexParser, StructuredRunner, Task};
+
+use super::{Postprocessor, SearchCandidate};
+
+const TASK_DESCRIPTION: &str =
+    "You decide if a passage contains information that helps answer a query. You answer yes if the passage is relevant and no if it is not.";
+
+const EXAMPLES: [(&str, &str); 2] = [
+    (
+        "Query: How do I reset my password?\nPassage: To reset your password, open the settings page and click \"Forgot password\". A reset link will be sent to your email.",
+        "Relevant: yes",
+    ),
+    (
+        "Query: How do I reset my password?\nPassage: Our offices are closed on public holidays.",
+        "Relevant: no",
+    ),
+];
+
+const ANSWERS: [&str; 2] = ["yes", "no"];
+
+type Constraints = kalosm_sample::SequenceParser<LiteralParser, IndexParser<LiteralParser>>;
+
+fn create_constraints() -> Constraints {
+    LiteralParser::new("Relevant: ").then(IndexParser::new(
+        ANSWERS.iter().copied().map(LiteralParser::new).collect(),
+    ))
+}
+
+/// Uses a language model to check if search results are relevant to a query.
+pub struct RelevanceFilter {
+    task: Task<StructuredRunner<Constraints>>,
+}
+
+impl Default for RelevanceFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelevanceFilter {
+    /// Create a new relevance filter.
+    pub fn new() -> Self {
+        let task = Task::builder(TASK_DESCRIPTION)
+            .with_constraints(create_constraints())
+            .with_examples(EXAMPLES)
+            .build();
+        Self { task }
+    }
+
+    /// Check if the text is relevant to the query.
+    pub async fn is_relevant<M>(&self, query: &str, text: &str, model: &M) -> anyhow::Result<bool>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+    {
+        let prompt = format!("Query: {query}\nPassage: {text}");
+        let ((), (answer, ())) = self.task.run(prompt, model).result().await?;
+        Ok(answer == 0)
+    }
+
+    /// Turn this relevance filter into a [`Postprocessor`] that removes candidates the model doesn't think are relevant.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Llama::new_chat().await?;
+    /// let filter = RelevanceFilter::new();
+    /// let candidates = vec![
+    ///     SearchCandidate::new("To reset your password, open the settings page.", 1.0, ()),
+    ///     SearchCandidate::new("Our offices are closed on public holidays.", 0.5, ()),
+    /// ];
+    /// let relevant = filter
+    ///     .postprocessor(&llm)
+    ///     .process("How do I reset my password?", candidates)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn postprocessor<'a, M>(&'a self, model: &'a M) -> RelevanceFilterPostprocessor<'a, M>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+    {
+        RelevanceFilterPostprocessor {
+            filter: self,
+            model,
+        }
+    }
+}
+
+/// A [`Postprocessor`] that removes irrelevant candidates with a [`RelevanceFilter`]. Every candidate is checked at the same time, so the model can queue the checks without waiting for each answer.
+pub struct RelevanceFilterPostprocessor<'a, M> {
+    filter: &'a RelevanceFilter,
+    model: &'a M,
+}
+
+impl<'a, M> Postprocessor for RelevanceFilterPostprocessor<'a, M>
+where
+    M: Model,
+    <M::SyncModel as SyncModel>::Session: Sync + Send,
+{
+    async fn process<T: Send>(
+        &self,
+        query: &str,
+        candidates: Vec<SearchCandidate<T>>,
+    ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+        let relevant = try_join_all(
+            candidates
+                .iter()
+                .map(|candidate| self.filter.is_relevant(query, &candidate.text, self.model)),
+        )
+        .await?;
+        Ok(candidates
+            .into_iter()
+            .zip(relevant)
+            .filter_map(|(candidate, relevant)| relevant.then_some(candidate))
+            .collect())
+    }
+}
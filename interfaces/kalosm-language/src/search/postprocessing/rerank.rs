@@ -0,0 +1,49 @@
+use rbert::BertCrossEncoder;
+
+use super::{sort_and_truncate, Postprocessor, SearchCandidate};
+
+/// A [`Postprocessor`] that reranks candidates with a [`BertCrossEncoder`].
+///
+/// Cross encoders are more accurate than embedding similarity, but they need to run the model once for every candidate. Retrieve a few dozen candidates with a fast search and rerank them to get the best results.
+pub struct CrossEncoderReranker {
+    cross_encoder: BertCrossEncoder,
+    top_k: Option<usize>,
+}
+
+impl CrossEncoderReranker {
+    /// Create a new reranker from a cross encoder.
+    pub fn new(cross_encoder: BertCrossEncoder) -> Self {
+        Self {
+            cross_encoder,
+            top_k: None,
+        }
+    }
+
+    /// Set the number of candidates to keep after reranking (defaults to every candidate)
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+}
+
+impl Postprocessor for CrossEncoderReranker {
+    async fn process<T: Send>(
+        &self,
+        query: &str,
+        mut candidates: Vec<SearchCandidate<T>>,
+    ) -> anyhow::Result<Vec<SearchCandidate<T>>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let texts = candidates
+            .iter()
+            .map(|candidate| candidate.text.clone())
+            .collect();
+        let scores = self.cross_encoder.score(query, texts).await?;
+        for (candidate, score) in candidates.iter_mut().zip(scores) {
+            candidate.score = score;
+        }
+        sort_and_truncate(&mut candidates, self.top_k);
+        Ok(candidates)
+    }
+}
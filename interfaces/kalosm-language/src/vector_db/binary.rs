@@ -0,0 +1,300 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+use kalosm_language_model::{
+    hamming_distance, int8_cosine_similarity, pack_sign_bits, quantize_i8, Embedding,
+    UnknownVectorSpace, VectorSpace,
+};
+
+/// An in-memory index of binary quantized embeddings for corpora that are too large to keep full embeddings in memory.
+///
+/// Searches compare the binary query with every embedding in the index with the Hamming distance, then rescore the closest candidates with the full query embedding against an 8 bit copy of each embedding. Codes are stored back to back in a single buffer, so a 384 dimension embedding takes 48 bytes for the binary code and 384 bytes for the rescoring copy (plus the key, which is kept in a list and a lookup map) instead of 1536 bytes as floats. Rescoring can be disabled with [`BinaryVectorIndex::without_rescoring`] to only store the binary codes.
+///
+/// The index only lives in memory. [`VectorDB`](crate::prelude::VectorDB) still stores full embeddings on disk, so the index needs to be rebuilt with [`VectorDB::binary_index`](crate::prelude::VectorDB::binary_index) or by inserting embeddings again after a restart.
+///
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let bert = Bert::new_for_search().await?;
+/// let mut index = BinaryVectorIndex::new();
+/// index.insert(0, &bert.embed("Kalosm is a library for local AI in Rust").await?);
+/// index.insert(1, &bert.embed("The quick brown fox jumps over the lazy dog").await?);
+///
+/// let results = index.search(&bert.embed_query("What is Kalosm?").await?, 1);
+/// assert_eq!(results[0].key, 0);
+/// # Ok(())
+/// # }
+/// ```
+pub struct BinaryVectorIndex<K, S: VectorSpace = UnknownVectorSpace> {
+    rescore_multiplier: Option<usize>,
+    dimensions: usize,
+    positions: HashMap<K, usize>,
+    keys: Vec<K>,
+    // The packed bits of every embedding. Entry `i` is stored in `bits[i * words..(i + 1) * words]`
+    bits: Vec<u64>,
+    // The 8 bit values of every embedding when rescoring is enabled. Entry `i` is stored in `values[i * dimensions..(i + 1) * dimensions]`
+    values: Vec<i8>,
+    model: std::marker::PhantomData<S>,
+}
+
+impl<K, S: VectorSpace> Default for BinaryVectorIndex<K, S> {
+    fn default() -> Self {
+        Self {
+            rescore_multiplier: Some(4),
+            dimensions: 0,
+            positions: HashMap::new(),
+            keys: Vec::new(),
+            bits: Vec::new(),
+            values: Vec::new(),
+            model: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash, S: VectorSpace> BinaryVectorIndex<K, S> {
+    /// Create a new empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how many candidates are rescored with the full query for every result that is returned. Larger multipliers are more accurate, but slower (defaults to 4)
+    ///
+    /// This should be set before any embeddings are inserted. Embeddings inserted into an index without rescoring don't keep an 8 bit copy, so the index will only rank them by the Hamming distance.
+    pub fn with_rescore_multiplier(mut self, multiplier: usize) -> Self {
+        self.rescore_multiplier = Some(multiplier.max(1));
+        self
+    }
+
+    /// Only store binary codes and rank results by the Hamming distance alone. This uses the least memory, but is much less accurate.
+    pub fn without_rescoring(mut self) -> Self {
+        self.rescore_multiplier = None;
+        self.values = Vec::new();
+        self
+    }
+
+    fn words(&self) -> usize {
+        self.dimensions.div_ceil(64)
+    }
+
+    // Rescoring is only possible if every entry has an 8 bit copy
+    fn rescoring(&self) -> bool {
+        self.rescore_multiplier.is_some() && self.values.len() == self.keys.len() * self.dimensions
+    }
+
+    /// Add an embedding to the index. If the key is already in the index, the old embedding is replaced.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the embedding has a different number of dimensions than the embeddings already in the index.
+    pub fn insert(&mut self, key: K, embedding: &Embedding<S>) {
+        let floats = embedding.to_vec();
+        if self.keys.is_empty() {
+            self.dimensions = floats.len();
+        }
+        assert_eq!(
+            floats.len(),
+            self.dimensions,
+            "every embedding in a binary index must have the same number of dimensions"
+        );
+        let words = self.words();
+        let dimensions = self.dimensions;
+        let rescoring = self.rescoring();
+
+        let position = match self.positions.get(&key) {
+            Some(position) => *position,
+            None => {
+                let position = self.keys.len();
+                self.positions.insert(key.clone(), position);
+                self.keys.push(key);
+                self.bits.resize(self.bits.len() + words, 0);
+                if rescoring {
+                    self.values.resize(self.values.len() + dimensions, 0);
+                }
+                position
+            }
+        };
+
+        pack_sign_bits(
+            &floats,
+            &mut self.bits[position * words..(position + 1) * words],
+        );
+
+        if rescoring {
+            // Cosine similarity doesn't depend on the scale of the values, so only the quantized values are stored
+            let values = &mut self.values[position * dimensions..(position + 1) * dimensions];
+            quantize_i8(&floats, values);
+        }
+    }
+
+    /// Remove an embedding from the index. Returns true if the key was in the index.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(position) = self.positions.remove(key) else {
+            return false;
+        };
+        let rescoring = self.rescoring();
+        // Move the last entry into the removed slot to keep the buffers contiguous
+        let last = self.keys.len() - 1;
+        self.keys.swap_remove(position);
+        if position != last {
+            self.positions.insert(self.keys[position].clone(), position);
+        }
+        let words = self.words();
+        swap_remove_chunk(&mut self.bits, position, last, words);
+        if rescoring {
+            swap_remove_chunk(&mut self.values, position, last, self.dimensions);
+        }
+        true
+    }
+
+    /// Remove every embedding from the index.
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.keys.clear();
+        self.bits.clear();
+        self.values.clear();
+    }
+
+    /// Get the number of embeddings in the index.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Check if the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Find the k embeddings closest to the query, sorted from the closest to the furthest.
+    pub fn search(&self, query: &Embedding<S>, k: usize) -> Vec<BinarySearchResult<K>> {
+        if k == 0 || self.is_empty() {
+            return Vec::new();
+        }
+        let query = query.to_vec();
+        if query.len() != self.dimensions {
+            tracing::error!(
+                "The query has {} dimensions, but the binary index has {} dimensions",
+                query.len(),
+                self.dimensions
+            );
+            return Vec::new();
+        }
+        let words = self.words();
+        let mut binary_query = vec![0u64; words];
+        pack_sign_bits(&query, &mut binary_query);
+
+        // Keep the closest candidates in a max heap so the search only allocates space for the candidates
+        let candidate_count = k
+            .saturating_mul(self.rescore_multiplier.unwrap_or(1))
+            .min(self.len());
+        let mut candidates = BinaryHeap::with_capacity(candidate_count + 1);
+        for (position, bits) in self.bits.chunks_exact(words).enumerate() {
+            let distance = hamming_distance(bits, &binary_query);
+            if candidates.len() < candidate_count {
+                candidates.push((distance, position));
+            } else if let Some(mut furthest) = candidates.peek_mut() {
+                if distance < furthest.0 {
+                    *furthest = (distance, position);
+                }
+            }
+        }
+
+        let rescoring = self.rescoring();
+        let query_norm = query.iter().map(|value| value * value).sum::<f32>().sqrt();
+        let mut results = candidates
+            .into_iter()
+            .map(|(hamming_distance, position)| BinarySearchResult {
+                key: self.keys[position].clone(),
+                hamming_distance,
+                similarity: rescoring.then(|| {
+                    let values =
+                        &self.values[position * self.dimensions..(position + 1) * self.dimensions];
+                    int8_cosine_similarity(values, &query, query_norm)
+                }),
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| {
+            let a_similarity = a.similarity.unwrap_or(f32::NEG_INFINITY);
+            let b_similarity = b.similarity.unwrap_or(f32::NEG_INFINITY);
+            b_similarity
+                .total_cmp(&a_similarity)
+                .then(a.hamming_distance.cmp(&b.hamming_distance))
+        });
+        results.truncate(k);
+        results
+    }
+}
+
+fn swap_remove_chunk<T: Copy>(buffer: &mut Vec<T>, position: usize, last: usize, size: usize) {
+    if position != last {
+        buffer.copy_within(last * size..(last + 1) * size, position * size);
+    }
+    buffer.truncate(last * size);
+}
+
+/// A result from a [`BinaryVectorIndex`] search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinarySearchResult<K> {
+    /// The key of the embedding
+    pub key: K,
+    /// The Hamming distance between the binary query and the binary embedding
+    pub hamming_distance: u32,
+    /// The cosine similarity between the full query and the 8 bit embedding, if the index rescores results
+    pub similarity: Option<f32>,
+}
+
+#[test]
+fn binary_index_rescores_candidates() {
+    let mut index = BinaryVectorIndex::<_, UnknownVectorSpace>::new().with_rescore_multiplier(2);
+    index.insert("close", &Embedding::from([0.9, 0.1, -0.5, 0.2]));
+    index.insert("same_bits", &Embedding::from([0.1, 0.9, -0.1, 0.1]));
+    index.insert("far", &Embedding::from([-0.9, -0.1, 0.5, -0.2]));
+
+    let query = Embedding::from([1., 0.1, -0.4, 0.2]);
+    // Both close embeddings have the same binary code, so only rescoring can tell them apart
+    let results = index.search(&query, 2);
+    assert_eq!(results[0].key, "close");
+    assert_eq!(results[1].key, "same_bits");
+    assert_eq!(results[0].hamming_distance, 0);
+    assert_eq!(results[1].hamming_distance, 0);
+
+    let mut index = BinaryVectorIndex::<_, UnknownVectorSpace>::new().without_rescoring();
+    index.insert("close", &Embedding::from([0.9, 0.1, -0.5, 0.2]));
+    index.insert("far", &Embedding::from([-0.9, -0.1, 0.5, -0.2]));
+    let results = index.search(&query, 10);
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1].key, "far");
+    assert_eq!(results[1].hamming_distance, 4);
+    assert_eq!(results[1].similarity, None);
+}
+
+#[test]
+fn binary_index_keeps_entries_contiguous() {
+    let mut index = BinaryVectorIndex::<_, UnknownVectorSpace>::new();
+    index.insert("a", &Embedding::from([1., -1., 1.]));
+    index.insert("b", &Embedding::from([-1., 1., -1.]));
+    index.insert("c", &Embedding::from([1., 1., 1.]));
+    assert_eq!(index.bits.len(), 3);
+    assert_eq!(index.values.len(), 9);
+
+    // Removing an entry moves the last entry into its slot
+    assert!(index.remove(&"a"));
+    assert!(!index.remove(&"a"));
+    assert_eq!(index.len(), 2);
+    assert_eq!(index.bits.len(), 2);
+    assert_eq!(index.values.len(), 6);
+    let results = index.search(&Embedding::from([1., 1., 1.]), 1);
+    assert_eq!(results[0].key, "c");
+    assert_eq!(results[0].hamming_distance, 0);
+
+    // Replacing an entry overwrites it in place
+    index.insert("c", &Embedding::from([-1., 1., -1.]));
+    assert_eq!(index.len(), 2);
+    let results = index.search(&Embedding::from([-1., 1., -1.]), 2);
+    assert_eq!(results[0].hamming_distance, 0);
+    assert_eq!(results[1].hamming_distance, 0);
+
+    assert!(index.search(&Embedding::from([1., 1.]), 1).is_empty());
+    index.clear();
+    assert!(index.search(&Embedding::from([1., 1., 1.]), 1).is_empty());
+}
@@ -0,0 +1,97 @@
+pub use arroy::distances::{Angular, DotProduct, Euclidean, Manhattan};
+
+/// A distance metric a [`VectorDB`](super::VectorDB) can use to compare embeddings.
+///
+/// Search results report the same distances for [`SearchMode::Exact`](super::SearchMode::Exact) and [`SearchMode::Approximate`](super::SearchMode::Approximate) searches. Smaller distances are closer for every metric except [`DotProduct`], where the distance is the dot product itself and larger distances are closer.
+///
+/// Kalosm implements this trait for:
+/// - [`Angular`]: `(1 - cos) / 2` where `cos` is the cosine similarity of the embeddings. This ranges from 0 for embeddings that point in the same direction to 1 for opposite embeddings. This is the default and works well with most embedding models.
+/// - [`Euclidean`]: The straight line distance between embeddings.
+/// - [`Manhattan`]: The sum of the absolute differences between embeddings.
+/// - [`DotProduct`]: The dot product of embeddings. This works well with models that produce embeddings that are not normalized.
+pub trait VectorDistance: arroy::Distance {
+    /// Compute the distance embeddings are ranked by. Unlike the reported distance, smaller raw distances are always closer.
+    fn raw_distance(a: &[f32], b: &[f32]) -> f32;
+
+    /// Compute the distance between two vectors that search results report. This is used for [`SearchMode::Exact`](super::SearchMode::Exact) searches.
+    fn exact_distance(a: &[f32], b: &[f32]) -> f32 {
+        Self::normalized_distance(Self::raw_distance(a, b))
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+impl VectorDistance for Angular {
+    fn raw_distance(a: &[f32], b: &[f32]) -> f32 {
+        let norms = dot(a, a) * dot(b, b);
+        if norms > 0. {
+            (1. - dot(a, b) / norms.sqrt()) / 2.
+        } else {
+            0.
+        }
+    }
+}
+
+impl VectorDistance for Euclidean {
+    fn raw_distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).powi(2)).sum()
+    }
+}
+
+impl VectorDistance for Manhattan {
+    fn raw_distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+    }
+}
+
+impl VectorDistance for DotProduct {
+    fn raw_distance(a: &[f32], b: &[f32]) -> f32 {
+        -dot(a, b)
+    }
+}
+
+#[cfg(test)]
+fn assert_exact_matches_approximate<D: VectorDistance>() -> anyhow::Result<()> {
+    use super::{SearchMode, VectorDB};
+    use kalosm_language_model::{Embedding, UnknownVectorSpace};
+
+    let embeddings = [
+        [1., 0., 0.5],
+        [0., 2., -1.],
+        [-1., 0.5, 0.],
+        [0.3, 0.3, 3.],
+        [2., -2., 1.],
+    ];
+    let query = Embedding::from([0.5, 1., 0.25]);
+
+    let db = VectorDB::<UnknownVectorSpace, D>::new_with_distance()?;
+    db.add_embeddings(embeddings.map(Embedding::from))?;
+    let approximate = db.get_closest(query.clone(), embeddings.len())?;
+    let db = db.with_search_mode(SearchMode::Exact);
+    let exact = db.get_closest(query, embeddings.len())?;
+
+    assert_eq!(approximate.len(), embeddings.len());
+    assert_eq!(exact.len(), embeddings.len());
+    for (exact, approximate) in exact.iter().zip(&approximate) {
+        assert_eq!(exact.value, approximate.value);
+        assert!(
+            (exact.distance - approximate.distance).abs() < 1e-4,
+            "{}: the exact distance {} doesn't match the approximate distance {}",
+            std::any::type_name::<D>(),
+            exact.distance,
+            approximate.distance
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn exact_distances_match_approximate_distances() -> anyhow::Result<()> {
+    assert_exact_matches_approximate::<Angular>()?;
+    assert_exact_matches_approximate::<Euclidean>()?;
+    assert_exact_matches_approximate::<Manhattan>()?;
+    assert_exact_matches_approximate::<DotProduct>()?;
+    Ok(())
+}
@@ -0,0 +1,535 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+/// A value in the [`Metadata`] of an embedding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    /// A boolean value
+    Bool(bool),
+    /// A number
+    Number(f64),
+    /// A string. Dates stored as ISO 8601 strings (like `2024-03-01`) can be compared with range filters.
+    String(String),
+}
+
+impl PartialOrd for MetadataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
+            (Self::Number(a), Self::Number(b)) => a.partial_cmp(b),
+            (Self::String(a), Self::String(b)) => a.partial_cmp(b),
+            // Values of different types are never ordered
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+macro_rules! impl_from_number {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for MetadataValue {
+                fn from(value: $ty) -> Self {
+                    Self::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_number!(f32, f64, i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+/// Key-value metadata stored alongside an embedding in a [`VectorDB`](super::VectorDB).
+///
+/// ```rust
+/// # use kalosm_language::prelude::*;
+/// let metadata = Metadata::new()
+///     .with("author", "Evan")
+///     .with("year", 2024)
+///     .with("published", true);
+/// assert_eq!(metadata.get("year"), Some(&MetadataValue::Number(2024.)));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Metadata(BTreeMap<String, MetadataValue>);
+
+impl Metadata {
+    /// Create empty metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a key in the metadata.
+    pub fn with(mut self, key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Insert a key into the metadata, returning the old value if there was one.
+    pub fn insert(
+        &mut self,
+        key: impl ToString,
+        value: impl Into<MetadataValue>,
+    ) -> Option<MetadataValue> {
+        self.0.insert(key.to_string(), value.into())
+    }
+
+    /// Get the value of a key.
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.0.get(key)
+    }
+
+    /// Remove a key from the metadata, returning its value if there was one.
+    pub fn remove(&mut self, key: &str) -> Option<MetadataValue> {
+        self.0.remove(key)
+    }
+
+    /// Iterate over the keys and values in the metadata.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Get the number of keys in the metadata.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the metadata has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: ToString, V: Into<MetadataValue>> FromIterator<(K, V)> for Metadata {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(key, value)| (key.to_string(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// A filter over the [`Metadata`] of embeddings used in [`VectorDB::get_closest_filtered`](super::VectorDB::get_closest_filtered).
+///
+/// Filters can be combined with [`MetadataFilter::and`], [`MetadataFilter::or`] and negated with `!`:
+///
+/// ```rust
+/// # use kalosm_language::prelude::*;
+/// let filter = MetadataFilter::eq("author", "Evan")
+///     .and(MetadataFilter::gte("date", "2024-01-01"))
+///     .and(!MetadataFilter::eq("draft", true));
+///
+/// let metadata = Metadata::new()
+///     .with("author", "Evan")
+///     .with("date", "2024-03-01");
+/// assert!(filter.matches(&metadata));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataFilter {
+    /// The key is equal to the value
+    Eq(String, MetadataValue),
+    /// The key is greater than the value
+    Gt(String, MetadataValue),
+    /// The key is greater than or equal to the value
+    Gte(String, MetadataValue),
+    /// The key is less than the value
+    Lt(String, MetadataValue),
+    /// The key is less than or equal to the value
+    Lte(String, MetadataValue),
+    /// The key is equal to any of the values
+    In(String, Vec<MetadataValue>),
+    /// The key is set
+    Exists(String),
+    /// Every filter matches
+    And(Vec<MetadataFilter>),
+    /// Any filter matches
+    Or(Vec<MetadataFilter>),
+    /// The filter does not match
+    Not(Box<MetadataFilter>),
+}
+
+impl MetadataFilter {
+    /// Match metadata where the key is equal to the value.
+    pub fn eq(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        Self::Eq(key.to_string(), value.into())
+    }
+
+    /// Match metadata where the key is set and is not equal to the value.
+    pub fn not_eq(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        let key = key.to_string();
+        Self::Exists(key.clone()).and(!Self::Eq(key, value.into()))
+    }
+
+    /// Match metadata where the key is greater than the value.
+    pub fn gt(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        Self::Gt(key.to_string(), value.into())
+    }
+
+    /// Match metadata where the key is greater than or equal to the value.
+    pub fn gte(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        Self::Gte(key.to_string(), value.into())
+    }
+
+    /// Match metadata where the key is less than the value.
+    pub fn lt(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        Self::Lt(key.to_string(), value.into())
+    }
+
+    /// Match metadata where the key is less than or equal to the value.
+    pub fn lte(key: impl ToString, value: impl Into<MetadataValue>) -> Self {
+        Self::Lte(key.to_string(), value.into())
+    }
+
+    /// Match metadata where the key is equal to any of the values.
+    pub fn is_in<V: Into<MetadataValue>>(
+        key: impl ToString,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        Self::In(
+            key.to_string(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    /// Match metadata where the key is set.
+    pub fn exists(key: impl ToString) -> Self {
+        Self::Exists(key.to_string())
+    }
+
+    /// Match metadata that matches both this filter and the other filter.
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            Self::And(mut filters) => {
+                filters.push(other);
+                Self::And(filters)
+            }
+            _ => Self::And(vec![self, other]),
+        }
+    }
+
+    /// Match metadata that matches either this filter or the other filter.
+    pub fn or(self, other: Self) -> Self {
+        match self {
+            Self::Or(mut filters) => {
+                filters.push(other);
+                Self::Or(filters)
+            }
+            _ => Self::Or(vec![self, other]),
+        }
+    }
+
+    /// Check if the metadata matches the filter.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let compare = |key: &str, value: &MetadataValue, expected: fn(Ordering) -> bool| {
+            metadata
+                .get(key)
+                .and_then(|found| found.partial_cmp(value))
+                .is_some_and(expected)
+        };
+        match self {
+            Self::Eq(key, value) => metadata.get(key) == Some(value),
+            Self::Gt(key, value) => compare(key, value, Ordering::is_gt),
+            Self::Gte(key, value) => compare(key, value, Ordering::is_ge),
+            Self::Lt(key, value) => compare(key, value, Ordering::is_lt),
+            Self::Lte(key, value) => compare(key, value, Ordering::is_le),
+            Self::In(key, values) => metadata
+                .get(key)
+                .is_some_and(|found| values.contains(found)),
+            Self::Exists(key) => metadata.get(key).is_some(),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(metadata)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(metadata)),
+            Self::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
+
+impl std::ops::Not for MetadataFilter {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Self::Not(filter) => *filter,
+            _ => Self::Not(Box::new(self)),
+        }
+    }
+}
+
+/// A metadata value that can be used as a key in [`MetadataIndex`]. Values are ordered by type first, then by value.
+#[derive(Debug, Clone, PartialEq)]
+struct IndexedValue(MetadataValue);
+
+impl IndexedValue {
+    fn new(value: &MetadataValue) -> Self {
+        match value {
+            // -0 and 0 are equal in filters, so they need to be the same key
+            MetadataValue::Number(number) if *number == 0. => Self(MetadataValue::Number(0.)),
+            _ => Self(value.clone()),
+        }
+    }
+
+    fn same_type(&self, other: &Self) -> bool {
+        std::mem::discriminant(&self.0) == std::mem::discriminant(&other.0)
+    }
+}
+
+impl Eq for IndexedValue {}
+
+impl PartialOrd for IndexedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let rank = |value: &MetadataValue| match value {
+            MetadataValue::Bool(_) => 0,
+            MetadataValue::Number(_) => 1,
+            MetadataValue::String(_) => 2,
+        };
+        match (&self.0, &other.0) {
+            (MetadataValue::Bool(a), MetadataValue::Bool(b)) => a.cmp(b),
+            (MetadataValue::Number(a), MetadataValue::Number(b)) => a.total_cmp(b),
+            (MetadataValue::String(a), MetadataValue::String(b)) => a.cmp(b),
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+}
+
+/// An inverted index from metadata values to the ids of the embeddings in a [`VectorDB`](super::VectorDB) that have them. Filters are evaluated with set operations on the index instead of checking the metadata of every embedding.
+#[derive(Debug, Default)]
+pub(crate) struct MetadataIndex {
+    /// Every embedding in each namespace
+    namespaces: HashMap<u16, RoaringBitmap>,
+    /// The embeddings with each value of each key
+    values: HashMap<String, BTreeMap<IndexedValue, RoaringBitmap>>,
+}
+
+impl MetadataIndex {
+    /// Add an embedding to the index
+    pub(crate) fn insert(&mut self, id: u32, namespace: u16, metadata: &Metadata) {
+        self.namespaces.entry(namespace).or_default().insert(id);
+        for (key, value) in metadata.iter() {
+            self.values
+                .entry(key.to_string())
+                .or_default()
+                .entry(IndexedValue::new(value))
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    /// Remove an embedding with the metadata it was inserted with from the index
+    pub(crate) fn remove(&mut self, id: u32, namespace: u16, metadata: &Metadata) {
+        if let Some(ids) = self.namespaces.get_mut(&namespace) {
+            ids.remove(id);
+        }
+        for (key, value) in metadata.iter() {
+            let Some(values) = self.values.get_mut(key) else {
+                continue;
+            };
+            let value = IndexedValue::new(value);
+            if let Some(ids) = values.get_mut(&value) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    values.remove(&value);
+                }
+            }
+            if values.is_empty() {
+                self.values.remove(key);
+            }
+        }
+    }
+
+    /// Remove every embedding from the index
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Get the ids of the embeddings in a namespace that match the filter
+    pub(crate) fn matching(&self, namespace: u16, filter: &MetadataFilter) -> RoaringBitmap {
+        match self.namespaces.get(&namespace) {
+            Some(ids) => self.evaluate(ids, filter) & ids,
+            None => RoaringBitmap::new(),
+        }
+    }
+
+    /// Get the ids that match the filter. `all` is the set of ids `Not` filters are relative to.
+    fn evaluate(&self, all: &RoaringBitmap, filter: &MetadataFilter) -> RoaringBitmap {
+        let range = |key: &str, value: &MetadataValue, expected: fn(Ordering) -> bool| {
+            let Some(values) = self.values.get(key) else {
+                return RoaringBitmap::new();
+            };
+            let value = IndexedValue::new(value);
+            let (lower, upper) = if expected(Ordering::Greater) {
+                (Bound::Included(&value), Bound::Unbounded)
+            } else {
+                (Bound::Unbounded, Bound::Included(&value))
+            };
+            values
+                .range::<IndexedValue, _>((lower, upper))
+                // Values of different types and NaN are never ordered
+                .filter(|(found, _)| {
+                    found.same_type(&value) && found.0.partial_cmp(&value.0).is_some_and(expected)
+                })
+                .fold(RoaringBitmap::new(), |matching, (_, ids)| matching | ids)
+        };
+        match filter {
+            MetadataFilter::Eq(key, value) => self.equal(key, value),
+            MetadataFilter::Gt(key, value) => range(key, value, Ordering::is_gt),
+            MetadataFilter::Gte(key, value) => range(key, value, Ordering::is_ge),
+            MetadataFilter::Lt(key, value) => range(key, value, Ordering::is_lt),
+            MetadataFilter::Lte(key, value) => range(key, value, Ordering::is_le),
+            MetadataFilter::In(key, values) => values
+                .iter()
+                .map(|value| self.equal(key, value))
+                .fold(RoaringBitmap::new(), |a, b| a | b),
+            MetadataFilter::Exists(key) => self
+                .values
+                .get(key)
+                .map(|values| {
+                    values
+                        .values()
+                        .fold(RoaringBitmap::new(), |matching, ids| matching | ids)
+                })
+                .unwrap_or_default(),
+            MetadataFilter::And(filters) => {
+                let mut filters = filters.iter();
+                let Some(first) = filters.next() else {
+                    return all.clone();
+                };
+                let mut ids = self.evaluate(all, first);
+                for filter in filters {
+                    if ids.is_empty() {
+                        break;
+                    }
+                    ids &= self.evaluate(all, filter);
+                }
+                ids
+            }
+            MetadataFilter::Or(filters) => filters
+                .iter()
+                .map(|filter| self.evaluate(all, filter))
+                .fold(RoaringBitmap::new(), |a, b| a | b),
+            MetadataFilter::Not(filter) => all - self.evaluate(all, filter),
+        }
+    }
+
+    fn equal(&self, key: &str, value: &MetadataValue) -> RoaringBitmap {
+        // NaN is never equal to anything
+        if matches!(value, MetadataValue::Number(number) if number.is_nan()) {
+            return RoaringBitmap::new();
+        }
+        self.values
+            .get(key)
+            .and_then(|values| values.get(&IndexedValue::new(value)))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[test]
+fn metadata_filters_match() {
+    let metadata = Metadata::new()
+        .with("author", "Evan")
+        .with("date", "2024-03-01")
+        .with("pages", 12)
+        .with("draft", false);
+
+    assert!(MetadataFilter::eq("author", "Evan").matches(&metadata));
+    assert!(!MetadataFilter::eq("author", "Alex").matches(&metadata));
+    assert!(MetadataFilter::not_eq("author", "Alex").matches(&metadata));
+    assert!(!MetadataFilter::not_eq("missing", "Alex").matches(&metadata));
+    assert!(MetadataFilter::gt("pages", 10).matches(&metadata));
+    assert!(MetadataFilter::lte("pages", 12).matches(&metadata));
+    assert!(!MetadataFilter::lt("pages", 12).matches(&metadata));
+    assert!(MetadataFilter::gte("date", "2024-01-01").matches(&metadata));
+    assert!(!MetadataFilter::gt("date", "2024-12-31").matches(&metadata));
+    // Values of different types never compare
+    assert!(!MetadataFilter::gt("pages", "10").matches(&metadata));
+    assert!(MetadataFilter::is_in("author", ["Alex", "Evan"]).matches(&metadata));
+    assert!(MetadataFilter::exists("draft").matches(&metadata));
+    assert!(!MetadataFilter::exists("tags").matches(&metadata));
+    assert!(MetadataFilter::eq("draft", true)
+        .or(MetadataFilter::eq("author", "Evan"))
+        .matches(&metadata));
+    assert!(!MetadataFilter::eq("draft", false)
+        .and(!MetadataFilter::exists("pages"))
+        .matches(&metadata));
+}
+
+#[test]
+fn metadata_index_matches_filters() {
+    let items = [
+        Metadata::new().with("author", "Evan").with("pages", 12),
+        Metadata::new().with("author", "Alex").with("pages", -0.),
+        Metadata::new()
+            .with("author", "Evan")
+            .with("pages", f64::NAN),
+        Metadata::new().with("pages", "12"),
+        Metadata::new().with("author", "Evan"),
+    ];
+    let mut index = MetadataIndex::default();
+    for (id, metadata) in items.iter().enumerate() {
+        index.insert(id as u32, 0, metadata);
+    }
+    // An embedding in another namespace is never returned
+    index.insert(10, 1, &items[0]);
+
+    let filters = [
+        MetadataFilter::eq("author", "Evan"),
+        MetadataFilter::eq("pages", 0),
+        MetadataFilter::eq("pages", f64::NAN),
+        MetadataFilter::gt("pages", 0),
+        MetadataFilter::gte("pages", 0),
+        MetadataFilter::lt("pages", 12),
+        MetadataFilter::lte("pages", "12"),
+        MetadataFilter::is_in("author", ["Alex", "Evan"]),
+        MetadataFilter::exists("pages"),
+        MetadataFilter::not_eq("author", "Evan"),
+        !MetadataFilter::exists("author").or(MetadataFilter::lt("pages", 1)),
+        MetadataFilter::And(Vec::new()),
+    ];
+    for filter in filters {
+        let expected: RoaringBitmap = items
+            .iter()
+            .enumerate()
+            .filter(|(_, metadata)| filter.matches(metadata))
+            .map(|(id, _)| id as u32)
+            .collect();
+        assert_eq!(index.matching(0, &filter), expected, "{filter:?}");
+    }
+
+    index.remove(0, 0, &items[0]);
+    assert_eq!(
+        index.matching(0, &MetadataFilter::eq("author", "Evan")),
+        RoaringBitmap::from_iter([2, 4])
+    );
+    assert_eq!(
+        index.matching(1, &MetadataFilter::eq("author", "Evan")),
+        RoaringBitmap::from_iter([10])
+    );
+}
@@ -0,0 +1,1033 @@
+//! A vector database that can be used to store embeddings and search for similar embeddings.
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use arroy::{Database as ArroyDatabase, Reader, Writer};
+use candle_core::Tensor;
+use heed::byteorder::BE;
+use heed::types::{SerdeJson, Str, U16, U32};
+use heed::EnvOpenOptions;
+use kalosm_language_model::*;
+use kalosm_llama::accelerated_device_if_available;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+mod binary;
+pub use binary::*;
+mod distance;
+pub use distance::*;
+mod metadata;
+pub use metadata::*;
+mod namespace;
+pub use namespace::*;
+
+/// A vector database that can be used to store embeddings and search for similar embeddings.
+///
+/// It uses an in memory database with fast lookups for nearest neighbors and points within a certain distance.
+///
+/// Embeddings are compared with [`Angular`] distance by default. You can choose a different [`VectorDistance`] with [`VectorDB::new_with_distance`].
+///
+/// By default, the index is rebuilt after every change. If you are adding a large number of embeddings, you can defer building the index with [`VectorDB::with_build_strategy`].
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # use kalosm_language_model::*;
+/// # use rbert::*;
+/// # use std::collections::HashMap;
+/// # #[tokio::main]
+/// # async fn main() {
+/// // Create a good default Bert model for search
+/// let bert = Bert::new_for_search().await.unwrap();
+/// let sentences = [
+///     "Kalosm can be used to build local AI applications",
+///     "With private LLMs data never leaves your computer",
+///     "The quick brown fox jumps over the lazy dog",
+/// ];
+/// // Embed sentences into the vector space
+/// let embeddings = bert.embed_batch(sentences).await.unwrap();
+/// println!("embeddings {:?}", embeddings);
+///
+/// // Create a vector database from the embeddings along with a map between the embedding ids and the sentences
+/// let db = VectorDB::new().unwrap();
+/// let embeddings = db.add_embeddings(embeddings).unwrap();
+/// let embedding_id_to_sentence: HashMap<EmbeddingId, &str> =
+///     HashMap::from_iter(embeddings.into_iter().zip(sentences));
+///
+/// // Find the closest sentence to "What is Kalosm?"
+/// let query = "What is Kalosm?";
+/// // Embed the query into the vector space. We use `embed_query` instead of `embed` because some models embed queries differently than normal text.
+/// let embedding = bert.embed_query(query).await.unwrap();
+/// let closest = db.get_closest(embedding, 1).unwrap();
+/// if let [closest] = closest.as_slice() {
+///     let distance = closest.distance;
+///     let text = embedding_id_to_sentence.get(&closest.value).unwrap();
+///     println!("distance: {distance}");
+///     println!("closest:  {text}");
+/// }
+/// # }
+/// ```
+#[doc(alias = "VectorDatabase")]
+#[doc(alias = "Vector Database")]
+pub struct VectorDB<S = UnknownVectorSpace, D: VectorDistance = Angular> {
+    database: ArroyDatabase<D>,
+    /// The namespace and metadata of every embedding
+    items: heed::Database<U32<BE>, SerdeJson<StoredItem>>,
+    /// The index of every namespace other than the default namespace
+    namespaces: heed::Database<Str, U16<BE>>,
+    /// An in memory index of the metadata in `items`
+    metadata_index: RwLock<MetadataIndex>,
+    env: heed::Env,
+    max_id: Mutex<EmbeddingId>,
+    recycled_ids: Mutex<Vec<EmbeddingId>>,
+    index: Arc<IndexState>,
+    build_strategy: IndexBuildStrategy,
+    /// The thread that builds the index with [`IndexBuildStrategy::Background`]
+    background_build: Option<JoinHandle<()>>,
+    search_mode: SearchMode,
+    _phantom: std::marker::PhantomData<S>,
+}
+
+/// The name of the namespace embeddings are added to when no namespace is specified
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// The arroy index of the default namespace
+const DEFAULT_INDEX: u16 = 0;
+
+/// Filtered searches that match at most this many embeddings compare the query with every match instead of searching the approximate index
+const EXACT_SEARCH_CANDIDATES: u64 = 1024;
+
+/// The information stored alongside each embedding
+#[derive(Serialize, Deserialize)]
+struct StoredItem {
+    namespace: u16,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+/// When a [`VectorDB`] rebuilds its index after embeddings are added or removed.
+///
+/// Searching always builds any pending changes first, so every strategy returns the same results. Deferring the build makes adding many embeddings one at a time much faster because the index is only built once for the whole batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexBuildStrategy {
+    /// Rebuild the index after every change.
+    #[default]
+    Immediate,
+    /// Only rebuild the index when [`VectorDB::flush`] is called or before the next search. This is the fastest way to bulk ingest embeddings.
+    Manual,
+    /// Rebuild the index on a background thread once there are `max_pending_changes` changes that are not in the index yet, or when no changes have been made for `idle_time`.
+    Background {
+        /// The number of changes that triggers a rebuild
+        max_pending_changes: usize,
+        /// How long to wait after the last change before rebuilding
+        idle_time: Duration,
+    },
+}
+
+/// How a [`VectorDB`] finds the closest embeddings to a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Search the approximate nearest neighbor index. This is much faster than an exact search for large collections, but it may miss some of the closest embeddings.
+    #[default]
+    Approximate,
+    /// Compare the query with every embedding. This always finds the closest embeddings, but the time it takes grows with the size of the collection. This is useful for small collections and for measuring the recall of the approximate index.
+    Exact,
+}
+
+/// Changes that are not in the index yet
+#[derive(Default)]
+struct Pending {
+    changes: usize,
+    /// The namespace indexes that need to be rebuilt
+    indexes: BTreeSet<u16>,
+}
+
+/// The state of the index shared with the background build thread
+struct IndexState {
+    dim: AtomicUsize,
+    pending: Mutex<Pending>,
+    changed: Condvar,
+    closed: AtomicBool,
+}
+
+impl IndexState {
+    fn build<D: VectorDistance>(
+        &self,
+        env: &heed::Env,
+        database: ArroyDatabase<D>,
+    ) -> anyhow::Result<()> {
+        self.build_and_commit(env.write_txn()?, database, None)
+    }
+
+    /// Build every pending index and the `changed` index in the write transaction, then commit it
+    fn build_and_commit<D: VectorDistance>(
+        &self,
+        mut wtxn: heed::RwTxn,
+        database: ArroyDatabase<D>,
+        changed: Option<u16>,
+    ) -> anyhow::Result<()> {
+        let dims = self.dim.load(Ordering::Relaxed);
+        if dims == 0 {
+            wtxn.commit()?;
+            return Ok(());
+        }
+        // Only one write transaction can be open at a time, so every change counted so far is visible to this transaction
+        let mut pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        pending.indexes.extend(changed);
+        let mut rng = StdRng::from_entropy();
+        let result = pending
+            .indexes
+            .iter()
+            .try_for_each(|&index| {
+                Writer::<D>::new(database, index, dims).build(&mut wtxn, &mut rng, None)
+            })
+            .and_then(|_| wtxn.commit().map_err(Into::into));
+        if result.is_err() {
+            let mut locked = self.pending.lock().unwrap();
+            locked.changes += pending.changes;
+            locked.indexes.extend(pending.indexes);
+        }
+        Ok(result?)
+    }
+
+    fn build_in_background<D: VectorDistance>(
+        self: Arc<Self>,
+        env: heed::Env,
+        database: ArroyDatabase<D>,
+        max_pending_changes: usize,
+        idle_time: Duration,
+    ) -> Option<JoinHandle<()>> {
+        let spawned = std::thread::Builder::new()
+            .name("vector-db-index".to_string())
+            .spawn(move || {
+                let mut pending = self.pending.lock().unwrap();
+                loop {
+                    if self.closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if pending.changes < max_pending_changes {
+                        let (guard, timeout) =
+                            self.changed.wait_timeout(pending, idle_time).unwrap();
+                        pending = guard;
+                        // Wait until there are enough changes or the database is idle
+                        if pending.changes == 0
+                            || (!timeout.timed_out() && pending.changes < max_pending_changes)
+                        {
+                            continue;
+                        }
+                    }
+                    drop(pending);
+                    if let Err(err) = self.build(&env, database) {
+                        tracing::error!("Failed to build the vector database index: {err}");
+                    }
+                    pending = self.pending.lock().unwrap();
+                }
+            });
+        match spawned {
+            Ok(thread) => Some(thread),
+            Err(err) => {
+                tracing::error!("Failed to spawn the vector database index thread: {err}");
+                None
+            }
+        }
+    }
+
+    /// Stop the background build thread and wait for it to exit
+    fn stop_background_build(&self, thread: JoinHandle<()>) {
+        {
+            // Hold the lock so the thread can't miss the notification between checking `closed` and waiting
+            let _pending = self.pending.lock().unwrap();
+            self.closed.store(true, Ordering::Relaxed);
+            self.changed.notify_all();
+        }
+        if thread.join().is_err() {
+            tracing::error!("The vector database index thread panicked");
+        }
+        self.closed.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<S: VectorSpace + Sync, D: VectorDistance> Default for VectorDB<S, D> {
+    fn default() -> Self {
+        Self::new_with_distance().unwrap()
+    }
+}
+
+impl<S, D: VectorDistance> Drop for VectorDB<S, D> {
+    fn drop(&mut self) {
+        if let Some(thread) = self.background_build.take() {
+            self.index.stop_background_build(thread);
+        }
+    }
+}
+
+impl<S: VectorSpace + Sync> VectorDB<S> {
+    /// Create a new temporary vector database.
+    #[tracing::instrument]
+    pub fn new() -> heed::Result<Self> {
+        Self::new_with_distance()
+    }
+
+    /// Create a new vector database at the given path.
+    pub fn new_at(path: impl AsRef<std::path::Path>) -> heed::Result<Self> {
+        Self::new_at_with_distance(path)
+    }
+}
+
+impl<S: VectorSpace + Sync, D: VectorDistance> VectorDB<S, D> {
+    fn set_dim(&self, dim: usize) {
+        if dim == 0 {
+            panic!("Dimension cannot be 0");
+        }
+        self.index.dim.store(dim, Ordering::Relaxed);
+    }
+
+    fn get_dim(&self, index: u16) -> anyhow::Result<usize> {
+        let mut dims = self.index.dim.load(Ordering::Relaxed);
+        if dims == 0 {
+            let rtxn = self.env.read_txn()?;
+            let reader = Reader::<D>::open(&rtxn, index, self.database)?;
+            dims = reader.dimensions();
+            self.set_dim(dims);
+        }
+        Ok(dims)
+    }
+
+    /// Create a new temporary vector database that compares embeddings with the distance metric `D`.
+    ///
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// let db = VectorDB::<UnknownVectorSpace, Euclidean>::new_with_distance().unwrap();
+    /// ```
+    pub fn new_with_distance() -> heed::Result<Self> {
+        let dir = tempfile::tempdir()?;
+
+        Self::new_at_with_distance(dir.path())
+    }
+
+    /// Create a new vector database at the given path that compares embeddings with the distance metric `D`. A database must always be opened with the distance metric it was created with.
+    pub fn new_at_with_distance(path: impl AsRef<std::path::Path>) -> heed::Result<Self> {
+        const TWENTY_HUNDRED_MIB: usize = 2 * 1024 * 1024 * 1024;
+
+        std::fs::create_dir_all(&path)?;
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(TWENTY_HUNDRED_MIB)
+                .max_dbs(8)
+                .open(path)
+        }?;
+
+        let mut wtxn = env.write_txn()?;
+        // The embeddings stay in the unnamed database so databases created before namespaces existed can still be opened
+        let db: ArroyDatabase<D> = env.create_database(&mut wtxn, None)?;
+        let items: heed::Database<U32<BE>, SerdeJson<StoredItem>> =
+            env.create_database(&mut wtxn, Some("kalosm-items"))?;
+        let namespaces = env.create_database(&mut wtxn, Some("kalosm-namespaces"))?;
+        wtxn.commit()?;
+
+        let mut metadata_index = MetadataIndex::default();
+        {
+            let rtxn = env.read_txn()?;
+            for item in items.iter(&rtxn)? {
+                let (id, item) = item?;
+                metadata_index.insert(id, item.namespace, &item.metadata);
+            }
+        }
+
+        Ok(Self {
+            database: db,
+            items,
+            namespaces,
+            metadata_index: RwLock::new(metadata_index),
+            env,
+            max_id: Mutex::new(EmbeddingId(0)),
+            recycled_ids: Mutex::new(Vec::new()),
+            index: Arc::new(IndexState {
+                dim: AtomicUsize::new(0),
+                pending: Mutex::new(Pending::default()),
+                changed: Condvar::new(),
+                closed: AtomicBool::new(false),
+            }),
+            build_strategy: IndexBuildStrategy::Immediate,
+            background_build: None,
+            search_mode: SearchMode::Approximate,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Set when the index is rebuilt after embeddings are added or removed (defaults to [`IndexBuildStrategy::Immediate`])
+    ///
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let db = VectorDB::new()?.with_build_strategy(IndexBuildStrategy::Manual);
+    /// for sentence in ["The quick brown fox", "jumps over the lazy dog"] {
+    ///     db.add_embedding(bert.embed(sentence).await?)?;
+    /// }
+    /// // Build the index once for every embedding that was added
+    /// db.flush()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_build_strategy(mut self, build_strategy: IndexBuildStrategy) -> Self {
+        // Stop the thread for the old strategy so it doesn't keep building with the old settings
+        if let Some(thread) = self.background_build.take() {
+            self.index.stop_background_build(thread);
+        }
+        if let IndexBuildStrategy::Background {
+            max_pending_changes,
+            idle_time,
+        } = build_strategy
+        {
+            self.background_build = self.index.clone().build_in_background(
+                self.env.clone(),
+                self.database,
+                max_pending_changes.max(1),
+                idle_time,
+            );
+        }
+        self.build_strategy = build_strategy;
+        self
+    }
+
+    /// Set how the closest embeddings are found (defaults to [`SearchMode::Approximate`])
+    pub fn with_search_mode(mut self, search_mode: SearchMode) -> Self {
+        self.search_mode = search_mode;
+        self
+    }
+
+    /// Get the number of changes that have not been built into the index yet
+    pub fn pending_changes(&self) -> usize {
+        self.index.pending.lock().unwrap().changes
+    }
+
+    /// Build any changes that are not in the index yet. This does nothing if there are no pending changes.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        if self.pending_changes() == 0 {
+            return Ok(());
+        }
+        self.index.build(&self.env, self.database)
+    }
+
+    /// Build the index even if there are no pending changes. This is useful after modifying the database directly with [`VectorDB::raw`].
+    pub fn rebuild(&self) -> anyhow::Result<()> {
+        self.get_dim(DEFAULT_INDEX)?;
+        let indexes = {
+            let rtxn = self.env.read_txn()?;
+            let indexes = self
+                .namespaces
+                .iter(&rtxn)?
+                .map(|namespace| namespace.map(|(_, index)| index))
+                .collect::<heed::Result<Vec<_>>>()?;
+            indexes
+        };
+        self.index
+            .pending
+            .lock()
+            .unwrap()
+            .indexes
+            .extend(std::iter::once(DEFAULT_INDEX).chain(indexes));
+        self.index.build(&self.env, self.database)
+    }
+
+    /// Commit a write transaction that changed a namespace index. With [`IndexBuildStrategy::Immediate`], the index is built in the same transaction so a failed build never leaves embeddings outside of the index.
+    fn commit(&self, wtxn: heed::RwTxn, index: u16, changes: usize) -> anyhow::Result<()> {
+        if let IndexBuildStrategy::Immediate = self.build_strategy {
+            return self
+                .index
+                .build_and_commit(wtxn, self.database, Some(index));
+        }
+        wtxn.commit()?;
+        {
+            let mut pending = self.index.pending.lock().unwrap();
+            pending.changes += changes;
+            pending.indexes.insert(index);
+        }
+        if let IndexBuildStrategy::Background { .. } = self.build_strategy {
+            self.index.changed.notify_all();
+        }
+        Ok(())
+    }
+
+    fn take_id(&self) -> EmbeddingId {
+        self.recycled_ids.lock().unwrap().pop().unwrap_or_else(|| {
+            let mut locked = self.max_id.lock().unwrap();
+            let id = *locked;
+            locked.0 += 1;
+            id
+        })
+    }
+
+    fn recycle_id(&self, id: EmbeddingId) {
+        self.recycled_ids.lock().unwrap().push(id);
+    }
+
+    /// Get the underlying database.
+    pub fn raw(&self) -> (&ArroyDatabase<D>, &heed::Env) {
+        (&self.database, &self.env)
+    }
+
+    /// Get a namespace in the database, creating it if it doesn't exist. Each namespace has a separate index, so searches in one namespace never return embeddings from another.
+    ///
+    /// Namespaces are useful for storing embeddings for multiple users or collections in one database:
+    ///
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let db = VectorDB::new()?;
+    /// let alice = db.namespace("alice")?;
+    /// let bob = db.namespace("bob")?;
+    /// alice.add_embedding(bert.embed("Alice's notes").await?)?;
+    /// bob.add_embedding(bert.embed("Bob's notes").await?)?;
+    ///
+    /// // Only Alice's notes are searched
+    /// let closest = alice.get_closest(bert.embed_query("notes").await?, 10)?;
+    /// assert_eq!(closest.len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn namespace(&self, name: &str) -> anyhow::Result<VectorDBNamespace<'_, S, D>> {
+        if name == DEFAULT_NAMESPACE {
+            return Ok(VectorDBNamespace::new(self, name, DEFAULT_INDEX));
+        }
+
+        let mut wtxn = self.env.write_txn()?;
+        if let Some(index) = self.namespaces.get(&wtxn, name)? {
+            return Ok(VectorDBNamespace::new(self, name, index));
+        }
+        let mut last = DEFAULT_INDEX;
+        for namespace in self.namespaces.iter(&wtxn)? {
+            let (_, index) = namespace?;
+            last = last.max(index);
+        }
+        let index = last
+            .checked_add(1)
+            .ok_or_else(|| anyhow::anyhow!("The vector database has too many namespaces"))?;
+        self.namespaces.put(&mut wtxn, name, &index)?;
+        wtxn.commit()?;
+
+        Ok(VectorDBNamespace::new(self, name, index))
+    }
+
+    /// Get the names of every namespace in the database, including the [default namespace](DEFAULT_NAMESPACE).
+    pub fn namespaces(&self) -> anyhow::Result<Vec<String>> {
+        let rtxn = self.env.read_txn()?;
+        let mut names = vec![DEFAULT_NAMESPACE.to_string()];
+        for namespace in self.namespaces.iter(&rtxn)? {
+            let (name, _) = namespace?;
+            names.push(name.to_string());
+        }
+        Ok(names)
+    }
+
+    /// Clear the vector database, including every namespace.
+    pub async fn clear(&self) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        let dims = self.get_dim(DEFAULT_INDEX)?;
+        let mut indexes = vec![DEFAULT_INDEX];
+        for namespace in self.namespaces.iter(&wtxn)? {
+            let (_, index) = namespace?;
+            indexes.push(index);
+        }
+        for index in indexes {
+            Writer::<D>::new(self.database, index, dims).clear(&mut wtxn)?;
+        }
+        self.items.clear(&mut wtxn)?;
+        self.namespaces.clear(&mut wtxn)?;
+        wtxn.commit()?;
+        self.metadata_index.write().unwrap().clear();
+        *self.index.pending.lock().unwrap() = Pending::default();
+
+        // Reset the ids
+        self.max_id.lock().unwrap().0 = 0;
+        self.recycled_ids.lock().unwrap().clear();
+
+        Ok(())
+    }
+
+    /// Remove every embedding in a namespace
+    pub(crate) fn clear_namespace(&self, index: u16) -> anyhow::Result<()> {
+        let dims = self.get_dim(index)?;
+        let mut wtxn = self.env.write_txn()?;
+        let mut removed = Vec::new();
+        for item in self.items.iter(&wtxn)? {
+            let (id, item) = item?;
+            if item.namespace == index {
+                removed.push((EmbeddingId(id), item.metadata));
+            }
+        }
+        for (id, _) in &removed {
+            self.items.delete(&mut wtxn, &id.0)?;
+        }
+        Writer::<D>::new(self.database, index, dims).clear(&mut wtxn)?;
+        // Build the empty index so the namespace can still be searched
+        self.commit(wtxn, index, removed.len().max(1))?;
+        let mut metadata_index = self.metadata_index.write().unwrap();
+        for (id, metadata) in &removed {
+            metadata_index.remove(id.0, index, metadata);
+            self.recycle_id(*id);
+        }
+        Ok(())
+    }
+
+    /// Remove an embedding from the vector database.
+    pub fn remove_embedding(&self, embedding_id: EmbeddingId) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+
+        // Embeddings added before namespaces existed don't have an item and are always in the default namespace
+        let item = self.items.get(&wtxn, &embedding_id.0)?;
+        let index = item.as_ref().map_or(DEFAULT_INDEX, |item| item.namespace);
+        let dims = self.get_dim(index)?;
+        let writer = Writer::<D>::new(self.database, index, dims);
+
+        writer.del_item(&mut wtxn, embedding_id.0)?;
+        self.items.delete(&mut wtxn, &embedding_id.0)?;
+        self.commit(wtxn, index, 1)?;
+        if let Some(item) = item {
+            self.metadata_index
+                .write()
+                .unwrap()
+                .remove(embedding_id.0, index, &item.metadata);
+        }
+        self.recycle_id(embedding_id);
+
+        Ok(())
+    }
+
+    /// Add a new embedding to the vector database.
+    ///
+    /// Note: Adding embeddings in a batch with [`VectorDB::add_embeddings`], or deferring the index build with [`VectorDB::with_build_strategy`] will be faster.
+    pub fn add_embedding(&self, embedding: Embedding<S>) -> anyhow::Result<EmbeddingId> {
+        self.add_embedding_with_metadata(embedding, Metadata::default())
+    }
+
+    /// Add a new embedding with [`Metadata`] to the vector database. The metadata can be used to filter results in [`VectorDB::get_closest_filtered`].
+    pub fn add_embedding_with_metadata(
+        &self,
+        embedding: Embedding<S>,
+        metadata: Metadata,
+    ) -> anyhow::Result<EmbeddingId> {
+        let mut ids = self.add_items(DEFAULT_INDEX, [(embedding, metadata)])?;
+        Ok(ids.remove(0))
+    }
+
+    /// Add a new batch of embeddings to the vector database.
+    pub fn add_embeddings(
+        &self,
+        embedding: impl IntoIterator<Item = Embedding<S>>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        self.add_items(
+            DEFAULT_INDEX,
+            embedding
+                .into_iter()
+                .map(|embedding| (embedding, Metadata::default())),
+        )
+    }
+
+    /// Add a new batch of embeddings with [`Metadata`] to the vector database.
+    pub fn add_embeddings_with_metadata(
+        &self,
+        embeddings: impl IntoIterator<Item = (Embedding<S>, Metadata)>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        self.add_items(DEFAULT_INDEX, embeddings)
+    }
+
+    /// Add embeddings to a namespace index in one transaction
+    pub(crate) fn add_items(
+        &self,
+        index: u16,
+        items: impl IntoIterator<Item = (Embedding<S>, Metadata)>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        let mut items = items.into_iter();
+        let (first_embedding, first_metadata) = match items.next() {
+            Some((embedding, metadata)) => (embedding.vector().to_vec1()?, metadata),
+            None => return Ok(Vec::new()),
+        };
+        self.set_dim(first_embedding.len());
+
+        let mut wtxn = self.env.write_txn()?;
+        let writer = Writer::<D>::new(self.database, index, first_embedding.len());
+
+        let mut added: Vec<_> = Vec::with_capacity(items.size_hint().0 + 1);
+        let mut add = |embedding: Vec<f32>, metadata: Metadata| -> anyhow::Result<()> {
+            let id = self.take_id();
+            writer.add_item(&mut wtxn, id.0, &embedding)?;
+            let item = StoredItem {
+                namespace: index,
+                metadata,
+            };
+            self.items.put(&mut wtxn, &id.0, &item)?;
+            added.push((id, item.metadata));
+            Ok(())
+        };
+
+        add(first_embedding, first_metadata)?;
+        for (embedding, metadata) in items {
+            add(embedding.vector().to_vec1()?, metadata)?;
+        }
+
+        self.commit(wtxn, index, added.len())?;
+        let mut metadata_index = self.metadata_index.write().unwrap();
+        for (id, metadata) in &added {
+            metadata_index.insert(id.0, index, metadata);
+        }
+
+        Ok(added.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Get the embedding for an embedding id.
+    pub fn get_embedding(&self, embedding_id: EmbeddingId) -> anyhow::Result<Embedding<S>> {
+        self.flush()?;
+        let rtxn = self.env.read_txn()?;
+        let index = self
+            .items
+            .get(&rtxn, &embedding_id.0)?
+            .map_or(DEFAULT_INDEX, |item| item.namespace);
+        let reader = Reader::<D>::open(&rtxn, index, self.database)?;
+
+        let embedding = reader
+            .item_vector(&rtxn, embedding_id.0)?
+            .ok_or_else(|| anyhow::anyhow!("Embedding not found"))?;
+
+        let shape = (embedding.len(),);
+        Ok(Embedding::new(Tensor::from_vec(
+            embedding,
+            shape,
+            &accelerated_device_if_available()?,
+        )?))
+    }
+
+    /// Get the [`Metadata`] of an embedding. Embeddings added without metadata have empty metadata.
+    pub fn metadata(&self, embedding_id: EmbeddingId) -> anyhow::Result<Metadata> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self
+            .items
+            .get(&rtxn, &embedding_id.0)?
+            .map(|item| item.metadata)
+            .unwrap_or_default())
+    }
+
+    /// Replace the [`Metadata`] of an embedding.
+    pub fn set_metadata(
+        &self,
+        embedding_id: EmbeddingId,
+        metadata: Metadata,
+    ) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        let old = self.items.get(&wtxn, &embedding_id.0)?;
+        let namespace = old.as_ref().map_or(DEFAULT_INDEX, |item| item.namespace);
+        let item = StoredItem {
+            namespace,
+            metadata,
+        };
+        self.items.put(&mut wtxn, &embedding_id.0, &item)?;
+        wtxn.commit()?;
+        let mut metadata_index = self.metadata_index.write().unwrap();
+        if let Some(old) = old {
+            metadata_index.remove(embedding_id.0, namespace, &old.metadata);
+        }
+        metadata_index.insert(embedding_id.0, namespace, &item.metadata);
+        Ok(())
+    }
+
+    /// Get the closest N embeddings to the given embedding.
+    pub fn get_closest(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        self.search(DEFAULT_INDEX, embedding, n, None)
+    }
+
+    /// Get the closest N embeddings to the given embedding that have [`Metadata`] matching the filter.
+    ///
+    /// The filter is evaluated with an in memory index of the metadata. If only a few embeddings match the filter, they are compared with the query directly, so filtered searches return N embeddings whenever at least N embeddings match.
+    ///
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let db = VectorDB::new()?;
+    /// db.add_embedding_with_metadata(
+    ///     bert.embed("Kalosm release notes").await?,
+    ///     Metadata::new().with("author", "Evan").with("date", "2024-03-01"),
+    /// )?;
+    /// let filter = MetadataFilter::eq("author", "Evan").and(MetadataFilter::gte("date", "2024-01-01"));
+    /// let closest = db.get_closest_filtered(bert.embed_query("What's new?").await?, 5, &filter)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_closest_filtered(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+        filter: &MetadataFilter,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        self.search(DEFAULT_INDEX, embedding, n, Some(filter))
+    }
+
+    /// Copy every embedding in the default namespace into a [`BinaryVectorIndex`].
+    ///
+    /// The database keeps full embeddings on disk, so it can be used to build a compact in-memory index for fast searches over very large collections.
+    pub fn binary_index(&self) -> anyhow::Result<BinaryVectorIndex<EmbeddingId, S>> {
+        self.flush()?;
+        let rtxn = self.env.read_txn()?;
+        let reader = Reader::<D>::open(&rtxn, DEFAULT_INDEX, self.database)?;
+
+        let mut index = BinaryVectorIndex::new();
+        for item in reader.iter(&rtxn)? {
+            let (id, vector) = item?;
+            index.insert(EmbeddingId(id), &Embedding::from(vector));
+        }
+        Ok(index)
+    }
+
+    /// Search a namespace index, optionally only including embeddings that match a filter
+    pub(crate) fn search(
+        &self,
+        index: u16,
+        embedding: Embedding<S>,
+        n: usize,
+        filter: Option<&MetadataFilter>,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        self.flush()?;
+        let rtxn = self.env.read_txn()?;
+        let reader = Reader::<D>::open(&rtxn, index, self.database)?;
+        // Arroy can't search an empty index, like a namespace that was just cleared
+        if reader.n_items() == 0 {
+            return Ok(Vec::new());
+        }
+
+        let candidates = match filter {
+            Some(filter) => {
+                let candidates = self.metadata_index.read().unwrap().matching(index, filter);
+                if candidates.is_empty() {
+                    return Ok(Vec::new());
+                }
+                Some(candidates)
+            }
+            None => None,
+        };
+
+        let vector = embedding.vector().to_vec1()?;
+        let arroy_results = match (self.search_mode, &candidates) {
+            (SearchMode::Exact, _) => exact_search(&reader, &rtxn, &vector, n, candidates)?,
+            // The approximate index may only visit a few of the embeddings that match a narrow filter
+            (SearchMode::Approximate, Some(matching))
+                if matching.len() <= EXACT_SEARCH_CANDIDATES =>
+            {
+                exact_search(&reader, &rtxn, &vector, n, candidates)?
+            }
+            (SearchMode::Approximate, _) => {
+                let results = reader.nns_by_vector(&rtxn, &vector, n, None, candidates.as_ref())?;
+                match &candidates {
+                    // Fall back to an exact search if the index didn't find enough of the matching embeddings
+                    Some(matching)
+                        if results.len() < n && (results.len() as u64) < matching.len() =>
+                    {
+                        exact_search(&reader, &rtxn, &vector, n, candidates)?
+                    }
+                    _ => results,
+                }
+            }
+        };
+
+        Ok(arroy_results
+            .into_iter()
+            .map(|(id, distance)| {
+                let value = EmbeddingId(id);
+                VectorDBSearchResult { distance, value }
+            })
+            .collect::<Vec<_>>())
+    }
+}
+
+/// Compare the vector with every item in the index and return the closest N items
+fn exact_search<D: VectorDistance>(
+    reader: &Reader<D>,
+    rtxn: &heed::RoTxn,
+    vector: &[f32],
+    n: usize,
+    candidates: Option<RoaringBitmap>,
+) -> anyhow::Result<Vec<(u32, f32)>> {
+    let mut results = Vec::new();
+    match candidates {
+        Some(candidates) => {
+            for id in candidates {
+                if let Some(item) = reader.item_vector(rtxn, id)? {
+                    results.push((id, D::raw_distance(vector, &item)));
+                }
+            }
+        }
+        None => {
+            for item in reader.iter(rtxn)? {
+                let (id, item) = item?;
+                results.push((id, D::raw_distance(vector, &item)));
+            }
+        }
+    }
+    results.sort_unstable_by(|(_, a), (_, b)| a.total_cmp(b));
+    results.truncate(n);
+    // Report the same distances as the approximate index
+    Ok(results
+        .into_iter()
+        .map(|(id, distance)| (id, D::normalized_distance(distance)))
+        .collect())
+}
+
+/// A resulting point from a search.
+#[derive(Debug, Clone)]
+pub struct VectorDBSearchResult {
+    /// The distance from the searched point.
+    pub distance: f32,
+    /// The value of the point.
+    pub value: EmbeddingId,
+}
+
+/// A unique identifier for an embedding. If you delete an embedding, the id will be recycled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EmbeddingId(pub u32);
+
+#[test]
+fn manual_builds_wait_for_flush() -> anyhow::Result<()> {
+    let db = VectorDB::<UnknownVectorSpace>::new()?;
+    db.add_embedding(Embedding::from([1., 0.]))?;
+    // The default strategy builds the index in the same transaction as the change
+    assert_eq!(db.pending_changes(), 0);
+
+    let db = db.with_build_strategy(IndexBuildStrategy::Manual);
+    let ids = db.add_embeddings([[0., 1.], [-1., 0.]].map(Embedding::from))?;
+    assert_eq!(db.pending_changes(), 2);
+    db.flush()?;
+    assert_eq!(db.pending_changes(), 0);
+    // Flushing without pending changes does nothing
+    db.flush()?;
+
+    // Searches build pending changes first
+    db.remove_embedding(ids[1])?;
+    assert_eq!(db.pending_changes(), 1);
+    let closest = db.get_closest(Embedding::from([-0.1, 1.]), 3)?;
+    assert_eq!(db.pending_changes(), 0);
+    assert_eq!(closest.len(), 2);
+    assert_eq!(closest[0].value, ids[0]);
+    Ok(())
+}
+
+#[test]
+fn background_builds_after_enough_changes() -> anyhow::Result<()> {
+    let db = VectorDB::<UnknownVectorSpace>::new()?.with_build_strategy(
+        IndexBuildStrategy::Background {
+            max_pending_changes: 2,
+            idle_time: Duration::from_secs(60),
+        },
+    );
+    db.add_embedding(Embedding::from([1., 0.]))?;
+    db.add_embedding(Embedding::from([0., 1.]))?;
+    let start = std::time::Instant::now();
+    while db.pending_changes() > 0 {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "the background thread never built the index"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Changing the strategy stops the background thread
+    let db = db.with_build_strategy(IndexBuildStrategy::Manual);
+    db.add_embeddings([[-1., 0.], [0., -1.]].map(Embedding::from))?;
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(db.pending_changes(), 2);
+    assert_eq!(db.get_closest(Embedding::from([0., -1.]), 4)?.len(), 4);
+    Ok(())
+}
+
+#[test]
+fn namespaces_are_isolated() -> anyhow::Result<()> {
+    let db = VectorDB::<UnknownVectorSpace>::new()?;
+    let alice = db.namespace("alice")?;
+    let bob = db.namespace("bob")?;
+    let alice_ids = alice.add_embeddings_with_metadata(
+        [[1., 0.], [0., 1.]]
+            .map(|vector| (Embedding::from(vector), Metadata::new().with("tag", 1))),
+    )?;
+    let bob_id =
+        bob.add_embedding_with_metadata(Embedding::from([1., 0.]), Metadata::new().with("tag", 1))?;
+    let default_id = db.add_embedding(Embedding::from([1., 0.1]))?;
+
+    let query = Embedding::from([1., 0.]);
+    let closest = alice.get_closest(query.clone(), 10)?;
+    assert_eq!(closest.len(), 2);
+    assert!(closest
+        .iter()
+        .all(|result| alice_ids.contains(&result.value)));
+    let filtered = bob.get_closest_filtered(query.clone(), 10, &MetadataFilter::eq("tag", 1))?;
+    assert_eq!(filtered.len(), 1);
+    assert_eq!(filtered[0].value, bob_id);
+    let closest = db.get_closest(query.clone(), 10)?;
+    assert_eq!(closest.len(), 1);
+    assert_eq!(closest[0].value, default_id);
+
+    // Clearing one namespace doesn't change the others
+    alice.clear()?;
+    assert!(alice.get_closest(query.clone(), 10)?.is_empty());
+    assert!(alice
+        .get_closest_filtered(query.clone(), 10, &MetadataFilter::eq("tag", 1))?
+        .is_empty());
+    assert_eq!(bob.get_closest(query, 10)?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn filtered_search_returns_k_results() -> anyhow::Result<()> {
+    let db = VectorDB::<UnknownVectorSpace>::new()?;
+    let embeddings = (0..4000).map(|i| {
+        let angle = i as f32 * 0.37;
+        let vector = Embedding::from([angle.cos(), angle.sin(), (angle * 0.5).cos(), 1.]);
+        (
+            vector,
+            Metadata::new()
+                .with("bucket", i % 400)
+                .with("even", i % 2 == 0),
+        )
+    });
+    db.add_embeddings_with_metadata(embeddings)?;
+    let query = Embedding::from([1., 0., 1., 1.]);
+
+    // Narrow filters are searched exactly
+    let narrow = MetadataFilter::eq("bucket", 7);
+    let results = db.get_closest_filtered(query.clone(), 5, &narrow)?;
+    assert_eq!(results.len(), 5);
+    for result in &results {
+        assert!(narrow.matches(&db.metadata(result.value)?));
+    }
+    assert!(results
+        .windows(2)
+        .all(|pair| pair[0].distance <= pair[1].distance));
+
+    // Broad filters search the approximate index
+    let broad = MetadataFilter::eq("even", true);
+    let results = db.get_closest_filtered(query.clone(), 50, &broad)?;
+    assert_eq!(results.len(), 50);
+    for result in &results {
+        assert!(broad.matches(&db.metadata(result.value)?));
+    }
+
+    // Asking for more embeddings than match returns every match
+    let results = db.get_closest_filtered(query, 100, &narrow)?;
+    assert_eq!(results.len(), 10);
+    Ok(())
+}
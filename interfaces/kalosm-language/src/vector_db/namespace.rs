@@ -0,0 +1,94 @@
+use kalosm_language_model::*;
+
+use super::{
+    Angular, EmbeddingId, Metadata, MetadataFilter, VectorDB, VectorDBSearchResult, VectorDistance,
+};
+
+/// A namespace in a [`VectorDB`] created with [`VectorDB::namespace`]. Embeddings added to a namespace are only returned by searches in the same namespace.
+///
+/// Embedding ids are shared between every namespace in the database, so methods that take an [`EmbeddingId`] like [`VectorDB::remove_embedding`] and [`VectorDB::metadata`] work on the database directly.
+pub struct VectorDBNamespace<'a, S, D: VectorDistance = Angular> {
+    db: &'a VectorDB<S, D>,
+    name: String,
+    index: u16,
+}
+
+impl<'a, S: VectorSpace + Sync, D: VectorDistance> VectorDBNamespace<'a, S, D> {
+    pub(crate) fn new(db: &'a VectorDB<S, D>, name: &str, index: u16) -> Self {
+        Self {
+            db,
+            name: name.to_string(),
+            index,
+        }
+    }
+
+    /// Get the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the database the namespace is in.
+    pub fn database(&self) -> &'a VectorDB<S, D> {
+        self.db
+    }
+
+    /// Add a new embedding to the namespace.
+    pub fn add_embedding(&self, embedding: Embedding<S>) -> anyhow::Result<EmbeddingId> {
+        self.add_embedding_with_metadata(embedding, Metadata::default())
+    }
+
+    /// Add a new embedding with [`Metadata`] to the namespace.
+    pub fn add_embedding_with_metadata(
+        &self,
+        embedding: Embedding<S>,
+        metadata: Metadata,
+    ) -> anyhow::Result<EmbeddingId> {
+        let mut ids = self.db.add_items(self.index, [(embedding, metadata)])?;
+        Ok(ids.remove(0))
+    }
+
+    /// Add a new batch of embeddings to the namespace.
+    pub fn add_embeddings(
+        &self,
+        embeddings: impl IntoIterator<Item = Embedding<S>>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        self.db.add_items(
+            self.index,
+            embeddings
+                .into_iter()
+                .map(|embedding| (embedding, Metadata::default())),
+        )
+    }
+
+    /// Add a new batch of embeddings with [`Metadata`] to the namespace.
+    pub fn add_embeddings_with_metadata(
+        &self,
+        embeddings: impl IntoIterator<Item = (Embedding<S>, Metadata)>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        self.db.add_items(self.index, embeddings)
+    }
+
+    /// Get the closest N embeddings in the namespace to the given embedding.
+    pub fn get_closest(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        self.db.search(self.index, embedding, n, None)
+    }
+
+    /// Get the closest N embeddings in the namespace to the given embedding that have [`Metadata`] matching the filter. See [`VectorDB::get_closest_filtered`] for more details.
+    pub fn get_closest_filtered(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+        filter: &MetadataFilter,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        self.db.search(self.index, embedding, n, Some(filter))
+    }
+
+    /// Remove every embedding in the namespace. Embeddings in other namespaces are not changed.
+    pub fn clear(&self) -> anyhow::Result<()> {
+        self.db.clear_namespace(self.index)
+    }
+}
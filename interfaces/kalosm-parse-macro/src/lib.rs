@@ -122,6 +122,45 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 ///     Quit,
 /// }
 /// ```
+///
+/// - `#[parse(format = "format")]` changes the syntax of a struct or an enum with data. Every format parses into the same value (defaults to "json")
+///   - `"json"`: `{ "name": "John", "age": 30 }`
+///   - `"yaml"`: one `name: "John"` pair per line
+///   - `"lines"`: one `name: John` pair per line with unquoted strings that end at the newline
+///   - `"xml"`: one `<name>John</name>` element per line inside an element named after the type
+///
+/// Smaller models tend to follow line based formats more reliably than JSON. Nested types use the syntax of their own parser. In every format other than JSON, they are written as a block indented by two spaces on the lines after the name of the field. Strings in the xml format write `<`, `>` and `&` as `&lt;`, `&gt;` and `&amp;`. The schema of the type is a template in the same format.
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(format = "lines")]
+/// struct Person {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let parser = Person::new_parser();
+/// let state = parser.create_parser_state();
+/// let person = parser.parse(&state, b"name: John\nage: 30\n").unwrap().unwrap_finished();
+/// assert_eq!(person.name, "John");
+/// assert_eq!(person.age, 30);
+/// ```
+///
+/// - `#[parse(partial)]` generates a `Partial{Type}` struct with an optional version of every field for a struct with named fields. It is used as the partial value of the struct while it is streamed. Every field type must implement `Debug` (defaults to `Option<Self>` which is only set once the whole struct is parsed)
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone)]
+/// #[parse(partial)]
+/// struct Person {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let partial: PartialPerson = Person::partial_value("{ \"name\": \"Jo");
+/// assert_eq!(partial.name.as_deref(), Some("Jo"));
+/// ```
 #[proc_macro_derive(Parse, attributes(parse))]
 pub fn derive_parse(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
@@ -138,12 +177,17 @@ pub fn derive_parse(input: TokenStream) -> TokenStream {
                         quote! { Self {} },
                     ));
                 }
-                let struct_parser = match StructParser::new(input.attrs, fields, ty) {
+                let struct_parser = match StructParser::new(input.attrs, input.vis, fields, ty) {
                     Ok(parser) => parser,
                     Err(err) => return err.to_compile_error().into(),
                 };
 
-                TokenStream::from(struct_parser.parser())
+                let parser = struct_parser.parser();
+                let partial = struct_parser.quote_partial();
+                TokenStream::from(quote! {
+                    #parser
+                    #partial
+                })
             }
             syn::Fields::Unit => {
                 let ty = input.ident;
@@ -202,7 +246,7 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
                 if fields.named.is_empty() {
                     return TokenStream::from(unit_schema(&input.attrs, &ty));
                 }
-                let struct_parser = match StructParser::new(input.attrs, fields, ty) {
+                let struct_parser = match StructParser::new(input.attrs, input.vis, fields, ty) {
                     Ok(parser) => parser,
                     Err(err) => return err.to_compile_error().into(),
                 };
@@ -256,23 +300,37 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
 
 struct StructParser {
     attributes: Vec<syn::Attribute>,
+    vis: syn::Visibility,
     ty: Ident,
     name: String,
+    format: OutputFormat,
+    partial: bool,
     fields: FieldsParser,
 }
 
 impl StructParser {
-    fn new(attributes: Vec<syn::Attribute>, fields: FieldsNamed, ty: Ident) -> syn::Result<Self> {
+    fn new(
+        attributes: Vec<syn::Attribute>,
+        vis: syn::Visibility,
+        fields: FieldsNamed,
+        ty: Ident,
+    ) -> syn::Result<Self> {
         let named = fields.named.into_iter().collect::<Vec<_>>();
 
         let mut name = ty.unraw().to_string();
+        let mut format = OutputFormat::default();
+        let mut partial = false;
         for attr in &attributes {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
                     if let Some(value) = parse_rename_attribute(&meta)? {
                         name = value.value();
+                    } else if let Some(value) = parse_format_attribute(&meta)? {
+                        format = value;
+                    } else if meta.path.is_ident("partial") {
+                        partial = true;
                     } else {
-                        return Err(meta.error("expected `rename`"));
+                        return Err(meta.error("expected `rename`, `format` or `partial`"));
                     }
                     Ok(())
                 })?;
@@ -281,8 +339,11 @@ impl StructParser {
 
         Ok(Self {
             attributes,
+            vis,
             name,
             ty,
+            format,
+            partial,
             fields: FieldsParser::new(&named)?,
         })
     }
@@ -301,10 +362,19 @@ impl StructParser {
             }
         };
 
-        let parser = match self.fields.parser(construct) {
+        let parser = match self.fields.parser(construct, self.format, "") {
             Ok(parser) => parser,
             Err(err) => return err.to_compile_error(),
         };
+        // XML-like structs are wrapped in an element with the name of the struct
+        let parser = match self.xml_element() {
+            Some((open, close)) => quote! {
+                kalosm_sample::LiteralParser::from(#open)
+                    .ignore_output_then(#parser)
+                    .then_literal(#close)
+            },
+            None => parser,
+        };
 
         let ty = &self.ty;
 
@@ -317,12 +387,157 @@ impl StructParser {
         }
     }
 
+    fn xml_element(&self) -> Option<(LitStr, LitStr)> {
+        (self.format == OutputFormat::Xml).then(|| {
+            let name = &self.name;
+            (
+                LitStr::new(&format!("<{name}>\n"), self.ty.span()),
+                LitStr::new(&format!("</{name}>"), self.ty.span()),
+            )
+        })
+    }
+
+    fn quote_partial(&self) -> TokenStream2 {
+        let ty = &self.ty;
+        if !self.partial {
+            return impl_finished_partial(ty);
+        }
+
+        let vis = &self.vis;
+        let partial_ty = format_ident!("Partial{}", ty.unraw());
+        let partial_doc = format!(
+            "A partially parsed [`{}`]. Each field is `None` until it has been parsed. Strings contain the text parsed so far and nested types contain their partial value.",
+            ty.unraw()
+        );
+        let partial_fields = self.fields.fields.iter().map(|field| {
+            let docs = field
+                .field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"));
+            let vis = &field.field.vis;
+            let ident = field.field.ident.as_ref().unwrap();
+            let ty = &field.field.ty;
+            let ty = if field.parser.is_nested_type() {
+                quote! { <#ty as kalosm_sample::PartialParse>::Partial }
+            } else {
+                quote! { #ty }
+            };
+            quote! {
+                #(#docs)*
+                #vis #ident: ::std::option::Option<#ty>
+            }
+        });
+        let into_partial_fields = self.fields.fields.iter().map(|field| {
+            let ident = field.field.ident.as_ref().unwrap();
+            if field.parser.is_nested_type() {
+                quote! { #ident: ::std::option::Option::Some(kalosm_sample::PartialParse::into_partial(self.#ident)) }
+            } else {
+                quote! { #ident: ::std::option::Option::Some(self.#ident) }
+            }
+        });
+
+        // Split the text into steps that each fill in part of the partial value
+        let mut steps = Vec::new();
+        let literal_step = |text: &str| {
+            let text = LitStr::new(text, ty.span());
+            quote! {
+                state
+                    .parse(&kalosm_sample::LiteralParser::from(#text), input)
+                    .map(|status| status.map(|_| ()))
+            }
+        };
+        let element = self.xml_element();
+        if let Some((open, _)) = &element {
+            steps.push(literal_step(&open.value()));
+        }
+        for (i, field) in self.fields.fields.iter().enumerate() {
+            let (start, parser, end) =
+                self.format
+                    .quote_field(i, &field.parser, &field.name, "", true);
+            steps.push(literal_step(&start));
+            let ident = field.field.ident.as_ref().unwrap();
+            let (finished, incomplete) = if field.parser.is_nested_type() {
+                (
+                    quote! { ::std::option::Option::Some(kalosm_sample::PartialParse::into_partial(result)) },
+                    quote! { ::std::option::Option::Some(new_state.partial_value(&parser)) },
+                )
+            } else {
+                (
+                    quote! { ::std::option::Option::Some(result) },
+                    quote! { new_state.partial_output(&parser) },
+                )
+            };
+            steps.push(quote! {
+                {
+                    let parser = #parser;
+                    match state.parse(&parser, input)? {
+                        kalosm_sample::ParseStatus::Finished { result, remaining } => {
+                            partial.#ident = #finished;
+                            Ok(kalosm_sample::ParseStatus::Finished { result: (), remaining })
+                        }
+                        kalosm_sample::ParseStatus::Incomplete { new_state, required_next } => {
+                            partial.#ident = #incomplete;
+                            Ok(kalosm_sample::ParseStatus::Incomplete { new_state, required_next })
+                        }
+                    }
+                }
+            });
+            if let Some(end) = end {
+                steps.push(literal_step(&end));
+            }
+        }
+        let step_count = steps.len();
+        let step_indexes = 0..step_count;
+
+        quote! {
+            #[doc = #partial_doc]
+            #[derive(Debug, Clone, Default)]
+            #vis struct #partial_ty {
+                #(#partial_fields,)*
+            }
+
+            impl kalosm_sample::PartialParse for #ty {
+                type Partial = #partial_ty;
+
+                fn new_partial_parser() -> impl kalosm_sample::SendCreateParserState<
+                    Output = Self,
+                    PartialState: kalosm_sample::PartialValueState<Partial = Self::Partial> + 'static,
+                > {
+                    fn step<'a>(
+                        partial: &mut #partial_ty,
+                        step: usize,
+                        state: &kalosm_sample::ErasedParserState,
+                        input: &'a [u8],
+                    ) -> kalosm_sample::ParseResult<kalosm_sample::ParseStatus<'a, kalosm_sample::ErasedParserState, ()>> {
+                        match step {
+                            #(#step_indexes => #steps,)*
+                            _ => Ok(kalosm_sample::ParseStatus::Finished { result: (), remaining: input }),
+                        }
+                    }
+
+                    kalosm_sample::PartialStepsParser::new(
+                        <Self as kalosm_sample::Parse>::new_parser(),
+                        step,
+                        #step_count,
+                    )
+                }
+
+                fn into_partial(self) -> Self::Partial {
+                    #partial_ty {
+                        #(#into_partial_fields,)*
+                    }
+                }
+            }
+        }
+    }
+
     fn quote_schema(&self) -> proc_macro2::TokenStream {
         let title = &self.name;
         let ty = &self.ty;
         let description = doc_comment(&self.attributes);
         let description = description.map(|description| quote! { .with_description(#description) });
-        let schema = self.fields.quote_schema();
+        let schema = self.fields.quote_schema(self.format);
 
         quote! {
             impl kalosm_sample::Schema for #ty {
@@ -366,8 +581,29 @@ fn quote_fields(fields: Fields) -> TokenStream2 {
     }
 }
 
+/// Types without a partial version only have a partial value once the whole value is parsed
+fn impl_finished_partial(ty: &Ident) -> TokenStream2 {
+    quote! {
+        impl kalosm_sample::PartialParse for #ty {
+            type Partial = ::std::option::Option<Self>;
+
+            fn new_partial_parser() -> impl kalosm_sample::SendCreateParserState<
+                Output = Self,
+                PartialState: kalosm_sample::PartialValueState<Partial = Self::Partial> + 'static,
+            > {
+                kalosm_sample::PartialOutputParser::new(<Self as kalosm_sample::Parse>::new_parser())
+            }
+
+            fn into_partial(self) -> Self::Partial {
+                ::std::option::Option::Some(self)
+            }
+        }
+    }
+}
+
 fn impl_unit_parser(attrs: &[syn::Attribute], ty: &Ident, construct: TokenStream2) -> TokenStream2 {
     let unit_parser = unit_parser(attrs, ty);
+    let partial = impl_finished_partial(ty);
     quote! {
         impl kalosm_sample::Parse for #ty {
             fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
@@ -375,6 +611,8 @@ fn impl_unit_parser(attrs: &[syn::Attribute], ty: &Ident, construct: TokenStream
                     .map_output(|_| #construct)
             }
         }
+
+        #partial
     }
 }
 
@@ -437,6 +675,7 @@ struct EnumParser {
     ty: Ident,
     tag: String,
     data: String,
+    format: OutputFormat,
     variants: Vec<EnumVariant>,
 }
 
@@ -445,10 +684,14 @@ impl EnumParser {
         // Look for the tag and content attributes within the #[parse] attribute
         let mut tag = "type".to_string();
         let mut content = "data".to_string();
+        let mut format = OutputFormat::default();
         for attr in attrs.iter() {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
-                    if meta.path.is_ident("tag") {
+                    if let Some(value) = parse_format_attribute(&meta)? {
+                        format = value;
+                        Ok(())
+                    } else if meta.path.is_ident("tag") {
                         let value = meta
                             .value()
                             .and_then(|value| value.parse::<syn::LitStr>())?;
@@ -461,7 +704,7 @@ impl EnumParser {
                         content = value.value();
                         Ok(())
                     } else {
-                        Err(meta.error("expected `tag` or `content`"))
+                        Err(meta.error("expected `tag`, `content` or `format`"))
                     }
                 })?;
             }
@@ -477,6 +720,7 @@ impl EnumParser {
             ty,
             tag,
             data: content,
+            format,
             variants,
         })
     }
@@ -485,10 +729,11 @@ impl EnumParser {
         let tag = &self.tag;
         let ty = &self.ty;
         let content = &self.data;
+        let format = self.format;
         let mut parser = None;
 
         for variant in &self.variants {
-            let parse_variant = variant.quote_parser(content)?;
+            let parse_variant = variant.quote_parser(tag, content, format)?;
             match &mut parser {
                 Some(current) => {
                     *current = quote! {
@@ -504,16 +749,31 @@ impl EnumParser {
             }
         }
 
-        let struct_start = format!("{{ \"{tag}\": \"");
+        let name = ty.unraw().to_string();
+        let struct_start = format.tag_start(&name, tag);
+        let struct_end = format.tag_end(&name);
+        let parser = quote! {
+            kalosm_sample::LiteralParser::from(#struct_start)
+                .ignore_output_then(#parser)
+        };
+        let parser = match struct_end {
+            Some(struct_end) => quote! {
+                #parser
+                    .then_literal(#struct_end)
+            },
+            None => parser,
+        };
+
+        let partial = impl_finished_partial(ty);
 
         Ok(quote! {
             impl kalosm_sample::Parse for #ty {
                 fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
-                    kalosm_sample::LiteralParser::from(#struct_start)
-                        .ignore_output_then(#parser)
-                        .then_literal(r#" }"#)
+                    #parser
                 }
             }
+
+            #partial
         })
     }
 
@@ -521,15 +781,26 @@ impl EnumParser {
         let tag = &self.tag;
         let content = &self.data;
         let ty = &self.ty;
+        let format = self.format;
+        let schema_format = format.quote_schema_format();
+        // XML-like enums are wrapped in an element with the name of the enum
+        let title = (format == OutputFormat::Xml).then(|| {
+            let name = ty.unraw().to_string();
+            quote! { .with_title(#name) }
+        });
 
         let variants: Vec<_> = self
             .variants
             .iter()
             .map(|variant| {
                 let variant_name = &variant.name;
-                let variant_parser = variant.quote_schema(tag, content, variant_name)?;
+                let variant_schema = variant.quote_schema(tag, content, variant_name, format)?;
                 Ok(quote! {
-                    #variant_parser
+                    kalosm_sample::SchemaType::Object(
+                        #variant_schema
+                            .with_format(#schema_format)
+                            #title
+                    )
                 })
             })
             .collect::<syn::Result<_>>()?;
@@ -606,27 +877,38 @@ impl EnumVariant {
         }
     }
 
-    fn quote_parser(&self, content_name: &str) -> syn::Result<TokenStream2> {
+    fn quote_parser(
+        &self,
+        tag: &str,
+        content_name: &str,
+        format: OutputFormat,
+    ) -> syn::Result<TokenStream2> {
         let construct_variant = self.construct_variant();
         match &self.ty {
             EnumVariantType::Struct(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
+                parser.quote_parser(&self.name, tag, content_name, format, construct_variant)
             }
             EnumVariantType::Tuple(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
+                parser.quote_parser(&self.name, tag, content_name, format, construct_variant)
+            }
+            EnumVariantType::Unit(parser) => {
+                parser.quote_parser(&self.name, tag, format, construct_variant)
             }
-            EnumVariantType::Unit(parser) => parser.quote_parser(&self.name, construct_variant),
         }
     }
 
+    /// Quote the object schema of the variant
     fn quote_schema(
         &self,
         tag: &str,
         content: &str,
         variant_name: &str,
+        format: OutputFormat,
     ) -> syn::Result<proc_macro2::TokenStream> {
         match &self.ty {
-            EnumVariantType::Struct(parser) => parser.quote_schema(tag, content, variant_name),
+            EnumVariantType::Struct(parser) => {
+                parser.quote_schema(tag, content, variant_name, format)
+            }
             EnumVariantType::Tuple(parser) => parser.quote_schema(tag, content, variant_name),
             EnumVariantType::Unit(parser) => parser.quote_schema(tag, variant_name),
         }
@@ -649,9 +931,14 @@ impl UnitEnumVariantParser {
     fn quote_parser(
         &self,
         variant_name: &str,
+        tag: &str,
+        format: OutputFormat,
         construct_variant: TokenStream2,
     ) -> syn::Result<TokenStream2> {
-        let lit_str_name = LitStr::new(&format!("{variant_name}\""), Span::call_site());
+        let lit_str_name = LitStr::new(
+            &format!("{variant_name}{}", format.variant_name_end(tag)),
+            Span::call_site(),
+        );
         Ok(quote! {
             kalosm_sample::LiteralParser::from(#lit_str_name).map_output(|_| #construct_variant)
         })
@@ -659,19 +946,17 @@ impl UnitEnumVariantParser {
 
     fn quote_schema(&self, tag: &str, variant_name: &str) -> syn::Result<proc_macro2::TokenStream> {
         Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
+            kalosm_sample::JsonObjectSchema::new([
+                kalosm_sample::JsonPropertySchema::new(
+                    #tag,
+                    kalosm_sample::SchemaType::Const(
+                        kalosm_sample::ConstSchema::new(
+                            kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
                         )
                     )
-                    .with_required(true)
-                ])
-            )
+                )
+                .with_required(true)
+            ])
         })
     }
 }
@@ -691,16 +976,30 @@ impl StructEnumVariantParser {
     fn quote_parser(
         &self,
         variant_name: &str,
+        tag: &str,
         content_name: &str,
+        format: OutputFormat,
         construct_variant: TokenStream2,
     ) -> syn::Result<TokenStream2> {
         let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content_name}\": "),
+            &format!(
+                "{variant_name}{}{}",
+                format.variant_name_end(tag),
+                format.content_start(content_name)
+            ),
             Span::call_site(),
         );
-        let field_parser = self.fields.parser(construct_variant)?;
-        Ok(quote! {
+        let field_parser =
+            self.fields
+                .parser(construct_variant, format, format.content_indent())?;
+        let parser = quote! {
             kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(#field_parser)
+        };
+        Ok(match format.content_end(content_name) {
+            Some(content_end) => quote! {
+                #parser.then_literal(#content_end)
+            },
+            None => parser,
         })
     }
 
@@ -709,29 +1008,28 @@ impl StructEnumVariantParser {
         tag: &str,
         content: &str,
         variant_name: &str,
+        format: OutputFormat,
     ) -> syn::Result<proc_macro2::TokenStream> {
-        let variant_parser = self.fields.quote_schema();
+        let variant_parser = self.fields.quote_schema(format);
         Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
+            kalosm_sample::JsonObjectSchema::new([
+                kalosm_sample::JsonPropertySchema::new(
+                    #tag,
+                    kalosm_sample::SchemaType::Const(
+                        kalosm_sample::ConstSchema::new(
+                            kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
                         )
                     )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        kalosm_sample::SchemaType::Object(
-                            #variant_parser
-                        )
+                )
+                .with_required(true),
+                kalosm_sample::JsonPropertySchema::new(
+                    #content,
+                    kalosm_sample::SchemaType::Object(
+                        #variant_parser
                     )
-                    .with_required(true)
-                ])
-            )
+                )
+                .with_required(true)
+            ])
         })
     }
 }
@@ -750,16 +1048,25 @@ impl TupleEnumVariantParser {
     fn quote_parser(
         &self,
         variant_name: &str,
+        tag: &str,
         content: &str,
+        format: OutputFormat,
         construct_variant: TokenStream2,
     ) -> syn::Result<TokenStream2> {
+        let parser: Parser = syn::parse2(self.field.ty.to_token_stream())?;
+        let (value_start, value_parser, value_end) =
+            format.quote_field(1, &parser, content, "", false);
         let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content}\": "),
+            &format!(
+                "{variant_name}{}{value_start}",
+                format.variant_name_end(tag),
+            ),
             Span::call_site(),
         );
-        let ty = &self.field.ty;
+        let value_end = value_end.map(|end| quote! { .then_literal(#end) });
+        let value_parser = quote! { #value_parser #value_end };
         Ok(quote! {
-            kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(<#ty as kalosm_sample::Parse>::new_parser()).map_output(|data0| #construct_variant)
+            kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(#value_parser).map_output(|data0| #construct_variant)
         })
     }
 
@@ -771,24 +1078,22 @@ impl TupleEnumVariantParser {
     ) -> syn::Result<proc_macro2::TokenStream> {
         let ty = &self.field.ty;
         Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
+            kalosm_sample::JsonObjectSchema::new([
+                kalosm_sample::JsonPropertySchema::new(
+                    #tag,
+                    kalosm_sample::SchemaType::Const(
+                        kalosm_sample::ConstSchema::new(
+                            kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
                         )
                     )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        <#ty as kalosm_sample::Schema>::schema()
-                    )
-                    .with_required(true)
-                ])
-            )
+                )
+                .with_required(true),
+                kalosm_sample::JsonPropertySchema::new(
+                    #content,
+                    <#ty as kalosm_sample::Schema>::schema()
+                )
+                .with_required(true)
+            ])
         })
     }
 }
@@ -970,6 +1275,8 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
         }
     };
 
+    let partial = impl_finished_partial(&ty);
+
     quote! {
         impl kalosm_sample::Parse for #ty {
             fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
@@ -979,6 +1286,8 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
                 #parser
             }
         }
+
+        #partial
     }
 }
 
@@ -1037,6 +1346,197 @@ fn parse_rename_attribute(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>>
     Ok(None)
 }
 
+fn parse_format_attribute(meta: &ParseNestedMeta) -> syn::Result<Option<OutputFormat>> {
+    if meta.path.is_ident("format") {
+        let value = meta
+            .value()
+            .and_then(|value| value.parse::<syn::LitStr>())?;
+        return OutputFormat::from_lit(&value).map(Some);
+    }
+    Ok(None)
+}
+
+/// The syntax of the text a derived parser accepts. Every format produces the same rust value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// `{ "name": "John", "age": 30 }`
+    #[default]
+    Json,
+    /// One `name: "John"` pair per line. Nested data is indented with two spaces.
+    Yaml,
+    /// `<name>John</name>` elements, one per line. Strings are unquoted.
+    Xml,
+    /// One `name: John` pair per line. Strings are unquoted and end at the newline.
+    Lines,
+}
+
+impl OutputFormat {
+    fn from_lit(lit: &LitStr) -> syn::Result<Self> {
+        match lit.value().as_str() {
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "xml" => Ok(Self::Xml),
+            "lines" => Ok(Self::Lines),
+            _ => Err(syn::Error::new(
+                lit.span(),
+                "Expected one of the following formats: json, yaml, xml or lines",
+            )),
+        }
+    }
+
+    /// The text before the value of the field at `index`
+    fn field_start(self, index: usize, name: &str, indent: &str) -> String {
+        match self {
+            Self::Json if index == 0 => format!("{{ \"{name}\": "),
+            Self::Json => format!(", \"{name}\": "),
+            Self::Yaml | Self::Lines => format!("{indent}{name}: "),
+            Self::Xml => format!("{indent}<{name}>"),
+        }
+    }
+
+    /// The text after the value of a field
+    fn field_end(self, name: &str) -> Option<String> {
+        match self {
+            Self::Json => None,
+            Self::Yaml | Self::Lines => Some("\n".to_string()),
+            Self::Xml => Some(format!("</{name}>\n")),
+        }
+    }
+
+    /// The text after the last field
+    fn fields_end(self) -> Option<&'static str> {
+        match self {
+            Self::Json => Some(" }"),
+            _ => None,
+        }
+    }
+
+    /// The text before the tag of an enum
+    fn tag_start(self, name: &str, tag: &str) -> String {
+        match self {
+            Self::Json => format!("{{ \"{tag}\": \""),
+            Self::Yaml | Self::Lines => format!("{tag}: "),
+            Self::Xml => format!("<{name}>\n<{tag}>"),
+        }
+    }
+
+    /// The text after the whole enum
+    fn tag_end(self, name: &str) -> Option<String> {
+        match self {
+            Self::Json => Some(" }".to_string()),
+            Self::Yaml | Self::Lines => None,
+            Self::Xml => Some(format!("</{name}>")),
+        }
+    }
+
+    /// The text after the name of an enum variant
+    fn variant_name_end(self, tag: &str) -> String {
+        match self {
+            Self::Json => "\"".to_string(),
+            Self::Yaml | Self::Lines => "\n".to_string(),
+            Self::Xml => format!("</{tag}>\n"),
+        }
+    }
+
+    /// The text before the fields of a struct enum variant
+    fn content_start(self, content: &str) -> String {
+        match self {
+            Self::Json => format!(", \"{content}\": "),
+            Self::Yaml | Self::Lines => format!("{content}:\n"),
+            Self::Xml => format!("<{content}>\n"),
+        }
+    }
+
+    /// The text after the fields of a struct enum variant
+    fn content_end(self, content: &str) -> Option<String> {
+        match self {
+            Self::Xml => Some(format!("</{content}>\n")),
+            _ => None,
+        }
+    }
+
+    /// The indentation of the fields of a struct enum variant
+    fn content_indent(self) -> &'static str {
+        match self {
+            Self::Json => "",
+            _ => "  ",
+        }
+    }
+
+    /// Quote the text before the value of a field, the parser for the value and the text that ends the field
+    ///
+    /// If `partial` is true, nested types are parsed with their partial parser.
+    fn quote_field(
+        self,
+        index: usize,
+        parser: &Parser,
+        name: &str,
+        indent: &str,
+        partial: bool,
+    ) -> (String, TokenStream2, Option<String>) {
+        let value = if partial && parser.is_nested_type() {
+            parser.quote_partial_parser()
+        } else {
+            parser.to_token_stream()
+        };
+        if self != Self::Json && parser.is_nested_type() {
+            // Nested types are written as an indented block after the name of the field
+            let block_indent = format!("{indent}  ");
+            let parser = quote! {
+                kalosm_sample::IndentParser::new(#value, #block_indent)
+            };
+            return match self {
+                Self::Xml => (
+                    format!("{indent}<{name}>\n"),
+                    parser,
+                    Some(format!("{indent}</{name}>\n")),
+                ),
+                _ => (format!("{indent}{name}:\n"), parser, None),
+            };
+        }
+
+        let start = self.field_start(index, name, indent);
+        if parser.is_unconstrained_string() {
+            // Unquoted strings run until the text that ends the field
+            match self {
+                Self::Lines => {
+                    let parser = quote! {
+                        kalosm_sample::StopOn::new("\n")
+                            .map_output(|string: String| string.trim_end_matches("\n").to_string())
+                    };
+                    return (start, parser, None);
+                }
+                Self::Xml => {
+                    // The string ends at the first `<`, so `<`, `>` and `&` are written as entities
+                    let parser = quote! {
+                        kalosm_sample::StopOn::new("<").map_output(|string: String| {
+                            string
+                                .trim_end_matches("<")
+                                .replace("&lt;", "<")
+                                .replace("&gt;", ">")
+                                .replace("&amp;", "&")
+                        })
+                    };
+                    return (start, parser, Some(format!("/{name}>\n")));
+                }
+                _ => {}
+            }
+        }
+
+        (start, value, self.field_end(name))
+    }
+
+    /// The schema format that describes this output format
+    fn quote_schema_format(self) -> TokenStream2 {
+        match self {
+            Self::Json => quote! { kalosm_sample::SchemaFormat::Json },
+            Self::Yaml => quote! { kalosm_sample::SchemaFormat::Yaml },
+            Self::Xml => quote! { kalosm_sample::SchemaFormat::Xml },
+            Self::Lines => quote! { kalosm_sample::SchemaFormat::Lines },
+        }
+    }
+}
+
 struct FieldsParser {
     fields: Vec<FieldParser>,
 }
@@ -1051,30 +1551,50 @@ impl FieldsParser {
         })
     }
 
-    fn parser(&self, construct: TokenStream2) -> syn::Result<TokenStream2> {
-        let mut parsers = Vec::new();
+    /// Quote the parser for each field including the text before the value and the text that ends the field
+    fn field_parsers(
+        &self,
+        format: OutputFormat,
+        indent: &str,
+    ) -> Vec<(TokenStream2, Option<LitStr>)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let (literal_text, field_parser, field_end) =
+                    format.quote_field(i, &field.parser, &field.name, indent, false);
+                let literal_text = LitStr::new(&literal_text, field.field.ident.span());
+                let parser = quote! {
+                    kalosm_sample::LiteralParser::from(#literal_text)
+                        .ignore_output_then(#field_parser)
+                };
+                let field_end = field_end.map(|end| LitStr::new(&end, field.field.ident.span()));
+                (parser, field_end)
+            })
+            .collect()
+    }
+
+    fn parser(
+        &self,
+        construct: TokenStream2,
+        format: OutputFormat,
+        indent: &str,
+    ) -> syn::Result<TokenStream2> {
         let idents: Vec<_> = self
             .fields
             .iter()
             .map(|f| format_ident!("{}_parser", f.field.ident.as_ref().unwrap().unraw()))
             .collect();
-        for (i, (field, parser_ident)) in self.fields.iter().zip(idents.iter()).enumerate() {
-            let mut literal_text = String::new();
-            if i == 0 {
-                literal_text.push_str("{ ");
-            } else {
-                literal_text.push_str(", ");
-            }
-            let field_name = &field.name;
-            let field_parser = &field.parser;
-            literal_text.push_str(&format!("\"{field_name}\": "));
-            let literal_text = LitStr::new(&literal_text, field.field.ident.span());
-
-            parsers.push(quote! {
-                let #parser_ident = kalosm_sample::LiteralParser::from(#literal_text)
-                    .ignore_output_then(#field_parser);
+        let parsers = self
+            .field_parsers(format, indent)
+            .into_iter()
+            .zip(idents.iter())
+            .map(|((field_parser, field_end), parser_ident)| {
+                let field_end = field_end.map(|end| quote! { .then_literal(#end) });
+                quote! {
+                    let #parser_ident = #field_parser #field_end;
+                }
             });
-        }
 
         let mut output_tuple = None;
         for field in self.fields.iter() {
@@ -1104,6 +1624,12 @@ impl FieldsParser {
             }
         }
 
+        let fields_end = format.fields_end().map(|end| {
+            quote! {
+                .then_literal(#end)
+            }
+        });
+
         Ok(quote! {
             {
                 #(
@@ -1111,18 +1637,20 @@ impl FieldsParser {
                 )*
 
                 #join_parser
-                    .then_literal(r#" }"#)
+                    #fields_end
                     .map_output(|#output_tuple| #construct)
             }
         })
     }
 
-    fn quote_schema(&self) -> proc_macro2::TokenStream {
+    fn quote_schema(&self, format: OutputFormat) -> proc_macro2::TokenStream {
         let properties = self.fields.iter().map(|field| field.quote_schema());
+        let format = format.quote_schema_format();
         quote! {
             kalosm_sample::JsonObjectSchema::new(
                 vec![#(#properties),*]
             )
+            .with_format(#format)
         }
     }
 }
@@ -1290,6 +1818,35 @@ impl Parse for Parser {
 }
 
 impl Parser {
+    /// Check if this is a type that is parsed with its own `Parse` implementation
+    fn is_nested_type(&self) -> bool {
+        self.with.is_none() && matches!(self.ty, ParserType::Custom(_))
+    }
+
+    /// Quote the partial parser of a nested type
+    fn quote_partial_parser(&self) -> TokenStream2 {
+        match &self.ty {
+            ParserType::Custom(ty) => quote_spanned! {
+                ty.span() =>
+                <#ty as kalosm_sample::PartialParse>::new_partial_parser()
+            },
+            _ => self.to_token_stream(),
+        }
+    }
+
+    /// Check if this is a plain `String` without any constraints or custom parser
+    fn is_unconstrained_string(&self) -> bool {
+        match &self.ty {
+            ParserType::String(options) => {
+                self.with.is_none()
+                    && options.character_filter.is_none()
+                    && options.len.is_none()
+                    && options.pattern.is_none()
+            }
+            _ => false,
+        }
+    }
+
     fn apply_attribute(&mut self, input: &syn::meta::ParseNestedMeta) -> syn::Result<bool> {
         if input.path.is_ident("with") {
             self.with = Some(input.value()?.parse()?);
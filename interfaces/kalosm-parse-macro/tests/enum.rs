@@ -210,3 +210,115 @@ fn unit_enum_parses() {
     let color = parser.parse(&state, b"\"Red\" ").unwrap().unwrap_finished();
     assert_eq!(color, Color::Red);
 }
+
+#[derive(Parse, Schema, Debug, Clone, PartialEq)]
+#[parse(format = "yaml")]
+enum YamlEnum {
+    Search { query: String },
+    Say(String),
+    Quit,
+}
+
+#[test]
+fn yaml_enum_parses() {
+    let parser = YamlEnum::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(&state, b"type: Search\ndata:\n  query: \"my query\"\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        YamlEnum::Search {
+            query: "my query".to_string()
+        }
+    );
+
+    let output = parser
+        .parse(&state, b"type: Say\ndata: \"hello\"\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output, YamlEnum::Say("hello".to_string()));
+
+    let output = parser
+        .parse(&state, b"type: Quit\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output, YamlEnum::Quit);
+
+    assert_eq!(
+        YamlEnum::schema().to_string(),
+        "type: Search\ndata:\n  query: \"string\"\n\nor\n\ntype: Say\ndata: \"string\"\n\nor\n\ntype: Quit\n"
+    );
+}
+
+#[derive(Parse, Schema, Debug, Clone, PartialEq)]
+#[parse(format = "lines", tag = "action", content = "arguments")]
+enum LinesEnum {
+    Search { query: String },
+    Say(String),
+}
+
+#[test]
+fn lines_enum_parses() {
+    let parser = LinesEnum::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(&state, b"action: Search\narguments:\n  query: my query\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        LinesEnum::Search {
+            query: "my query".to_string()
+        }
+    );
+
+    let output = parser
+        .parse(&state, b"action: Say\narguments: hello\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output, LinesEnum::Say("hello".to_string()));
+}
+
+#[derive(Parse, Schema, Debug, Clone, PartialEq)]
+#[parse(format = "xml")]
+enum XmlEnum {
+    Search { query: String },
+    Say(String),
+    Quit,
+}
+
+#[test]
+fn xml_enum_parses() {
+    let parser = XmlEnum::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(
+            &state,
+            b"<XmlEnum>\n<type>Search</type>\n<data>\n  <query>my query</query>\n</data>\n</XmlEnum>",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        XmlEnum::Search {
+            query: "my query".to_string()
+        }
+    );
+
+    let output = parser
+        .parse(
+            &state,
+            b"<XmlEnum>\n<type>Say</type>\n<data>hello</data>\n</XmlEnum>",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output, XmlEnum::Say("hello".to_string()));
+
+    let output = parser
+        .parse(&state, b"<XmlEnum>\n<type>Quit</type>\n</XmlEnum>")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output, XmlEnum::Quit);
+}
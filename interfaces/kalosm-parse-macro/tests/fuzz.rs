@@ -0,0 +1,79 @@
+#![allow(unused)]
+
+use kalosm::language::*;
+use proptest::prelude::*;
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Person {
+    name: String,
+    #[parse(range = 0..=120)]
+    age: u32,
+    hobbies: Vec<String>,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "yaml")]
+struct YamlPerson {
+    name: String,
+    age: u32,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "lines")]
+struct LinesPerson {
+    #[parse(pattern = "[A-Z][a-z]{1,10}")]
+    name: String,
+    height: u8,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(format = "xml")]
+struct XmlPerson {
+    name: String,
+    age: u32,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+#[parse(tag = "type", content = "data")]
+enum Action {
+    Move { x: i32, y: i32 },
+    Say(String),
+    Wait,
+}
+
+#[derive(Parse, Clone, Debug, PartialEq)]
+struct Scene {
+    background: Color,
+    actions: Vec<Action>,
+}
+
+macro_rules! round_trip {
+    ($($name:ident: $ty:ty),* $(,)?) => {
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+            $(
+                #[test]
+                fn $name(sample in parse_strategy::<$ty>()) {
+                    check_round_trip(&sample)?;
+                }
+            )*
+        }
+    };
+}
+
+round_trip! {
+    person_round_trips: Person,
+    yaml_person_round_trips: YamlPerson,
+    lines_person_round_trips: LinesPerson,
+    xml_person_round_trips: XmlPerson,
+    color_round_trips: Color,
+    action_round_trips: Action,
+    scene_round_trips: Scene,
+}
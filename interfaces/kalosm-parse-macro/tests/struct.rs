@@ -132,3 +132,253 @@ async fn with_struct() {
     assert!(output.contains("\"name\":"));
     assert!(output.contains("\"field name\":"));
 }
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "yaml")]
+struct YamlStruct {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn yaml_struct_parses() {
+    let parser = YamlStruct::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(&state, b"name: \"John\"\nage: 30\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        YamlStruct {
+            name: "John".to_string(),
+            age: 30
+        }
+    );
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "lines")]
+struct LinesStruct {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn lines_struct_parses() {
+    let parser = LinesStruct::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(&state, b"name: John Smith\nage: 30\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        LinesStruct {
+            name: "John Smith".to_string(),
+            age: 30
+        }
+    );
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "xml", rename = "person")]
+struct XmlStruct {
+    name: String,
+    #[parse(rename = "years")]
+    age: u32,
+}
+
+#[test]
+fn xml_struct_parses() {
+    let parser = XmlStruct::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(
+            &state,
+            b"<person>\n<name>John Smith</name>\n<years>30</years>\n</person>",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        XmlStruct {
+            name: "John Smith".to_string(),
+            age: 30
+        }
+    );
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(partial)]
+struct StreamedStruct {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn partial_struct_value() {
+    let partial = StreamedStruct::partial_value("{ \"name\": \"Jo");
+    assert_eq!(partial.name.as_deref(), Some("Jo"));
+    assert_eq!(partial.age, None);
+
+    let partial = StreamedStruct::partial_value("{ \"name\": \"John\", \"age\": 30 }");
+    assert_eq!(partial.name.as_deref(), Some("John"));
+    assert_eq!(partial.age, Some(30));
+
+    let parser = StreamedStruct::new_partial_parser();
+    let state = parser.create_parser_state();
+    let (state, _) = parser
+        .parse(&state, b"{ \"name\": \"John\", \"age\": ")
+        .unwrap()
+        .unwrap_incomplete();
+    let partial = state.partial_value();
+    assert_eq!(partial.name.as_deref(), Some("John"));
+    assert_eq!(partial.age, None);
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "lines", partial)]
+struct StreamedLinesStruct {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn partial_lines_struct_value() {
+    let partial = StreamedLinesStruct::partial_value("name: John Sm");
+    assert_eq!(partial.name.as_deref(), Some("John Sm"));
+    assert_eq!(partial.age, None);
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "yaml")]
+struct YamlAddress {
+    street: String,
+    number: u32,
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "yaml")]
+struct YamlPerson {
+    /// The full name of the person
+    name: String,
+    address: YamlAddress,
+}
+
+#[test]
+fn yaml_nested_struct_is_indented() {
+    let parser = YamlPerson::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(
+            &state,
+            b"name: \"John\"\naddress:\n  street: \"Main Street\"\n  number: 12\n",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        output,
+        YamlPerson {
+            name: "John".to_string(),
+            address: YamlAddress {
+                street: "Main Street".to_string(),
+                number: 12
+            }
+        }
+    );
+
+    // Nested fields without the indent are rejected
+    assert!(parser
+        .parse(
+            &state,
+            b"name: \"John\"\naddress:\nstreet: \"Main Street\"\nnumber: 12\n",
+        )
+        .is_err());
+
+    assert_eq!(
+        YamlPerson::schema().to_string(),
+        "# The full name of the person\nname: \"string\"\naddress:\n  street: \"string\"\n  number: integer\n"
+    );
+}
+
+#[test]
+fn xml_string_entities() {
+    let parser = XmlStruct::new_parser();
+    let state = parser.create_parser_state();
+    let output = parser
+        .parse(
+            &state,
+            b"<person>\n<name>Tom &amp; Jerry &lt;3</name>\n<years>30</years>\n</person>",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output.name, "Tom & Jerry <3");
+
+    assert_eq!(
+        XmlStruct::schema().to_string(),
+        "<person>\n<name>string</name>\n<years>integer</years>\n</person>\n"
+    );
+}
+
+#[test]
+fn lines_struct_schema() {
+    assert_eq!(
+        LinesStruct::schema().to_string(),
+        "name: string\nage: integer\n"
+    );
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(partial)]
+struct StreamedAddress {
+    street: String,
+    number: u32,
+}
+
+#[derive(Parse, Schema, Clone, PartialEq, Debug)]
+#[parse(format = "yaml", partial)]
+struct StreamedPerson {
+    name: String,
+    address: StreamedAddress,
+    tags: Vec<String>,
+}
+
+#[test]
+fn partial_nested_struct_value() {
+    let text = "name: \"John\"\naddress:\n  { \"street\": \"Main St\", \"number\": 12 }\ntags:\n  [\"a\"]\n";
+    let parser = StreamedPerson::new_partial_parser();
+    let mut state = parser.create_parser_state();
+    // Feed the text one byte at a time like a stream of tokens
+    for (i, byte) in text.bytes().enumerate().take(text.len() - 1) {
+        state = parser.parse(&state, &[byte]).unwrap().unwrap_incomplete().0;
+        let partial = state.partial_value();
+        // The incremental partial value matches parsing the whole text again
+        assert_eq!(
+            format!("{partial:?}"),
+            format!("{:?}", StreamedPerson::partial_value(&text[..=i]))
+        );
+        if text[..=i].ends_with("\"Main") {
+            let address = partial.address.unwrap();
+            assert_eq!(address.street.as_deref(), Some("Main"));
+            assert_eq!(address.number, None);
+            assert_eq!(partial.tags, None);
+        }
+    }
+
+    let person = parser.parse(&state, b"\n").unwrap().unwrap_finished();
+    let partial = person.clone().into_partial();
+    assert_eq!(partial.name.as_deref(), Some("John"));
+    assert_eq!(partial.address.unwrap().number, Some(12));
+    assert_eq!(partial.tags, Some(Some(vec!["a".to_string()])));
+
+    // Structs without #[parse(partial)] only have a partial value once they are finished
+    assert_eq!(YamlStruct::partial_value("name: \"Jo"), None);
+    assert_eq!(
+        YamlStruct::partial_value("name: \"Jo\"\nage: 3\n"),
+        Some(YamlStruct {
+            name: "Jo".to_string(),
+            age: 3
+        })
+    );
+}
@@ -0,0 +1,224 @@
+use std::fmt::{Display, Formatter};
+
+use crate::ParserError;
+
+/// The number of rejected tokens that are kept in a [`ParseDiagnostic`] by default.
+pub const DEFAULT_REJECTED_TOKEN_COUNT: usize = 5;
+
+/// A human readable explanation of why a parser could not accept any more text.
+///
+/// When constrained generation fails because the parser rejects every token the model could generate, the error returned contains a `ParseDiagnostic`. You can downcast the error to read the diagnostic:
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let diagnostic = ParseDiagnostic::new("{ \"name\": ")
+///     .with_expected(vec!["\"".to_string()])
+///     .with_rejected_token(RejectedToken::new(42, "1", 12.5, ParserError::msg("Expected a string")));
+/// let error = anyhow::Error::from(diagnostic);
+///
+/// let diagnostic = error.downcast_ref::<ParseDiagnostic>().unwrap();
+/// assert_eq!(diagnostic.position(), 10);
+/// assert_eq!(diagnostic.expected(), ["\""]);
+/// println!("{diagnostic}");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParseDiagnostic {
+    position: usize,
+    text: String,
+    expected: Vec<String>,
+    rejected_tokens: Vec<RejectedToken>,
+}
+
+impl ParseDiagnostic {
+    /// Create a new diagnostic for a parser that failed after parsing the given text. The position defaults to the end of the text.
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        Self {
+            position: text.len(),
+            text,
+            expected: Vec::new(),
+            rejected_tokens: Vec::new(),
+        }
+    }
+
+    /// Set the byte position the parser failed at.
+    pub fn with_position(mut self, position: usize) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Set the alternatives the parser expected at the position it failed at.
+    pub fn with_expected(mut self, expected: Vec<String>) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    /// Add a token the parser rejected.
+    pub fn with_rejected_token(mut self, token: RejectedToken) -> Self {
+        self.rejected_tokens.push(token);
+        self
+    }
+
+    /// Prepend text that was parsed before the text of this diagnostic.
+    pub(crate) fn with_prefix(mut self, prefix: &[u8]) -> Self {
+        let prefix = String::from_utf8_lossy(prefix);
+        self.position += prefix.len();
+        self.text.insert_str(0, &prefix);
+        self
+    }
+
+    /// Get the byte position the parser failed at.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Get the text that was parsed before the parser failed.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the alternatives the parser expected at the position it failed at. This may be empty if the parser doesn't know what text it expects.
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+
+    /// Get the most likely tokens that the parser rejected.
+    pub fn rejected_tokens(&self) -> &[RejectedToken] {
+        &self.rejected_tokens
+    }
+}
+
+impl Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "No valid tokens found at byte {}", self.position)?;
+        write!(f, "text so far: {:?}", self.text)?;
+        if !self.expected.is_empty() {
+            write!(f, "\nexpected one of: ")?;
+            for (i, expected) in self.expected.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{:?}", expected)?;
+            }
+        }
+        if !self.rejected_tokens.is_empty() {
+            write!(f, "\ntop rejected tokens:")?;
+            for token in &self.rejected_tokens {
+                write!(f, "\n  {}", token)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseDiagnostic {}
+
+/// A token that was rejected by a parser.
+#[derive(Debug, Clone)]
+pub struct RejectedToken {
+    token_id: u32,
+    text: String,
+    logit: f32,
+    error: ParserError,
+}
+
+impl RejectedToken {
+    /// Create a new rejected token.
+    pub fn new(token_id: u32, text: impl Into<String>, logit: f32, error: ParserError) -> Self {
+        Self {
+            token_id,
+            text: text.into(),
+            logit,
+            error,
+        }
+    }
+
+    /// Get the id of the token.
+    pub fn token_id(&self) -> u32 {
+        self.token_id
+    }
+
+    /// Get the text of the token.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the logit the model assigned to the token.
+    pub fn logit(&self) -> f32 {
+        self.logit
+    }
+
+    /// Get the error the parser returned for the token.
+    pub fn error(&self) -> &ParserError {
+        &self.error
+    }
+}
+
+impl Display for RejectedToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} (token {}, logit {:.2}): {}",
+            self.text, self.token_id, self.logit, &*self.error
+        )
+    }
+}
+
+#[test]
+fn diagnostic_display() {
+    use crate::{ChoiceParser, CreateParserState, LiteralParser, Parser};
+
+    let parser = ChoiceParser::new(LiteralParser::new("yes"), LiteralParser::new("no"));
+    let state = parser.create_parser_state();
+    let error = parser.parse(&state, b"maybe").unwrap_err();
+    let diagnostic = ParseDiagnostic::new("answer: ")
+        .with_expected(parser.expected_next(&state))
+        .with_rejected_token(RejectedToken::new(1, "maybe", 1.5, error));
+
+    assert_eq!(diagnostic.position(), 8);
+    assert_eq!(
+        diagnostic.to_string(),
+        "No valid tokens found at byte 8\n\
+         text so far: \"answer: \"\n\
+         expected one of: \"yes\", \"no\"\n\
+         top rejected tokens:\n  \"maybe\" (token 1, logit 1.50): Literal mismatch"
+    );
+}
+
+#[test]
+fn parser_error_diagnostic() {
+    use crate::{ChoiceParser, CreateParserState, LiteralParser, Parser, ParserExt, StringParser};
+
+    // The error points at the byte the parser rejected, even inside a sequence
+    let parser = LiteralParser::new("{ \"name\": ").then(StringParser::new(0..=10));
+    let state = parser.create_parser_state();
+    let error = parser.parse(&state, b"{ \"name\": 1 }").unwrap_err();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.position(), 10);
+    assert_eq!(diagnostic.text(), "{ \"name\": ");
+    assert_eq!(diagnostic.expected(), ["\""]);
+
+    // Choices keep the alternative that got furthest
+    let parser = ChoiceParser::new(LiteralParser::new("yes"), LiteralParser::new("no"));
+    let state = parser.create_parser_state();
+    let error = parser.parse(&state, b"yep").unwrap_err();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.position(), 2);
+    assert_eq!(diagnostic.expected(), ["s"]);
+
+    // And merge the expected text of alternatives that failed at the same position
+    let error = parser.parse(&state, b"maybe").unwrap_err();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.position(), 0);
+    assert_eq!(diagnostic.expected(), ["yes", "no"]);
+
+    // When input is fed in chunks, the position is relative to the latest chunk
+    let parser = LiteralParser::new("{ \"name\": ").then(StringParser::new(0..=10));
+    let state = parser.create_parser_state();
+    let (state, _) = parser.parse(&state, b"{ \"na").unwrap().unwrap_incomplete();
+    let error = parser.parse(&state, b"me\": 1").unwrap_err();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.position(), 5);
+    assert_eq!(diagnostic.text(), "me\": ");
+}
@@ -1,4 +1,4 @@
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{CreateParserState, ParseStatus, Parser, ParserError};
 use std::ops::RangeInclusive;
 
 #[derive(Debug, PartialEq, Eq, Default, Copy, Clone)]
@@ -214,6 +214,10 @@ impl Parser for FloatParser {
         let mut positive = state.positive;
         let mut state = state.state;
 
+        // Numbers don't have a fixed set of expected text, so only the position is recorded
+        let rejected =
+            |error: ParserError, index: usize| error.rejected_after(&input[..index], Vec::new());
+
         for index in 0..input.len() {
             let input_byte = input[index];
             let digit = match input_byte {
@@ -222,7 +226,7 @@ impl Parser for FloatParser {
                         || state == FloatParserProgress::AfterSign)
                         && input_byte == b'0'
                     {
-                        crate::bail!(LeadingZeroError);
+                        return Err(rejected(LeadingZeroError.into(), index));
                     }
                     input_byte - b'0'
                 }
@@ -232,10 +236,10 @@ impl Parser for FloatParser {
                     let end_digits = self.range.end().abs().log10() + 1.;
                     if positive {
                         if value_digits > end_digits {
-                            crate::bail!(OutOfRangeError);
+                            return Err(rejected(OutOfRangeError.into(), index));
                         }
                     } else if value_digits > start_digits {
-                        crate::bail!(OutOfRangeError);
+                        return Err(rejected(OutOfRangeError.into(), index));
                     }
                     if state == FloatParserProgress::AfterDigit {
                         state = FloatParserProgress::AfterDecimalPoint {
@@ -243,7 +247,7 @@ impl Parser for FloatParser {
                         };
                         continue;
                     } else {
-                        crate::bail!(InvalidDecimalLocation);
+                        return Err(rejected(InvalidDecimalLocation.into(), index));
                     }
                 }
                 b'+' | b'-' => {
@@ -252,11 +256,11 @@ impl Parser for FloatParser {
                         positive = input_byte == b'+';
 
                         if !self.sign_valid(positive) {
-                            crate::bail!(InvalidSignLocation);
+                            return Err(rejected(InvalidSignLocation.into(), index));
                         }
                         continue;
                     } else {
-                        crate::bail!(InvalidSignLocation);
+                        return Err(rejected(InvalidSignLocation.into(), index));
                     }
                 }
                 _ => {
@@ -273,7 +277,7 @@ impl Parser for FloatParser {
                             remaining: &input[index..],
                         });
                     } else {
-                        crate::bail!(EmptyNumber)
+                        return Err(rejected(EmptyNumber.into(), index));
                     }
                 }
             };
@@ -294,7 +298,7 @@ impl Parser for FloatParser {
                         value * if positive { 1.0 } else { -1.0 },
                         FloatParserProgress::AfterDigit,
                     ) {
-                        crate::bail!(OutOfRangeError);
+                        return Err(rejected(OutOfRangeError.into(), index));
                     }
                 }
                 FloatParserProgress::AfterDecimalPoint {
@@ -311,7 +315,7 @@ impl Parser for FloatParser {
                             *digits_after_decimal_point,
                         )
                     {
-                        crate::bail!(OutOfRangeError);
+                        return Err(rejected(OutOfRangeError.into(), index));
                     }
                 }
             }
@@ -0,0 +1,235 @@
+use crate::{CreateParserState, ParseStatus, Parser, ParserError, PartialParse, PartialValueState};
+
+/// A parser for an indented block of lines.
+///
+/// Every line of the block must start with the indent. The inner parser sees the text without the indentation. The block always ends with a newline: if the inner parser finishes in the middle of a line, the newline is parsed after it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct IndentParser<P> {
+    parser: P,
+    indent: &'static str,
+}
+
+impl<P> IndentParser<P> {
+    /// Create a new parser that indents every line of the inner parser.
+    pub fn new(parser: P, indent: &'static str) -> Self {
+        Self { parser, indent }
+    }
+}
+
+/// An error that can occur while parsing an indented block.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndentParseError;
+
+impl std::fmt::Display for IndentParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "IndentParseError".fmt(f)
+    }
+}
+
+impl std::error::Error for IndentParseError {}
+
+/// The state of an [`IndentParser`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndentParserState<S, O> {
+    state: S,
+    progress: IndentProgress<O>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum IndentProgress<O> {
+    /// Parsing the indent at the start of a line
+    Indent(usize),
+    /// Parsing the rest of a line with the inner parser
+    Line,
+    /// The inner parser finished in the middle of a line and the block still needs a newline
+    Newline(O),
+}
+
+impl<S, O> IndentParserState<S, O> {
+    /// Create a new indent state from the state of the inner parser.
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            progress: IndentProgress::Indent(0),
+        }
+    }
+}
+
+impl<S: PartialValueState, O: PartialParse<Partial = S::Partial>> PartialValueState
+    for IndentParserState<S, O>
+{
+    type Partial = S::Partial;
+
+    fn partial_value(&self) -> Self::Partial {
+        match &self.progress {
+            IndentProgress::Newline(result) => result.clone().into_partial(),
+            _ => self.state.partial_value(),
+        }
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for IndentParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        IndentParserState::new(self.parser.create_parser_state())
+    }
+}
+
+impl<P: Parser> Parser for IndentParser<P> {
+    type Output = P::Output;
+    type PartialState = IndentParserState<P::PartialState, P::Output>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut inner_state = state.state.clone();
+        let mut progress = state.progress.clone();
+        let mut remaining = input;
+
+        loop {
+            match progress {
+                IndentProgress::Indent(offset) => {
+                    let expected = &self.indent[offset..];
+                    let len = expected.len().min(remaining.len());
+                    if remaining[..len] != expected.as_bytes()[..len] {
+                        return Err(ParserError::from(IndentParseError).rejected_after(
+                            &input[..input.len() - remaining.len()],
+                            vec![expected.to_string()],
+                        ));
+                    }
+                    remaining = &remaining[len..];
+                    if len < expected.len() {
+                        return Ok(ParseStatus::Incomplete {
+                            new_state: IndentParserState {
+                                state: inner_state,
+                                progress: IndentProgress::Indent(offset + len),
+                            },
+                            required_next: self.indent[offset + len..].into(),
+                        });
+                    }
+                    progress = IndentProgress::Line;
+                }
+                IndentProgress::Line => {
+                    if remaining.is_empty() {
+                        return Ok(ParseStatus::Incomplete {
+                            new_state: IndentParserState {
+                                state: inner_state,
+                                progress,
+                            },
+                            required_next: "".into(),
+                        });
+                    }
+                    // Only pass the inner parser one line at a time so the indent of the next line can be removed
+                    let line_len = remaining
+                        .iter()
+                        .position(|byte| *byte == b'\n')
+                        .map_or(remaining.len(), |newline| newline + 1);
+                    let line = &remaining[..line_len];
+                    let result = self
+                        .parser
+                        .parse(&inner_state, line)
+                        .map_err(|err| err.with_prefix(&input[..input.len() - remaining.len()]))?;
+                    match result {
+                        ParseStatus::Finished {
+                            result,
+                            remaining: unparsed,
+                        } => {
+                            let parsed = &line[..line_len - unparsed.len()];
+                            remaining = &remaining[parsed.len()..];
+                            if parsed.ends_with(b"\n") {
+                                return Ok(ParseStatus::Finished { result, remaining });
+                            }
+                            progress = IndentProgress::Newline(result);
+                        }
+                        ParseStatus::Incomplete {
+                            new_state,
+                            required_next,
+                        } => {
+                            inner_state = new_state;
+                            remaining = &remaining[line_len..];
+                            if !line.ends_with(b"\n") {
+                                return Ok(ParseStatus::Incomplete {
+                                    new_state: IndentParserState {
+                                        state: inner_state,
+                                        progress,
+                                    },
+                                    required_next,
+                                });
+                            }
+                            progress = IndentProgress::Indent(0);
+                        }
+                    }
+                }
+                IndentProgress::Newline(result) => {
+                    return match remaining.split_first() {
+                        Some((b'\n', remaining)) => Ok(ParseStatus::Finished { result, remaining }),
+                        Some(_) => Err(ParserError::from(IndentParseError).rejected_after(
+                            &input[..input.len() - remaining.len()],
+                            vec!["\n".to_string()],
+                        )),
+                        None => Ok(ParseStatus::Incomplete {
+                            new_state: IndentParserState {
+                                state: inner_state,
+                                progress: IndentProgress::Newline(result),
+                            },
+                            required_next: "\n".into(),
+                        }),
+                    };
+                }
+            }
+        }
+    }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        match &state.progress {
+            IndentProgress::Newline(result) => Some(result.clone()),
+            _ => self.parser.partial_output(&state.state),
+        }
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match &state.progress {
+            IndentProgress::Indent(offset) => vec![self.indent[*offset..].to_string()],
+            IndentProgress::Line => self.parser.expected_next(&state.state),
+            IndentProgress::Newline(_) => vec!["\n".to_string()],
+        }
+    }
+}
+
+#[test]
+fn indent_parser() {
+    use crate::{LiteralParser, ParserExt};
+
+    let parser = IndentParser::new(
+        LiteralParser::new("a: 1\n").then(LiteralParser::new("b: 2")),
+        "  ",
+    );
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b"  a: 1\n  b: 2\nrest").unwrap(),
+        ParseStatus::Finished {
+            result: ((), ()),
+            remaining: b"rest"
+        }
+    );
+    // Lines without the indent are rejected
+    assert!(parser.parse(&state, b"  a: 1\nb: 2\n").is_err());
+
+    // The block can be split at any point
+    let text = b"  a: 1\n  b: 2\n";
+    for split in 0..text.len() {
+        let (first, second) = text.split_at(split);
+        let state = match parser.parse(&state, first).unwrap() {
+            ParseStatus::Incomplete { new_state, .. } => new_state,
+            ParseStatus::Finished { .. } => panic!("the block should not be finished"),
+        };
+        assert_eq!(
+            parser.parse(&state, second).unwrap(),
+            ParseStatus::Finished {
+                result: ((), ()),
+                remaining: b""
+            }
+        );
+    }
+}
@@ -1,8 +1,6 @@
-use crate::bail;
-
 use crate::{
     CreateParserState, EmptyNumber, InvalidSignLocation, LeadingZeroError, OutOfRangeError,
-    ParseStatus, Parser,
+    ParseStatus, Parser, ParserError,
 };
 use std::ops::RangeInclusive;
 
@@ -154,6 +152,10 @@ impl Parser for IntegerParser {
         let mut positive = state.positive;
         let mut state = state.state;
 
+        // Numbers don't have a fixed set of expected text, so only the position is recorded
+        let rejected =
+            |error: ParserError, index: usize| error.rejected_after(&input[..index], Vec::new());
+
         for index in 0..input.len() {
             let input_byte = input[index];
             let digit = match input_byte {
@@ -162,7 +164,7 @@ impl Parser for IntegerParser {
                         && value == 0
                         && input_byte == b'0'
                     {
-                        bail!(LeadingZeroError);
+                        return Err(rejected(LeadingZeroError.into(), index));
                     }
                     input_byte - b'0'
                 }
@@ -171,11 +173,11 @@ impl Parser for IntegerParser {
                         state = IntegerParserProgress::AfterSign;
                         positive = false;
                         if !self.can_be_negative() {
-                            bail!(OutOfRangeError)
+                            return Err(rejected(OutOfRangeError.into(), index));
                         }
                         continue;
                     } else {
-                        bail!(InvalidSignLocation)
+                        return Err(rejected(InvalidSignLocation.into(), index));
                     }
                 }
                 _ => {
@@ -187,9 +189,9 @@ impl Parser for IntegerParser {
                                 remaining: &input[index..],
                             });
                         }
-                        bail!(OutOfRangeError)
+                        return Err(rejected(OutOfRangeError.into(), index));
                     } else {
-                        bail!(EmptyNumber)
+                        return Err(rejected(EmptyNumber.into(), index));
                     }
                 }
             };
@@ -205,7 +207,7 @@ impl Parser for IntegerParser {
                             remaining: &input[index..],
                         });
                     }
-                    bail!(OutOfRangeError)
+                    return Err(rejected(OutOfRangeError.into(), index));
                 }
             }
 
@@ -225,7 +227,7 @@ impl Parser for IntegerParser {
                         remaining: &input[index + 1..],
                     });
                 }
-                bail!(OutOfRangeError)
+                return Err(rejected(OutOfRangeError.into(), index));
             }
         }
 
@@ -1,8 +1,6 @@
 use std::borrow::Cow;
 
-use crate::bail;
-
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{CreateParserState, ParseStatus, Parser, ParserError};
 
 /// A parser for a literal.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -74,7 +72,11 @@ impl Parser for LiteralParser {
             .zip(self.literal.as_bytes()[state.offset..].iter())
         {
             if input_byte != literal_byte {
-                bail!(LiteralMismatchError);
+                let expected = &self.literal.as_bytes()[state.offset + bytes_consumed..];
+                return Err(ParserError::from(LiteralMismatchError).rejected_after(
+                    &input[..bytes_consumed],
+                    vec![String::from_utf8_lossy(expected).into_owned()],
+                ));
             }
             bytes_consumed += 1;
         }
@@ -102,6 +104,13 @@ impl Parser for LiteralParser {
             })
         }
     }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match self.literal.get(state.offset..) {
+            Some(remaining) if !remaining.is_empty() => vec![remaining.to_string()],
+            _ => Vec::new(),
+        }
+    }
 }
 
 #[test]
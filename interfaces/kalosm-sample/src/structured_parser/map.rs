@@ -63,4 +63,12 @@ impl<P: Parser, O: Clone, F: Fn(P::Output) -> O> Parser for MapOutputParser<P, O
             }),
         }
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        self.parser.partial_output(state).map(&self.map)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.parser.expected_next(state)
+    }
 }
@@ -28,6 +28,16 @@ mod separated;
 pub use separated::*;
 mod parse;
 pub use parse::*;
+mod partial;
+pub use partial::*;
+mod diagnostic;
+pub use diagnostic::*;
+mod sampler;
+pub use sampler::*;
+mod whitespace;
+pub use whitespace::*;
+mod indent;
+pub use indent::*;
 mod word;
 pub use word::*;
 mod sentence;
@@ -45,7 +55,10 @@ pub use schema::*;
 
 /// An error that occurred while parsing.
 #[derive(Debug, Clone)]
-pub struct ParserError(Arc<anyhow::Error>);
+pub struct ParserError {
+    error: Arc<anyhow::Error>,
+    diagnostic: Option<Arc<ParseDiagnostic>>,
+}
 
 /// Bail out with the given error.
 #[macro_export]
@@ -64,13 +77,70 @@ macro_rules! bail {
 impl ParserError {
     /// Create a new error with the given message.
     pub fn msg(msg: impl Display + Debug + Send + Sync + 'static) -> Self {
-        Self(Arc::new(anyhow::Error::msg(msg)))
+        Self {
+            error: Arc::new(anyhow::Error::msg(msg)),
+            diagnostic: None,
+        }
+    }
+
+    /// Attach a [`ParseDiagnostic`] that explains where the parser failed.
+    pub fn with_diagnostic(mut self, diagnostic: ParseDiagnostic) -> Self {
+        self.diagnostic = Some(Arc::new(diagnostic));
+        self
+    }
+
+    /// Attach a [`ParseDiagnostic`] for a parser that failed after accepting `parsed` from the start of its input while expecting one of `expected`.
+    pub fn rejected_after(self, parsed: &[u8], expected: Vec<String>) -> Self {
+        self.with_diagnostic(
+            ParseDiagnostic::new(String::from_utf8_lossy(parsed)).with_expected(expected),
+        )
+    }
+
+    /// Move the diagnostic attached to this error back by `prefix`: the text a parent parser accepted before the input of the parser that failed.
+    pub fn with_prefix(mut self, prefix: &[u8]) -> Self {
+        if let Some(diagnostic) = self.diagnostic.take() {
+            let diagnostic = Arc::unwrap_or_clone(diagnostic).with_prefix(prefix);
+            self.diagnostic = Some(Arc::new(diagnostic));
+        }
+        self
+    }
+
+    /// Combine the errors of two alternatives that both failed on the same input. The error that got furthest into the input is kept and the expected text of alternatives that failed at the same position is merged.
+    pub(crate) fn or(self, other: Self) -> Self {
+        let (Some(first), Some(second)) = (self.diagnostic(), other.diagnostic()) else {
+            return if self.diagnostic().is_some() || other.diagnostic().is_none() {
+                self
+            } else {
+                other
+            };
+        };
+        match first.position().cmp(&second.position()) {
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Equal => {
+                let mut expected = first.expected().to_vec();
+                for alternative in second.expected() {
+                    if !expected.contains(alternative) {
+                        expected.push(alternative.clone());
+                    }
+                }
+                let diagnostic = first.clone().with_expected(expected);
+                self.with_diagnostic(diagnostic)
+            }
+        }
+    }
+
+    /// Get the [`ParseDiagnostic`] attached to this error. The position of the diagnostic is relative to the start of the input passed to the parser that returned the error.
+    pub fn diagnostic(&self) -> Option<&ParseDiagnostic> {
+        self.diagnostic
+            .as_deref()
+            .or_else(|| self.error.downcast_ref::<ParseDiagnostic>())
     }
 }
 
 impl PartialEq for ParserError {
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.0, &other.0)
+        Arc::ptr_eq(&self.error, &other.error)
     }
 }
 
@@ -78,14 +148,14 @@ impl Eq for ParserError {}
 
 impl AsRef<dyn Error> for ParserError {
     fn as_ref(&self) -> &(dyn Error + 'static) {
-        let err: &anyhow::Error = self.0.as_ref();
+        let err: &anyhow::Error = self.error.as_ref();
         err.as_ref()
     }
 }
 
 impl AsRef<dyn std::error::Error + Send + Sync + 'static> for ParserError {
     fn as_ref(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
-        let err: &anyhow::Error = self.0.as_ref();
+        let err: &anyhow::Error = self.error.as_ref();
         err.as_ref()
     }
 }
@@ -94,7 +164,7 @@ impl Deref for ParserError {
     type Target = (dyn Error + Send + Sync + 'static);
 
     fn deref(&self) -> &(dyn Error + Send + Sync + 'static) {
-        let err: &anyhow::Error = self.0.as_ref();
+        let err: &anyhow::Error = self.error.as_ref();
         err.deref()
     }
 }
@@ -104,7 +174,10 @@ where
     E: std::error::Error + Send + Sync + 'static,
 {
     fn from(value: E) -> Self {
-        Self(Arc::new(anyhow::Error::from(value)))
+        Self {
+            error: Arc::new(anyhow::Error::from(value)),
+            diagnostic: None,
+        }
     }
 }
 
@@ -165,6 +238,20 @@ pub trait Parser {
         state: &Self::PartialState,
         input: &'a [u8],
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>>;
+
+    /// Get the output that has been parsed so far from an incomplete state. Returns `None` if the parser cannot produce a partial output.
+    ///
+    /// For example, a string parser returns the text it has parsed so far.
+    fn partial_output(&self, _state: &Self::PartialState) -> Option<Self::Output> {
+        None
+    }
+
+    /// Get the text the parser expects next from the given state. Each item is one alternative the parser would accept. Returns an empty list if the parser does not know what text it expects.
+    ///
+    /// This is used to explain why a parser rejected some input. For example, a choice between the literals `"yes"` and `"no"` expects either `"yes"` or `"no"`.
+    fn expected_next(&self, _state: &Self::PartialState) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl Parser for () {
@@ -194,6 +281,14 @@ impl<P: ?Sized + Parser> Parser for &P {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         (*self).parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        (*self).partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        (*self).expected_next(state)
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Box<P> {
@@ -208,6 +303,16 @@ impl<P: ?Sized + Parser> Parser for Box<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        let _self: &P = self;
+        _self.partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        let _self: &P = self;
+        _self.expected_next(state)
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Arc<P> {
@@ -222,6 +327,16 @@ impl<P: ?Sized + Parser> Parser for Arc<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        let _self: &P = self;
+        _self.partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        let _self: &P = self;
+        _self.expected_next(state)
+    }
 }
 
 trait AnyCreateParserState:
@@ -268,6 +383,16 @@ impl<O: Clone> Parser for ArcParser<O> {
         let _self: &dyn Parser<Output = O, PartialState = Arc<dyn Any + Send + Sync>> = &self.0;
         _self.parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        let _self: &dyn Parser<Output = O, PartialState = Arc<dyn Any + Send + Sync>> = &self.0;
+        _self.partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        let _self: &dyn Parser<Output = O, PartialState = Arc<dyn Any + Send + Sync>> = &self.0;
+        _self.expected_next(state)
+    }
 }
 
 /// A wrapper for a parser that implements an easily boxable version of Parser.
@@ -305,6 +430,18 @@ where
             .parse(state, input)
             .map(|result| result.map_state(|state| Arc::new(state) as Arc<dyn Any + Sync + Send>))
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        let state = state.downcast_ref::<P::PartialState>()?;
+        self.0.partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match state.downcast_ref::<P::PartialState>() {
+            Some(state) => self.0.expected_next(state),
+            None => Vec::new(),
+        }
+    }
 }
 
 impl<P: CreateParserState> CreateParserState for AnyParser<P>
@@ -417,6 +554,14 @@ pub trait ParserExt: Parser {
         }
     }
 
+    /// Allow any amount of whitespace before this parser.
+    fn allow_leading_whitespace(self) -> LeadingWhitespace<Self>
+    where
+        Self: Sized,
+    {
+        LeadingWhitespace::new(self)
+    }
+
     /// Get a boxed version of this parser.
     fn boxed(self) -> ArcParser<Self::Output>
     where
@@ -474,6 +619,14 @@ impl<P: Parser, F: Fn() -> P::PartialState + Clone> Parser for WithInitialState<
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.parser.parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        self.parser.partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.parser.expected_next(state)
+    }
 }
 
 /// A parser that is lazily initialized.
@@ -520,6 +673,14 @@ impl<P: Parser, F: FnOnce() -> P> Parser for LazyParser<P, F> {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.get_parser().parse(state, input)
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        self.get_parser().partial_output(state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.get_parser().expected_next(state)
+    }
 }
 
 /// A parser for a choice between two parsers.
@@ -186,8 +186,8 @@ impl<P1: Parser, P2: Parser> Parser for ChoiceParser<P1, P2> {
                         })
                     }
 
-                    // If both parsers fail, we return the error from the first parser
-                    (Err(err1), Err(_)) => Err(err1),
+                    // If both parsers fail, we return the error from the parser that got furthest
+                    (Err(err1), Err(err2)) => Err(err1.or(err2)),
                 }
             }
             (Ok(p1), Err(err2)) => {
@@ -239,6 +239,21 @@ impl<P1: Parser, P2: Parser> Parser for ChoiceParser<P1, P2> {
             }
         }
     }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        let mut expected = Vec::new();
+        if let Ok(state1) = &state.state1 {
+            expected.extend(self.parser1.expected_next(state1));
+        }
+        if let Ok(state2) = &state.state2 {
+            for alternative in self.parser2.expected_next(state2) {
+                if !expected.contains(&alternative) {
+                    expected.push(alternative);
+                }
+            }
+        }
+        expected
+    }
 }
 
 #[test]
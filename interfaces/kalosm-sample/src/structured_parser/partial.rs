@@ -0,0 +1,393 @@
+use std::{any::Any, fmt::Debug, sync::Arc};
+
+use crate::{
+    CreateParserState, Parse, ParseStatus, Parser, ParserExt, SendCreateParserState, Sentence, Word,
+};
+
+/// Data that can be read while it is still being parsed.
+///
+/// This trait is implemented automatically when you derive [`Parse`]. By default, the partial value of a derived type is `Option<Self>` which is only set once the whole value is parsed. Structs with named fields can opt into a partial version of the struct where every field is optional with `#[parse(partial)]`. For example, a `Person` struct gets a `PartialPerson` struct:
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// #[derive(Parse, Clone, Debug)]
+/// #[parse(partial)]
+/// struct Person {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let partial: PartialPerson = Person::partial_value("{ \"name\": \"Jo");
+/// assert_eq!(partial.name.as_deref(), Some("Jo"));
+/// assert_eq!(partial.age, None);
+/// ```
+///
+/// Fields with another type that implements [`PartialParse`] hold the partial value of that type, so nested structs fill in as they are parsed.
+pub trait PartialParse: Parse {
+    /// A version of this type where each part of the value may still be missing or incomplete.
+    type Partial: Clone + Default + Send + Sync + 'static;
+
+    /// Create a parser for this type that keeps track of the partial value as it parses. The partial value can be read from the state of the parser with [`PartialValueState::partial_value`].
+    ///
+    /// Each call to [`Parser::parse`] only does work for the new input, so this is the best way to read the partial value of a stream of text.
+    fn new_partial_parser() -> impl SendCreateParserState<
+        Output = Self,
+        PartialState: PartialValueState<Partial = Self::Partial> + 'static,
+    >;
+
+    /// Convert a finished value into its partial version.
+    fn into_partial(self) -> Self::Partial;
+
+    /// Read the partially parsed value from the text that has been parsed so far. If the text does not match the parser, the default partial value is returned.
+    ///
+    /// This parses the whole text every time it is called. Use [`PartialParse::new_partial_parser`] to update the partial value as new text is parsed.
+    fn partial_value(text: &str) -> Self::Partial {
+        let parser = Self::new_partial_parser();
+        let state = parser.create_parser_state();
+        match parser.parse(&state, text.as_bytes()) {
+            Ok(ParseStatus::Finished { result, .. }) => result.into_partial(),
+            Ok(ParseStatus::Incomplete { new_state, .. }) => new_state.partial_value(),
+            Err(_) => Self::Partial::default(),
+        }
+    }
+}
+
+/// The state of a parser that knows the partial value it has parsed so far.
+pub trait PartialValueState {
+    /// The partial value the parser produces.
+    type Partial;
+
+    /// Get the value that has been parsed so far.
+    fn partial_value(&self) -> Self::Partial;
+}
+
+/// A parser whose partial value is the partial output of the inner parser. This is the partial parser for types that only have a value once they are finished.
+#[derive(Debug, Clone)]
+pub struct PartialOutputParser<P> {
+    parser: P,
+}
+
+impl<P> PartialOutputParser<P> {
+    /// Create a new partial parser from a parser for the finished value.
+    pub fn new(parser: P) -> Self {
+        Self { parser }
+    }
+}
+
+/// The state of a [`PartialOutputParser`].
+#[derive(Debug, Clone)]
+pub struct PartialOutputState<S, O> {
+    state: S,
+    partial: Option<O>,
+}
+
+impl<S, O: Clone> PartialValueState for PartialOutputState<S, O> {
+    type Partial = Option<O>;
+
+    fn partial_value(&self) -> Self::Partial {
+        self.partial.clone()
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for PartialOutputParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        PartialOutputState {
+            state: self.parser.create_parser_state(),
+            partial: None,
+        }
+    }
+}
+
+impl<P: Parser> Parser for PartialOutputParser<P> {
+    type Output = P::Output;
+    type PartialState = PartialOutputState<P::PartialState, P::Output>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let result = self.parser.parse(&state.state, input)?;
+        Ok(result.map_state(|state| PartialOutputState {
+            partial: self.parser.partial_output(&state),
+            state,
+        }))
+    }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        state.partial.clone()
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.parser.expected_next(&state.state)
+    }
+}
+
+/// Implement [`PartialParse`] for types that only have a partial value once they are finished
+macro_rules! impl_partial_output {
+    ($($ty:ty $(where [$($generics:tt)*])?),* $(,)?) => {
+        $(
+            impl $(<$($generics)*>)? PartialParse for $ty {
+                type Partial = Option<Self>;
+
+                fn new_partial_parser() -> impl SendCreateParserState<
+                    Output = Self,
+                    PartialState: PartialValueState<Partial = Self::Partial> + 'static,
+                > {
+                    PartialOutputParser::new(Self::new_parser())
+                }
+
+                fn into_partial(self) -> Self::Partial {
+                    Some(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_partial_output!(
+    u8,
+    u16,
+    u32,
+    u64,
+    i8,
+    i16,
+    i32,
+    i64,
+    String,
+    Vec<T> where [T: Parse + 'static],
+    [T; N] where [const N: usize, T: Parse + 'static],
+    Option<T> where [T: Parse + 'static],
+    Word<MIN, MAX> where [const MIN: usize, const MAX: usize],
+    Sentence<MIN, MAX> where [const MIN: usize, const MAX: usize],
+);
+
+impl<T: PartialParse + 'static> PartialParse for Box<T> {
+    type Partial = T::Partial;
+
+    fn new_partial_parser() -> impl SendCreateParserState<
+        Output = Self,
+        PartialState: PartialValueState<Partial = Self::Partial> + 'static,
+    > {
+        T::new_partial_parser().map_output(Box::new)
+    }
+
+    fn into_partial(self) -> Self::Partial {
+        (*self).into_partial()
+    }
+}
+
+/// The state of the current step of a [`PartialStepsParser`]. The type of the state depends on the parser of the step, so it is stored as [`Any`].
+#[doc(hidden)]
+#[derive(Clone, Default)]
+pub struct ErasedParserState(Option<Arc<dyn Any + Send + Sync>>);
+
+impl Debug for ErasedParserState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ErasedParserState").finish()
+    }
+}
+
+impl ErasedParserState {
+    fn get<P: CreateParserState>(&self, parser: &P) -> Arc<dyn Any + Send + Sync>
+    where
+        P::PartialState: Send + Sync + 'static,
+    {
+        match &self.0 {
+            Some(state) if state.is::<P::PartialState>() => state.clone(),
+            _ => Arc::new(parser.create_parser_state()),
+        }
+    }
+
+    /// Parse the input with the parser of the current step, starting from this state.
+    pub fn parse<'a, P: CreateParserState>(
+        &self,
+        parser: &P,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self, P::Output>>
+    where
+        P::PartialState: Send + Sync + 'static,
+    {
+        let state = self.get(parser);
+        let state = state.downcast_ref::<P::PartialState>().unwrap();
+        Ok(parser
+            .parse(state, input)?
+            .map_state(|state| Self(Some(Arc::new(state)))))
+    }
+
+    /// Get the partial output of the parser of the current step.
+    pub fn partial_output<P: CreateParserState>(&self, parser: &P) -> Option<P::Output>
+    where
+        P::PartialState: Send + Sync + 'static,
+    {
+        let state = self.get(parser);
+        parser.partial_output(state.downcast_ref::<P::PartialState>().unwrap())
+    }
+
+    /// Get the partial value of the parser of the current step.
+    pub fn partial_value<P: CreateParserState>(
+        &self,
+        parser: &P,
+    ) -> <P::PartialState as PartialValueState>::Partial
+    where
+        P::PartialState: PartialValueState + Send + Sync + 'static,
+    {
+        let state = self.get(parser);
+        state
+            .downcast_ref::<P::PartialState>()
+            .unwrap()
+            .partial_value()
+    }
+}
+
+/// Parse the next part of a partial value from the input. The step returns [`ParseStatus::Finished`] with the input after the step once it is done.
+#[doc(hidden)]
+pub type PartialStep<T> = for<'a> fn(
+    &mut T,
+    usize,
+    &ErasedParserState,
+    &'a [u8],
+) -> crate::ParseResult<ParseStatus<'a, ErasedParserState, ()>>;
+
+/// A parser that updates a partial value as it parses. The text is split into a fixed number of steps (like the name and the value of each field in a struct) that fill in the partial value. Only the state of the current step is kept, so each call to parse only does work for the new input.
+///
+/// This is used by the derive macro for [`PartialParse`].
+#[doc(hidden)]
+pub struct PartialStepsParser<P, T> {
+    parser: P,
+    step: PartialStep<T>,
+    steps: usize,
+}
+
+impl<P: Clone, T> Clone for PartialStepsParser<P, T> {
+    fn clone(&self) -> Self {
+        Self {
+            parser: self.parser.clone(),
+            step: self.step,
+            steps: self.steps,
+        }
+    }
+}
+
+impl<P: Debug, T> Debug for PartialStepsParser<P, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.parser.fmt(f)
+    }
+}
+
+impl<P, T> PartialStepsParser<P, T> {
+    /// Create a new parser that parses the value with `parser` and fills in the partial value with `steps` calls to `step`.
+    pub fn new(parser: P, step: PartialStep<T>, steps: usize) -> Self {
+        Self {
+            parser,
+            step,
+            steps,
+        }
+    }
+}
+
+/// The state of a [`PartialStepsParser`].
+#[doc(hidden)]
+#[derive(Debug, Clone)]
+pub struct PartialStepsState<S, T> {
+    state: S,
+    partial: T,
+    step: usize,
+    step_state: ErasedParserState,
+}
+
+impl<S, T: Clone> PartialValueState for PartialStepsState<S, T> {
+    type Partial = T;
+
+    fn partial_value(&self) -> Self::Partial {
+        self.partial.clone()
+    }
+}
+
+impl<P: CreateParserState, T: Clone + Default> CreateParserState for PartialStepsParser<P, T> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        PartialStepsState {
+            state: self.parser.create_parser_state(),
+            partial: T::default(),
+            step: 0,
+            step_state: ErasedParserState::default(),
+        }
+    }
+}
+
+impl<P: Parser, T: Clone> Parser for PartialStepsParser<P, T> {
+    type Output = P::Output;
+    type PartialState = PartialStepsState<P::PartialState, T>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let (new_state, required_next) = match self.parser.parse(&state.state, input)? {
+            ParseStatus::Finished { result, remaining } => {
+                return Ok(ParseStatus::Finished { result, remaining })
+            }
+            ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            } => (new_state, required_next),
+        };
+
+        let mut partial = state.partial.clone();
+        let mut step = state.step;
+        let mut step_state = state.step_state.clone();
+        let mut remaining = input;
+        while step < self.steps {
+            match (self.step)(&mut partial, step, &step_state, remaining)? {
+                ParseStatus::Finished {
+                    remaining: after_step,
+                    ..
+                } => {
+                    step += 1;
+                    step_state = ErasedParserState::default();
+                    remaining = after_step;
+                    if remaining.is_empty() {
+                        break;
+                    }
+                }
+                ParseStatus::Incomplete { new_state, .. } => {
+                    step_state = new_state;
+                    break;
+                }
+            }
+        }
+
+        Ok(ParseStatus::Incomplete {
+            new_state: PartialStepsState {
+                state: new_state,
+                partial,
+                step,
+                step_state,
+            },
+            required_next,
+        })
+    }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        self.parser.partial_output(&state.state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.parser.expected_next(&state.state)
+    }
+}
+
+#[test]
+fn partial_output_parser() {
+    let parser = String::new_partial_parser();
+    let state = parser.create_parser_state();
+    let (state, _) = parser.parse(&state, b"\"Hel").unwrap().unwrap_incomplete();
+    assert_eq!(state.partial_value().as_deref(), Some("Hel"));
+    let (state, _) = parser.parse(&state, b"lo").unwrap().unwrap_incomplete();
+    assert_eq!(state.partial_value().as_deref(), Some("Hello"));
+
+    assert_eq!(u32::partial_value("12"), None);
+    assert_eq!(u32::partial_value("12 "), Some(12));
+}
@@ -63,7 +63,11 @@ impl Parser for RegexParser {
                     remaining: iter.as_slice(),
                 });
             } else if self.dfa.is_dead_state(state.state) || self.dfa.is_quit_state(state.state) {
-                crate::bail!(regex_automata::MatchError::quit(b, 0));
+                let position = input.len() - iter.as_slice().len() - 1;
+                return Err(crate::ParserError::from(regex_automata::MatchError::quit(
+                    b, position,
+                ))
+                .rejected_after(&input[..position], Vec::new()));
             }
         }
 
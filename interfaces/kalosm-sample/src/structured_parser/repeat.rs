@@ -178,7 +178,7 @@ where
                             remaining,
                         });
                     } else {
-                        return Err(e);
+                        return Err(e.with_prefix(&input[..input.len() - remaining.len()]));
                     }
                 }
             }
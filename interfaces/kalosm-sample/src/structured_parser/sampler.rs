@@ -0,0 +1,319 @@
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{CreateParserState, ParseDiagnostic, ParseResult, ParseStatus, ParserError};
+
+/// An example generated by a [`ParserSampler`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParserSample<O> {
+    /// The text that was fed to the parser. This may end with a few bytes the parser did not consume if the parser needed to see them before it knew it was finished. (For example, an integer parser only finishes once it sees a byte that is not a digit)
+    pub text: String,
+    /// The end of [`Self::text`] that the parser did not consume.
+    pub remaining: String,
+    /// The output the parser produced for the text.
+    pub output: O,
+}
+
+/// Generates random example text that a parser accepts without a model.
+///
+/// The sampler performs a random walk over the parser. At each step it adds any text the parser requires next, or probes the parser with each byte from the alphabet and the alternatives the parser expects next and picks a random input the parser accepts. Once the text is longer than the target length, the sampler tries to finish the parser as quickly as possible.
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// #[derive(Parse, Clone, Debug)]
+/// struct Person {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let sampler = ParserSampler::new(Person::new_parser());
+/// let sample = sampler.sample().unwrap();
+/// println!("{}", sample.text);
+///
+/// // The text the sampler generates is always accepted by the parser
+/// let parser = Person::new_parser();
+/// let state = parser.create_parser_state();
+/// let result = parser.parse(&state, sample.text.as_bytes()).unwrap();
+/// assert!(matches!(result, ParseStatus::Finished { .. }));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParserSampler<P> {
+    parser: P,
+    alphabet: Vec<u8>,
+    target_length: usize,
+    max_length: usize,
+}
+
+impl<P: CreateParserState> ParserSampler<P> {
+    /// Create a new sampler for a parser. The default alphabet is printable ascii and newlines.
+    pub fn new(parser: P) -> Self {
+        Self {
+            parser,
+            alphabet: (b' '..=b'~').chain([b'\n']).collect(),
+            target_length: 32,
+            max_length: 1024,
+        }
+    }
+
+    /// Set the bytes the sampler probes the parser with. Text the parser requires or expects next is always tried in addition to the alphabet.
+    pub fn with_alphabet(mut self, alphabet: impl Into<Vec<u8>>) -> Self {
+        self.alphabet = alphabet.into();
+        self
+    }
+
+    /// Set the length in bytes after which the sampler tries to finish the parser.
+    pub fn with_target_length(mut self, target_length: usize) -> Self {
+        self.target_length = target_length;
+        self
+    }
+
+    /// Set the maximum length in bytes of a sample. If the parser is not finished after this many bytes, sampling fails.
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Get the parser this sampler generates examples for.
+    pub fn parser(&self) -> &P {
+        &self.parser
+    }
+
+    /// Generate a random example with the thread local random number generator.
+    pub fn sample(&self) -> ParseResult<ParserSample<P::Output>> {
+        self.sample_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Generate a random example with the given random number generator.
+    pub fn sample_with_rng(&self, rng: &mut impl Rng) -> ParseResult<ParserSample<P::Output>> {
+        let mut state = self.parser.create_parser_state();
+        let mut text = Vec::new();
+        let mut candidates = Vec::new();
+
+        while text.len() < self.max_length {
+            candidates.clear();
+            candidates.extend(
+                self.parser
+                    .expected_next(&state)
+                    .into_iter()
+                    .map(String::into_bytes),
+            );
+            candidates.extend(self.alphabet.iter().map(|byte| vec![*byte]));
+            candidates.shuffle(rng);
+
+            let finishing = text.len() >= self.target_length;
+            let mut next = None;
+            for candidate in &candidates {
+                let Ok(result) = self.parser.parse(&state, candidate) else {
+                    continue;
+                };
+                let finished = matches!(result, ParseStatus::Finished { .. });
+                // Prefer inputs that finish the parser or that are not part of a word once we are past the target length
+                let preferred = finished || !candidate.iter().all(u8::is_ascii_alphanumeric);
+                let replace = match &next {
+                    None => true,
+                    Some((_, _, was_finished, was_preferred)) => {
+                        finishing && ((finished && !was_finished) || (preferred && !was_preferred))
+                    }
+                };
+                if replace {
+                    next = Some((candidate, result, finished, preferred));
+                }
+                // If we aren't trying to finish, the first valid input is a random valid input
+                if !finishing || finished {
+                    break;
+                }
+            }
+
+            let Some((candidate, result, _, _)) = next else {
+                return Err(ParseDiagnostic::new(String::from_utf8_lossy(&text))
+                    .with_expected(self.parser.expected_next(&state))
+                    .into());
+            };
+            text.extend_from_slice(candidate);
+            let mut unparsed = remaining_len(&result);
+            let mut result = result.without_remaining();
+
+            // Add any text the parser requires next
+            loop {
+                match result {
+                    ParseStatus::Finished { result, .. } => {
+                        return Ok(ParserSample {
+                            text: String::from_utf8_lossy(&text).into_owned(),
+                            remaining: String::from_utf8_lossy(&text[text.len() - unparsed..])
+                                .into_owned(),
+                            output: result,
+                        });
+                    }
+                    ParseStatus::Incomplete {
+                        new_state,
+                        required_next,
+                    } => {
+                        if required_next.is_empty() {
+                            state = new_state;
+                            break;
+                        }
+                        let required_next = required_next.into_owned();
+                        text.extend_from_slice(required_next.as_bytes());
+                        let next = self.parser.parse(&new_state, required_next.as_bytes())?;
+                        unparsed = remaining_len(&next);
+                        result = next.without_remaining();
+                    }
+                }
+            }
+        }
+
+        Err(ParserError::msg(format!(
+            "The parser did not finish within {} bytes",
+            self.max_length
+        )))
+    }
+}
+
+/// Get the number of bytes of the last input a parser did not consume.
+fn remaining_len<P, R>(status: &ParseStatus<P, R>) -> usize {
+    match status {
+        ParseStatus::Finished { remaining, .. } => remaining.len(),
+        ParseStatus::Incomplete { .. } => 0,
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod strategy {
+    use std::{fmt::Debug, sync::Arc};
+
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{CreateParserState, Parse, ParseStatus, Parser, ParserSample, ParserSampler};
+
+    /// Create a [`proptest`] strategy that generates samples the parser accepts.
+    pub fn parser_strategy<P>(
+        sampler: ParserSampler<P>,
+    ) -> impl Strategy<Value = ParserSample<P::Output>>
+    where
+        P: CreateParserState + Send + Sync + 'static,
+        P::Output: Debug,
+    {
+        let sampler = Arc::new(sampler);
+        any::<u64>().prop_filter_map("the parser did not finish", move |seed| {
+            sampler
+                .sample_with_rng(&mut StdRng::seed_from_u64(seed))
+                .ok()
+        })
+    }
+
+    /// Create a [`proptest`] strategy that generates samples that the parser for `T` accepts.
+    pub fn parse_strategy<T: Parse + Debug + 'static>() -> impl Strategy<Value = ParserSample<T>> {
+        parser_strategy(ParserSampler::new(T::new_parser()))
+    }
+
+    /// Parse the text of a sample with the parser for `T` and check that the parser produces the output of the sample and leaves the same text unparsed.
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    /// use proptest::prelude::*;
+    ///
+    /// #[derive(Parse, Clone, Debug, PartialEq)]
+    /// enum Color {
+    ///     Red,
+    ///     Green,
+    /// }
+    ///
+    /// proptest!(|(sample in parse_strategy::<Color>())| {
+    ///     check_round_trip(&sample)?;
+    /// });
+    /// ```
+    pub fn check_round_trip<T: Parse + PartialEq + Debug>(
+        sample: &ParserSample<T>,
+    ) -> Result<(), TestCaseError> {
+        let parser = T::new_parser();
+        let state = parser.create_parser_state();
+        match parser.parse(&state, sample.text.as_bytes()) {
+            Ok(ParseStatus::Finished { result, remaining }) => {
+                prop_assert_eq!(&result, &sample.output, "parsing {:?}", sample.text);
+                prop_assert_eq!(
+                    remaining,
+                    sample.remaining.as_bytes(),
+                    "parsing {:?}",
+                    sample.text
+                );
+                Ok(())
+            }
+            Ok(ParseStatus::Incomplete { .. }) => Err(TestCaseError::fail(format!(
+                "The parser did not finish parsing {:?}",
+                sample.text
+            ))),
+            Err(err) => Err(TestCaseError::fail(format!(
+                "The parser rejected {:?}: {}",
+                sample.text, &*err
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+pub use strategy::*;
+
+#[test]
+fn sampled_text_parses() {
+    use crate::{IntegerParser, LiteralParser, Parser, ParserExt, StringParser};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let parser = LiteralParser::new("{ \"name\": ")
+        .ignore_output_then(StringParser::new(1..=20))
+        .then_literal(", \"age\": ")
+        .then(IntegerParser::new(0..=100))
+        .then_literal(" }");
+    let sampler = ParserSampler::new(parser.clone());
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for _ in 0..100 {
+        let sample = sampler.sample_with_rng(&mut rng).unwrap();
+        assert!(sample.text.starts_with("{ \"name\": \""));
+        assert!(sample.text.ends_with(" }"));
+        let state = parser.create_parser_state();
+        let result = parser.parse(&state, sample.text.as_bytes()).unwrap();
+        assert_eq!(
+            result,
+            ParseStatus::Finished {
+                result: sample.output,
+                remaining: &[]
+            }
+        );
+    }
+}
+
+#[test]
+fn sampler_reports_dead_ends() {
+    use crate::LiteralParser;
+
+    let sampler = ParserSampler::new(LiteralParser::new("héllo")).with_alphabet(*b"abc");
+    // The literal is always expected next, so the sampler can finish it even though it is not in the alphabet
+    assert_eq!(sampler.sample().unwrap().text, "héllo");
+
+    let sampler = ParserSampler::new(crate::IntegerParser::new(0..=9)).with_alphabet(*b"abc");
+    assert!(sampler.sample().is_err());
+}
+
+#[test]
+fn sample_remaining() {
+    use crate::{IntegerParser, Parser};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // An integer is only finished once the parser sees a byte that is not a digit
+    let parser = IntegerParser::new(0..=1000);
+    let sampler = ParserSampler::new(parser.clone());
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..20 {
+        let sample = sampler.sample_with_rng(&mut rng).unwrap();
+        let state = parser.create_parser_state();
+        let ParseStatus::Finished { result, remaining } =
+            parser.parse(&state, sample.text.as_bytes()).unwrap()
+        else {
+            panic!("the sample {:?} should finish the parser", sample.text);
+        };
+        assert_eq!(result, sample.output);
+        assert_eq!(remaining, sample.remaining.as_bytes());
+        assert!(sample.text.ends_with(&sample.remaining));
+    }
+}
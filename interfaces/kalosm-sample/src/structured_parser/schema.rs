@@ -236,6 +236,26 @@ impl OneOfSchema {
         f: &mut std::fmt::Formatter<'_>,
         description: Option<&str>,
     ) -> std::fmt::Result {
+        // Enums that are parsed from a format other than JSON are displayed as one template for each variant
+        let templates = self
+            .one_of
+            .iter()
+            .map(|schema| match schema {
+                SchemaType::Object(object) if object.format != SchemaFormat::Json => Some(object),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|templates| !templates.is_empty());
+        if let Some(templates) = templates {
+            for (i, template) in templates.iter().enumerate() {
+                if i > 0 {
+                    f.write_str("\nor\n\n")?;
+                }
+                template.write_template(f, description.filter(|_| i == 0))?;
+            }
+            return Ok(());
+        }
+
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
@@ -725,12 +745,27 @@ fn test_array_schema() {
     assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\"\n\t},\n\t\"unevaluatedItems\": false\n}");
 }
 
+/// The syntax of the text an object is parsed from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// The object is parsed from JSON and displayed as a JSON schema
+    #[default]
+    Json,
+    /// The object is parsed from one `name: "value"` pair per line and displayed as a YAML template
+    Yaml,
+    /// The object is parsed from one `<name>value</name>` element per line and displayed as an XML template
+    Xml,
+    /// The object is parsed from one `name: value` pair per line and displayed as a template with unquoted strings
+    Lines,
+}
+
 /// A schema for an object
 #[derive(Debug, Clone)]
 pub struct JsonObjectSchema {
     title: Option<String>,
     description: Option<&'static str>,
     properties: Vec<JsonPropertySchema>,
+    format: SchemaFormat,
 }
 
 impl JsonObjectSchema {
@@ -740,9 +775,21 @@ impl JsonObjectSchema {
             title: None,
             description: None,
             properties: properties.into_iter().collect(),
+            format: SchemaFormat::Json,
         }
     }
 
+    /// Set the syntax the object is parsed from. Objects with a format other than [`SchemaFormat::Json`] are displayed as a template in that format instead of a JSON schema (defaults to [`SchemaFormat::Json`])
+    pub fn with_format(mut self, format: SchemaFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Get the syntax the object is parsed from
+    pub fn format(&self) -> SchemaFormat {
+        self.format
+    }
+
     /// Set the title of the object
     pub fn with_title(mut self, title: impl ToString) -> Self {
         self.title = Some(title.to_string());
@@ -760,6 +807,9 @@ impl JsonObjectSchema {
         f: &mut std::fmt::Formatter<'_>,
         description: Option<&str>,
     ) -> std::fmt::Result {
+        if self.format != SchemaFormat::Json {
+            return self.write_template(f, description);
+        }
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
@@ -813,6 +863,88 @@ impl JsonObjectSchema {
     }
 }
 
+impl JsonObjectSchema {
+    /// Write an example of the object in the format it is parsed from with a placeholder for each value
+    fn write_template(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        description: Option<&str>,
+    ) -> std::fmt::Result {
+        let comment = |f: &mut dyn std::fmt::Write, text: &str| match self.format {
+            SchemaFormat::Xml => writeln!(f, "<!-- {} -->", text.replace('\n', " ")),
+            _ => text.lines().try_for_each(|line| writeln!(f, "# {line}")),
+        };
+        for description in description.into_iter().chain(self.description) {
+            comment(f, description)?;
+        }
+        let element = self
+            .title
+            .as_ref()
+            .filter(|_| self.format == SchemaFormat::Xml);
+        if let Some(title) = element {
+            writeln!(f, "<{title}>")?;
+        }
+        for property in &self.properties {
+            if let Some(description) = property.description {
+                comment(f, description)?;
+            }
+            let name = &property.name;
+            match &property.ty {
+                // Nested objects are written in their own format as an indented block
+                SchemaType::Object(object) => {
+                    match self.format {
+                        SchemaFormat::Xml => writeln!(f, "<{name}>")?,
+                        _ => writeln!(f, "{name}:")?,
+                    }
+                    let nested = object.to_string();
+                    for line in nested.lines() {
+                        writeln!(f, "  {line}")?;
+                    }
+                    if self.format == SchemaFormat::Xml {
+                        writeln!(f, "</{name}>")?;
+                    }
+                }
+                ty => {
+                    let value = template_value(ty, self.format);
+                    match self.format {
+                        SchemaFormat::Xml => writeln!(f, "<{name}>{value}</{name}>")?,
+                        _ => writeln!(f, "{name}: {value}")?,
+                    }
+                }
+            }
+        }
+        if let Some(title) = element {
+            writeln!(f, "</{title}>")?;
+        }
+        Ok(())
+    }
+}
+
+/// A placeholder for a value in a template. Simple values are described by their type and anything else falls back to the JSON schema of the value
+fn template_value(ty: &SchemaType, format: SchemaFormat) -> String {
+    let literal = |literal: &SchemaLiteral| match literal {
+        // Tags are written without quotes in every format other than JSON
+        SchemaLiteral::String(string) => string.clone(),
+        literal => literal.to_string(),
+    };
+    match ty {
+        SchemaType::String(_) if format == SchemaFormat::Yaml => "\"string\"".to_string(),
+        SchemaType::String(_) => "string".to_string(),
+        SchemaType::Number(_) => "number".to_string(),
+        SchemaType::Integer(_) => "integer".to_string(),
+        SchemaType::Boolean(_) => "true | false".to_string(),
+        SchemaType::Const(schema) => literal(&schema.value),
+        SchemaType::Enum(schema) => schema
+            .variants
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" | "),
+        SchemaType::Null => "null".to_string(),
+        ty => ty.to_string().replace('\n', " ").replace('\t', ""),
+    }
+}
+
 impl Display for JsonObjectSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.display_with_description(f, None)
@@ -851,6 +983,7 @@ fn test_object_schema() {
                 }),
             },
         ],
+        format: SchemaFormat::Json,
     };
 
     assert_eq!(schema.to_string(), "{\n\t\"title\": \"Person\",\n\t\"description\": \"A person\",\n\t\"type\": \"object\",\n\t\"properties\": {\n\t\t\"name\": {\n\t\t\t\"type\": \"string\",\n\t\t\t\"minLength\": 1,\n\t\t\t\"maxLength\": 10\n\t\t},\n\t\t\"age\": {\n\t\t\t\"type\": \"number\",\n\t\t\t\"minimum\": 0,\n\t\t\t\"maximum\": 100\n\t\t},\n\t\t\"height\": {\n\t\t\t\"type\": \"number\",\n\t\t\t\"minimum\": 0,\n\t\t\t\"maximum\": 500\n\t\t}\n\t},\n\t\"required\": [\"name\", \"age\"],\n\t\"additionalProperties\": false\n}");
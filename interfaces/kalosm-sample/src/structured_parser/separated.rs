@@ -182,7 +182,7 @@ impl<P: CreateParserState, S: CreateParserState> Parser for SeparatedParser<P, S
                                     remaining,
                                 });
                             } else {
-                                crate::bail!(e);
+                                return Err(e.with_prefix(&input[..input.len() - remaining.len()]));
                             }
                         }
                     }
@@ -211,6 +211,7 @@ impl<P: CreateParserState, S: CreateParserState> Parser for SeparatedParser<P, S
                                     }) => required_next = Some(new_required_next),
                                     _ => required_next = None,
                                 }
+                                state.last_state = SeparatedItemState::Item(item_state);
                                 break;
                             }
                             state.last_state = SeparatedItemState::Item(item_state);
@@ -231,7 +232,7 @@ impl<P: CreateParserState, S: CreateParserState> Parser for SeparatedParser<P, S
                                     remaining,
                                 });
                             } else {
-                                crate::bail!(e);
+                                return Err(e.with_prefix(&input[..input.len() - remaining.len()]));
                             }
                         }
                     }
@@ -317,3 +318,21 @@ fn repeat_parser() {
         panic!("expected incomplete");
     }
 }
+
+#[test]
+fn separated_parser_split_after_separator() {
+    use crate::{CreateParserState, IntegerParser, LiteralParser};
+
+    // Splitting the input right after a separator must not make the parser parse the separator again
+    let parser = SeparatedParser::new(IntegerParser::new(1..=3), LiteralParser::from(","), 1..=3);
+    let state = parser.create_parser_state();
+    let (state, required_next) = parser.parse(&state, b"1,").unwrap().unwrap_incomplete();
+    assert_eq!(required_next, "");
+    assert_eq!(
+        parser.parse(&state, b"2,3"),
+        Ok(ParseStatus::Finished {
+            result: vec![1, 2, 3],
+            remaining: b"",
+        })
+    );
+}
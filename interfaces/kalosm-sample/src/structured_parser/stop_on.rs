@@ -1,4 +1,4 @@
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{CreateParserState, ParseStatus, Parser, ParserError};
 
 type CharFilter = fn(char) -> bool;
 
@@ -96,7 +96,15 @@ impl<S: AsRef<str>, F: Fn(char) -> bool + 'static> Parser for StopOn<S, F> {
 
         for (i, input_char) in input_str.char_indices() {
             if !(self.character_filter)(input_char) {
-                crate::bail!(StopOnParseError);
+                return Err(ParserError::from(StopOnParseError).rejected_after(
+                    &input[..i],
+                    vec![self
+                        .literal
+                        .as_ref()
+                        .get(new_offset..)
+                        .unwrap_or_default()
+                        .to_string()],
+                ));
             }
 
             let literal_char = literal_iter.next();
@@ -127,6 +135,10 @@ impl<S: AsRef<str>, F: Fn(char) -> bool + 'static> Parser for StopOn<S, F> {
             required_next: "".into(),
         })
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        Some(state.text.clone())
+    }
 }
 
 #[test]
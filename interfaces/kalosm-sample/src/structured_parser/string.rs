@@ -1,4 +1,4 @@
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{CreateParserState, ParseStatus, Parser, ParserError};
 
 type CharFilter = fn(char) -> bool;
 
@@ -116,23 +116,27 @@ impl<F: Fn(char) -> bool + 'static> Parser for StringParser<F> {
             mut next_char_escaped,
         } = state.clone();
 
+        let rejected = |index: usize, expected: Vec<String>| {
+            ParserError::from(StringParseError).rejected_after(&input[..index], expected)
+        };
+
         for (i, byte) in input.iter().enumerate() {
             match progress {
                 StringParserProgress::BeforeQuote => {
                     if *byte == b'"' {
                         progress = StringParserProgress::InString;
                     } else {
-                        crate::bail!(StringParseError);
+                        return Err(rejected(i, vec!["\"".to_string()]));
                     }
                 }
                 StringParserProgress::InString => {
                     let byte_unescaped_quote = !state.next_char_escaped && *byte == b'"';
                     if !byte_unescaped_quote && !(self.character_filter)(*byte as char) {
-                        crate::bail!(StringParseError);
+                        return Err(rejected(i, Vec::new()));
                     }
 
                     if string.len() == *self.len_range.end() && !byte_unescaped_quote {
-                        crate::bail!(StringParseError);
+                        return Err(rejected(i, vec!["\"".to_string()]));
                     }
 
                     if next_char_escaped {
@@ -140,7 +144,7 @@ impl<F: Fn(char) -> bool + 'static> Parser for StringParser<F> {
                         string.push(*byte as char);
                     } else if *byte == b'"' {
                         if !self.len_range.contains(&string.len()) {
-                            crate::bail!(StringParseError);
+                            return Err(rejected(i, Vec::new()));
                         }
                         return Ok(ParseStatus::Finished {
                             remaining: &input[i + 1..],
@@ -164,6 +168,20 @@ impl<F: Fn(char) -> bool + 'static> Parser for StringParser<F> {
             required_next: "".into(),
         })
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        match state.progress {
+            StringParserProgress::BeforeQuote => None,
+            StringParserProgress::InString => Some(state.string.clone()),
+        }
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match state.progress {
+            StringParserProgress::BeforeQuote => vec!["\"".to_string()],
+            StringParserProgress::InString => Vec::new(),
+        }
+    }
 }
 
 #[test]
@@ -62,7 +62,12 @@ impl<P1: Parser, P2: CreateParserState> Parser for SequenceParser<P1, P2> {
                         remaining,
                     } => {
                         let second_parser_state = self.parser2.create_parser_state();
-                        let result = self.parser2.parse(&second_parser_state, remaining)?;
+                        let result = self
+                            .parser2
+                            .parse(&second_parser_state, remaining)
+                            .map_err(|err| {
+                                err.with_prefix(&input[..input.len() - remaining.len()])
+                            })?;
                         match result {
                             ParseStatus::Finished { result, remaining } => {
                                 Ok(ParseStatus::Finished {
@@ -115,6 +120,22 @@ impl<P1: Parser, P2: CreateParserState> Parser for SequenceParser<P1, P2> {
             }
         }
     }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        match state {
+            SequenceParserState::FirstParser(_) => None,
+            SequenceParserState::SecondParser(p2, o1) => {
+                self.parser2.partial_output(p2).map(|o2| (o1.clone(), o2))
+            }
+        }
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match state {
+            SequenceParserState::FirstParser(p1) => self.parser1.expected_next(p1),
+            SequenceParserState::SecondParser(p2, _) => self.parser2.expected_next(p2),
+        }
+    }
 }
 
 #[test]
@@ -249,7 +270,12 @@ impl<P1: Parser, P2: CreateParserState, F: Fn(&P1::Output) -> P2> Parser for The
                     } => {
                         let parser2 = Arc::new((self.parser_fn)(&o1));
                         let second_parser_state = parser2.create_parser_state();
-                        let result = parser2.parse(&second_parser_state, remaining)?;
+                        let result =
+                            parser2
+                                .parse(&second_parser_state, remaining)
+                                .map_err(|err| {
+                                    err.with_prefix(&input[..input.len() - remaining.len()])
+                                })?;
                         match result {
                             ParseStatus::Finished { result, remaining } => {
                                 Ok(ParseStatus::Finished {
@@ -314,4 +340,15 @@ impl<P1: Parser, P2: CreateParserState, F: Fn(&P1::Output) -> P2> Parser for The
             }
         }
     }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        match state {
+            ThenLazyParserState::FirstParser(p1) => self.parser1.expected_next(p1),
+            ThenLazyParserState::SecondParser {
+                second_parser,
+                second_state,
+                ..
+            } => second_parser.expected_next(second_state),
+        }
+    }
 }
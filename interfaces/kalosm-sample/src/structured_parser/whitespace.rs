@@ -0,0 +1,125 @@
+use crate::{CreateParserState, ParseStatus, Parser};
+
+/// A parser that allows any amount of whitespace before another parser.
+///
+/// Leading whitespace that the inner parser accepts is passed to the inner parser. Any other leading whitespace is skipped.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct LeadingWhitespace<P> {
+    parser: P,
+}
+
+impl<P> LeadingWhitespace<P> {
+    /// Create a new parser that allows whitespace before the inner parser.
+    pub fn new(parser: P) -> Self {
+        Self { parser }
+    }
+}
+
+/// The state of a [`LeadingWhitespace`] parser.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct LeadingWhitespaceState<S> {
+    state: S,
+    started: bool,
+}
+
+impl<S> LeadingWhitespaceState<S> {
+    /// Create a new leading whitespace state from the state of the inner parser.
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            started: false,
+        }
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for LeadingWhitespace<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        LeadingWhitespaceState::new(self.parser.create_parser_state())
+    }
+}
+
+impl<P: Parser> Parser for LeadingWhitespace<P> {
+    type Output = P::Output;
+    type PartialState = LeadingWhitespaceState<P::PartialState>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut remaining = input;
+        if !state.started {
+            while let Some((&byte, rest)) = remaining.split_first() {
+                // If the inner parser accepts the whitespace, let it handle the rest of the input
+                if !byte.is_ascii_whitespace() || self.parser.parse(&state.state, &[byte]).is_ok() {
+                    break;
+                }
+                remaining = rest;
+            }
+            if remaining.is_empty() {
+                return Ok(ParseStatus::Incomplete {
+                    new_state: state.clone(),
+                    required_next: "".into(),
+                });
+            }
+        }
+
+        let result = self
+            .parser
+            .parse(&state.state, remaining)
+            .map_err(|err| err.with_prefix(&input[..input.len() - remaining.len()]))?;
+        Ok(result.map_state(|state| LeadingWhitespaceState {
+            state,
+            started: true,
+        }))
+    }
+
+    fn partial_output(&self, state: &Self::PartialState) -> Option<Self::Output> {
+        self.parser.partial_output(&state.state)
+    }
+
+    fn expected_next(&self, state: &Self::PartialState) -> Vec<String> {
+        self.parser.expected_next(&state.state)
+    }
+}
+
+#[test]
+fn leading_whitespace() {
+    use crate::{LiteralParser, StringParser};
+
+    let parser = LeadingWhitespace::new(LiteralParser::new("Hello"));
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b" \n Hello world").unwrap(),
+        ParseStatus::Finished {
+            result: (),
+            remaining: b" world"
+        }
+    );
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, b"  ").unwrap() else {
+        panic!("expected the parser to be incomplete");
+    };
+    assert_eq!(
+        parser.parse(&new_state, b" Hello").unwrap(),
+        ParseStatus::Finished {
+            result: (),
+            remaining: b""
+        }
+    );
+    assert!(parser.parse(&state, b" Hi").is_err());
+
+    // Whitespace the inner parser accepts is not skipped
+    let parser = LeadingWhitespace::new(LiteralParser::new(" Hello"));
+    let state = parser.create_parser_state();
+    assert!(parser.parse(&state, b" Hello").is_ok());
+
+    let parser = LeadingWhitespace::new(StringParser::new(0..=10));
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b"\n\"hi\"").unwrap(),
+        ParseStatus::Finished {
+            result: "hi".to_string(),
+            remaining: b""
+        }
+    );
+}
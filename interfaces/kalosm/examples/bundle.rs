@@ -0,0 +1,45 @@
+//! Provision an air-gapped machine with models.
+//!
+//! On a machine with internet access, export the models you need into a bundle directory:
+//! `cargo run --example bundle --features language -- export ./bundle llama-3-1-8b-chat bge-small-en`
+//!
+//! Then copy the directory to the offline machine and import it into the cache:
+//! `cargo run --example bundle --features language -- import ./bundle`
+//!
+//! Set `KALOSM_OFFLINE=1` on the offline machine to make sure models are only loaded from the cache.
+
+use kalosm::{Cache, ModelPreset};
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    let bundle = args.next();
+    match (command.as_deref(), bundle) {
+        (Some("export"), Some(bundle)) => {
+            for id in args {
+                let Some(preset) = ModelPreset::get(&id) else {
+                    anyhow::bail!("Unknown model {id}");
+                };
+                println!("Exporting {id}");
+                preset.export_bundle(&bundle).await?;
+            }
+        }
+        (Some("import"), Some(bundle)) => {
+            for source in Cache::default().import_bundle(&bundle).await? {
+                println!("Imported {source}");
+            }
+        }
+        _ => {
+            println!("Usage: bundle export <directory> <model id>...");
+            println!("       bundle import <directory>");
+            println!();
+            println!("Available models:");
+            for preset in ModelPreset::all() {
+                println!("  {}", preset.id());
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -9,6 +9,7 @@ use std::ops::RangeInclusive;
 use async_trait::async_trait;
 use kalosm_language::prelude::Bert;
 use kalosm_language::prelude::Embedder;
+use kalosm_language::prelude::{Model, ModelExt};
 
 /// A metric is a way to compare two pieces of data. It is used to evaluate the performance of a model.
 #[async_trait]
@@ -47,6 +48,65 @@ impl<S: ToString + Send + Sync> Metric<S> for BertDistance {
     }
 }
 
+/// A metric that compares how likely a language model finds two strings. It returns the ratio of the lower perplexity to the higher perplexity, so 1.0 means the model finds both strings equally likely.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use kalosm::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let llm = Llama::new().await?;
+///     let mut metric = PerplexityDistance::new(llm).with_prompt("The capital of France is");
+///     let mut cases = TestCases::new().with_case(" Paris".to_string(), " paris".to_string());
+///     println!("{}", cases.evaluate(&mut metric).await);
+///     Ok(())
+/// }
+/// ```
+pub struct PerplexityDistance<M> {
+    model: M,
+    prompt: String,
+}
+
+impl<M> PerplexityDistance<M> {
+    /// Create a new PerplexityDistance metric.
+    pub fn new(model: M) -> Self {
+        PerplexityDistance {
+            model,
+            prompt: String::new(),
+        }
+    }
+
+    /// Set the prompt the strings are scored after. Only the tokens in the strings are scored. (Defaults to no prompt)
+    pub fn with_prompt(mut self, prompt: impl ToString) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+}
+
+impl<M: Model> PerplexityDistance<M> {
+    async fn perplexity(&self, text: &str) -> f64 {
+        let likelihood = if self.prompt.is_empty() {
+            self.model.log_likelihood(text).await
+        } else {
+            self.model.score(&self.prompt, text).await
+        };
+        likelihood
+            .expect("Failed to score text with the model")
+            .perplexity()
+    }
+}
+
+#[async_trait]
+impl<M: Model, S: ToString + Send + Sync> Metric<S> for PerplexityDistance<M> {
+    async fn distance(&mut self, first: &S, other: &S) -> f64 {
+        let first = self.perplexity(&first.to_string()).await;
+        let other = self.perplexity(&other.to_string()).await;
+        first.min(other) / first.max(other)
+    }
+}
+
 /// A set of test cases to evaluate a model.
 pub struct TestCases<I> {
     name: String,
@@ -2,19 +2,27 @@
 #![doc = include_str!("../README.md")]
 
 pub use futures_util::StreamExt as _;
+pub use kalosm_common::{
+    Cache, CacheEntry, DownloadBackend, HttpBackend, PrunePolicy, S3Backend, S3Credentials,
+};
 pub use kalosm_streams::timed_stream::*;
 
 #[cfg(feature = "language")]
 pub mod language {
     #![doc = include_str!("../docs/language.md")]
     pub use kalosm_common::ModelLoadingProgress;
-    pub use kalosm_common::{accelerated_device_if_available, FileSource};
+    pub use kalosm_common::{
+        accelerated_device_if_available, FileSource, InferenceThreads, KvCacheQuantization,
+        ThreadPool, ThreadPoolBuilder,
+    };
     pub use kalosm_language::chat::*;
     pub use kalosm_language::context::*;
     pub use kalosm_language::kalosm_language_model::{
         Embedder as _, EmbedderExt as _, Model as _, ModelExt as _, *,
     };
-    pub use kalosm_language::kalosm_llama::{Llama, LlamaBuilder, LlamaSession, LlamaSource};
+    pub use kalosm_language::kalosm_llama::{
+        Llama, LlamaBuilder, LlamaPooling, LlamaSession, LlamaSource, LlamaSpace, RopeScaling,
+    };
     pub use kalosm_language::kalosm_sample::{self, *};
     pub use kalosm_language::prelude::Html;
     pub use kalosm_language::rbert::{Bert, BertBuilder, BertSource, BertSpace};
@@ -32,6 +40,7 @@ pub mod language {
 pub mod sound {
     #![doc = include_str!("../docs/sound.md")]
     pub use futures_util::StreamExt as _;
+    pub use kalosm_common::Cache;
     pub use kalosm_sound::*;
     pub use kalosm_streams::text_stream::*;
     pub use kalosm_streams::timed_stream::*;
@@ -53,6 +62,11 @@ mod prompt_annealing;
 #[cfg(feature = "language")]
 pub use prompt_annealing::*;
 
+#[cfg(any(feature = "language", feature = "sound", feature = "vision"))]
+mod registry;
+#[cfg(any(feature = "language", feature = "sound", feature = "vision"))]
+pub use registry::*;
+
 #[cfg(feature = "surrealdb")]
 mod surrealdb_integration;
 #[cfg(feature = "surrealdb")]
@@ -403,11 +403,7 @@ where
     let examples_tokens: usize = examples
         .iter()
         .filter_map(|example| {
-            llm.tokenizer()
-                .encode(example.input, false)
-                .ok()
-                .map(|x| x.len())
-                .and_then(|x| Some(x + llm.tokenizer().encode(example.output, false).ok()?.len()))
+            Some(llm.count_tokens(example.input).ok()? + llm.count_tokens(example.output).ok()?)
         })
         .sum();
 
@@ -0,0 +1,710 @@
+//! A registry of every model preset kalosm knows about, and specs that select a model from a config file.
+
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use kalosm_common::{Cache, FileSource};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "language")]
+use kalosm_language::{
+    kalosm_language_model::{AnyModelExt, ChatMarkers, DynEmbedder, DynModel, EmbedderExt},
+    kalosm_llama::{Llama, LlamaBuilder, LlamaSource},
+    rbert::{Bert, BertBuilder, BertSource},
+    rphi::{Phi, PhiBuilder, PhiSource},
+};
+#[cfg(feature = "sound")]
+use kalosm_sound::{Whisper, WhisperBuilder, WhisperSource};
+#[cfg(feature = "vision")]
+use kalosm_vision::{Ocr, OcrBuilder, OcrSource};
+
+/// The family of a model. Every model in a family is loaded with the same builder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelFamily {
+    /// A text generation model loaded with [`Llama`]. This includes Mistral, Qwen, Phi-3 and other models in the GGUF format.
+    Llama,
+    /// A text generation model loaded with [`Phi`].
+    Phi,
+    /// An embedding model loaded with [`Bert`].
+    Bert,
+    /// A transcription model loaded with [`Whisper`].
+    Whisper,
+    /// A text recognition model loaded with [`Ocr`].
+    Ocr,
+}
+
+impl ModelFamily {
+    /// Get the name of the family as it is written in model specs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelFamily::Llama => "llama",
+            ModelFamily::Phi => "phi",
+            ModelFamily::Bert => "bert",
+            ModelFamily::Whisper => "whisper",
+            ModelFamily::Ocr => "ocr",
+        }
+    }
+}
+
+impl Display for ModelFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PresetSource {
+    #[cfg(feature = "language")]
+    Llama(fn() -> LlamaSource),
+    #[cfg(feature = "language")]
+    Phi(fn() -> PhiSource),
+    #[cfg(feature = "language")]
+    Bert(fn() -> BertSource),
+    #[cfg(feature = "sound")]
+    Whisper(WhisperSource),
+    #[cfg(feature = "vision")]
+    Ocr(fn() -> OcrSource),
+}
+
+/// A model preset with metadata about the model. Presets are only listed for the families enabled with the `language`, `sound` and `vision` features.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::ModelPreset;
+///
+/// for preset in ModelPreset::all() {
+///     println!(
+///         "{} ({}, {} parameters, {})",
+///         preset.id(),
+///         preset.family(),
+///         preset.parameters(),
+///         preset.license()
+///     );
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ModelPreset {
+    id: &'static str,
+    family: ModelFamily,
+    parameters: &'static str,
+    context_length: Option<usize>,
+    license: &'static str,
+    source: PresetSource,
+}
+
+impl ModelPreset {
+    const fn new(
+        id: &'static str,
+        family: ModelFamily,
+        parameters: &'static str,
+        context_length: Option<usize>,
+        license: &'static str,
+        source: PresetSource,
+    ) -> Self {
+        Self {
+            id,
+            family,
+            parameters,
+            context_length,
+            license,
+            source,
+        }
+    }
+
+    /// Get every known preset.
+    pub fn all() -> impl Iterator<Item = &'static ModelPreset> {
+        let presets: [&'static [ModelPreset]; 3] = [
+            #[cfg(feature = "language")]
+            LANGUAGE_PRESETS,
+            #[cfg(not(feature = "language"))]
+            &[],
+            #[cfg(feature = "sound")]
+            SOUND_PRESETS,
+            #[cfg(not(feature = "sound"))]
+            &[],
+            #[cfg(feature = "vision")]
+            VISION_PRESETS,
+            #[cfg(not(feature = "vision"))]
+            &[],
+        ];
+        presets.into_iter().flatten()
+    }
+
+    /// Get every known preset in a family.
+    pub fn in_family(family: ModelFamily) -> impl Iterator<Item = &'static ModelPreset> {
+        Self::all().filter(move |preset| preset.family == family)
+    }
+
+    /// Find a preset by id.
+    pub fn get(id: &str) -> Option<&'static ModelPreset> {
+        Self::all().find(|preset| preset.id == id)
+    }
+
+    /// Get the id of the preset. This is the id used in model specs.
+    pub fn id(&self) -> &'static str {
+        self.id
+    }
+
+    /// Get the family of the model.
+    pub fn family(&self) -> ModelFamily {
+        self.family
+    }
+
+    /// Get the number of parameters in the model, for example "7B".
+    pub fn parameters(&self) -> &'static str {
+        self.parameters
+    }
+
+    /// Get the number of tokens the model was trained to attend to. This is `None` for models that don't process text.
+    pub fn context_length(&self) -> Option<usize> {
+        self.context_length
+    }
+
+    /// Get the license the model weights are released under.
+    pub fn license(&self) -> &'static str {
+        self.license
+    }
+
+    /// Get the chat markers for the model if it is a chat model.
+    #[cfg(feature = "language")]
+    pub fn chat_markers(&self) -> Option<ChatMarkers> {
+        match self.source {
+            PresetSource::Llama(source) => source().chat_markers(),
+            PresetSource::Phi(source) => source().chat_markers(),
+            _ => None,
+        }
+    }
+
+    /// Get the files the model loads.
+    pub fn files(&self) -> Vec<FileSource> {
+        match self.source {
+            #[cfg(feature = "language")]
+            PresetSource::Llama(source) => source().files(),
+            #[cfg(feature = "language")]
+            PresetSource::Phi(source) => source().files(),
+            #[cfg(feature = "language")]
+            PresetSource::Bert(source) => source().files(),
+            #[cfg(feature = "sound")]
+            PresetSource::Whisper(source) => Whisper::builder().with_source(source).files(),
+            #[cfg(feature = "vision")]
+            PresetSource::Ocr(source) => source().files(),
+        }
+    }
+
+    /// Check if every file the model loads is already downloaded.
+    pub fn downloaded(&self) -> bool {
+        self.files().iter().all(FileSource::downloaded)
+    }
+
+    /// Get the total size of the files the model loads in bytes. Files that are not downloaded yet are looked up on Hugging Face.
+    pub async fn download_size(&self) -> anyhow::Result<u64> {
+        let mut size = 0;
+        for file in self.files() {
+            size += file.file_size().await?;
+        }
+        Ok(size)
+    }
+
+    /// Copy the files the model loads into a bundle directory, downloading them first if needed. The bundle can be copied to a machine without internet access and loaded into its cache with [`Cache::import_bundle`].
+    pub async fn export_bundle(&self, bundle: impl AsRef<Path>) -> anyhow::Result<()> {
+        Cache::default().export_bundle(&self.files(), bundle).await
+    }
+
+    /// Create a spec that loads this preset.
+    pub fn spec(&self) -> ModelSpec {
+        ModelSpec::preset(self.id)
+    }
+}
+
+macro_rules! presets {
+    ($($id:literal $family:ident($source:expr) $parameters:literal $context_length:expr, $license:expr;)*) => {
+        &[$(ModelPreset::new(
+            $id,
+            ModelFamily::$family,
+            $parameters,
+            $context_length,
+            $license,
+            PresetSource::$family($source),
+        )),*]
+    };
+}
+
+#[cfg(feature = "language")]
+const LLAMA_2: &str = "Llama 2 Community License";
+#[cfg(feature = "language")]
+const LLAMA_3: &str = "Llama 3 Community License";
+#[cfg(feature = "language")]
+const LLAMA_3_1: &str = "Llama 3.1 Community License";
+#[cfg(feature = "language")]
+const LLAMA_3_2: &str = "Llama 3.2 Community License";
+
+#[cfg(feature = "language")]
+const LANGUAGE_PRESETS: &[ModelPreset] = presets! {
+    "mistral-7b" Llama(LlamaSource::mistral_7b) "7B" Some(32768), "Apache-2.0";
+    "mistral-7b-instruct" Llama(LlamaSource::mistral_7b_instruct) "7B" Some(32768), "Apache-2.0";
+    "mistral-7b-instruct-2" Llama(LlamaSource::mistral_7b_instruct_2) "7B" Some(32768), "Apache-2.0";
+    "neural-hermes-2-5-mistral-7b" Llama(LlamaSource::neural_hermes_2_5_mistral_7b) "7B" Some(32768), "Apache-2.0";
+    "neural-chat-7b-v3-3" Llama(LlamaSource::neural_chat_7b_v3_3) "7B" Some(32768), "Apache-2.0";
+    "zephyr-7b-alpha" Llama(LlamaSource::zephyr_7b_alpha) "7B" Some(32768), "MIT";
+    "zephyr-7b-beta" Llama(LlamaSource::zephyr_7b_beta) "7B" Some(32768), "MIT";
+    "open-chat-7b" Llama(LlamaSource::open_chat_7b) "7B" Some(8192), "Apache-2.0";
+    "starling-7b-alpha" Llama(LlamaSource::starling_7b_alpha) "7B" Some(8192), "Apache-2.0";
+    "starling-7b-beta" Llama(LlamaSource::starling_7b_beta) "7B" Some(8192), "Apache-2.0";
+    "wizard-lm-7b-v2" Llama(LlamaSource::wizard_lm_7b_v2) "7B" Some(32768), "Apache-2.0";
+    "tiny-llama-1-1b-chat" Llama(LlamaSource::tiny_llama_1_1b_chat) "1.1B" Some(2048), "Apache-2.0";
+    "tiny-llama-1-1b" Llama(LlamaSource::tiny_llama_1_1b) "1.1B" Some(2048), "Apache-2.0";
+    "phi-3-mini-4k-instruct" Llama(LlamaSource::phi_3_mini_4k_instruct) "3.8B" Some(4096), "MIT";
+    "phi-3-1-mini-4k-instruct" Llama(LlamaSource::phi_3_1_mini_4k_instruct) "3.8B" Some(4096), "MIT";
+    "phi-3-5-mini-4k-instruct" Llama(LlamaSource::phi_3_5_mini_4k_instruct) "3.8B" Some(131072), "MIT";
+    "llama-7b" Llama(LlamaSource::llama_7b) "7B" Some(4096), LLAMA_2;
+    "llama-8b" Llama(LlamaSource::llama_8b) "8B" Some(8192), LLAMA_3;
+    "llama-8b-chat" Llama(LlamaSource::llama_8b_chat) "8B" Some(8192), LLAMA_3;
+    "llama-3-1-8b-chat" Llama(LlamaSource::llama_3_1_8b_chat) "8B" Some(131072), LLAMA_3_1;
+    "llama-8b-chat-q8" Llama(LlamaSource::llama_8b_chat_q8) "8B" Some(8192), LLAMA_3;
+    "llama-8b-sppo-iter3" Llama(LlamaSource::llama_8b_sppo_iter3) "8B" Some(8192), LLAMA_3;
+    "llama-3-2-1b-chat" Llama(LlamaSource::llama_3_2_1b_chat) "1B" Some(131072), LLAMA_3_2;
+    "llama-3-2-3b-chat" Llama(LlamaSource::llama_3_2_3b_chat) "3B" Some(131072), LLAMA_3_2;
+    "llama-13b" Llama(LlamaSource::llama_13b) "13B" Some(4096), LLAMA_2;
+    "llama-70b" Llama(LlamaSource::llama_70b) "70B" Some(4096), LLAMA_2;
+    "llama-7b-chat" Llama(LlamaSource::llama_7b_chat) "7B" Some(4096), LLAMA_2;
+    "llama-13b-chat" Llama(LlamaSource::llama_13b_chat) "13B" Some(4096), LLAMA_2;
+    "llama-70b-chat" Llama(LlamaSource::llama_70b_chat) "70B" Some(4096), LLAMA_2;
+    "llama-7b-code" Llama(LlamaSource::llama_7b_code) "7B" Some(16384), LLAMA_2;
+    "llama-13b-code" Llama(LlamaSource::llama_13b_code) "13B" Some(16384), LLAMA_2;
+    "llama-34b-code" Llama(LlamaSource::llama_34b_code) "34B" Some(16384), LLAMA_2;
+    "solar-10-7b" Llama(LlamaSource::solar_10_7b) "10.7B" Some(4096), "Apache-2.0";
+    "solar-10-7b-instruct" Llama(LlamaSource::solar_10_7b_instruct) "10.7B" Some(4096), "CC-BY-NC-4.0";
+    "qwen-2-5-0-5b-instruct" Llama(LlamaSource::qwen_2_5_0_5b_instruct) "0.5B" Some(32768), "Apache-2.0";
+    "qwen-2-5-1-5b-instruct" Llama(LlamaSource::qwen_2_5_1_5b_instruct) "1.5B" Some(32768), "Apache-2.0";
+    "qwen-2-5-3b-instruct" Llama(LlamaSource::qwen_2_5_3b_instruct) "3B" Some(32768), "Qwen Research License";
+    "qwen-2-5-7b-instruct" Llama(LlamaSource::qwen_2_5_7b_instruct) "7B" Some(32768), "Apache-2.0";
+    "phi-v1" Phi(PhiSource::v1) "1.3B" Some(2048), "MIT";
+    "phi-v1-5" Phi(PhiSource::v1_5) "1.3B" Some(2048), "MIT";
+    "phi-v2" Phi(PhiSource::v2) "2.7B" Some(2048), "MIT";
+    "puffin-phi-v2" Phi(PhiSource::puffin_phi_v2) "1.3B" Some(2048), "MIT";
+    "dolphin-phi-v2" Phi(PhiSource::dolphin_phi_v2) "2.7B" Some(2048), "MIT";
+    "bge-large-en" Bert(BertSource::bge_large_en) "335M" Some(512), "MIT";
+    "bge-base-en" Bert(BertSource::bge_base_en) "109M" Some(512), "MIT";
+    "bge-small-en" Bert(BertSource::bge_small_en) "33M" Some(512), "MIT";
+    "mini-lm-l6-v2" Bert(BertSource::mini_lm_l6_v2) "22M" Some(512), "Apache-2.0";
+    "snowflake-arctic-embed-extra-small" Bert(BertSource::snowflake_arctic_embed_extra_small) "22M" Some(512), "Apache-2.0";
+    "snowflake-arctic-embed-small" Bert(BertSource::snowflake_arctic_embed_small) "33M" Some(512), "Apache-2.0";
+    "snowflake-arctic-embed-medium" Bert(BertSource::snowflake_arctic_embed_medium) "109M" Some(512), "Apache-2.0";
+    "snowflake-arctic-embed-medium-long" Bert(BertSource::snowflake_arctic_embed_medium_long) "137M" Some(8192), "Apache-2.0";
+    "snowflake-arctic-embed-large" Bert(BertSource::snowflake_arctic_embed_large) "335M" Some(512), "Apache-2.0";
+};
+
+#[cfg(feature = "sound")]
+const SOUND_PRESETS: &[ModelPreset] = presets! {
+    "whisper-tiny" Whisper(WhisperSource::Tiny) "39M" None, "MIT";
+    "whisper-quantized-tiny" Whisper(WhisperSource::QuantizedTiny) "39M" None, "MIT";
+    "whisper-tiny-en" Whisper(WhisperSource::TinyEn) "39M" None, "MIT";
+    "whisper-quantized-tiny-en" Whisper(WhisperSource::QuantizedTinyEn) "39M" None, "MIT";
+    "whisper-base" Whisper(WhisperSource::Base) "74M" None, "MIT";
+    "whisper-base-en" Whisper(WhisperSource::BaseEn) "74M" None, "MIT";
+    "whisper-small" Whisper(WhisperSource::Small) "244M" None, "MIT";
+    "whisper-small-en" Whisper(WhisperSource::SmallEn) "244M" None, "MIT";
+    "whisper-medium" Whisper(WhisperSource::Medium) "769M" None, "MIT";
+    "whisper-medium-en" Whisper(WhisperSource::MediumEn) "769M" None, "MIT";
+    "whisper-quantized-distil-medium-en" Whisper(WhisperSource::QuantizedDistilMediumEn) "394M" None, "MIT";
+    "whisper-large" Whisper(WhisperSource::Large) "1.55B" None, "MIT";
+    "whisper-large-v2" Whisper(WhisperSource::LargeV2) "1.55B" None, "MIT";
+    "whisper-distil-medium-en" Whisper(WhisperSource::DistilMediumEn) "394M" None, "MIT";
+    "whisper-distil-large-v2" Whisper(WhisperSource::DistilLargeV2) "756M" None, "MIT";
+    "whisper-distil-large-v3" Whisper(WhisperSource::DistilLargeV3) "756M" None, "MIT";
+    "whisper-quantized-distil-large-v3" Whisper(WhisperSource::QuantizedDistilLargeV3) "756M" None, "MIT";
+};
+
+#[cfg(feature = "vision")]
+const VISION_PRESETS: &[ModelPreset] = presets! {
+    "trocr-base-handwritten" Ocr(OcrSource::base) "334M" None, "MIT";
+    "trocr-large-handwritten" Ocr(OcrSource::large) "558M" None, "MIT";
+    "trocr-base-printed" Ocr(OcrSource::base_printed) "334M" None, "MIT";
+    "trocr-large-printed" Ocr(OcrSource::large_printed) "558M" None, "MIT";
+};
+
+/// A spec that selects a model. Specs can start from a [`ModelPreset`] and override some of its files, or load local files for a model family directly.
+///
+/// Specs can be read from TOML or JSON so the model a deployment uses can be changed without changing code:
+///
+/// ```toml
+/// # Start from a preset and load the weights from a local file
+/// preset = "llama-3-1-8b-chat"
+/// model = { local = "/models/llama-3.1-8b-instruct-q8.gguf" }
+/// context_length = 16384
+/// ```
+///
+/// ```toml
+/// # Load a model that isn't in the registry
+/// family = "llama"
+/// model = { huggingface = { model_id = "my-org/my-model-GGUF", revision = "main", file = "my-model-Q4_K_M.gguf" } }
+/// tokenizer = { local = "/models/my-model/tokenizer.json" }
+/// ```
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use kalosm::ModelSpec;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let spec = ModelSpec::from_file("model.toml")?;
+///     let model = spec.build_text_model().await?;
+///     let mut stream = model.stream_text("The capital of France is").await?;
+///     stream.to_std_out().await?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelSpec {
+    /// The id of the preset to start from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// The family of the model. This is required if no preset is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<ModelFamily>,
+    /// The model weights to load instead of the weights in the preset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<FileSource>,
+    /// The tokenizer to load instead of the tokenizer in the preset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<FileSource>,
+    /// The config file to load instead of the config in the preset. Only Bert and Ocr models use a config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<FileSource>,
+    /// The maximum number of tokens the model attends to. Only Llama models support changing the context length.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+}
+
+impl ModelSpec {
+    /// Create a spec that loads a preset.
+    pub fn preset(id: impl ToString) -> Self {
+        Self {
+            preset: Some(id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Create a spec that loads a model in a family from the given weights. Other files the family needs must be set with [`Self::with_tokenizer`] and [`Self::with_config`].
+    pub fn new(family: ModelFamily, model: FileSource) -> Self {
+        Self {
+            family: Some(family),
+            model: Some(model),
+            ..Default::default()
+        }
+    }
+
+    /// Set the model weights to load.
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Set the tokenizer to load.
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Set the config file to load.
+    pub fn with_config(mut self, config: FileSource) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Set the maximum number of tokens the model attends to.
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+        self
+    }
+
+    /// Read a spec from a TOML string.
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Read a spec from a JSON string.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Read a spec from a file. Files with the `json` extension are read as JSON, and every other file is read as TOML.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&contents),
+            _ => Self::from_toml(&contents),
+        }
+    }
+
+    /// Get the preset this spec starts from, if any.
+    pub fn get_preset(&self) -> anyhow::Result<Option<&'static ModelPreset>> {
+        let Some(id) = &self.preset else {
+            return Ok(None);
+        };
+        match ModelPreset::get(id) {
+            Some(preset) => Ok(Some(preset)),
+            None => anyhow::bail!(
+                "unknown model preset {id:?}. The model family may not be enabled with a cargo feature"
+            ),
+        }
+    }
+
+    /// Get the family of the model this spec loads.
+    pub fn family(&self) -> anyhow::Result<ModelFamily> {
+        match (self.get_preset()?, self.family) {
+            (Some(preset), Some(family)) if preset.family != family => anyhow::bail!(
+                "the preset {} is a {} model, but the spec sets the family to {family}",
+                preset.id,
+                preset.family
+            ),
+            (Some(preset), _) => Ok(preset.family),
+            (None, Some(family)) => Ok(family),
+            (None, None) => anyhow::bail!("a model spec must set either a preset or a family"),
+        }
+    }
+
+    fn expect_family(&self, family: ModelFamily) -> anyhow::Result<Option<&'static ModelPreset>> {
+        let found = self.family()?;
+        if found != family {
+            anyhow::bail!("expected a {family} model, but the spec loads a {found} model");
+        }
+        self.get_preset()
+    }
+
+    fn unsupported(&self, family: ModelFamily, fields: &[&str]) -> anyhow::Result<()> {
+        let set_fields = [
+            ("model", self.model.is_some()),
+            ("tokenizer", self.tokenizer.is_some()),
+            ("config", self.config.is_some()),
+            ("context_length", self.context_length.is_some()),
+        ];
+        for (field, set) in set_fields {
+            if set && fields.contains(&field) {
+                anyhow::bail!("{family} models don't support setting `{field}` in a model spec");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "language", feature = "vision"))]
+fn required<'a>(
+    family: ModelFamily,
+    field: &str,
+    value: &'a Option<FileSource>,
+) -> anyhow::Result<&'a FileSource> {
+    value
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("{family} model specs without a preset must set `{field}`"))
+}
+
+#[cfg(feature = "language")]
+impl ModelSpec {
+    /// Create a [`LlamaBuilder`] for the spec.
+    pub fn llama_builder(&self) -> anyhow::Result<LlamaBuilder> {
+        let family = ModelFamily::Llama;
+        self.unsupported(family, &["config"])?;
+        let mut source = match self.expect_family(family)?.map(|preset| preset.source) {
+            Some(PresetSource::Llama(source)) => source(),
+            _ => LlamaSource::new(
+                required(family, "model", &self.model)?.clone(),
+                required(family, "tokenizer", &self.tokenizer)?.clone(),
+            ),
+        };
+        if let Some(model) = &self.model {
+            source = source.with_model(model.clone());
+        }
+        if let Some(tokenizer) = &self.tokenizer {
+            source = source.with_tokenizer(tokenizer.clone());
+        }
+        let mut builder = Llama::builder().with_source(source);
+        if let Some(context_length) = self.context_length {
+            builder = builder.with_context_length(context_length);
+        }
+        Ok(builder)
+    }
+
+    /// Create a [`PhiBuilder`] for the spec. Phi models must start from a preset because the preset sets the architecture of the model.
+    pub fn phi_builder(&self) -> anyhow::Result<PhiBuilder> {
+        let family = ModelFamily::Phi;
+        self.unsupported(family, &["config", "context_length"])?;
+        let Some(PresetSource::Phi(source)) =
+            self.expect_family(family)?.map(|preset| preset.source)
+        else {
+            anyhow::bail!("phi model specs must set a preset");
+        };
+        let mut source = source();
+        if let Some(model) = &self.model {
+            source = source.with_model(model.clone());
+        }
+        if let Some(tokenizer) = &self.tokenizer {
+            source = source.with_tokenizer(tokenizer.clone());
+        }
+        Ok(Phi::builder().with_source(source))
+    }
+
+    /// Create a [`BertBuilder`] for the spec.
+    pub fn bert_builder(&self) -> anyhow::Result<BertBuilder> {
+        let family = ModelFamily::Bert;
+        self.unsupported(family, &["context_length"])?;
+        let source = match self.expect_family(family)?.map(|preset| preset.source) {
+            Some(PresetSource::Bert(source)) => source(),
+            _ => {
+                required(family, "model", &self.model)?;
+                required(family, "tokenizer", &self.tokenizer)?;
+                required(family, "config", &self.config)?;
+                BertSource::default()
+            }
+        };
+        let mut source = source;
+        if let Some(model) = &self.model {
+            source = source.with_model(model.clone());
+        }
+        if let Some(tokenizer) = &self.tokenizer {
+            source = source.with_tokenizer(tokenizer.clone());
+        }
+        if let Some(config) = &self.config {
+            source = source.with_config(config.clone());
+        }
+        Ok(Bert::builder().with_source(source))
+    }
+
+    /// Build the text generation model the spec selects. The spec must select a Llama or Phi model.
+    pub async fn build_text_model(&self) -> anyhow::Result<DynModel> {
+        match self.family()? {
+            ModelFamily::Llama => Ok(self.llama_builder()?.build().await?.into_any_model()),
+            ModelFamily::Phi => Ok(self.phi_builder()?.build().await?.into_any_model()),
+            family => anyhow::bail!("{family} models can't generate text"),
+        }
+    }
+
+    /// Build the embedding model the spec selects. The spec must select a Bert or Llama model.
+    pub async fn build_embedding_model(&self) -> anyhow::Result<DynEmbedder> {
+        match self.family()? {
+            ModelFamily::Bert => Ok(self.bert_builder()?.build().await?.into_any_embedder()),
+            ModelFamily::Llama => Ok(self.llama_builder()?.build().await?.into_any_embedder()),
+            family => anyhow::bail!("{family} models can't embed text"),
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+impl ModelSpec {
+    /// Create a [`WhisperBuilder`] for the spec. Whisper models must be loaded from a preset.
+    pub fn whisper_builder(&self) -> anyhow::Result<WhisperBuilder> {
+        let family = ModelFamily::Whisper;
+        self.unsupported(family, &["model", "tokenizer", "config", "context_length"])?;
+        let Some(PresetSource::Whisper(source)) =
+            self.expect_family(family)?.map(|preset| preset.source)
+        else {
+            anyhow::bail!("whisper model specs must set a preset");
+        };
+        Ok(Whisper::builder().with_source(source))
+    }
+
+    /// Build the transcription model the spec selects.
+    pub async fn build_transcription_model(&self) -> anyhow::Result<Whisper> {
+        self.whisper_builder()?.build().await
+    }
+}
+
+#[cfg(feature = "vision")]
+impl ModelSpec {
+    /// Create an [`OcrBuilder`] for the spec.
+    pub fn ocr_builder(&self) -> anyhow::Result<OcrBuilder> {
+        let family = ModelFamily::Ocr;
+        self.unsupported(family, &["tokenizer", "context_length"])?;
+        let mut source = match self.expect_family(family)?.map(|preset| preset.source) {
+            Some(PresetSource::Ocr(source)) => source(),
+            _ => OcrSource::new(
+                required(family, "model", &self.model)?.clone(),
+                required(family, "config", &self.config)?.clone(),
+            ),
+        };
+        if let Some(model) = &self.model {
+            source = source.with_model(model.clone());
+        }
+        if let Some(config) = &self.config {
+            source = source.with_config(config.clone());
+        }
+        Ok(Ocr::builder().with_source(source))
+    }
+
+    /// Build the text recognition model the spec selects.
+    pub async fn build_ocr_model(&self) -> anyhow::Result<Ocr> {
+        self.ocr_builder()?.build().await
+    }
+}
+
+impl FromStr for ModelSpec {
+    type Err = anyhow::Error;
+
+    /// Parse a preset id into a spec that loads the preset.
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let spec = Self::preset(id);
+        spec.get_preset()?;
+        Ok(spec)
+    }
+}
+
+#[test]
+fn parse_model_specs() {
+    let spec = ModelSpec::from_toml(
+        r#"
+        family = "llama"
+        model = { local = "/models/model.gguf" }
+        tokenizer = { huggingface = { model_id = "org/model", revision = "main", file = "tokenizer.json" } }
+        context_length = 2048
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        spec,
+        ModelSpec::new(
+            ModelFamily::Llama,
+            FileSource::Local("/models/model.gguf".into())
+        )
+        .with_tokenizer(FileSource::huggingface(
+            "org/model",
+            "main",
+            "tokenizer.json"
+        ))
+        .with_context_length(2048)
+    );
+    assert_eq!(spec.family().unwrap(), ModelFamily::Llama);
+
+    let json = serde_json::to_string(&spec).unwrap();
+    assert_eq!(ModelSpec::from_json(&json).unwrap(), spec);
+
+    assert!(ModelSpec::from_toml("unknown = 1").is_err());
+    assert!(ModelSpec::default().family().is_err());
+    assert!(ModelSpec::preset("not-a-model").family().is_err());
+}
+
+#[cfg(feature = "language")]
+#[test]
+fn presets_are_unique() {
+    let mut ids = std::collections::HashSet::new();
+    for preset in ModelPreset::all() {
+        assert!(ids.insert(preset.id()), "duplicate preset {}", preset.id());
+    }
+
+    let spec: ModelSpec = "llama-3-1-8b-chat".parse().unwrap();
+    assert_eq!(spec.family().unwrap(), ModelFamily::Llama);
+    assert!(ModelPreset::get("llama-3-1-8b-chat")
+        .unwrap()
+        .chat_markers()
+        .is_some());
+    assert!(spec
+        .clone()
+        .with_config(FileSource::Local("config.json".into()))
+        .llama_builder()
+        .is_err());
+    assert!(spec.phi_builder().is_err());
+}
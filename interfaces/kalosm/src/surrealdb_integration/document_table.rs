@@ -1,11 +1,14 @@
 use std::any::Any;
 use std::any::TypeId;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
 
-use super::{EmbeddingIndexedTable, EmbeddingIndexedTableSearchResult};
+use super::{EmbeddingIndexedTable, EmbeddingIndexedTableSearchResult, ObjectWithEmbeddingIds};
 use kalosm_language::prelude::*;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
-use surrealdb::sql::Id;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::{Id, Thing};
 use surrealdb::Connection;
 use surrealdb::Surreal;
 
@@ -62,6 +65,26 @@ use surrealdb::Surreal;
 ///     println!("{:?}", nearest_5);
 /// }
 /// ```
+///
+/// Embedding search can miss exact identifiers like error codes or product names. [`DocumentTable::select_hybrid`] combines embedding search with full-text search to find those matches:
+///
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # use surrealdb::{engine::local::RocksDb, Surreal};
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+/// # db.use_ns("rag").use_db("rag").await?;
+/// let document_table = db.document_table_builder("documents").build::<Document>().await?;
+/// let results = document_table
+///     .select_hybrid("What does error E1042 mean?", 5, FusionStrategy::default())
+///     .await?;
+/// for result in results {
+///     println!("{}: {}", result.score, result.text());
+/// }
+/// # Ok(())
+/// # }
+/// ```
 pub struct DocumentTable<
     C: Connection,
     R = Document,
@@ -71,6 +94,71 @@ pub struct DocumentTable<
     embedding_model: M,
     chunker: K,
     table: EmbeddingIndexedTable<C, R, M::VectorSpace>,
+    lexical_index: Mutex<LexicalIndex>,
+}
+
+/// A chunk of a record in the table
+type ChunkKey = (Id, Range<usize>);
+
+/// The full-text index over the chunks in the table. The index is built the first time it is searched and kept up to date after that.
+#[derive(Default)]
+struct LexicalIndex {
+    index: Option<Bm25Index<ChunkKey>>,
+    /// The chunks of each record in the index
+    chunks: HashMap<Id, Vec<Range<usize>>>,
+    /// Records that were changed without their text and the generation they changed in. They are read from the table and indexed before the next search.
+    stale: HashMap<Id, u64>,
+    /// Incremented on every change to the table so an index built while the table changed is not cached
+    generation: u64,
+}
+
+impl LexicalIndex {
+    /// Add the chunks of a record to the index, replacing any chunks that were already indexed for the record
+    fn insert_record(
+        &mut self,
+        id: &Id,
+        body: &str,
+        ranges: impl IntoIterator<Item = Range<usize>>,
+    ) {
+        self.remove_record(id);
+        let Some(index) = &mut self.index else {
+            return;
+        };
+        let mut indexed = Vec::new();
+        for range in ranges {
+            // Updated records may be shorter than the chunks they were embedded with
+            if let Some(text) = body.get(range.clone()) {
+                index.insert((id.clone(), range.clone()), text);
+                indexed.push(range);
+            }
+        }
+        self.chunks.insert(id.clone(), indexed);
+    }
+
+    /// Remove every chunk of a record from the index
+    fn remove_record(&mut self, id: &Id) {
+        if let (Some(index), Some(ranges)) = (&mut self.index, self.chunks.remove(id)) {
+            for range in ranges {
+                index.remove(&(id.clone(), range));
+            }
+        }
+    }
+
+    /// Mark a record as changed so it is read from the table and indexed again before the next search
+    fn mark_stale(&mut self, id: Id) {
+        self.generation += 1;
+        if self.index.is_some() {
+            self.stale.insert(id, self.generation);
+        }
+    }
+}
+
+/// A record read from the table along with its id
+#[derive(Deserialize)]
+struct RecordWithId<R> {
+    id: Thing,
+    #[serde(flatten)]
+    record: ObjectWithEmbeddingIds<R>,
 }
 
 impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
@@ -84,6 +172,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             embedding_model,
             table,
             chunker,
+            lexical_index: Mutex::new(LexicalIndex::default()),
         }
     }
 
@@ -114,7 +203,10 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     where
         R: Serialize + DeserializeOwned,
     {
-        self.table.insert(chunks, value).await
+        let id = self.table.insert(chunks, value).await?;
+        // The record may not be a document, so it is read back from the table and indexed the next time the index is searched
+        self.lexical_index.lock().unwrap().mark_stale(id.clone());
+        Ok(id)
     }
 
     /// Insert a new record into the table and return the id of the record.
@@ -126,7 +218,29 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .chunker
             .chunk(value.as_ref(), &self.embedding_model)
             .await?;
-        self.insert_with_chunks(value, chunks).await
+        self.insert_chunked(value, chunks).await
+    }
+
+    /// Insert a record with chunks of a document and add the chunks to the full-text index
+    async fn insert_chunked(
+        &self,
+        value: R,
+        chunks: Vec<Chunk<M::VectorSpace>>,
+    ) -> anyhow::Result<Id>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let body = value.as_ref().body().to_string();
+        let ranges = chunks
+            .iter()
+            .map(|chunk| chunk.byte_range.clone())
+            .collect::<Vec<_>>();
+        let id = self.table.insert(chunks, value).await?;
+
+        let mut lexical_index = self.lexical_index.lock().unwrap();
+        lexical_index.generation += 1;
+        lexical_index.insert_record(&id, &body, ranges);
+        Ok(id)
     }
 
     /// Extend the table with a iterator of new records.
@@ -143,7 +257,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .await?;
         let mut ids = Vec::new();
         for (value, embeddings) in entries.into_iter().zip(embeddings) {
-            let id = self.table.insert(embeddings, value).await?;
+            let id = self.insert_chunked(value, embeddings).await?;
             ids.push(id);
         }
         Ok(ids)
@@ -154,7 +268,12 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     where
         R: Serialize + DeserializeOwned,
     {
-        self.table.update(id, value).await
+        let old = self.table.update(id.clone(), value).await?;
+        // The text of the record may have changed, so it is indexed again the next time the index is searched
+        let mut lexical_index = self.lexical_index.lock().unwrap();
+        lexical_index.remove_record(&id);
+        lexical_index.mark_stale(id);
+        Ok(old)
     }
 
     /// Select a record from the table with the given embedding id.
@@ -170,7 +289,12 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     where
         R: Serialize + DeserializeOwned,
     {
-        self.table.delete(id).await
+        let old = self.table.delete(id.clone()).await?;
+        let mut lexical_index = self.lexical_index.lock().unwrap();
+        lexical_index.generation += 1;
+        lexical_index.remove_record(&id);
+        lexical_index.stale.remove(&id);
+        Ok(old)
     }
 
     /// Select all records from the table.
@@ -195,6 +319,165 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
         let embedding = embedding.into_embedding(&self.embedding_model).await?;
         self.table.select_nearest(embedding, k).await
     }
+
+    /// Select the top k chunks that best match the query with both full-text search and embedding search. The two rankings are combined with the [`FusionStrategy`].
+    ///
+    /// The full-text index is built from the chunks in the table the first time this is called, and kept up to date as records are inserted, updated and deleted with this table.
+    pub async fn select_hybrid(
+        &self,
+        query: &str,
+        k: usize,
+        fusion: FusionStrategy,
+    ) -> anyhow::Result<Vec<HybridSearchResult<R>>>
+    where
+        R: AsRef<Document> + DeserializeOwned,
+    {
+        // Search deeper than k in each ranking so results found by both searches can be fused
+        let candidates = k.saturating_mul(4);
+
+        let lexical = self.search_lexical(query, candidates).await?;
+
+        let embedding = self.embedding_model.embed_query(query).await?;
+        let mut vector: Vec<(ChunkKey, f32)> = Vec::new();
+        for result in self.table.select_nearest(embedding, candidates).await? {
+            let key = (result.record_id, result.byte_range);
+            // Chunks with more than one embedding can be found more than once
+            if !vector.iter().any(|(existing, _)| *existing == key) {
+                vector.push((key, result.distance));
+            }
+        }
+
+        let mut results = Vec::new();
+        for fused in fusion.fuse(lexical, vector, k) {
+            let (record_id, byte_range) = fused.key;
+            let record = self.table.select(record_id.clone()).await?;
+            results.push(HybridSearchResult {
+                score: fused.score,
+                lexical_score: fused.lexical_score,
+                distance: fused.distance,
+                record_id,
+                byte_range,
+                record,
+            });
+        }
+        Ok(results)
+    }
+
+    /// Search the full-text index, building it from the table if it doesn't exist yet
+    async fn search_lexical(&self, query: &str, k: usize) -> anyhow::Result<Vec<(ChunkKey, f32)>>
+    where
+        R: AsRef<Document> + DeserializeOwned,
+    {
+        let (generation, stale) = {
+            let lexical_index = self.lexical_index.lock().unwrap();
+            match &lexical_index.index {
+                Some(index) if lexical_index.stale.is_empty() => {
+                    return Ok(lexical_results(index, query, k));
+                }
+                Some(_) => (lexical_index.generation, Some(lexical_index.stale.clone())),
+                None => (lexical_index.generation, None),
+            }
+        };
+
+        // Only read the records that changed since the index was built
+        if let Some(stale) = stale {
+            let mut records = Vec::with_capacity(stale.len());
+            for (id, changed) in stale {
+                let thing = Thing {
+                    tb: self.table.table().to_string(),
+                    id: id.clone(),
+                };
+                let record: Option<RecordWithId<R>> = self.table.db().select(thing).await?;
+                records.push((id, changed, record));
+            }
+
+            let mut lexical_index = self.lexical_index.lock().unwrap();
+            for (id, changed, record) in records {
+                // Records that changed again or were deleted while they were read are indexed in the next search
+                if lexical_index.stale.get(&id) != Some(&changed) {
+                    continue;
+                }
+                lexical_index.stale.remove(&id);
+                if let Some(RecordWithId { record, .. }) = record {
+                    let ranges = record.chunks.into_iter().map(|(range, _)| range);
+                    lexical_index.insert_record(&id, record.object.as_ref().body(), ranges);
+                }
+            }
+            if let Some(index) = &lexical_index.index {
+                return Ok(lexical_results(index, query, k));
+            }
+        }
+
+        let mut built = LexicalIndex {
+            index: Some(Bm25Index::new()),
+            ..Default::default()
+        };
+        let records: Vec<RecordWithId<R>> = self
+            .table
+            .db()
+            .select(self.table.table().to_string())
+            .await?;
+        for RecordWithId { id, record } in records {
+            let ranges = record.chunks.into_iter().map(|(range, _)| range);
+            built.insert_record(&id.id, record.object.as_ref().body(), ranges);
+        }
+        let results = built
+            .index
+            .as_ref()
+            .map(|index| lexical_results(index, query, k))
+            .unwrap_or_default();
+
+        let mut lexical_index = self.lexical_index.lock().unwrap();
+        if lexical_index.generation == generation {
+            lexical_index.index = built.index;
+            lexical_index.chunks = built.chunks;
+            lexical_index.stale.clear();
+        }
+        Ok(results)
+    }
+}
+
+fn lexical_results(index: &Bm25Index<ChunkKey>, query: &str, k: usize) -> Vec<(ChunkKey, f32)> {
+    index
+        .search(query, k)
+        .into_iter()
+        .map(|result| (result.key, result.score))
+        .collect()
+}
+
+/// The result of a [`DocumentTable::select_hybrid`] search.
+#[derive(Debug, Clone)]
+pub struct HybridSearchResult<R> {
+    /// The combined score of the result from the [`FusionStrategy`]. Higher scores are better matches.
+    pub score: f32,
+    /// The BM25 score of the chunk, if the full-text search found it.
+    pub lexical_score: Option<f32>,
+    /// The distance of the chunk from the query embedding, if the embedding search found it.
+    pub distance: Option<f32>,
+    /// The record id.
+    pub record_id: Id,
+    /// The byte range of the chunk in the record.
+    pub byte_range: Range<usize>,
+    /// The record.
+    pub record: R,
+}
+
+impl<R: AsRef<Document>> HybridSearchResult<R> {
+    /// Get the text of the chunk. Returns an empty string if the record was updated and no longer contains the chunk.
+    pub fn text(&self) -> String {
+        self.record
+            .as_ref()
+            .body()
+            .get(self.byte_range.clone())
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+impl<R: AsRef<Document>> From<HybridSearchResult<R>> for SearchCandidate<HybridSearchResult<R>> {
+    fn from(result: HybridSearchResult<R>) -> Self {
+        SearchCandidate::new(result.text(), result.score, result)
+    }
 }
 
 impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
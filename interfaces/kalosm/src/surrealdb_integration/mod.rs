@@ -275,6 +275,17 @@ where
     }
 }
 
+impl<R> From<EmbeddingIndexedTableSearchResult<R>>
+    for SearchCandidate<EmbeddingIndexedTableSearchResult<R>>
+where
+    R: AsRef<Document> + DeserializeOwned,
+{
+    fn from(result: EmbeddingIndexedTableSearchResult<R>) -> Self {
+        // Closer results have smaller distances, so the distance is negated to get a score
+        SearchCandidate::new(result.text(), -result.distance, result)
+    }
+}
+
 /// A builder for creating a new document table.
 pub struct EmbeddingIndexedTableBuilder<C: Connection> {
     table: String,
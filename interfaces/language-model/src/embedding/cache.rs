@@ -4,7 +4,7 @@ use std::{hash::BuildHasher, io::BufWriter, num::NonZeroUsize, path::Path, sync:
 use postcard::{from_bytes, to_io};
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{Embedder, Embedding, EmbeddingInput};
+use crate::{Embedder, Embedding, EmbeddingInput, Int8Embedding, VectorSpace};
 
 /// Embedding models can be expensive to run. This struct wraps an embedding model with a cache that stores embeddings that have been computed before.
 ///
@@ -63,7 +63,31 @@ use crate::{Embedder, Embedding, EmbeddingInput};
 /// ```
 pub struct CachedEmbeddingModel<M: Embedder, S = lru::DefaultHasher> {
     model: M,
-    cache: Mutex<lru::LruCache<EmbeddingInput, Embedding<M::VectorSpace>, S>>,
+    cache: Mutex<lru::LruCache<EmbeddingInput, CachedEmbedding<M::VectorSpace>, S>>,
+    quantize: bool,
+}
+
+/// An embedding stored in the cache
+enum CachedEmbedding<S: VectorSpace> {
+    Full(Embedding<S>),
+    Int8(Int8Embedding<S>),
+}
+
+impl<S: VectorSpace> CachedEmbedding<S> {
+    fn new(embedding: Embedding<S>, quantize: bool) -> Self {
+        if quantize {
+            Self::Int8(embedding.quantize())
+        } else {
+            Self::Full(embedding)
+        }
+    }
+
+    fn embedding(&self) -> Embedding<S> {
+        match self {
+            Self::Full(embedding) => embedding.clone(),
+            Self::Int8(embedding) => embedding.dequantize(),
+        }
+    }
 }
 
 impl<M: Embedder> CachedEmbeddingModel<M> {
@@ -72,6 +96,7 @@ impl<M: Embedder> CachedEmbeddingModel<M> {
         Self {
             model,
             cache: Mutex::new(lru::LruCache::new(cache_size)),
+            quantize: false,
         }
     }
 }
@@ -94,10 +119,21 @@ impl<M: Embedder, S: BuildHasher> CachedEmbeddingModel<M, S> {
         Self {
             model,
             cache: Mutex::new(lru::LruCache::with_hasher(cache_size, hasher)),
+            quantize: false,
+        }
+    }
+
+    /// Store cached embeddings as 8 bit integers with [`Embedding::quantize`] to use about a quarter of the memory. Embeddings read from the cache are dequantized, so they will be slightly different from the output of the model (defaults to false)
+    pub fn with_quantization(mut self, quantize: bool) -> Self {
+        self.quantize = quantize;
+        let cache = self.cache.get_mut().unwrap();
+        for (_, cached) in cache.iter_mut() {
+            *cached = CachedEmbedding::new(cached.embedding(), quantize);
         }
+        self
     }
 
-    /// Save the cache to a file for future use. You can load the cache from the file with [`Self::load_cache`].
+    /// Save the cache to a file for future use. You can load the cache from the file with [`Self::load_cache`]. Quantized embeddings are saved as full embeddings.
     ///
     /// # Example
     /// ```rust, no_run
@@ -133,7 +169,7 @@ impl<M: Embedder, S: BuildHasher> CachedEmbeddingModel<M, S> {
         let cache = self.cache.lock().unwrap();
         let items = cache
             .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
+            .map(|(k, v)| (k.clone(), v.embedding()))
             .collect::<Vec<_>>();
         to_io(&items, &mut writer)?;
 
@@ -173,10 +209,10 @@ impl<M: Embedder, S: BuildHasher> CachedEmbeddingModel<M, S> {
         M::VectorSpace: DeserializeOwned,
     {
         let contents = std::fs::read(path)?;
-        let items: Vec<_> = from_bytes(&contents)?;
+        let items: Vec<(EmbeddingInput, Embedding<M::VectorSpace>)> = from_bytes(&contents)?;
         let mut cache = self.cache.lock().unwrap();
         for (k, v) in items {
-            cache.put(k, v);
+            cache.put(k, CachedEmbedding::new(v, self.quantize));
         }
 
         Ok(())
@@ -197,13 +233,16 @@ impl<M: Embedder> Embedder for CachedEmbeddingModel<M> {
                 // first check if the embedding is in the cache
                 let mut write = self.cache.lock().unwrap();
                 if let Some(embedding) = write.get(&input) {
-                    return Ok(embedding.clone());
+                    return Ok(embedding.embedding());
                 }
             }
             // if not, embed the string and add it to the cache
             let embedding = self.model.embed_for(input.clone()).await?;
             let mut cache = self.cache.lock().unwrap();
-            cache.put(input, embedding.clone());
+            cache.put(
+                input,
+                CachedEmbedding::new(embedding.clone(), self.quantize),
+            );
             Ok(embedding)
         })
     }
@@ -222,7 +261,7 @@ impl<M: Embedder> Embedder for CachedEmbeddingModel<M> {
                 let mut cache = self.cache.lock().unwrap();
                 for (i, input) in inputs.into_iter().enumerate() {
                     if let Some(embedding) = cache.get(&input) {
-                        embeddings[i] = embedding.clone();
+                        embeddings[i] = embedding.embedding();
                     } else {
                         text_not_in_cache.push(input);
                         indices_not_in_cache.push(i);
@@ -245,7 +284,7 @@ impl<M: Embedder> Embedder for CachedEmbeddingModel<M> {
                 .zip(text_not_in_cache)
             {
                 let mut cache = self.cache.lock().unwrap();
-                cache.put(text, input.clone());
+                cache.put(text, CachedEmbedding::new(input.clone(), self.quantize));
                 embeddings[i] = input;
             }
             Ok(embeddings)
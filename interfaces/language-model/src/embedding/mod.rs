@@ -15,6 +15,8 @@ mod model;
 pub use model::*;
 mod into_embedding;
 pub use into_embedding::*;
+mod quantized;
+pub use quantized::*;
 
 /// An untyped vector space that is not associated with a model. This can be used to erase the vector type from an embedding.
 pub struct UnknownVectorSpace;
@@ -160,6 +160,17 @@ pub trait EmbedderExt: Embedder {
     ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
         self.embed_vec_for(inputs.into_iter().collect())
     }
+
+    /// Truncate every embedding this embedder creates to the first `dimensions` values with [`Embedding::truncate`]. This only works well with models trained with Matryoshka representation learning.
+    fn truncated(self, dimensions: usize) -> TruncatedEmbedder<Self>
+    where
+        Self: Sized,
+    {
+        TruncatedEmbedder {
+            model: self,
+            dimensions,
+        }
+    }
 }
 
 impl<E: Embedder> EmbedderExt for E {}
@@ -212,3 +223,47 @@ impl<E: Embedder + Send + Sync + 'static> Embedder for AnyEmbedder<E> {
         })
     }
 }
+
+/// An embedder that truncates the embeddings of another embedder. Created with [`EmbedderExt::truncated`].
+pub struct TruncatedEmbedder<E> {
+    model: E,
+    dimensions: usize,
+}
+
+impl<E> TruncatedEmbedder<E> {
+    /// Get a reference to the underlying embedder.
+    pub fn get_embedder(&self) -> &E {
+        &self.model
+    }
+
+    /// Get the number of dimensions embeddings are truncated to.
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+}
+
+impl<E: Embedder> Embedder for TruncatedEmbedder<E> {
+    type VectorSpace = E::VectorSpace;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        let future = self.model.embed_for(input);
+        Box::pin(async move { Ok(future.await?.truncate(self.dimensions)) })
+    }
+
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        let future = self.model.embed_vec_for(inputs);
+        Box::pin(async move {
+            Ok(future
+                .await?
+                .into_iter()
+                .map(|embedding| embedding.truncate(self.dimensions))
+                .collect())
+        })
+    }
+}
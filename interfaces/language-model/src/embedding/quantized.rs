@@ -0,0 +1,273 @@
+use std::marker::PhantomData;
+
+use super::{Embedding, VectorSpace};
+
+impl<S: VectorSpace> Embedding<S> {
+    /// Keep only the first `dimensions` values of the embedding and normalize the result to unit length.
+    ///
+    /// Models trained with [Matryoshka representation learning](https://arxiv.org/abs/2205.13147) (like nomic-embed-text-v1.5 or OpenAI's text-embedding-3 models) pack the most important information into the first dimensions, so truncated embeddings can be searched with little loss in quality. Truncating embeddings from other models will hurt search quality much more.
+    pub fn truncate(&self, dimensions: usize) -> Self {
+        let mut values = self.to_vec();
+        values.truncate(dimensions);
+        let norm = values.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0. {
+            for value in &mut values {
+                *value /= norm;
+            }
+        }
+        Self::from(values)
+    }
+
+    /// Quantize every value in the embedding to an 8 bit integer. The quantized embedding uses a quarter of the memory of the full embedding.
+    pub fn quantize(&self) -> Int8Embedding<S> {
+        Int8Embedding::from_floats(&self.to_vec())
+    }
+
+    /// Quantize every value in the embedding to a single bit that is set if the value is positive. The binary embedding uses 1/32nd of the memory of the full embedding and can be compared with [`BinaryEmbedding::hamming_distance`].
+    pub fn binarize(&self) -> BinaryEmbedding<S> {
+        BinaryEmbedding::from_floats(&self.to_vec())
+    }
+}
+
+/// Quantize floats to 8 bit integers and return the scale that converts them back into floats. Values are scaled so the largest value maps to 127.
+///
+/// This is the quantization [`Embedding::quantize`] uses. It is useful to store quantized values in your own buffers. `values` must be the same length as `floats`.
+pub fn quantize_i8(floats: &[f32], values: &mut [i8]) -> f32 {
+    debug_assert_eq!(floats.len(), values.len());
+    let max = floats.iter().fold(0f32, |max, value| max.max(value.abs()));
+    let scale = if max > 0. { max / i8::MAX as f32 } else { 1. };
+    for (quantized, value) in values.iter_mut().zip(floats) {
+        *quantized = (value / scale).round().clamp(-127., 127.) as i8;
+    }
+    scale
+}
+
+/// Compute the cosine similarity between 8 bit values created with [`quantize_i8`] and full precision values with the length `other_norm`.
+///
+/// The scale of the quantized values doesn't change the cosine similarity, so it isn't needed. Computing the norm of the full precision values once lets you compare them with many quantized vectors.
+pub fn int8_cosine_similarity(values: &[i8], other: &[f32], other_norm: f32) -> f32 {
+    let mut dot = 0.;
+    let mut norm = 0.;
+    for (value, other) in values.iter().zip(other) {
+        let value = *value as f32;
+        dot += value * other;
+        norm += value * value;
+    }
+    let norm = norm.sqrt() * other_norm;
+    if norm > 0. {
+        dot / norm
+    } else {
+        0.
+    }
+}
+
+/// Pack the sign of every float into bits. Dimension `i` is set in bit `i % 64` of word `i / 64` if it is positive.
+///
+/// This is the quantization [`Embedding::binarize`] uses. `bits` must have room for every float.
+pub fn pack_sign_bits(floats: &[f32], bits: &mut [u64]) {
+    debug_assert_eq!(floats.len().div_ceil(64), bits.len());
+    bits.fill(0);
+    for (index, value) in floats.iter().enumerate() {
+        if *value > 0. {
+            bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+}
+
+/// Count the number of bits that are different between two sets of bits packed with [`pack_sign_bits`].
+pub fn hamming_distance(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum()
+}
+
+/// An [`Embedding`] with every value quantized to an 8 bit integer. Created with [`Embedding::quantize`].
+///
+/// Values are scaled so the largest value in the embedding maps to 127. The scale is stored alongside the values so the embedding can be converted back into floats with [`Int8Embedding::dequantize`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
+pub struct Int8Embedding<S: VectorSpace> {
+    values: Vec<i8>,
+    scale: f32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    model: PhantomData<S>,
+}
+
+impl<S: VectorSpace> Int8Embedding<S> {
+    fn from_floats(floats: &[f32]) -> Self {
+        let mut values = vec![0; floats.len()];
+        let scale = quantize_i8(floats, &mut values);
+        Self {
+            values,
+            scale,
+            model: PhantomData,
+        }
+    }
+
+    /// Get the quantized values of the embedding.
+    pub fn values(&self) -> &[i8] {
+        &self.values
+    }
+
+    /// Get the scale that converts the quantized values back into floats.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Get the number of dimensions in the embedding.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Check if the embedding has no dimensions.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Convert the quantized values back into floats.
+    pub fn to_vec(&self) -> Vec<f32> {
+        self.values
+            .iter()
+            .map(|value| *value as f32 * self.scale)
+            .collect()
+    }
+
+    /// Convert the quantized embedding back into a full [`Embedding`]. Some precision is lost in quantization, so the values will be slightly different from the original embedding.
+    pub fn dequantize(&self) -> Embedding<S> {
+        Embedding::from(self.to_vec())
+    }
+
+    /// Compute the cosine similarity between a full embedding and this quantized embedding without dequantizing it.
+    ///
+    /// This is typically used to rescore candidates found with a [`BinaryEmbedding`] search with the full query embedding.
+    pub fn cosine_similarity(&self, other: &Embedding<S>) -> f32 {
+        let other = other.to_vec();
+        let other_norm = other.iter().map(|value| value * value).sum::<f32>().sqrt();
+        int8_cosine_similarity(&self.values, &other, other_norm)
+    }
+}
+
+impl<S: VectorSpace> std::fmt::Debug for Int8Embedding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Int8Embedding")
+            .field("values", &self.values)
+            .field("scale", &self.scale)
+            .field("model", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: VectorSpace> Clone for Int8Embedding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            scale: self.scale,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> PartialEq for Int8Embedding<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values && self.scale == other.scale
+    }
+}
+
+/// An [`Embedding`] with every value quantized to a single bit. Created with [`Embedding::binarize`].
+///
+/// Binary embeddings are compared with the [Hamming distance](https://en.wikipedia.org/wiki/Hamming_distance), which only needs a few instructions for every 64 dimensions. Binary search is much less precise than full embeddings, so the top candidates are usually rescored with the full query embedding.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "")
+)]
+pub struct BinaryEmbedding<S: VectorSpace> {
+    bits: Vec<u64>,
+    dimensions: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    model: PhantomData<S>,
+}
+
+impl<S: VectorSpace> BinaryEmbedding<S> {
+    fn from_floats(floats: &[f32]) -> Self {
+        let mut bits = vec![0u64; floats.len().div_ceil(64)];
+        pack_sign_bits(floats, &mut bits);
+        Self {
+            bits,
+            dimensions: floats.len(),
+            model: PhantomData,
+        }
+    }
+
+    /// Get the packed bits of the embedding. Dimension `i` is stored in bit `i % 64` of word `i / 64`.
+    pub fn bits(&self) -> &[u64] {
+        &self.bits
+    }
+
+    /// Get the number of dimensions in the embedding.
+    pub fn len(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Check if the embedding has no dimensions.
+    pub fn is_empty(&self) -> bool {
+        self.dimensions == 0
+    }
+
+    /// Count the number of dimensions that are different between this embedding and another embedding. Smaller distances are closer.
+    pub fn hamming_distance(&self, other: &Self) -> u32 {
+        hamming_distance(&self.bits, &other.bits)
+    }
+}
+
+impl<S: VectorSpace> std::fmt::Debug for BinaryEmbedding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinaryEmbedding")
+            .field("bits", &self.bits)
+            .field("dimensions", &self.dimensions)
+            .field("model", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: VectorSpace> Clone for BinaryEmbedding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            bits: self.bits.clone(),
+            dimensions: self.dimensions,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> PartialEq for BinaryEmbedding<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits && self.dimensions == other.dimensions
+    }
+}
+
+#[test]
+fn quantized_embeddings() {
+    use super::UnknownVectorSpace;
+
+    let embedding = Embedding::<UnknownVectorSpace>::from(vec![0.5, -1.0, 0.25, 0.0]);
+
+    let quantized = embedding.quantize();
+    assert_eq!(quantized.values(), [64, -127, 32, 0]);
+    for (original, dequantized) in embedding.to_vec().iter().zip(quantized.to_vec()) {
+        assert!((original - dequantized).abs() < 0.01);
+    }
+    assert!((quantized.cosine_similarity(&embedding) - 1.).abs() < 1e-4);
+
+    let binary = embedding.binarize();
+    assert_eq!(binary.bits(), [0b0101]);
+    assert_eq!(binary.len(), 4);
+    let other = Embedding::<UnknownVectorSpace>::from(vec![0.5, 1.0, -0.25, 0.0]).binarize();
+    assert_eq!(binary.hamming_distance(&other), 2);
+    assert_eq!(binary.hamming_distance(&binary), 0);
+
+    let truncated = embedding.truncate(2);
+    let norm = 1.25f32.sqrt();
+    assert_eq!(truncated.to_vec(), [0.5 / norm, -1.0 / norm]);
+}
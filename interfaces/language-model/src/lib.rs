@@ -31,10 +31,14 @@ mod remote;
 pub use remote::*;
 
 mod structured;
+pub use structured::{StructuredGenerationConfig, TokenHealing, WhitespaceMode};
 mod token_stream;
 pub use token_stream::*;
+mod token_trie;
 
 mod embedding;
 pub use embedding::*;
+mod likelihood;
+pub use likelihood::LogLikelihood;
 mod model;
 pub use model::*;
@@ -0,0 +1,231 @@
+use std::ops::Range;
+
+use crate::SyncModel;
+
+/// The log-likelihood a model assigns to a sequence of tokens. Created with [`crate::ModelExt::score`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let model = Llama::new().await?;
+///     let prompt = "The capital of France is";
+///     for answer in [" Paris", " Berlin"] {
+///         let score = model.score(prompt, answer).await?;
+///         println!("{answer}: {}", score.total());
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogLikelihood {
+    tokens: Vec<u32>,
+    log_probs: Vec<f64>,
+}
+
+impl LogLikelihood {
+    /// Create a new log-likelihood from the scored tokens and the natural log probability of each token.
+    pub fn new(tokens: Vec<u32>, log_probs: Vec<f64>) -> Self {
+        assert_eq!(
+            tokens.len(),
+            log_probs.len(),
+            "every token must have a log probability"
+        );
+        Self { tokens, log_probs }
+    }
+
+    /// Get the tokens that were scored.
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// Get the natural log probability of each scored token.
+    pub fn token_log_probs(&self) -> &[f64] {
+        &self.log_probs
+    }
+
+    /// Get the number of scored tokens.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Check if no tokens were scored.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Get the sum of the log probabilities of every token. This is the log probability of the whole sequence.
+    pub fn total(&self) -> f64 {
+        self.log_probs.iter().sum()
+    }
+
+    /// Get the mean log probability per token. This is useful to compare sequences with a different number of tokens. Returns 0 if no tokens were scored.
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        self.total() / self.len() as f64
+    }
+
+    /// Get the perplexity of the sequence. Lower perplexity means the model found the sequence more likely.
+    pub fn perplexity(&self) -> f64 {
+        (-self.mean()).exp()
+    }
+
+    /// Append the scores from another log-likelihood.
+    pub fn extend(&mut self, other: LogLikelihood) {
+        self.tokens.extend(other.tokens);
+        self.log_probs.extend(other.log_probs);
+    }
+}
+
+/// Get the natural log probability of a token from the logits of the model.
+pub(crate) fn token_log_prob(logits: &[f32], token: u32) -> anyhow::Result<f64> {
+    let Some(logit) = logits.get(token as usize) else {
+        anyhow::bail!(
+            "the model returned {} logits which doesn't include token {token}",
+            logits.len()
+        )
+    };
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+    let sum: f64 = logits.iter().map(|logit| (*logit as f64 - max).exp()).sum();
+    Ok(*logit as f64 - max - sum.ln())
+}
+
+/// Score the tokens in the range `scored` with a new session. The tokens before the range are used as context. The range must not include the first token because there are no logits to predict it.
+pub(crate) fn score_tokens<M: SyncModel + ?Sized>(
+    llm: &M,
+    tokens: &[u32],
+    scored: Range<usize>,
+) -> anyhow::Result<LogLikelihood> {
+    if scored.is_empty() {
+        return Ok(LogLikelihood::default());
+    }
+    if scored.start == 0 || scored.end > tokens.len() {
+        anyhow::bail!(
+            "cannot score tokens {scored:?} of a sequence with {} tokens",
+            tokens.len()
+        );
+    }
+    let mut session = llm.new_session()?;
+    let mut log_probs = Vec::new();
+    llm.feed_tokens_with_log_probs(
+        &mut session,
+        &tokens[..scored.end - 1],
+        &tokens[scored.clone()],
+        &mut log_probs,
+    )?;
+    if log_probs.len() != scored.len() {
+        anyhow::bail!(
+            "the model returned {} log probabilities for {} tokens",
+            log_probs.len(),
+            scored.len()
+        );
+    }
+    Ok(LogLikelihood::new(tokens[scored].to_vec(), log_probs))
+}
+
+/// Score every token after the first with windows of at most `window` tokens. Each window after the first overlaps the previous window by half so every token has some context.
+pub(crate) fn score_windows<M: SyncModel + ?Sized>(
+    llm: &M,
+    tokens: &[u32],
+    window: usize,
+) -> anyhow::Result<LogLikelihood> {
+    if window < 2 {
+        anyhow::bail!("the scoring window must contain at least 2 tokens");
+    }
+    let stride = window / 2;
+    let mut likelihood = LogLikelihood::default();
+    let mut start = 0;
+    let mut scored_until = 1;
+    while scored_until < tokens.len() {
+        let end = (start + window).min(tokens.len());
+        let window_tokens = &tokens[start..end];
+        likelihood.extend(score_tokens(
+            llm,
+            window_tokens,
+            scored_until - start..window_tokens.len(),
+        )?);
+        scored_until = end;
+        start += stride;
+    }
+    Ok(likelihood)
+}
+
+#[test]
+fn score_windows_matches_full_context() {
+    use crate::Session;
+    use std::sync::Arc;
+    use tokenizers::Tokenizer;
+
+    // A model that predicts the next token from the number of tokens it has seen
+    struct Counter;
+
+    struct CounterSession(usize);
+
+    impl Session for CounterSession {}
+
+    impl SyncModel for Counter {
+        type Session = CounterSession;
+
+        fn new_session(&self) -> anyhow::Result<Self::Session> {
+            Ok(CounterSession(0))
+        }
+
+        fn feed_text(
+            &self,
+            _: &mut Self::Session,
+            _: &str,
+            _: &mut Vec<f32>,
+        ) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn feed_tokens(
+            &self,
+            session: &mut Self::Session,
+            tokens: &[u32],
+            into: &mut Vec<f32>,
+        ) -> anyhow::Result<()> {
+            session.0 += tokens.len();
+            *into = vec![0.; 4];
+            into[session.0.min(3)] = 2.;
+            Ok(())
+        }
+
+        fn stop_token(&self) -> anyhow::Result<u32> {
+            Ok(0)
+        }
+
+        fn tokenizer(&self) -> Arc<Tokenizer> {
+            unimplemented!()
+        }
+    }
+
+    let tokens = [0, 1, 2, 3, 3, 3, 1];
+    let full = score_tokens(&Counter, &tokens, 1..tokens.len()).unwrap();
+    assert_eq!(full.tokens(), &tokens[1..]);
+    assert!(
+        (full.token_log_probs()[0] - token_log_prob(&[0., 2., 0., 0.], 1).unwrap()).abs() < 1e-9
+    );
+
+    // Scoring a suffix uses the tokens before it as context
+    let suffix = score_tokens(&Counter, &tokens, 3..tokens.len()).unwrap();
+    assert_eq!(suffix.tokens(), &tokens[3..]);
+    assert_eq!(suffix.token_log_probs(), &full.token_log_probs()[2..]);
+
+    // Windows larger than the sequence score the same tokens with the same context
+    assert_eq!(score_windows(&Counter, &tokens, 16).unwrap(), full);
+
+    // Smaller windows score every token exactly once
+    let windowed = score_windows(&Counter, &tokens, 4).unwrap();
+    assert_eq!(windowed.tokens(), &tokens[1..]);
+    assert!(windowed.perplexity() >= 1.);
+
+    let probability: f64 = (0..4)
+        .map(|token| token_log_prob(&[1., 2., 3., 4.], token).unwrap().exp())
+        .sum();
+    assert!((probability - 1.).abs() < 1e-9);
+}
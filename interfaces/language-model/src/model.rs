@@ -1,10 +1,12 @@
 use crate::structured::generate_structured;
+use crate::LogLikelihood;
+use crate::StructuredGenerationConfig;
 use crate::TokenOutputStream;
 use futures_util::{Future, FutureExt};
 use futures_util::{Stream, StreamExt};
 use kalosm_common::*;
 use kalosm_sample::StopOn;
-use kalosm_sample::{CreateParserState, Parse};
+use kalosm_sample::{CreateParserState, Parse, ParseStatus, PartialParse, PartialValueState};
 use kalosm_sample::{LiteralParser, Parser};
 use kalosm_streams::text_stream::ChannelTextStream;
 use llm_samplers::configure::SamplerChainBuilder;
@@ -340,7 +342,7 @@ pub trait ModelExt: Model + Send + Sync + 'static {
     /// async fn main() {
     ///     let mut llm = Llama::new().await.unwrap();
     ///
-    ///     let tokenizer = llm.tokenizer();
+    ///     let tokenizer = llm.tokenizer().unwrap();
     ///     // Start a sync task on the model
     ///     llm.run_sync(move |llm: &mut <Llama as Model>::SyncModel| {
     ///         Box::pin(async move {
@@ -403,6 +405,45 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         self.stream_structured_text(prompt, P::new_parser())
     }
 
+    /// Generate a type that implements [`PartialParse`] with the given prompt and stream the partially parsed value after every token.
+    ///
+    /// This is useful for showing the value in a UI while it is being generated. Deriving [`Parse`] implements [`PartialParse`] automatically. Add `#[parse(partial)]` to a struct to read each field while it is being generated.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Clone, Debug)]
+    /// #[parse(partial)]
+    /// struct Person {
+    ///     name: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Llama::new().await?;
+    /// let prompt = "A person with a realistic name and age in JSON format: ";
+    ///
+    /// let mut partials = llm.stream_structured_partials::<Person>(prompt);
+    /// while let Some(partial) = partials.next().await {
+    ///     println!("name: {:?}, age: {:?}", partial.name, partial.age);
+    /// }
+    /// let person = partials.await?;
+    /// println!("{person:?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn stream_structured_partials<P: PartialParse + 'static>(
+        &self,
+        prompt: &str,
+    ) -> StructurePartialResult<Self::TextStream, P>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        self.generate_parsed(prompt).partials()
+    }
+
     /// Generate structured text with the given prompt and constraints.
     ///
     /// # Example
@@ -445,6 +486,59 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         parser_state: P::PartialState,
         sampler: Arc<Mutex<dyn Sampler>>,
     ) -> StructureParserResult<Self::TextStream, P::Output>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+        P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
+    {
+        self.stream_structured_text_with_config(
+            prompt,
+            parser,
+            parser_state,
+            sampler,
+            StructuredGenerationConfig::default(),
+        )
+    }
+
+    /// Generate structured text with the given prompt, sampler and token healing and whitespace settings. See [`ModelExt::stream_structured_text`] for more information.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use std::sync::{Arc, Mutex};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let llm = Llama::new().await.unwrap();
+    ///
+    /// #[derive(Debug, Clone, Parse)]
+    /// #[parse(unquoted)]
+    /// enum Size {
+    ///     Small,
+    ///     Medium,
+    ///     Large,
+    /// }
+    ///
+    /// let parser = Size::new_parser();
+    /// let state = parser.create_parser_state();
+    /// let sampler = Arc::new(Mutex::new(GenerationParameters::default().sampler()));
+    /// // Let the model merge the end of the prompt with the start of the response and add its own whitespace
+    /// let config = StructuredGenerationConfig::new()
+    ///     .with_token_healing(TokenHealing::LongestPrefix { max_tokens: 3 })
+    ///     .with_whitespace(WhitespaceMode::Flexible);
+    /// let size = llm
+    ///     .stream_structured_text_with_config("A elephant is ", parser, state, sampler, config)
+    ///     .await
+    ///     .unwrap();
+    /// println!("{size:?}");
+    /// # }
+    /// ```
+    fn stream_structured_text_with_config<P>(
+        &self,
+        prompt: &str,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        config: StructuredGenerationConfig,
+    ) -> StructureParserResult<Self::TextStream, P::Output>
     where
         Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
         P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
@@ -458,14 +552,14 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         if let Err(err) = self.run_sync(move |llm: &mut Self::SyncModel| {
             let mut session = llm.new_session().unwrap();
             Box::pin(async move {
-                let result = llm.generate_structured(
+                let result = llm.generate_structured_with_config(
                     &mut session,
                     prompt,
                     parser,
                     parser_state,
                     sampler,
                     |token| Ok(sender.send(token)?),
-                    Some(64),
+                    config,
                 );
                 if let Some(sender) = result_sender.lock().unwrap().take() {
                     _ = sender.send(result);
@@ -493,8 +587,99 @@ pub trait ModelExt: Model + Send + Sync + 'static {
 
         Some(LiteralParser::from(end_assistant_marker))
     }
+
+    /// Score how likely the model thinks the continuation is after the prompt. The prompt is only used as context, only the tokens in the continuation are scored.
+    ///
+    /// If the prompt and continuation don't fit in the context length of the model, the start of the prompt is dropped.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let model = Llama::new().await?;
+    ///     let question = "Q: What is the largest planet in the solar system?\nA:";
+    ///     let mut best = None;
+    ///     for answer in [" Jupiter", " Mars", " Earth"] {
+    ///         let score = model.score(question, answer).await?.total();
+    ///         if best.map_or(true, |(_, best)| score > best) {
+    ///             best = Some((answer, score));
+    ///         }
+    ///     }
+    ///     println!("{best:?}");
+    ///     Ok(())
+    /// }
+    /// ```
+    async fn score(&self, prompt: &str, continuation: &str) -> anyhow::Result<LogLikelihood> {
+        let mut tokens = self.tokenize(prompt)?;
+        // The special tokens at the start of the sequence are already in the prompt
+        let special_tokens = self.tokenize("")?;
+        let mut continuation_tokens = self.tokenize(continuation)?;
+        if continuation_tokens.starts_with(&special_tokens) {
+            continuation_tokens.drain(..special_tokens.len());
+        }
+        tokens.extend_from_slice(&continuation_tokens);
+        // The first token has no logits that predict it
+        let scored_start = (tokens.len() - continuation_tokens.len()).max(1);
+        let window = self.context_length().unwrap_or(DEFAULT_SCORING_WINDOW);
+        let dropped = tokens.len().saturating_sub(window);
+        if dropped >= scored_start {
+            anyhow::bail!(
+                "the continuation is {} tokens long which doesn't fit in the context length of {window} tokens",
+                continuation_tokens.len()
+            );
+        }
+        let tokens = tokens.split_off(dropped);
+        let scored = scored_start - dropped..tokens.len();
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.run_sync(move |llm: &mut Self::SyncModel| {
+            Box::pin(async move {
+                _ = sender.send(crate::likelihood::score_tokens(llm, &tokens, scored));
+            })
+        })?;
+        receiver.await?
+    }
+
+    /// Compute the perplexity of some text. Lower perplexity means the model found the text more likely.
+    ///
+    /// Text longer than the context length of the model is scored with sliding windows that overlap by half of the context length.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let model = Llama::new().await?;
+    ///     let perplexity = model.perplexity("The quick brown fox jumps over the lazy dog.").await?;
+    ///     println!("perplexity: {perplexity}");
+    ///     Ok(())
+    /// }
+    /// ```
+    async fn perplexity(&self, text: &str) -> anyhow::Result<f64> {
+        Ok(self.log_likelihood(text).await?.perplexity())
+    }
+
+    /// Score every token in some text after the first token. Text longer than the context length of the model is scored with sliding windows that overlap by half of the context length.
+    async fn log_likelihood(&self, text: &str) -> anyhow::Result<LogLikelihood> {
+        let tokens = self.tokenize(text)?;
+        let window = self.context_length().unwrap_or(DEFAULT_SCORING_WINDOW);
+
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        self.run_sync(move |llm: &mut Self::SyncModel| {
+            Box::pin(async move {
+                _ = sender.send(crate::likelihood::score_windows(llm, &tokens, window));
+            })
+        })?;
+        receiver.await?
+    }
 }
 
+/// The number of tokens scored at once if the context length of the model is unknown.
+const DEFAULT_SCORING_WINDOW: usize = 2048;
+
 /// The result of a structured parser stream.
 pub struct StructureParserResult<S: Stream<Item = String> + Send + Unpin + 'static, O> {
     stream: S,
@@ -526,6 +711,36 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> StructureParserResult
     pub fn split(self) -> (S, tokio::sync::oneshot::Receiver<anyhow::Result<O>>) {
         (self.stream, self.result)
     }
+
+    /// Turn the text stream into a stream of partially parsed values.
+    pub fn partials(self) -> StructurePartialResult<S, O>
+    where
+        O: PartialParse + 'static,
+    {
+        let parser = O::new_partial_parser();
+        let mut state = Some(parser.create_parser_state());
+        // Parse each token once as it arrives instead of parsing all of the text for every token
+        let update = move |token: &str| {
+            let current = state.take()?;
+            match parser.parse(&current, token.as_bytes()) {
+                Ok(ParseStatus::Incomplete { new_state, .. }) => {
+                    let partial = new_state.partial_value();
+                    state = Some(new_state);
+                    Some(partial)
+                }
+                Ok(ParseStatus::Finished { result, .. }) => Some(result.into_partial()),
+                // If the text doesn't match the parser, keep the last partial value
+                Err(_) => None,
+            }
+        };
+        StructurePartialResult {
+            stream: self.stream,
+            text: String::new(),
+            partial: O::Partial::default(),
+            update: Box::new(update),
+            result: self.result,
+        }
+    }
 }
 
 impl<S: Stream<Item = String> + Send + Unpin + 'static, O> Future for StructureParserResult<S, O> {
@@ -552,6 +767,82 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> Stream for StructureP
     }
 }
 
+/// Parses the next token of the stream and returns the new partial value if it changed
+type PartialUpdate<P> = Box<dyn FnMut(&str) -> Option<P> + Send + Sync>;
+
+/// A stream of partially parsed values from a structured parser. Awaiting the stream returns the final value.
+pub struct StructurePartialResult<
+    S: Stream<Item = String> + Send + Unpin + 'static,
+    O: PartialParse,
+> {
+    stream: S,
+    text: String,
+    partial: O::Partial,
+    update: PartialUpdate<O::Partial>,
+    result: tokio::sync::oneshot::Receiver<anyhow::Result<O>>,
+}
+
+impl<S: Stream<Item = String> + Send + Unpin + 'static, O: PartialParse>
+    StructurePartialResult<S, O>
+{
+    /// Get the text that has been generated so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the value that has been parsed so far.
+    pub fn partial_value(&self) -> O::Partial {
+        self.partial.clone()
+    }
+
+    /// Get the final result of the structured parser.
+    pub async fn result(self) -> anyhow::Result<O> {
+        self.result.await?
+    }
+}
+
+// The partial value is never pinned, so the result can be moved even if the partial value can't
+impl<S: Stream<Item = String> + Send + Unpin + 'static, O: PartialParse> Unpin
+    for StructurePartialResult<S, O>
+{
+}
+
+impl<S: Stream<Item = String> + Send + Unpin + 'static, O: PartialParse> Future
+    for StructurePartialResult<S, O>
+{
+    type Output = anyhow::Result<O>;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let myself = self.get_mut();
+        myself.result.poll_unpin(cx).map(|result| result?)
+    }
+}
+
+impl<S: Stream<Item = String> + Send + Unpin + 'static, O: PartialParse> Stream
+    for StructurePartialResult<S, O>
+{
+    type Item = O::Partial;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.stream.poll_next_unpin(cx).map(|token| {
+            token.map(|token| {
+                this.text.push_str(&token);
+                if let Some(partial) = (this.update)(&token) {
+                    this.partial = partial;
+                }
+                this.partial.clone()
+            })
+        })
+    }
+}
+
 impl<M: Model + Send + Sync + 'static> ModelExt for M {}
 
 /// A raw interface for a model that can be used to generate text synchronously. This provides a very low level interface to a model's session:
@@ -565,7 +856,7 @@ impl<M: Model + Send + Sync + 'static> ModelExt for M {}
 /// async fn main() {
 ///     let mut llm = Llama::new().await.unwrap();
 ///
-///     let tokenizer = llm.tokenizer();
+///     let tokenizer = llm.tokenizer().unwrap();
 ///     // Start a sync task on the model
 ///     llm.run_sync(move |llm: &mut <Llama as Model>::SyncModel| {
 ///         Box::pin(async move {
@@ -607,6 +898,38 @@ pub trait SyncModel {
         into: &mut Vec<f32>,
     ) -> anyhow::Result<()>;
 
+    /// Run the model synchronously with a pre-tokenized input and return the natural log probability of each target token. The targets are predicted by the last `targets.len()` positions: `into[i]` is the log probability of `targets[i]` after `tokens[..tokens.len() - targets.len() + i + 1]`.
+    ///
+    /// By default, this feeds the tokens into the model one at a time. Models that can compute the logits for every position in one pass should override this method.
+    fn feed_tokens_with_log_probs(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        targets: &[u32],
+        into: &mut Vec<f64>,
+    ) -> anyhow::Result<()> {
+        if targets.len() > tokens.len() {
+            anyhow::bail!(
+                "cannot score {} targets with {} tokens",
+                targets.len(),
+                tokens.len()
+            );
+        }
+        into.clear();
+        let first_scored = tokens.len() - targets.len();
+        let mut logits = Vec::new();
+        for (index, token) in tokens.iter().enumerate() {
+            self.feed_tokens(session, &[*token], &mut logits)?;
+            if index >= first_scored {
+                into.push(crate::likelihood::token_log_prob(
+                    &logits,
+                    targets[index - first_scored],
+                )?);
+            }
+        }
+        Ok(())
+    }
+
     /// Get the token ID that represents the end of a sequence.
     fn stop_token(&self) -> anyhow::Result<u32>;
 
@@ -666,6 +989,29 @@ pub trait SyncModelExt: SyncModel {
         sampler: Arc<Mutex<dyn Sampler>>,
         on_token: impl FnMut(String) -> anyhow::Result<()>,
         top_k: Option<usize>,
+    ) -> anyhow::Result<P::Output> {
+        self.generate_structured_with_config(
+            session,
+            prompt,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            StructuredGenerationConfig::default().with_top_k(top_k),
+        )
+    }
+
+    /// Generate new text with the given prompt that conforms to the given parser with custom token healing and whitespace settings.
+    #[allow(clippy::too_many_arguments)]
+    fn generate_structured_with_config<P: Parser>(
+        &self,
+        session: &mut Self::Session,
+        prompt: impl Display,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(String) -> anyhow::Result<()>,
+        config: StructuredGenerationConfig,
     ) -> anyhow::Result<P::Output> {
         generate_structured(
             prompt,
@@ -675,7 +1021,7 @@ pub trait SyncModelExt: SyncModel {
             parser_state,
             sampler,
             on_token,
-            top_k,
+            config,
         )
     }
 
@@ -839,8 +1185,66 @@ pub trait Model: Send + Sync + 'static {
     /// The type of stream that this model generates.
     type TextStream: Stream<Item = String> + Send + Sync + Unpin + 'static;
 
-    /// Get the tokenizer associated with this model to use for constrained generation.
-    fn tokenizer(&self) -> Arc<Tokenizer>;
+    /// Get the tokenizer associated with this model to use for constrained generation. Returns an error if the model does not expose a tokenizer.
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>>;
+
+    /// Convert text into the tokens the model sees. This includes any special tokens the tokenizer adds to the start of a sequence, like the beginning of sequence token.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let model = Llama::new_chat().await?;
+    ///     let tokens = model.tokenize("Hello world")?;
+    ///     println!("{}", model.detokenize(&tokens)?);
+    ///     Ok(())
+    /// }
+    /// ```
+    fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        let tokenizer = self.tokenizer()?;
+        let special_tokens = tokenizer.encode("", true).map_err(anyhow::Error::msg)?;
+        let special_tokens = special_tokens.get_ids();
+        let encoding = tokenizer.encode(text, false).map_err(anyhow::Error::msg)?;
+        let tokens = encoding.get_ids();
+        // Prompt templates may already start with the special tokens
+        if tokens.starts_with(special_tokens) {
+            return Ok(tokens.to_vec());
+        }
+        Ok(special_tokens.iter().chain(tokens).copied().collect())
+    }
+
+    /// Convert tokens back into text.
+    fn detokenize(&self, tokens: &[u32]) -> anyhow::Result<String> {
+        self.tokenizer()?
+            .decode(tokens, false)
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Count the number of tokens the model sees for some text.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let model = Llama::new_chat().await?;
+    ///     let prompt = "Summarize the following document: ...";
+    ///     let prompt_tokens = model.count_tokens(prompt)?;
+    ///     if let Some(context_length) = model.context_length() {
+    ///         println!("{} tokens left for the response", context_length - prompt_tokens);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        Ok(self.tokenize(text)?.len())
+    }
+
+    /// Get the maximum number of tokens the model can attend to if it is known.
+    fn context_length(&self) -> Option<usize> {
+        None
+    }
 
     /// The raw sync model that backs this model.
     type SyncModel: SyncModel;
@@ -963,12 +1367,36 @@ impl Model for DynModel {
     type TextStream = ChannelTextStream;
     type SyncModel = BoxedSyncModel;
 
-    fn tokenizer(&self) -> Arc<Tokenizer> {
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
         let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
               + Send) = self.as_ref();
         self_ref.tokenizer()
     }
 
+    fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.tokenize(text)
+    }
+
+    fn detokenize(&self, tokens: &[u32]) -> anyhow::Result<String> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.detokenize(tokens)
+    }
+
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.count_tokens(text)
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.context_length()
+    }
+
     async fn stream_text_inner(
         &self,
         prompt: &str,
@@ -1034,6 +1462,17 @@ impl SyncModel for BoxedSyncModel {
         self_ref.feed_tokens(session, tokens, into)
     }
 
+    fn feed_tokens_with_log_probs(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        targets: &[u32],
+        into: &mut Vec<f64>,
+    ) -> anyhow::Result<()> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.feed_tokens_with_log_probs(session, tokens, targets, into)
+    }
+
     fn stop_token(&self) -> anyhow::Result<u32> {
         let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
         self_ref.stop_token()
@@ -1055,10 +1494,26 @@ where
     type TextStream = ChannelTextStream;
     type SyncModel = BoxedSyncModel;
 
-    fn tokenizer(&self) -> Arc<Tokenizer> {
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
         self.0.tokenizer()
     }
 
+    fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        self.0.tokenize(text)
+    }
+
+    fn detokenize(&self, tokens: &[u32]) -> anyhow::Result<String> {
+        self.0.detokenize(tokens)
+    }
+
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        self.0.count_tokens(text)
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        self.0.context_length()
+    }
+
     async fn stream_text_inner(
         &self,
         prompt: &str,
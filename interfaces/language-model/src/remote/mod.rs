@@ -1,2 +1,4 @@
 mod open_ai;
 pub use open_ai::*;
+mod tiktoken;
+pub use tiktoken::*;
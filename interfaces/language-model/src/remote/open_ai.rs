@@ -7,12 +7,14 @@ use std::pin::Pin;
 use std::sync::Arc;
 use tokenizers::tokenizer::Tokenizer;
 
-use crate::{Embedder, Embedding, GenerationParameters, ModelBuilder, VectorSpace};
+use crate::{Embedder, Embedding, GenerationParameters, ModelBuilder, TiktokenBpe, VectorSpace};
 
 /// A model that uses OpenAI's API.
 pub struct RemoteOpenAICompatibleModel {
     model: String,
     client: Client<async_openai::config::OpenAIConfig>,
+    tokenizer: Option<Arc<TiktokenBpe>>,
+    context_length: Option<usize>,
 }
 
 /// A builder for any remote OpenAI compatible model.
@@ -20,6 +22,8 @@ pub struct RemoteOpenAICompatibleModel {
 pub struct RemoteOpenAICompatibleModelBuilder<const WITH_NAME: bool> {
     model: Option<String>,
     config: async_openai::config::OpenAIConfig,
+    tokenizer: Option<Arc<TiktokenBpe>>,
+    context_length: Option<usize>,
 }
 
 impl RemoteOpenAICompatibleModelBuilder<false> {
@@ -28,6 +32,8 @@ impl RemoteOpenAICompatibleModelBuilder<false> {
         Self {
             model: None,
             config: Default::default(),
+            tokenizer: None,
+            context_length: None,
         }
     }
 
@@ -36,6 +42,8 @@ impl RemoteOpenAICompatibleModelBuilder<false> {
         RemoteOpenAICompatibleModelBuilder {
             model: Some(model.to_string()),
             config: self.config,
+            tokenizer: self.tokenizer,
+            context_length: self.context_length,
         }
     }
 }
@@ -58,6 +66,18 @@ impl<const WITH_NAME: bool> RemoteOpenAICompatibleModelBuilder<WITH_NAME> {
         self.config = self.config.with_org_id(organization_id);
         self
     }
+
+    /// Set the tokenizer the model uses. The tokenizer is used to count tokens locally because the API does not expose tokenization.
+    pub fn with_tokenizer(mut self, tokenizer: TiktokenBpe) -> Self {
+        self.tokenizer = Some(Arc::new(tokenizer));
+        self
+    }
+
+    /// Set the maximum number of tokens the model can use for the prompt and the generated text.
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+        self
+    }
 }
 
 impl RemoteOpenAICompatibleModelBuilder<true> {
@@ -66,6 +86,8 @@ impl RemoteOpenAICompatibleModelBuilder<true> {
         RemoteOpenAICompatibleModel {
             model: self.model.unwrap(),
             client: Client::with_config(self.config),
+            tokenizer: self.tokenizer,
+            context_length: self.context_length,
         }
     }
 }
@@ -75,6 +97,16 @@ impl RemoteOpenAICompatibleModel {
     pub fn builder() -> RemoteOpenAICompatibleModelBuilder<false> {
         RemoteOpenAICompatibleModelBuilder::new()
     }
+
+    fn tiktoken(&self) -> anyhow::Result<&TiktokenBpe> {
+        match &self.tokenizer {
+            Some(tokenizer) => Ok(tokenizer),
+            None => anyhow::bail!(
+                "{} does not expose tokenization. Add a tokenizer to the model with `with_tokenizer` to count tokens",
+                self.model
+            ),
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -82,8 +114,27 @@ impl crate::model::Model for RemoteOpenAICompatibleModel {
     type TextStream = ChannelTextStream;
     type SyncModel = crate::SyncModelNotSupported;
 
-    fn tokenizer(&self) -> Arc<Tokenizer> {
-        panic!("OpenAI does not expose tokenization")
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
+        anyhow::bail!(
+            "{} does not expose a huggingface tokenizer. Use `tokenize` or `count_tokens` instead",
+            self.model
+        )
+    }
+
+    fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        self.tiktoken()?.encode(text)
+    }
+
+    fn detokenize(&self, tokens: &[u32]) -> anyhow::Result<String> {
+        self.tiktoken()?.decode(tokens)
+    }
+
+    fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        Ok(self.tokenize(text)?.len())
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        self.context_length
     }
 
     async fn stream_text_inner(
@@ -133,7 +184,7 @@ impl crate::model::Model for RemoteOpenAICompatibleModel {
 }
 
 macro_rules! openai_completion_model {
-    ($ty: ident, $tybuilder: ident, $model: literal) => {
+    ($ty: ident, $tybuilder: ident, $model: literal, $context_length: literal) => {
         /// A model that uses OpenAI's API.
         pub struct $ty {
             inner: RemoteOpenAICompatibleModel,
@@ -150,7 +201,9 @@ macro_rules! openai_completion_model {
             /// Creates a new builder
             pub fn new() -> Self {
                 Self {
-                    inner: RemoteOpenAICompatibleModelBuilder::new().with_model($model),
+                    inner: RemoteOpenAICompatibleModelBuilder::new()
+                        .with_model($model)
+                        .with_context_length($context_length),
                 }
             }
 
@@ -172,6 +225,18 @@ macro_rules! openai_completion_model {
                 self
             }
 
+            /// Set the tokenizer the model uses. The tokenizer is used to count tokens locally because the API does not expose tokenization. (Defaults to `cl100k_base` when the model is started with [`ModelBuilder::start`], otherwise the model has no tokenizer)
+            pub fn with_tokenizer(mut self, tokenizer: TiktokenBpe) -> Self {
+                self.inner = self.inner.with_tokenizer(tokenizer);
+                self
+            }
+
+            /// Set the maximum number of tokens the model can use for the prompt and the generated text.
+            pub fn with_context_length(mut self, context_length: usize) -> Self {
+                self.inner = self.inner.with_context_length(context_length);
+                self
+            }
+
             /// Build the model.
             pub fn build(self) -> $ty {
                 $ty {
@@ -198,16 +263,20 @@ macro_rules! openai_completion_model {
             type Model = $ty;
 
             async fn start_with_loading_handler(
-                self,
+                mut self,
                 _: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
             ) -> anyhow::Result<$ty> {
+                // OpenAI doesn't expose tokenization, so the tokenizer is loaded from the ranks file OpenAI publishes
+                if self.inner.tokenizer.is_none() {
+                    self = self.with_tokenizer(TiktokenBpe::cl100k_base().await?);
+                }
                 Ok($ty {
                     inner: self.inner.build(),
                 })
             }
 
             fn requires_download(&self) -> bool {
-                false
+                self.inner.tokenizer.is_none() && !TiktokenBpe::cl100k_base_source().downloaded()
             }
         }
 
@@ -216,8 +285,24 @@ macro_rules! openai_completion_model {
             type TextStream = ChannelTextStream;
             type SyncModel = crate::SyncModelNotSupported;
 
-            fn tokenizer(&self) -> Arc<Tokenizer> {
-                panic!("OpenAI does not expose tokenization")
+            fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
+                self.inner.tokenizer()
+            }
+
+            fn tokenize(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+                self.inner.tokenize(text)
+            }
+
+            fn detokenize(&self, tokens: &[u32]) -> anyhow::Result<String> {
+                self.inner.detokenize(tokens)
+            }
+
+            fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+                self.inner.count_tokens(text)
+            }
+
+            fn context_length(&self) -> Option<usize> {
+                self.inner.context_length()
             }
 
             async fn stream_text_inner(
@@ -233,7 +318,7 @@ macro_rules! openai_completion_model {
     };
 }
 
-openai_completion_model!(Gpt3_5, Gpt3_5Builder, "gpt-3.5-turbo-instruct");
+openai_completion_model!(Gpt3_5, Gpt3_5Builder, "gpt-3.5-turbo-instruct", 4096);
 // The rest of the openai models only support the chat API which currently isn't supported for remote models in kalosm
 // openai_chat_model!(Gpt4, Gpt4Builder, "gpt-4");
 // openai_chat_model!(Gpt4Turbo, Gpt4TurboBuilder, "gpt-4-turbo");
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use fancy_regex::Regex;
+use kalosm_common::FileSource;
+use once_cell::sync::Lazy;
+
+/// The location of the `cl100k_base` ranks file published by OpenAI
+const CL100K_BASE_URL: &str =
+    "https://openaipublic.blob.core.windows.net/encodings/cl100k_base.tiktoken";
+
+/// A byte pair encoding tokenizer that is compatible with the tiktoken tokenizers used by OpenAI models.
+///
+/// The tokenizer is loaded from a tiktoken ranks file (like `cl100k_base.tiktoken`) where each line contains a base64 encoded token and its rank. Text is split with the `cl100k_base` pre-tokenization rules.
+///
+/// ```rust, no_run
+/// use kalosm_language_model::*;
+/// use kalosm_common::FileSource;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let tokenizer = TiktokenBpe::load(FileSource::local("cl100k_base.tiktoken".into())).await?;
+/// let model = Gpt3_5::builder().with_tokenizer(tokenizer).build();
+/// println!("{}", model.count_tokens("Hello world")?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TiktokenBpe {
+    encoder: HashMap<Vec<u8>, u32>,
+    decoder: HashMap<u32, Vec<u8>>,
+}
+
+impl TiktokenBpe {
+    /// Create a tokenizer from the bytes of each token and its rank.
+    pub fn new(ranks: impl IntoIterator<Item = (Vec<u8>, u32)>) -> Self {
+        let encoder: HashMap<_, _> = ranks.into_iter().collect();
+        let decoder = encoder
+            .iter()
+            .map(|(bytes, rank)| (*rank, bytes.clone()))
+            .collect();
+        Self { encoder, decoder }
+    }
+
+    /// Create a tokenizer from the contents of a tiktoken ranks file.
+    pub fn from_ranks(ranks: &str) -> anyhow::Result<Self> {
+        let ranks = ranks
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let Some((token, rank)) = line.split_once(' ') else {
+                    anyhow::bail!("invalid line in tiktoken ranks file: {line:?}");
+                };
+                let token = base64::engine::general_purpose::STANDARD.decode(token)?;
+                Ok((token, rank.trim().parse()?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::new(ranks))
+    }
+
+    /// Download (if necessary) and load a tokenizer from a tiktoken ranks file.
+    pub async fn load(source: FileSource) -> anyhow::Result<Self> {
+        let path = source.download(|_| {}).await?;
+        Self::from_ranks(&std::fs::read_to_string(path)?)
+    }
+
+    /// Download (if necessary) and load the `cl100k_base` tokenizer used by gpt-3.5 and gpt-4 models.
+    pub async fn cl100k_base() -> anyhow::Result<Self> {
+        Self::load(Self::cl100k_base_source()).await
+    }
+
+    pub(crate) fn cl100k_base_source() -> FileSource {
+        FileSource::url(CL100K_BASE_URL)
+    }
+
+    /// Convert text into tokens.
+    pub fn encode(&self, text: &str) -> anyhow::Result<Vec<u32>> {
+        let mut tokens = Vec::new();
+        for piece in split_cl100k(text)? {
+            let bytes = piece.as_bytes();
+            if let Some(token) = self.encoder.get(bytes) {
+                tokens.push(*token);
+                continue;
+            }
+            for part in self.byte_pair_merge(bytes) {
+                match self.encoder.get(part) {
+                    Some(token) => tokens.push(*token),
+                    None => anyhow::bail!("the tokenizer has no token for the bytes {part:?}"),
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Convert tokens back into text.
+    pub fn decode(&self, tokens: &[u32]) -> anyhow::Result<String> {
+        let mut bytes = Vec::new();
+        for token in tokens {
+            match self.decoder.get(token) {
+                Some(token_bytes) => bytes.extend_from_slice(token_bytes),
+                None => anyhow::bail!("unknown token {token}"),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Split bytes into single bytes and repeatedly merge the adjacent pair with the lowest rank
+    fn byte_pair_merge<'a>(&self, bytes: &'a [u8]) -> Vec<&'a [u8]> {
+        // The start of each part. The last boundary is the end of the bytes
+        let mut boundaries = (0..=bytes.len()).collect::<Vec<_>>();
+        loop {
+            let lowest = boundaries
+                .windows(3)
+                .enumerate()
+                .filter_map(|(i, window)| {
+                    self.encoder
+                        .get(&bytes[window[0]..window[2]])
+                        .map(|rank| (*rank, i))
+                })
+                .min();
+            match lowest {
+                Some((_, i)) => {
+                    boundaries.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        boundaries
+            .windows(2)
+            .map(|window| &bytes[window[0]..window[1]])
+            .collect()
+    }
+}
+
+/// The `cl100k_base` pre-tokenization pattern
+static CL100K_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+")
+        .unwrap()
+});
+
+/// Split text into pieces with the `cl100k_base` pre-tokenization pattern
+fn split_cl100k(text: &str) -> anyhow::Result<Vec<&str>> {
+    CL100K_PATTERN
+        .find_iter(text)
+        .map(|piece| Ok(piece?.as_str()))
+        .collect()
+}
+
+#[test]
+fn split_like_cl100k() {
+    assert_eq!(
+        split_cl100k("Hello world!\n\n  foo 123456 it's").unwrap(),
+        ["Hello", " world", "!\n\n", " ", " foo", " ", "123", "456", " it", "'s"]
+    );
+    assert_eq!(split_cl100k("a  \n").unwrap(), ["a", "  \n"]);
+    assert_eq!(split_cl100k(" ...\n\nb").unwrap(), [" ...\n\n", "b"]);
+    // Contractions are case insensitive
+    assert_eq!(split_cl100k("IT'S").unwrap(), ["IT", "'S"]);
+    // Roman numerals are alphabetic, but they are numbers, not letters
+    assert_eq!(split_cl100k("Ⅻabc").unwrap(), ["Ⅻ", "abc"]);
+}
+
+#[test]
+fn tiktoken_round_trip() {
+    // "IQ==" is "!" and "aGVsbG8=" is "hello"
+    let tokenizer = TiktokenBpe::from_ranks("IQ== 0\naGVsbG8= 1").unwrap();
+    assert_eq!(tokenizer.encode("hello!").unwrap(), [1, 0]);
+
+    let mut ranks = (0..=255u8).map(|b| (vec![b], b as u32)).collect::<Vec<_>>();
+    ranks.push((b"ll".to_vec(), 256));
+    ranks.push((b"he".to_vec(), 257));
+    ranks.push((b"hell".to_vec(), 258));
+    let tokenizer = TiktokenBpe::new(ranks);
+    let tokens = tokenizer.encode("hello hi").unwrap();
+    assert_eq!(
+        tokens,
+        [258, b'o' as u32, b' ' as u32, b'h' as u32, b'i' as u32]
+    );
+    assert_eq!(tokenizer.decode(&tokens).unwrap(), "hello hi");
+}
@@ -3,15 +3,89 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use crate::token_trie::TokenPrefixTrie;
 use crate::SyncModel;
 use crate::TokenOutputStream;
 use kalosm_sample::CreateParserState;
-use kalosm_sample::{LiteralParser, ParseStatus, Parser, ParserExt};
+use kalosm_sample::{
+    LiteralParser, ParseDiagnostic, ParseStatus, Parser, ParserExt, RejectedToken,
+    DEFAULT_REJECTED_TOKEN_COUNT,
+};
 use llm_samplers::prelude::{Logit, Logits};
 use llm_samplers::types::{HasSamplerResources, Sampler, SamplerError};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use tokenizers::tokenizer::Tokenizer;
 
+/// How the end of the prompt is healed before constrained generation starts.
+///
+/// Tokenizers often merge the end of the prompt with the start of the response. If the prompt ends in a partial word or a space, the tokens the model would naturally generate next may not line up with the end of the prompt. Token healing removes tokens from the end of the prompt and requires the generated text to start with the text of those tokens instead, so the model is free to pick the tokenization it prefers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenHealing {
+    /// Feed the whole prompt to the model without healing.
+    Disabled,
+    /// Remove the last token from the prompt and require the generated text to start with the text of that token.
+    #[default]
+    LastToken,
+    /// Remove the longest run of up to `max_tokens` tokens from the end of the prompt whose text is a prefix of a single token in the vocabulary. If no run of more than one token can be merged into a single token, this is the same as [`TokenHealing::LastToken`].
+    LongestPrefix {
+        /// The maximum number of tokens to remove from the end of the prompt.
+        max_tokens: usize,
+    },
+}
+
+/// How leading whitespace is handled during constrained generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhitespaceMode {
+    /// The generated text must match the parser exactly.
+    #[default]
+    Exact,
+    /// Whitespace at the end of the healed prompt is optional and the model can generate any amount of whitespace before the text the parser accepts.
+    Flexible,
+}
+
+/// Settings for constrained generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructuredGenerationConfig {
+    pub(crate) top_k: Option<usize>,
+    pub(crate) token_healing: TokenHealing,
+    pub(crate) whitespace: WhitespaceMode,
+}
+
+impl Default for StructuredGenerationConfig {
+    fn default() -> Self {
+        Self {
+            top_k: Some(64),
+            token_healing: TokenHealing::default(),
+            whitespace: WhitespaceMode::default(),
+        }
+    }
+}
+
+impl StructuredGenerationConfig {
+    /// Create a new config with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only consider the top k most likely tokens that the parser accepts. If this is `None`, every token that the parser accepts is considered. (Defaults to 64)
+    pub fn with_top_k(mut self, top_k: impl Into<Option<usize>>) -> Self {
+        self.top_k = top_k.into();
+        self
+    }
+
+    /// Set how the end of the prompt is healed. (Defaults to [`TokenHealing::LastToken`])
+    pub fn with_token_healing(mut self, token_healing: TokenHealing) -> Self {
+        self.token_healing = token_healing;
+        self
+    }
+
+    /// Set how leading whitespace is handled. (Defaults to [`WhitespaceMode::Exact`])
+    pub fn with_whitespace(mut self, whitespace: WhitespaceMode) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     prompt: impl Display,
@@ -19,189 +93,320 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     session: &mut M::Session,
     parser: P,
     parser_state: P::PartialState,
-    mut sampler: Arc<Mutex<dyn Sampler>>,
-    mut on_token: impl FnMut(String) -> anyhow::Result<()>,
-    top_k: Option<usize>,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    on_token: impl FnMut(String) -> anyhow::Result<()>,
+    config: StructuredGenerationConfig,
 ) -> anyhow::Result<P::Output> {
+    let StructuredGenerationConfig {
+        top_k,
+        token_healing,
+        whitespace,
+    } = config;
     let tokenizer = llm.tokenizer();
 
     let prompt_text = prompt.to_string();
     let prompt_tokens = tokenizer
         .encode(prompt_text, false)
         .map_err(|e| anyhow::anyhow!(e))?;
-    let mut prompt_tokens = prompt_tokens.get_ids();
+    let prompt_tokens = prompt_tokens.get_ids();
 
     // Prompt healing
-    // Trim the last token and add what it would decode to into the constraints
-    let last_token = if let Some((last, tokens)) = prompt_tokens.split_last() {
-        prompt_tokens = tokens;
-        Some(*last)
-    } else {
-        None
-    };
+    // Trim the last tokens and add what they would decode to into the constraints
+    let healed_token_count = healed_token_count(prompt_tokens, &tokenizer, token_healing)?;
+    let (prompt_tokens, healed_tokens) =
+        prompt_tokens.split_at(prompt_tokens.len() - healed_token_count);
 
-    let mut unprocessed_token_count = prompt_tokens.len();
+    let unprocessed_token_count = prompt_tokens.len();
     let mut token_stream = TokenOutputStream::new(tokenizer.clone());
     for token in prompt_tokens {
         token_stream.next_token(*token)?;
     }
 
-    let remaining_prompt_text = last_token
-        .map(|token| token_stream.peek_token(token))
-        .transpose()?
-        .flatten()
+    let mut remaining_prompt_text = token_stream
+        .peek_sequence(healed_tokens)?
         .unwrap_or_default();
+    if whitespace == WhitespaceMode::Flexible {
+        // Any whitespace at the end of the prompt is covered by the leading whitespace the parser accepts
+        remaining_prompt_text.truncate(remaining_prompt_text.trim_end().len());
+    }
 
-    let parser = LiteralParser::new(remaining_prompt_text.clone())
-        .ignore_output_then(parser.with_initial_state(move || parser_state.clone()));
-    let mut parser_state = parser.create_parser_state();
-    let mut strip_required_next = true;
-
-    let mut rng = rand::thread_rng();
-    let mut state_map = vec![];
-    let mut logits_indexed = Vec::new();
-    let mut token_cache = DetokenizationCache::new();
-    let mut logits = Logits::default();
-    let mut logit_probs = Vec::new();
-
-    loop {
-        let tokens = token_stream.tokens();
-        llm.feed_tokens(
+    let prompt_parser = LiteralParser::new(remaining_prompt_text.clone());
+    let parser = parser.with_initial_state(move || parser_state.clone());
+    let generation = HealedGeneration {
+        token_stream,
+        unprocessed_token_count,
+        remaining_prompt_text,
+        top_k,
+    };
+    match whitespace {
+        WhitespaceMode::Exact => generation.run(
+            llm,
             session,
-            &tokens[tokens.len() - unprocessed_token_count..],
-            &mut logit_probs,
-        )?;
-        let resources = &mut SamplerResources {
-            previous_tokens: tokens,
-            rng: &mut rng,
-        };
-
-        // fill the state map with None for each token
-        token_cache.clear(logit_probs.len());
-        state_map.clear();
-        logits_indexed.clear();
-        logits.clear();
-        for (id, prob) in logit_probs.iter().enumerate() {
-            logits_indexed.push(Logit {
-                token_id: id as u32,
-                logit: *prob,
-                prob: 0f32,
-            });
-            state_map.push(None);
-        }
+            prompt_parser.ignore_output_then(parser),
+            sampler,
+            on_token,
+        ),
+        WhitespaceMode::Flexible => generation.run(
+            llm,
+            session,
+            prompt_parser.ignore_output_then(parser.allow_leading_whitespace()),
+            sampler,
+            on_token,
+        ),
+    }
+}
 
-        let mut valid_tokens = false;
+/// The state of constrained generation after the prompt has been healed
+struct HealedGeneration {
+    token_stream: TokenOutputStream,
+    unprocessed_token_count: usize,
+    remaining_prompt_text: String,
+    top_k: Option<usize>,
+}
 
-        // If we don't have a top k, then we can just cache the entire detokenization
-        if top_k.is_none() {
-            token_cache.expand(
-                &(0..logit_probs.len() as u32).collect::<Vec<_>>(),
-                &token_stream,
+impl HealedGeneration {
+    fn run<M: ?Sized + SyncModel, P: CreateParserState>(
+        self,
+        llm: &M,
+        session: &mut M::Session,
+        parser: P,
+        mut sampler: Arc<Mutex<dyn Sampler>>,
+        mut on_token: impl FnMut(String) -> anyhow::Result<()>,
+    ) -> anyhow::Result<P::Output> {
+        let HealedGeneration {
+            mut token_stream,
+            mut unprocessed_token_count,
+            mut remaining_prompt_text,
+            top_k,
+        } = self;
+        let tokenizer = llm.tokenizer();
+        let mut parser_state = parser.create_parser_state();
+        // The text that has been generated so far. This is used to explain parse failures
+        let mut generated_text = String::new();
+
+        let mut rng = rand::thread_rng();
+        let mut state_map = vec![];
+        let mut logits_indexed = Vec::new();
+        let mut token_cache = DetokenizationCache::new();
+        let mut logits = Logits::default();
+        let mut logit_probs = Vec::new();
+
+        loop {
+            let tokens = token_stream.tokens();
+            llm.feed_tokens(
+                session,
+                &tokens[tokens.len() - unprocessed_token_count..],
+                &mut logit_probs,
             )?;
-        }
+            let resources = &mut SamplerResources {
+                previous_tokens: tokens,
+                rng: &mut rng,
+            };
 
-        const DETOKENIZATION_INITIAL_BATCH_SIZE: usize = 64;
-
-        // Constraints tend to be either very difficult to satisfy or very easy to satisfy
-        // We exponentially increase the batch size as a balance between the two
-        // If the first half of the tokens are invalid, it is unlikely that the first 64 tokens of the second half will be valid
-        let mut detokenization_batch_size = DETOKENIZATION_INITIAL_BATCH_SIZE;
-
-        let mut partitioned_logits_index = top_k.map(|_| 0);
-
-        for i in 0..logits_indexed.len() {
-            // If we have top k enabled, and there are less than top k - committed logits sorted, we need to expand the partitioned logits
-            if let (Some(top_k), Some(partitioned_index)) = (top_k, partitioned_logits_index) {
-                // If the remaining logits are less than the top k, no need to partition
-                let remaining_needed = top_k - logits.len();
-                let remaining_possible = partitioned_index - i;
-                if remaining_possible <= remaining_needed {
-                    // We batch together updates to the cache by detokenization_batch_size
-                    let logits_to_update = (remaining_needed.max(detokenization_batch_size))
-                        .min(logits_indexed.len() - 1 - i);
-                    let new_partitioned_index = i + logits_to_update;
-
-                    // If we eliminated a logit, our partitioning of the logits is no longer valid
-                    logits_indexed[i..].select_nth_unstable_by(logits_to_update, cmp_logits);
-                    logits_indexed[i..=new_partitioned_index].sort_unstable_by(cmp_logits);
-                    // Expand the cache to include the new logits
-                    partitioned_logits_index = Some(new_partitioned_index);
-                    token_cache.expand_with_logits(
-                        &logits_indexed[i..=new_partitioned_index],
-                        &token_stream,
-                    )?;
-
-                    // Double the batch size for next time
-                    detokenization_batch_size = detokenization_batch_size.saturating_mul(4);
-                }
+            // fill the state map with None for each token
+            token_cache.clear(logit_probs.len());
+            state_map.clear();
+            logits_indexed.clear();
+            logits.clear();
+            for (id, prob) in logit_probs.iter().enumerate() {
+                logits_indexed.push(Logit {
+                    token_id: id as u32,
+                    logit: *prob,
+                    prob: 0f32,
+                });
+                state_map.push(None);
             }
 
-            let Logit {
-                token_id, logit, ..
-            } = logits_indexed[i];
-            let Some(text) = token_cache.get(token_id as usize) else {
-                continue;
-            };
-            if let Ok(result) = parser.parse(&parser_state, text.as_bytes()) {
-                let parsed_bytes = match result {
-                    ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
-                    ParseStatus::Incomplete { .. } => text.len(),
+            let mut valid_tokens = false;
+
+            // If we don't have a top k, then we can just cache the entire detokenization
+            if top_k.is_none() {
+                token_cache.expand(
+                    &(0..logit_probs.len() as u32).collect::<Vec<_>>(),
+                    &token_stream,
+                )?;
+            }
+
+            const DETOKENIZATION_INITIAL_BATCH_SIZE: usize = 64;
+
+            // Constraints tend to be either very difficult to satisfy or very easy to satisfy
+            // We exponentially increase the batch size as a balance between the two
+            // If the first half of the tokens are invalid, it is unlikely that the first 64 tokens of the second half will be valid
+            let mut detokenization_batch_size = DETOKENIZATION_INITIAL_BATCH_SIZE;
+
+            let mut partitioned_logits_index = top_k.map(|_| 0);
+
+            for i in 0..logits_indexed.len() {
+                // If we have top k enabled, and there are less than top k - committed logits sorted, we need to expand the partitioned logits
+                if let (Some(top_k), Some(partitioned_index)) = (top_k, partitioned_logits_index) {
+                    // If the remaining logits are less than the top k, no need to partition
+                    let remaining_needed = top_k - logits.len();
+                    let remaining_possible = partitioned_index - i;
+                    if remaining_possible <= remaining_needed {
+                        // We batch together updates to the cache by detokenization_batch_size
+                        let logits_to_update = (remaining_needed.max(detokenization_batch_size))
+                            .min(logits_indexed.len() - 1 - i);
+                        let new_partitioned_index = i + logits_to_update;
+
+                        // If we eliminated a logit, our partitioning of the logits is no longer valid
+                        logits_indexed[i..].select_nth_unstable_by(logits_to_update, cmp_logits);
+                        logits_indexed[i..=new_partitioned_index].sort_unstable_by(cmp_logits);
+                        // Expand the cache to include the new logits
+                        partitioned_logits_index = Some(new_partitioned_index);
+                        token_cache.expand_with_logits(
+                            &logits_indexed[i..=new_partitioned_index],
+                            &token_stream,
+                        )?;
+
+                        // Double the batch size for next time
+                        detokenization_batch_size = detokenization_batch_size.saturating_mul(4);
+                    }
+                }
+
+                let Logit {
+                    token_id, logit, ..
+                } = logits_indexed[i];
+                let Some(text) = token_cache.get(token_id as usize) else {
+                    continue;
                 };
-                let result = result.without_remaining();
-                state_map[token_id as usize] = Some((result, parsed_bytes));
-                valid_tokens = true;
-                logits.push(Logit {
-                    token_id,
-                    logit,
-                    prob: 0f32,
-                });
-                // If we only need to keep the top k logits, then we can quit early once we have enough
-                if let Some(top_k) = top_k {
-                    if logits.len() >= top_k {
-                        break;
+                if let Ok(result) = parser.parse(&parser_state, text.as_bytes()) {
+                    let parsed_bytes = match result {
+                        ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
+                        ParseStatus::Incomplete { .. } => text.len(),
+                    };
+                    let result = result.without_remaining();
+                    state_map[token_id as usize] = Some((result, parsed_bytes));
+                    valid_tokens = true;
+                    logits.push(Logit {
+                        token_id,
+                        logit,
+                        prob: 0f32,
+                    });
+                    // If we only need to keep the top k logits, then we can quit early once we have enough
+                    if let Some(top_k) = top_k {
+                        if logits.len() >= top_k {
+                            break;
+                        }
                     }
                 }
             }
+
+            // If there are no valid tokens, return an error that explains why the parser rejected every token
+            if !valid_tokens {
+                return Err(parse_failure_diagnostic(
+                    &parser,
+                    &parser_state,
+                    &generated_text,
+                    &logits_indexed,
+                    &token_cache,
+                )
+                .into());
+            }
+            let token_id = sampler
+                .sample_token(resources, &mut logits)?
+                .ok_or(anyhow::anyhow!("Failed to sample constrained tokens"))?;
+
+            unprocessed_token_count = 1;
+            let (result, parsed_bytes) = state_map
+                .get_mut(token_id as usize)
+                .unwrap()
+                .take()
+                .ok_or(anyhow::anyhow!("Token {} not found in state map", token_id))?;
+            let mut token = token_stream.next_token(token_id)?.unwrap();
+            token.truncate(parsed_bytes);
+            tracing::trace!("Adding token {} to parser", token);
+            // If we are still generating the healed prompt, don't send that part of the text
+            strip_prompt_text(&mut remaining_prompt_text, &mut token);
+            if !token.is_empty() {
+                generated_text += &token;
+                on_token(token)?;
+            }
+
+            if let Some(result) = update_state(
+                &parser,
+                &mut parser_state,
+                result,
+                &tokenizer,
+                &mut token_stream,
+                &mut on_token,
+                &mut generated_text,
+                &mut remaining_prompt_text,
+                &mut unprocessed_token_count,
+            )? {
+                return Ok(result);
+            }
         }
+    }
+}
 
-        // If there are no valid tokens, return an error
-        if !valid_tokens {
-            return Err(anyhow::anyhow!("No valid tokens found"));
+/// Explain why the parser rejected every token the model could generate
+fn parse_failure_diagnostic<P: Parser>(
+    parser: &P,
+    parser_state: &P::PartialState,
+    generated_text: &str,
+    logits: &[Logit],
+    token_cache: &DetokenizationCache,
+) -> ParseDiagnostic {
+    let mut diagnostic =
+        ParseDiagnostic::new(generated_text).with_expected(parser.expected_next(parser_state));
+
+    // Only the most likely tokens are interesting, so we only collect the parse errors for those
+    let mut candidates: Vec<_> = logits
+        .iter()
+        .filter_map(|logit| {
+            token_cache
+                .get_cached(logit.token_id as usize)
+                .map(|text| (logit, text))
+        })
+        .collect();
+    candidates.sort_unstable_by(|(a, _), (b, _)| cmp_logits(a, b));
+    for (logit, text) in candidates.into_iter().take(DEFAULT_REJECTED_TOKEN_COUNT) {
+        if let Err(error) = parser.parse(parser_state, text.as_bytes()) {
+            diagnostic = diagnostic.with_rejected_token(RejectedToken::new(
+                logit.token_id,
+                text,
+                logit.logit,
+                // Make the position of the error relative to the start of the generated text
+                error.with_prefix(generated_text.as_bytes()),
+            ));
         }
-        let token_id = sampler
-            .sample_token(resources, &mut logits)?
-            .ok_or(anyhow::anyhow!("Failed to sample constrained tokens"))?;
-
-        unprocessed_token_count = 1;
-        let (result, parsed_bytes) = state_map
-            .get_mut(token_id as usize)
-            .unwrap()
-            .take()
-            .ok_or(anyhow::anyhow!("Token {} not found in state map", token_id))?;
-        let mut token = token_stream.next_token(token_id)?.unwrap();
-        token.truncate(parsed_bytes);
-        tracing::trace!("Adding token {} to parser", token);
-        // If we are still loading the initial prompt, don't send that part of the text
-        if strip_required_next {
-            if let Some(stripped) = token.strip_prefix(&remaining_prompt_text) {
-                token = stripped.to_string();
+    }
+
+    diagnostic
+}
+
+/// Find the number of tokens at the end of the prompt that should be healed
+fn healed_token_count(
+    prompt_tokens: &[u32],
+    tokenizer: &Arc<Tokenizer>,
+    token_healing: TokenHealing,
+) -> anyhow::Result<usize> {
+    let max_tokens = match token_healing {
+        TokenHealing::Disabled => return Ok(0),
+        TokenHealing::LastToken => 1,
+        TokenHealing::LongestPrefix { max_tokens } => max_tokens,
+    };
+    let max_tokens = max_tokens.min(prompt_tokens.len());
+
+    // Try to merge as many tokens as possible into a single token
+    if max_tokens > 1 {
+        let trie = TokenPrefixTrie::for_tokenizer(tokenizer)?;
+        for count in (2..=max_tokens).rev() {
+            let (prefix, healed) = prompt_tokens.split_at(prompt_tokens.len() - count);
+            let mut token_stream = TokenOutputStream::new(tokenizer.clone());
+            for token in prefix {
+                token_stream.next_token(*token)?;
+            }
+            let Some(healed_text) = token_stream.peek_sequence(healed)? else {
+                continue;
+            };
+            if trie.token_with_prefix(&healed_text).is_some() {
+                return Ok(count);
             }
-            strip_required_next = false;
-        }
-        on_token(token)?;
-
-        if let Some(result) = update_state(
-            &parser,
-            &mut parser_state,
-            result,
-            &tokenizer,
-            &mut token_stream,
-            &mut on_token,
-            &mut unprocessed_token_count,
-        )? {
-            return Ok(result);
         }
     }
+
+    Ok(max_tokens.min(1))
 }
 
 fn cmp_logits(a: &Logit, b: &Logit) -> std::cmp::Ordering {
@@ -219,6 +424,8 @@ fn update_state<P: Parser>(
     tokenizer: &Tokenizer,
     token_stream: &mut TokenOutputStream,
     on_token: &mut impl FnMut(String) -> anyhow::Result<()>,
+    generated_text: &mut String,
+    remaining_prompt_text: &mut String,
     unprocessed_token_count: &mut usize,
 ) -> anyhow::Result<Option<P::Output>> {
     match result {
@@ -253,7 +460,12 @@ fn update_state<P: Parser>(
                     return Ok(None);
                 }
                 *unprocessed_token_count += extra_tokens.len();
-                on_token(all_required_next.clone())?;
+                let mut new_text = all_required_next.clone();
+                strip_prompt_text(remaining_prompt_text, &mut new_text);
+                if !new_text.is_empty() {
+                    *generated_text += &new_text;
+                    on_token(new_text)?;
+                }
                 let mut result = parser
                     .parse(parser_state, all_required_next.as_bytes())
                     .unwrap_or_else(|_| {
@@ -266,6 +478,8 @@ fn update_state<P: Parser>(
                     tokenizer,
                     token_stream,
                     on_token,
+                    generated_text,
+                    remaining_prompt_text,
                     unprocessed_token_count,
                 )
             }
@@ -274,6 +488,23 @@ fn update_state<P: Parser>(
     }
 }
 
+/// Remove the part of the healed prompt the token regenerates from the start of the token. The healed text may be regenerated over several tokens, so the text that is still left is kept in `remaining_prompt_text`.
+fn strip_prompt_text(remaining_prompt_text: &mut String, token: &mut String) {
+    if remaining_prompt_text.is_empty() {
+        return;
+    }
+    if let Some(stripped) = token.strip_prefix(remaining_prompt_text.as_str()) {
+        *token = stripped.to_string();
+        remaining_prompt_text.clear();
+    } else if remaining_prompt_text.starts_with(token.as_str()) {
+        remaining_prompt_text.drain(..token.len());
+        token.clear();
+    } else {
+        // The parser only accepts tokens that continue the healed text, so this should never happen. If it does, stop stripping text
+        remaining_prompt_text.clear();
+    }
+}
+
 struct SamplerResources<'a, 'b, R: rand::Rng> {
     rng: &'a mut R,
     previous_tokens: &'b [u32],
@@ -336,6 +567,14 @@ impl DetokenizationCache {
         }
     }
 
+    /// Get the detokenized text for a token if it has already been cached
+    fn get_cached(&self, index: usize) -> Option<&str> {
+        match &self.cache[index] {
+            TokenCacheStatus::Valid(token) => Some(token),
+            TokenCacheStatus::Empty | TokenCacheStatus::Invalid => None,
+        }
+    }
+
     fn expand_with_logits(
         &mut self,
         tokens: &[Logit],
@@ -380,3 +619,220 @@ impl DetokenizationCache {
         self.vec.clear();
     }
 }
+
+/// A byte level BPE tokenizer with merges for "hello"
+#[cfg(test)]
+fn test_tokenizer() -> Arc<Tokenizer> {
+    use std::collections::HashMap;
+    use tokenizers::models::bpe::BPE;
+    use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+
+    let mut alphabet: Vec<_> = ByteLevel::alphabet().into_iter().collect();
+    alphabet.sort_unstable();
+    let merges = [("h", "e"), ("l", "l"), ("he", "ll"), ("hell", "o")];
+    let vocab: HashMap<String, u32> = alphabet
+        .iter()
+        .map(char::to_string)
+        .chain(merges.iter().map(|(a, b)| format!("{a}{b}")))
+        .enumerate()
+        .map(|(i, token)| (token, i as u32))
+        .collect();
+    let merges = merges
+        .iter()
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .collect();
+    let bpe = BPE::builder()
+        .vocab_and_merges(vocab, merges)
+        .build()
+        .unwrap();
+    let mut tokenizer = Tokenizer::new(bpe);
+    tokenizer.with_pre_tokenizer(ByteLevel::default().add_prefix_space(false));
+    tokenizer.with_decoder(ByteLevel::default());
+    Arc::new(tokenizer)
+}
+
+/// A model that prefers tokens that continue the text it was created with
+#[cfg(test)]
+struct ScriptedModel {
+    tokenizer: Arc<Tokenizer>,
+    script: String,
+    /// Prefer the shortest tokens that follow the script instead of the longest
+    prefer_short_tokens: bool,
+}
+
+#[cfg(test)]
+struct ScriptedSession(Vec<u32>);
+
+#[cfg(test)]
+impl crate::Session for ScriptedSession {}
+
+#[cfg(test)]
+impl SyncModel for ScriptedModel {
+    type Session = ScriptedSession;
+
+    fn new_session(&self) -> anyhow::Result<Self::Session> {
+        Ok(ScriptedSession(Vec::new()))
+    }
+
+    fn feed_text(&self, _: &mut Self::Session, _: &str, _: &mut Vec<f32>) -> anyhow::Result<()> {
+        unimplemented!()
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        session.0.extend_from_slice(tokens);
+        let vocab_size = self.tokenizer.get_vocab_size(true) as u32;
+        into.clear();
+        let mut sequence = session.0.clone();
+        for token in 0..vocab_size {
+            sequence.push(token);
+            let text = self.tokenizer.decode(&sequence, false).unwrap();
+            sequence.pop();
+            // Longer tokens that follow the script are more likely, unless the model prefers short tokens
+            into.push(if !self.script.starts_with(&text) {
+                -1.
+            } else if self.prefer_short_tokens {
+                (self.script.len() + 1 - text.len()) as f32
+            } else {
+                text.len() as f32
+            });
+        }
+        Ok(())
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        Ok(0)
+    }
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+#[cfg(test)]
+fn generate_scripted(
+    prompt: &str,
+    script: &str,
+    parser: impl CreateParserState<PartialState: Clone + Send + Sync + 'static>,
+    config: StructuredGenerationConfig,
+) -> String {
+    let model = ScriptedModel {
+        tokenizer: test_tokenizer(),
+        script: script.to_string(),
+        prefer_short_tokens: false,
+    };
+    generate_with_model(&model, prompt, parser, config)
+}
+
+#[cfg(test)]
+fn generate_with_model(
+    model: &ScriptedModel,
+    prompt: &str,
+    parser: impl CreateParserState<PartialState: Clone + Send + Sync + 'static>,
+    config: StructuredGenerationConfig,
+) -> String {
+    use llm_samplers::prelude::SampleGreedy;
+
+    let mut session = model.new_session().unwrap();
+    let state = parser.create_parser_state();
+    let mut generated = String::new();
+    generate_structured(
+        prompt,
+        model,
+        &mut session,
+        parser,
+        state,
+        Arc::new(Mutex::new(SampleGreedy::new())),
+        |token| {
+            generated += &token;
+            Ok(())
+        },
+        config,
+    )
+    .unwrap();
+    generated
+}
+
+#[test]
+fn token_healing() {
+    let tokenizer = test_tokenizer();
+    let encode = |text: &str| tokenizer.encode(text, false).unwrap().get_ids().to_vec();
+    let longest = TokenHealing::LongestPrefix { max_tokens: 3 };
+
+    // "hel" is tokenized as "he" + "l", which can be merged into the "hell" and "hello" tokens
+    let tokens = encode("hel");
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(healed_token_count(&tokens, &tokenizer, longest).unwrap(), 2);
+    assert_eq!(
+        healed_token_count(&tokens, &tokenizer, TokenHealing::LastToken).unwrap(),
+        1
+    );
+    assert_eq!(
+        healed_token_count(&tokens, &tokenizer, TokenHealing::Disabled).unwrap(),
+        0
+    );
+
+    // No token starts with "xhe", so only the last token is healed
+    let tokens = encode("xhe");
+    assert_eq!(healed_token_count(&tokens, &tokenizer, longest).unwrap(), 1);
+
+    // The healed text is generated again, but it is not streamed back to the caller
+    let generated = generate_scripted(
+        "hel",
+        "hello world",
+        LiteralParser::new("lo world"),
+        StructuredGenerationConfig::new().with_token_healing(longest),
+    );
+    assert_eq!(generated, "lo world");
+
+    // The healed text can also be generated again over several tokens
+    let model = ScriptedModel {
+        tokenizer: test_tokenizer(),
+        script: "hello world".to_string(),
+        prefer_short_tokens: true,
+    };
+    let generated = generate_with_model(
+        &model,
+        "hel",
+        LiteralParser::new("lo world"),
+        StructuredGenerationConfig::new().with_token_healing(longest),
+    );
+    assert_eq!(generated, "lo world");
+}
+
+#[test]
+fn exact_whitespace() {
+    // The model wants to add a second space, but the parser only accepts the text exactly
+    let generated = generate_scripted(
+        "Say hello: ",
+        "Say hello:  hello",
+        LiteralParser::new("hello"),
+        StructuredGenerationConfig::new().with_whitespace(WhitespaceMode::Exact),
+    );
+    assert_eq!(generated, "hello");
+}
+
+#[test]
+fn flexible_whitespace() {
+    // The model can generate any amount of whitespace before the text the parser accepts
+    let generated = generate_scripted(
+        "Say hello: ",
+        "Say hello:  hello",
+        LiteralParser::new("hello"),
+        StructuredGenerationConfig::new().with_whitespace(WhitespaceMode::Flexible),
+    );
+    assert_eq!(generated, "  hello");
+
+    // Or no whitespace at all
+    let generated = generate_scripted(
+        "Say hello: ",
+        "Say hello:hello",
+        LiteralParser::new("hello"),
+        StructuredGenerationConfig::new().with_whitespace(WhitespaceMode::Flexible),
+    );
+    assert_eq!(generated, "hello");
+}
@@ -229,6 +229,23 @@ impl TokenOutputStream {
         }
     }
 
+    /// Peek the text a sequence of tokens would add to the stream.
+    pub fn peek_sequence(&self, tokens: &[u32]) -> Result<Option<String>> {
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        let prev_text_len = self.current_text.len();
+        let mut all_tokens = self.tokens[self.prev_index..].to_vec();
+        all_tokens.extend_from_slice(tokens);
+        let text = self.decode(&all_tokens)?;
+        if text.len() > prev_text_len && text.chars().last().unwrap().is_ascii() {
+            let text = text.split_at(prev_text_len);
+            Ok(Some(text.1.to_string()))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Get the tokens
     pub fn tokens(&self) -> &[u32] {
         &self.tokens
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+use tokenizers::Tokenizer;
+
+use crate::TokenOutputStream;
+
+/// Tries that have already been built, keyed by the tokenizer they were built from
+type TrieCache = Vec<(Weak<Tokenizer>, Arc<TokenPrefixTrie>)>;
+
+static TRIE_CACHE: Lazy<Mutex<TrieCache>> = Lazy::new(Default::default);
+
+/// A byte trie over the text of every token in a vocabulary. It answers "is there a token whose text starts with this prefix" in time proportional to the length of the prefix instead of the size of the vocabulary.
+#[derive(Debug, Default)]
+pub(crate) struct TokenPrefixTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    /// The children of this node sorted by byte
+    children: Vec<(u8, u32)>,
+    /// A token whose text passes through this node
+    token: Option<u32>,
+}
+
+impl TokenPrefixTrie {
+    /// Build a trie from the text of each token.
+    pub(crate) fn new<'a>(tokens: impl IntoIterator<Item = (u32, &'a str)>) -> Self {
+        let mut trie = Self {
+            nodes: vec![TrieNode::default()],
+        };
+        for (token, text) in tokens {
+            trie.insert(token, text);
+        }
+        trie
+    }
+
+    /// Get the trie for a tokenizer. The trie is built the first time it is requested and shared until the tokenizer is dropped.
+    pub(crate) fn for_tokenizer(tokenizer: &Arc<Tokenizer>) -> anyhow::Result<Arc<Self>> {
+        let mut cache = TRIE_CACHE.lock().unwrap();
+        cache.retain(|(tokenizer, _)| tokenizer.strong_count() > 0);
+        if let Some((_, trie)) = cache
+            .iter()
+            .find(|(cached, _)| std::ptr::eq(cached.as_ptr(), Arc::as_ptr(tokenizer)))
+        {
+            return Ok(trie.clone());
+        }
+
+        let trie = Arc::new(Self::from_tokenizer(tokenizer)?);
+        cache.push((Arc::downgrade(tokenizer), trie.clone()));
+        Ok(trie)
+    }
+
+    fn from_tokenizer(tokenizer: &Arc<Tokenizer>) -> anyhow::Result<Self> {
+        // Tokens are decoded after a short context so that tokenizers which drop a leading space at the start of the text keep it
+        let context = tokenizer
+            .encode("a", false)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let mut token_stream = TokenOutputStream::new(tokenizer.clone());
+        token_stream.next_tokens(context.get_ids())?;
+
+        let vocab_size = tokenizer.get_vocab_size(true) as u32;
+        let mut texts = Vec::with_capacity(vocab_size as usize);
+        token_stream.peek_tokens(0..vocab_size, &mut texts)?;
+
+        Ok(Self::new(
+            (0..vocab_size)
+                .zip(&texts)
+                .filter_map(|(token, text)| Some((token, text.as_deref()?))),
+        ))
+    }
+
+    fn insert(&mut self, token: u32, text: &str) {
+        let mut node = 0;
+        self.nodes[node].token.get_or_insert(token);
+        for byte in text.bytes() {
+            node = match self.nodes[node]
+                .children
+                .binary_search_by_key(&byte, |(byte, _)| *byte)
+            {
+                Ok(index) => self.nodes[node].children[index].1 as usize,
+                Err(index) => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[node].children.insert(index, (byte, child as u32));
+                    child
+                }
+            };
+            self.nodes[node].token.get_or_insert(token);
+        }
+    }
+
+    /// Find a token whose text starts with the prefix.
+    pub(crate) fn token_with_prefix(&self, prefix: &str) -> Option<u32> {
+        let mut node = 0;
+        for byte in prefix.bytes() {
+            let children = &self.nodes[node].children;
+            let index = children
+                .binary_search_by_key(&byte, |(byte, _)| *byte)
+                .ok()?;
+            node = children[index].1 as usize;
+        }
+        self.nodes[node].token
+    }
+}
+
+#[test]
+fn token_prefix_trie() {
+    let trie = TokenPrefixTrie::new([(0, "he"), (1, "hello"), (2, " world"), (3, "")]);
+    assert_eq!(trie.token_with_prefix("h"), Some(0));
+    assert_eq!(trie.token_with_prefix("he"), Some(0));
+    assert_eq!(trie.token_with_prefix("hell"), Some(1));
+    assert_eq!(trie.token_with_prefix("hello"), Some(1));
+    assert_eq!(trie.token_with_prefix("hello "), None);
+    assert_eq!(trie.token_with_prefix(" w"), Some(2));
+    assert_eq!(trie.token_with_prefix("w"), None);
+    assert_eq!(trie.token_with_prefix(""), Some(0));
+}
@@ -0,0 +1,54 @@
+use candle_core::Device;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kalosm_llama::{prelude::*, InferenceThreads, LlamaModel};
+
+criterion_group!(mbenches, thread_scaling);
+criterion_main!(mbenches);
+
+fn create_model_sync() -> LlamaModel {
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async move {
+            LlamaModel::from_builder(
+                Llama::builder()
+                    .with_source(LlamaSource::qwen_2_5_0_5b_instruct())
+                    .with_device(Device::Cpu),
+                |_| {},
+            )
+            .await
+            .unwrap()
+        })
+}
+
+fn thread_counts() -> Vec<usize> {
+    let available = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1);
+    let mut counts = std::iter::successors(Some(1), |threads| Some(threads * 2))
+        .take_while(|threads| *threads < available)
+        .collect::<Vec<_>>();
+    counts.push(available);
+    counts
+}
+
+fn thread_scaling(c: &mut Criterion) {
+    let model = create_model_sync();
+    let prompt = "Hello world".repeat(10);
+    let mut logits = Vec::new();
+
+    let mut group = c.benchmark_group("feed text by threads");
+    for threads in thread_counts() {
+        let pool = InferenceThreads::Count(threads)
+            .build("kalosm-llama-bench")
+            .unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, _| {
+            b.iter(|| {
+                pool.install(|| {
+                    let mut session = model.new_session().unwrap();
+                    model.feed_text(&mut session, &prompt, &mut logits)
+                })
+            })
+        });
+    }
+    group.finish();
+}
@@ -4,10 +4,14 @@ use std::sync::{Arc, Mutex};
 pub use crate::Llama;
 use crate::{InferenceSettings, Task};
 use crate::{LlamaBuilder, LlamaModel};
-use kalosm_common::ModelLoadingProgress;
+use kalosm_common::{BoxedFuture, ModelLoadingProgress};
 use kalosm_language_model::ChatMarkers;
-use kalosm_language_model::{GenerationParameters, Model, ModelBuilder};
+use kalosm_language_model::{
+    Embedder, Embedding, EmbeddingInput, GenerationParameters, Model, ModelBuilder, ModelExt,
+    VectorSpace,
+};
 use kalosm_streams::text_stream::ChannelTextStream;
+use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
 
 #[async_trait::async_trait]
@@ -22,7 +26,9 @@ impl ModelBuilder for LlamaBuilder {
     }
 
     fn requires_download(&self) -> bool {
-        !self.source.model.downloaded() || !self.source.tokenizer.downloaded()
+        !self.source.model.downloaded()
+            || !self.source.tokenizer.downloaded()
+            || self.loras.iter().any(|(source, _)| !source.downloaded())
     }
 }
 
@@ -31,8 +37,12 @@ impl Model for Llama {
     type TextStream = ChannelTextStream;
     type SyncModel = LlamaModel;
 
-    fn tokenizer(&self) -> Arc<Tokenizer> {
-        self.get_tokenizer()
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
+        Ok(self.get_tokenizer())
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        Some(self.get_context_length())
     }
 
     fn run_sync_raw(
@@ -87,3 +97,56 @@ impl Model for Llama {
         self.chat_markers.deref().clone()
     }
 }
+
+impl Embedder for Llama {
+    type VectorSpace = LlamaSpace;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        self.embed_string(input.text)
+    }
+
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        self.embed_vec(inputs.into_iter().map(|input| input.text).collect())
+    }
+
+    fn embed_string(
+        &self,
+        input: String,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<LlamaSpace>>> {
+        Box::pin(async move {
+            let mut embeddings = self.embed_vec(vec![input]).await?;
+            Ok(embeddings.remove(0))
+        })
+    }
+
+    fn embed_vec(
+        &self,
+        inputs: Vec<String>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<LlamaSpace>>>> {
+        Box::pin(async move {
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            self.run_sync(move |model: &mut LlamaModel| {
+                Box::pin(async move {
+                    let embeddings = inputs
+                        .iter()
+                        .map(|input| model.embed(input))
+                        .collect::<anyhow::Result<Vec<_>>>();
+                    _ = sender.send(embeddings);
+                })
+            })?;
+            receiver.await?
+        })
+    }
+}
+
+/// A vector space for embeddings from a Llama model. Embeddings from different models are not comparable, so you should only compare embeddings from the same model.
+#[derive(Serialize, Deserialize)]
+pub struct LlamaSpace;
+
+impl VectorSpace for LlamaSpace {}
@@ -38,9 +38,11 @@ mod raw;
 mod session;
 mod source;
 
+pub use crate::language_model::LlamaSpace;
 pub use crate::model::LlamaModel;
 pub use crate::raw::cache::*;
-use crate::raw::Model;
+use crate::raw::{LlamaConfigOverrides, LoraAdapter, LoraAdapters, MappedFile, Model};
+pub use crate::raw::{LlamaPooling, RopeScaling};
 pub use crate::session::LlamaSession;
 use candle_core::{
     quantized::{ggml_file, gguf_file},
@@ -50,6 +52,7 @@ pub use kalosm_common::*;
 use kalosm_language_model::ChatMarkers;
 use llm_samplers::types::Sampler;
 pub use source::*;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use tokenizers::Tokenizer;
 
@@ -84,6 +87,7 @@ type SyncCallback = Box<
 pub struct Llama {
     task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
     tokenizer: Arc<Tokenizer>,
+    context_length: usize,
     chat_markers: Arc<Option<ChatMarkers>>,
 }
 
@@ -132,46 +136,58 @@ impl Llama {
         device: Device,
         cache: LlamaCache,
         chat_markers: Option<ChatMarkers>,
+        adapters: LoraAdapters,
+        thread_pool: InferenceThreadPool,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
         let arc_tokenizer = Arc::new(tokenizer);
+        let context_length = model.config.context_length;
 
         std::thread::spawn({
             let arc_tokenizer = arc_tokenizer.clone();
             move || {
-                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache);
-                tokio::runtime::Builder::new_current_thread()
+                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache, adapters);
+                let runtime = tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()
-                    .unwrap()
-                    .block_on(async move {
-                        while let Some(task) = task_receiver.recv().await {
-                            match task {
-                                Task::Kill => break,
-                                Task::Infer {
-                                    settings,
-                                    sender,
-                                    sampler,
-                                } => {
-                                    if let Err(err) = inner._infer(settings, sampler, sender) {
-                                        eprintln!("Error: {}", err);
-                                    }
-                                }
-                                Task::RunSync { callback } => {
-                                    callback(&mut inner).await;
-                                }
+                    .unwrap();
+                // Each task runs in the thread pool of the model so any parallel work (matmuls, structured generation) stays on the threads the model was built with
+                while let Some(task) = task_receiver.blocking_recv() {
+                    match task {
+                        Task::Kill => break,
+                        Task::Infer {
+                            settings,
+                            sender,
+                            sampler,
+                        } => {
+                            let result = thread_pool.install(|| {
+                                let _guard = runtime.enter();
+                                inner._infer(settings, sampler, sender)
+                            });
+                            if let Err(err) = result {
+                                eprintln!("Error: {}", err);
                             }
                         }
-                    })
+                        Task::RunSync { callback } => {
+                            thread_pool.install(|| runtime.block_on(callback(&mut inner)));
+                        }
+                    }
+                }
             }
         });
         Self {
             task_sender,
             tokenizer: arc_tokenizer,
+            context_length,
             chat_markers: chat_markers.into(),
         }
     }
 
+    /// Get the maximum number of tokens the model can attend to.
+    pub(crate) fn get_context_length(&self) -> usize {
+        self.context_length
+    }
+
     /// Get a reference to the tokenizer.
     pub(crate) fn get_tokenizer(&self) -> Arc<Tokenizer> {
         self.tokenizer.clone()
@@ -200,6 +216,15 @@ pub struct LlamaBuilder {
     source: source::LlamaSource,
     device: Option<Device>,
     flash_attn: bool,
+    loras: Vec<(FileSource, f32)>,
+    default_lora: Option<Option<usize>>,
+    context_length: Option<usize>,
+    rope_scaling: Option<RopeScaling>,
+    kv_cache_quantization: KvCacheQuantization,
+    threads: InferenceThreads,
+    disable_mmap: bool,
+    embedding_pooling: Option<LlamaPooling>,
+    normalize_embeddings: Option<bool>,
 }
 
 impl LlamaBuilder {
@@ -221,6 +246,252 @@ impl LlamaBuilder {
         self
     }
 
+    /// Set the maximum number of tokens the model attends to. (Defaults to the context length in the model file)
+    ///
+    /// If you set a context length longer than the model was trained with, you should also set [`LlamaBuilder::with_rope_scaling`] unless the model file already contains rope scaling metadata.
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+        self
+    }
+
+    /// Set the method used to scale rotary position embeddings. (Defaults to the rope scaling in the model file)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// // Run a model trained with 8k tokens of context with 32k tokens of context
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::mistral_7b_instruct_2())
+    ///     .with_context_length(32768)
+    ///     .with_rope_scaling(RopeScaling::yarn(4.0, 8192))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_rope_scaling(mut self, rope_scaling: RopeScaling) -> Self {
+        self.rope_scaling = Some(rope_scaling);
+        self
+    }
+
+    /// Set the precision the attention cache stores keys and values with. (Defaults to [`KvCacheQuantization::None`])
+    ///
+    /// Quantizing the cache reduces the memory used by long sessions at the cost of some accuracy. Saved sessions keep the quantization of the model they were created with.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_kv_cache_quantization(KvCacheQuantization::Q8_0)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_kv_cache_quantization(mut self, quantization: KvCacheQuantization) -> Self {
+        self.kv_cache_quantization = quantization;
+        self
+    }
+
+    /// Set the number of threads the model uses for inference on the CPU. The model gets a dedicated thread pool with this many threads. (Defaults to rayon's global thread pool)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_threads(16)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = InferenceThreads::Count(threads);
+        self
+    }
+
+    /// Set the thread pool the model uses for inference on the CPU. Models built with the same pool share its threads. (Defaults to rayon's global thread pool)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use std::sync::Arc;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let pool = Arc::new(ThreadPoolBuilder::new().num_threads(32).build()?);
+    /// let chat = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_thread_pool(pool.clone())
+    ///     .build()
+    ///     .await?;
+    /// let phi = Llama::builder()
+    ///     .with_source(LlamaSource::phi_3_5_mini_4k_instruct())
+    ///     .with_thread_pool(pool)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.threads = InferenceThreads::Pool(pool);
+        self
+    }
+
+    /// Set whether to memory map the model file. (Defaults to true)
+    ///
+    /// Memory mapped models start faster and processes that load the same model file share the file in the page cache. The token embeddings are read directly from the mapped file. The model file must not be modified while the model is loaded.
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.disable_mmap = !mmap;
+        self
+    }
+
+    /// Set how the hidden states of the model are pooled into an embedding when the model is used as an [`Embedder`](kalosm_language_model::Embedder). (Defaults to the pooling in the model file, or [`LlamaPooling::LastToken`] if the model file doesn't specify one)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::new(
+    ///         FileSource::local("./e5-mistral-7b-instruct-Q4_K_M.gguf".into()),
+    ///         FileSource::local("./tokenizer.json".into()),
+    ///     ))
+    ///     .with_embedding_pooling(LlamaPooling::LastToken)
+    ///     .build()
+    ///     .await?;
+    /// let embedding = model.embed("Kalosm runs models locally").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_embedding_pooling(mut self, pooling: LlamaPooling) -> Self {
+        self.embedding_pooling = Some(pooling);
+        self
+    }
+
+    /// Set whether embeddings are normalized to a length of one. (Defaults to true)
+    pub fn with_normalized_embeddings(mut self, normalize: bool) -> Self {
+        self.normalize_embeddings = Some(normalize);
+        self
+    }
+
+    /// Get the settings that override the values in the model file.
+    pub(crate) fn config_overrides(&self) -> LlamaConfigOverrides {
+        LlamaConfigOverrides {
+            context_length: self.context_length,
+            rope_scaling: self.rope_scaling,
+            kv_cache_quantization: self.kv_cache_quantization,
+            embedding_pooling: self.embedding_pooling,
+            normalize_embeddings: self.normalize_embeddings,
+        }
+    }
+
+    /// Add a LoRA adapter to the model. The adapter can be a PEFT safetensors file or a llama.cpp GGUF LoRA file.
+    ///
+    /// The low rank update of the adapter is multiplied by `scale`. GGUF adapters store their alpha value, so their update is also multiplied by `alpha / rank`. For PEFT adapters, `scale` should include `alpha / rank` from the adapter config.
+    ///
+    /// Adapters are applied to the quantized weights at inference time, so you can add multiple adapters without loading the base model more than once. New sessions use the first adapter unless you choose another with [`LlamaBuilder::with_default_lora`]. You can switch or disable the adapter for a session with [`LlamaSession::set_lora`].
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_lora(FileSource::local("./customer-a.gguf".into()), 1.0)
+    ///     .with_lora(FileSource::local("./customer-b.gguf".into()), 1.0)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_lora(mut self, source: FileSource, scale: f32) -> Self {
+        self.loras.push((source, scale));
+        self
+    }
+
+    /// Set the index of the LoRA adapter new sessions use, or disable adapters for new sessions with `None`. Adapters are indexed in the order they were added with [`LlamaBuilder::with_lora`]. (Defaults to the first adapter)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_lora(FileSource::local("./customer-a.gguf".into()), 1.0)
+    ///     .with_lora(FileSource::local("./customer-b.gguf".into()), 1.0)
+    ///     // Use the customer-b adapter unless a session picks another one
+    ///     .with_default_lora(Some(1))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_default_lora(mut self, index: Option<usize>) -> Self {
+        self.default_lora = Some(index);
+        self
+    }
+
+    /// Download and load the LoRA adapters for a model.
+    pub(crate) async fn load_loras(
+        &self,
+        model: &Model,
+        device: &Device,
+        mut handler: impl FnMut(ModelLoadingProgress),
+    ) -> anyhow::Result<LoraAdapters> {
+        let default = match self.default_lora {
+            Some(Some(index)) if index >= self.loras.len() => anyhow::bail!(
+                "cannot use LoRA adapter {index} by default, the model has {} adapters",
+                self.loras.len()
+            ),
+            Some(index) => index,
+            None => (!self.loras.is_empty()).then_some(0),
+        };
+        let mut adapters = Vec::with_capacity(self.loras.len());
+        for (source, scale) in &self.loras {
+            let source_display = format!("LoRA ({source})");
+            let mut create_progress = ModelLoadingProgress::downloading_progress(source_display);
+            let path = self
+                .source
+                .cache
+                .get(source, |progress| handler(create_progress(progress)))
+                .await?;
+            adapters.push(LoraAdapter::load(
+                &path,
+                &model.config,
+                *scale as f64,
+                device,
+            )?);
+        }
+        Ok(LoraAdapters { adapters, default })
+    }
+
+    /// Load the weights of the model from a GGUF or GGML file.
+    pub(crate) fn load_model(&self, filename: &Path, device: &Device) -> anyhow::Result<Model> {
+        let overrides = self.config_overrides();
+        let mut file = std::fs::File::open(filename)?;
+        let model = match filename.extension().and_then(|v| v.to_str()) {
+            Some("gguf") if self.disable_mmap => {
+                let model = gguf_file::Content::read(&mut file)?;
+                Model::from_gguf(model, &mut file, device, &overrides)?
+            }
+            Some("gguf") => {
+                let mut reader = std::io::Cursor::new(MappedFile::new(&file)?);
+                let model = gguf_file::Content::read(&mut reader)?;
+                Model::from_gguf(model, &mut reader, device, &overrides)?
+            }
+            Some("ggml" | "bin") | Some(_) | None => {
+                let model = ggml_file::Content::read(&mut file, device)?;
+                let gqa = self.source.group_query_attention;
+                Model::from_ggml(model, gqa as usize, device, &overrides)?
+            }
+        };
+        Ok(model)
+    }
+
     /// Get the device or the default device if not set.
     pub(crate) fn get_device(&self) -> anyhow::Result<Device> {
         match self.device.clone() {
@@ -261,6 +532,7 @@ impl LlamaBuilder {
         handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
     ) -> anyhow::Result<Llama> {
         let device = self.get_device()?;
+        let thread_pool = self.threads.build("kalosm-llama")?;
 
         let handler = Arc::new(Mutex::new(handler));
         let filename = tokio::spawn({
@@ -284,18 +556,13 @@ impl LlamaBuilder {
         };
         let filename = filename.await??;
 
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = self.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let model = self.load_model(&filename, &device)?;
+
+        let adapters = self
+            .load_loras(&model, &device, |progress| {
+                (handler.lock().unwrap())(progress)
+            })
+            .await?;
 
         let cache = LlamaCache::new(&model.config);
 
@@ -305,6 +572,8 @@ impl LlamaBuilder {
             device,
             cache,
             self.source.markers,
+            adapters,
+            thread_pool,
         ))
     }
 
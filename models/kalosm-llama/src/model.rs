@@ -1,18 +1,16 @@
 use crate::raw::cache::LlamaCache;
-use crate::{raw::Model, session::LlamaSession};
+use crate::raw::{LoraAdapter, LoraAdapters, Model};
+use crate::session::LlamaSession;
 use anyhow::{Error as E, Result};
 use kalosm_common::*;
 use kalosm_language_model::SyncModelExt;
 use std::sync::Arc;
 
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    DType, Device,
-};
-use kalosm_language_model::SyncModel;
+use candle_core::{DType, Device};
+use kalosm_language_model::{Embedding, SyncModel};
 use tokenizers::Tokenizer;
 
-use crate::InferenceSettings;
+use crate::{InferenceSettings, LlamaSpace};
 
 /// The inner, synchronous Llama model.
 pub struct LlamaModel {
@@ -20,6 +18,7 @@ pub struct LlamaModel {
     device: Device,
     tokenizer: Arc<Tokenizer>,
     cache: LlamaCache,
+    adapters: LoraAdapters,
 }
 
 impl SyncModel for LlamaModel {
@@ -27,7 +26,8 @@ impl SyncModel for LlamaModel {
 
     fn new_session(&self) -> anyhow::Result<Self::Session> {
         let cache = self.cache.clone();
-        Ok(Self::Session { cache })
+        let lora = self.adapters.default;
+        Ok(Self::Session { cache, lora })
     }
 
     fn feed_text(
@@ -47,15 +47,48 @@ impl SyncModel for LlamaModel {
         tokens: &[u32],
         logits: &mut Vec<f32>,
     ) -> anyhow::Result<()> {
+        let lora = self.session_lora(session)?;
         Self::forward(
             &self.model,
             &self.device,
             tokens,
             Some(&mut session.cache),
+            lora,
             logits,
         )
     }
 
+    fn feed_tokens_with_log_probs(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        targets: &[u32],
+        into: &mut Vec<f64>,
+    ) -> anyhow::Result<()> {
+        if tokens.is_empty() {
+            return Err(anyhow::anyhow!("Cannot run model on empty input"));
+        }
+        let cached_tokens = session.cache.tokens.len();
+        if cached_tokens + tokens.len() > self.model.config.context_length {
+            anyhow::bail!(
+                "cannot compute logits for {} tokens with {cached_tokens} cached tokens, the context length of the model is {}",
+                tokens.len(),
+                self.model.config.context_length
+            );
+        }
+        let lora = self.session_lora(session)?;
+        let log_probs = self.model.forward_log_probs(
+            tokens,
+            targets,
+            &self.device,
+            Some(&mut session.cache),
+            lora,
+        )?;
+        into.clear();
+        into.extend(log_probs.into_iter().map(f64::from));
+        Ok(())
+    }
+
     fn stop_token(&self) -> anyhow::Result<u32> {
         let vocab = self.tokenizer.get_vocab(true);
         let eos_token = match vocab.get("</s>").or(vocab.get("<|end_of_text|>")) {
@@ -71,18 +104,39 @@ impl SyncModel for LlamaModel {
 }
 
 impl LlamaModel {
+    /// Embed some text with the pooled hidden states of the model.
+    pub fn embed(&self, text: &str) -> anyhow::Result<Embedding<LlamaSpace>> {
+        let encoded = self.tokenizer.encode(text, true).map_err(E::msg)?;
+        let embedding = self.model.embed(encoded.get_ids(), &self.device)?;
+        Ok(Embedding::new(embedding))
+    }
+
+    fn session_lora(&self, session: &LlamaSession) -> anyhow::Result<Option<&LoraAdapter>> {
+        match session.lora {
+            Some(index) => match self.adapters.adapters.get(index) {
+                Some(adapter) => Ok(Some(adapter)),
+                None => anyhow::bail!(
+                    "cannot find LoRA adapter {index}, the model has {} adapters",
+                    self.adapters.adapters.len()
+                ),
+            },
+            None => Ok(None),
+        }
+    }
+
     fn forward(
         model: &Model,
         device: &Device,
         tokens: &[u32],
         cache: Option<&mut LlamaCache>,
+        lora: Option<&LoraAdapter>,
         logits_vec: &mut Vec<f32>,
     ) -> anyhow::Result<()> {
         if tokens.is_empty() {
             return Err(anyhow::anyhow!("Cannot run model on empty input"));
         }
 
-        let logits = model.forward(tokens, device, cache)?;
+        let logits = model.forward(tokens, device, cache, lora)?;
 
         let logits = logits.squeeze(0)?.to_dtype(DType::F32)?;
         copy_tensor_into_vec(&logits, logits_vec)?;
@@ -110,18 +164,9 @@ impl LlamaModel {
             .source
             .model(|progress| handler(create_progress(progress)))
             .await?;
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = builder.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let model = builder.load_model(&filename, &device)?;
+
+        let adapters = builder.load_loras(&model, &device, handler).await?;
 
         let cache = LlamaCache::new(&model.config);
         Ok(Self {
@@ -129,6 +174,7 @@ impl LlamaModel {
             tokenizer: Arc::new(tokenizer),
             device,
             cache,
+            adapters,
         })
     }
 
@@ -138,12 +184,14 @@ impl LlamaModel {
         tokenizer: Arc<Tokenizer>,
         device: Device,
         cache: LlamaCache,
+        adapters: LoraAdapters,
     ) -> Self {
         Self {
             cache,
             model,
             device,
             tokenizer,
+            adapters,
         }
     }
 
@@ -1,7 +1,8 @@
+use super::lora::{forward_with_lora, LoraLayer};
 use super::rope::RopeCache;
 use super::silu::fast_cpu_silu;
 use candle_core::{quantized::QMatMul, Module, Tensor};
-use candle_core::{Device, D};
+use candle_core::{DType, Device, D};
 use candle_transformers::quantized_nn::RmsNorm;
 use kalosm_common::AttentionMask;
 use kalosm_common::KvCache;
@@ -9,13 +10,22 @@ use kalosm_common::KvCache;
 pub enum FeedForwardVariant {
     Llama(LlamaFeedForward),
     Phi(PhiFeedForward),
+    Gemma(GemmaFeedForward),
+    MixtureOfExperts(MixtureOfExpertsFeedForward),
 }
 
 impl FeedForwardVariant {
-    pub(crate) fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+    pub(crate) fn forward(
+        &self,
+        x: &Tensor,
+        lora: Option<&LoraLayer>,
+    ) -> candle_core::Result<Tensor> {
         match self {
-            FeedForwardVariant::Llama(ffn) => ffn.forward(x),
-            FeedForwardVariant::Phi(ffn) => ffn.forward(x),
+            FeedForwardVariant::Llama(ffn) => ffn.forward(x, lora),
+            FeedForwardVariant::Phi(ffn) => ffn.forward(x, lora),
+            FeedForwardVariant::Gemma(ffn) => ffn.forward(x, lora),
+            // LoRA adapters are not applied to the experts
+            FeedForwardVariant::MixtureOfExperts(ffn) => ffn.forward(x),
         }
     }
 }
@@ -27,8 +37,12 @@ pub struct PhiFeedForward {
 }
 
 impl PhiFeedForward {
-    pub(crate) fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
-        let up_states = x.apply(&self.up)?;
+    pub(crate) fn forward(
+        &self,
+        x: &Tensor,
+        lora: Option<&LoraLayer>,
+    ) -> candle_core::Result<Tensor> {
+        let up_states = forward_with_lora(&self.up, lora.and_then(|l| l.up.as_ref()), x)?;
         let gate = up_states.narrow(D::Minus1, 0, self.feed_forward_length)?;
         let up_states = up_states.narrow(
             D::Minus1,
@@ -37,7 +51,7 @@ impl PhiFeedForward {
         )?;
         let gate = fast_cpu_silu(&gate)?;
         let up_states = (up_states * gate)?;
-        up_states.apply(&self.down)
+        forward_with_lora(&self.down, lora.and_then(|l| l.down.as_ref()), &up_states)
     }
 }
 
@@ -48,31 +62,137 @@ pub struct LlamaFeedForward {
 }
 
 impl LlamaFeedForward {
-    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+    fn forward(&self, x: &Tensor, lora: Option<&LoraLayer>) -> candle_core::Result<Tensor> {
+        let gate_lora = lora.and_then(|l| l.gate.as_ref());
+        let up_lora = lora.and_then(|l| l.up.as_ref());
+        let down_lora = lora.and_then(|l| l.down.as_ref());
         let device = x.device();
         if matches!(device, Device::Cpu) {
-            std::thread::scope(|scope| {
-                let w1 = scope.spawn(|| {
-                    let w1 = self.feed_forward_w1.forward(x)?;
+            // Run the gate and up projections in parallel on the thread pool the model is installed in
+            let (w1, w3) = rayon::join(
+                || {
+                    let w1 = forward_with_lora(&self.feed_forward_w1, gate_lora, x)?;
                     fast_cpu_silu(&w1)
-                });
+                },
+                || forward_with_lora(&self.feed_forward_w3, up_lora, x),
+            );
 
-                let w3 = self.feed_forward_w3.forward(x)?;
-                let w1 = w1
-                    .join()
-                    .map_err(|_| candle_core::Error::Msg("Failed to join thread".to_string()))??;
-
-                self.feed_forward_w2.forward(&(&w1 * w3)?)
-            })
+            forward_with_lora(&self.feed_forward_w2, down_lora, &(&w1? * w3?)?)
         } else {
-            let w1 = self.feed_forward_w1.forward(x)?;
+            let w1 = forward_with_lora(&self.feed_forward_w1, gate_lora, x)?;
             let w1 = fast_cpu_silu(&w1)?;
 
-            let w3 = self.feed_forward_w3.forward(x)?;
+            let w3 = forward_with_lora(&self.feed_forward_w3, up_lora, x)?;
+
+            forward_with_lora(&self.feed_forward_w2, down_lora, &(&w1 * w3)?)
+        }
+    }
+}
+
+pub struct GemmaFeedForward {
+    pub gate: QMatMul,
+    pub up: QMatMul,
+    pub down: QMatMul,
+}
+
+impl GemmaFeedForward {
+    fn forward(&self, x: &Tensor, lora: Option<&LoraLayer>) -> candle_core::Result<Tensor> {
+        // Gemma uses GeGLU (the tanh approximation of gelu) instead of SwiGLU
+        let gate = forward_with_lora(&self.gate, lora.and_then(|l| l.gate.as_ref()), x)?.gelu()?;
+        let up = forward_with_lora(&self.up, lora.and_then(|l| l.up.as_ref()), x)?;
+        forward_with_lora(
+            &self.down,
+            lora.and_then(|l| l.down.as_ref()),
+            &(gate * up)?,
+        )
+    }
+}
+
+/// An expert that is always active and mixed into the output of a mixture of experts layer (used by Qwen2-MoE)
+pub struct SharedExpert {
+    pub expert: LlamaFeedForward,
+    /// The gate weights with the shape (1, hidden_size)
+    pub gate: Tensor,
+}
+
+pub struct MixtureOfExpertsFeedForward {
+    pub gate_inp: QMatMul,
+    pub experts: Vec<LlamaFeedForward>,
+    pub n_expert_used: usize,
+    pub normalize_expert_weights: bool,
+    pub shared_expert: Option<SharedExpert>,
+}
+
+impl MixtureOfExpertsFeedForward {
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        let (b_sz, seq_len, hidden_dim) = x.dims3()?;
+        let x = x.reshape(((), hidden_dim))?;
+        let router_logits = self.gate_inp.forward(&x)?;
+        let (expert_tokens, expert_weights) = route_tokens(
+            &router_logits,
+            self.n_expert_used,
+            self.normalize_expert_weights,
+        )?;
+
+        let mut output = x.zeros_like()?;
+        for (expert_idx, expert) in self.experts.iter().enumerate() {
+            let tokens = &expert_tokens[expert_idx];
+            if tokens.is_empty() {
+                continue;
+            }
+            let tokens = Tensor::new(tokens.as_slice(), x.device())?;
+            let weights = Tensor::new(expert_weights[expert_idx].as_slice(), x.device())?
+                .reshape(((), 1))?
+                .to_dtype(x.dtype())?;
+            let expert_input = x.index_select(&tokens, 0)?;
+            let expert_output = expert
+                .forward(&expert_input, None)?
+                .broadcast_mul(&weights)?;
+            output = output.index_add(&tokens, &expert_output, 0)?;
+        }
+
+        if let Some(shared) = &self.shared_expert {
+            let gate = x.matmul(&shared.gate.to_dtype(x.dtype())?.t()?)?;
+            let gate = candle_nn::ops::sigmoid(&gate)?;
+            let shared_output = shared.expert.forward(&x, None)?.broadcast_mul(&gate)?;
+            output = (output + shared_output)?;
+        }
+
+        output.reshape((b_sz, seq_len, hidden_dim))
+    }
+}
 
-            self.feed_forward_w2.forward(&(&w1 * w3)?)
+/// The tokens routed to each expert and the weight of each of those tokens
+type ExpertRoutes = (Vec<Vec<u32>>, Vec<Vec<f32>>);
+
+/// Pick the top `n_expert_used` experts for each token from the router logits with the shape (tokens, experts).
+fn route_tokens(
+    router_logits: &Tensor,
+    n_expert_used: usize,
+    normalize_expert_weights: bool,
+) -> candle_core::Result<ExpertRoutes> {
+    let routing_weights = candle_nn::ops::softmax_last_dim(&router_logits.to_dtype(DType::F32)?)?;
+    let routing_weights = routing_weights.to_vec2::<f32>()?;
+    let expert_count = router_logits.dim(1)?;
+
+    // Find the top k experts for each token and group the tokens by expert
+    let mut expert_tokens = vec![vec![]; expert_count];
+    let mut expert_weights = vec![vec![]; expert_count];
+    for (token_idx, weights) in routing_weights.iter().enumerate() {
+        let mut experts = (0..weights.len()).collect::<Vec<_>>();
+        experts.sort_by(|&a, &b| weights[b].total_cmp(&weights[a]));
+        let experts = &experts[..n_expert_used.min(experts.len())];
+        let total_weight = if normalize_expert_weights {
+            experts.iter().map(|&expert| weights[expert]).sum::<f32>()
+        } else {
+            1.
+        };
+        for &expert in experts {
+            expert_tokens[expert].push(token_idx as u32);
+            expert_weights[expert].push(weights[expert] / total_weight);
         }
     }
+    Ok((expert_tokens, expert_weights))
 }
 
 pub enum AttentionVariant {
@@ -95,6 +215,7 @@ pub struct SeparateAttention {
 }
 
 impl SeparateAttention {
+    #[allow(clippy::too_many_arguments)]
     fn forward(
         &self,
         num_heads: usize,
@@ -103,37 +224,48 @@ impl SeparateAttention {
         hidden_states: &Tensor,
         rope_cache: &RopeCache,
         start_pos: usize,
+        lora: Option<&LoraLayer>,
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        let query_lora = lora.and_then(|l| l.query.as_ref());
+        let key_lora = lora.and_then(|l| l.key.as_ref());
+        let value_lora = lora.and_then(|l| l.value.as_ref());
         let b_sz = hidden_states.dims()[0];
         let seq_len = hidden_states.dims()[1];
         let device = hidden_states.device();
 
         if matches!(device, Device::Cpu) {
-            std::thread::scope(|s| -> Result<_, candle_core::Error> {
-                let query_states = s.spawn(|| {
-                    let mut query_states = self.attention_wq.forward(hidden_states)?;
-
-                    if let Some(bias) = &self.bias {
-                        query_states = query_states.broadcast_add(&bias.bias_q)?;
-                    }
-
-                    query_states
-                        .reshape((b_sz, seq_len, num_heads, head_dim))?
-                        .transpose(1, 2)
-                });
-                let key_states = s.spawn(|| {
-                    let mut key_states = self.attention_wk.forward(hidden_states)?;
-
-                    if let Some(bias) = &self.bias {
-                        key_states = key_states.broadcast_add(&bias.bias_k)?;
-                    }
-
-                    key_states
-                        .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
-                        .transpose(1, 2)
-                });
-                let value_states = s.spawn(|| {
-                    let mut value_states = self.attention_wv.forward(hidden_states)?;
+            let ((query_states, key_states), value_states) = rayon::join(
+                || {
+                    rayon::join(
+                        || {
+                            let mut query_states =
+                                forward_with_lora(&self.attention_wq, query_lora, hidden_states)?;
+
+                            if let Some(bias) = &self.bias {
+                                query_states = query_states.broadcast_add(&bias.bias_q)?;
+                            }
+
+                            query_states
+                                .reshape((b_sz, seq_len, num_heads, head_dim))?
+                                .transpose(1, 2)
+                        },
+                        || {
+                            let mut key_states =
+                                forward_with_lora(&self.attention_wk, key_lora, hidden_states)?;
+
+                            if let Some(bias) = &self.bias {
+                                key_states = key_states.broadcast_add(&bias.bias_k)?;
+                            }
+
+                            key_states
+                                .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
+                                .transpose(1, 2)
+                        },
+                    )
+                },
+                || {
+                    let mut value_states =
+                        forward_with_lora(&self.attention_wv, value_lora, hidden_states)?;
 
                     if let Some(bias) = &self.bias {
                         value_states = value_states.broadcast_add(&bias.bias_v)?;
@@ -142,30 +274,21 @@ impl SeparateAttention {
                     value_states
                         .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
                         .transpose(1, 2)
-                });
-
-                let query_states = query_states.join().map_err(|_| {
-                    candle_core::Error::Msg("failed to join query states".to_string())
-                })??;
-                let key_states = key_states.join().map_err(|_| {
-                    candle_core::Error::Msg("failed to join key states".to_string())
-                })??;
-
-                let (query_states, key_states) = if self.interleaved_rope {
-                    rope_cache.forward_i(&query_states, &key_states, start_pos)?
-                } else {
-                    rope_cache.forward(&query_states, &key_states, start_pos)?
-                };
-
-                let value_states = value_states.join().map_err(|_| {
-                    candle_core::Error::Msg("failed to join value states".to_string())
-                })??;
-
-                Ok((query_states, key_states, value_states))
-            })
+                },
+            );
+            let (query_states, key_states) = (query_states?, key_states?);
+
+            let (query_states, key_states) = if self.interleaved_rope {
+                rope_cache.forward_i(&query_states, &key_states, start_pos)?
+            } else {
+                rope_cache.forward(&query_states, &key_states, start_pos)?
+            };
+
+            Ok((query_states, key_states, value_states?))
         } else {
             let query_states = {
-                let mut query_states = self.attention_wq.forward(hidden_states)?;
+                let mut query_states =
+                    forward_with_lora(&self.attention_wq, query_lora, hidden_states)?;
 
                 if let Some(bias) = &self.bias {
                     query_states = query_states.broadcast_add(&bias.bias_q)?;
@@ -176,7 +299,8 @@ impl SeparateAttention {
                     .transpose(1, 2)?
             };
             let key_states = {
-                let mut key_states = self.attention_wk.forward(hidden_states)?;
+                let mut key_states =
+                    forward_with_lora(&self.attention_wk, key_lora, hidden_states)?;
 
                 if let Some(bias) = &self.bias {
                     key_states = key_states.broadcast_add(&bias.bias_k)?;
@@ -187,7 +311,8 @@ impl SeparateAttention {
                     .transpose(1, 2)?
             };
             let value_states = {
-                let mut value_states = self.attention_wv.forward(hidden_states)?;
+                let mut value_states =
+                    forward_with_lora(&self.attention_wv, value_lora, hidden_states)?;
 
                 if let Some(bias) = &self.bias {
                     value_states = value_states.broadcast_add(&bias.bias_v)?;
@@ -214,6 +339,7 @@ pub struct GroupedAttention {
 }
 
 impl GroupedAttention {
+    #[allow(clippy::too_many_arguments)]
     fn forward(
         &self,
         num_heads: usize,
@@ -222,10 +348,15 @@ impl GroupedAttention {
         x: &Tensor,
         rope_cache: &RopeCache,
         start_pos: usize,
+        lora: Option<&LoraLayer>,
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = x.dims()[0];
         let seq_len = x.dims()[1];
-        let qkv = self.attention_qkv.forward(x)?;
+        let qkv = forward_with_lora(
+            &self.attention_qkv,
+            lora.and_then(|l| l.query_key_value.as_ref()),
+            x,
+        )?;
 
         let query_pos = num_heads * head_dim;
         let query_states = qkv.narrow(D::Minus1, 0, query_pos)?;
@@ -259,10 +390,16 @@ pub struct LlamaAttention {
     pub attention_norm: RmsNorm,
     pub feed_forward_variant: FeedForwardVariant,
     pub ffn_norm: RmsNorm,
+    pub post_attention_norm: Option<RmsNorm>,
+    pub post_ffn_norm: Option<RmsNorm>,
     pub n_head: usize,
     pub n_kv_head: usize,
     pub head_dim: usize,
     pub hidden_size: usize,
+    pub attention_scale: f64,
+    pub attention_logit_softcapping: Option<f64>,
+    /// The number of previous tokens each token can attend to if the layer uses sliding window attention
+    pub sliding_window: Option<usize>,
     pub rope_cache: RopeCache,
 }
 
@@ -273,6 +410,7 @@ impl LlamaAttention {
         attention_mask: Option<&AttentionMask>,
         start_pos: usize,
         cache: Option<&mut KvCache>,
+        lora: Option<&LoraLayer>,
     ) -> candle_core::Result<Tensor> {
         let bsz = hidden_states.dims()[0];
         let q_len = hidden_states.dims()[1];
@@ -290,6 +428,7 @@ impl LlamaAttention {
                 hidden_states,
                 &self.rope_cache,
                 start_pos,
+                lora,
             )?,
             AttentionVariant::Grouped(ref attention) => attention.forward(
                 num_heads,
@@ -298,6 +437,7 @@ impl LlamaAttention {
                 hidden_states,
                 &self.rope_cache,
                 start_pos,
+                lora,
             )?,
         };
 
@@ -309,7 +449,11 @@ impl LlamaAttention {
             Some(cache) => cache.append(&key_states, &value_states)?,
         };
 
-        let mut attn_weights = (query_states.matmul(&key_states.t()?)? / (head_dim as f64).sqrt())?;
+        let mut attn_weights = (query_states.matmul(&key_states.t()?)? * self.attention_scale)?;
+
+        if let Some(softcapping) = self.attention_logit_softcapping {
+            attn_weights = soft_cap(&attn_weights, softcapping)?;
+        }
 
         if let Some(attention_mask) = attention_mask {
             attention_mask.forward(&mut attn_weights)?;
@@ -331,12 +475,21 @@ impl LlamaAttention {
 
         attn_output = attn_output.reshape(&[bsz, q_len, hidden_size])?;
 
-        attn_output = self.attention_wo.forward(&attn_output)?;
+        attn_output = forward_with_lora(
+            &self.attention_wo,
+            lora.and_then(|l| l.output.as_ref()),
+            &attn_output,
+        )?;
 
         Ok(attn_output)
     }
 }
 
+/// Smoothly limit the values of a tensor to the range (-cap, cap)
+pub(crate) fn soft_cap(x: &Tensor, cap: f64) -> candle_core::Result<Tensor> {
+    (x / cap)?.tanh()? * cap
+}
+
 fn repeat_kv(x: Tensor, num_key_value_groups: usize) -> candle_core::Result<Tensor> {
     if num_key_value_groups == 1 {
         Ok(x)
@@ -350,3 +503,53 @@ fn repeat_kv(x: Tensor, num_key_value_groups: usize) -> candle_core::Result<Tens
         ))
     }
 }
+
+#[test]
+fn route_tokens_to_top_experts() -> candle_core::Result<()> {
+    let ln = |x: f32| x.ln();
+    // The softmax of the logits is [0.1, 0.6, 0.3] for the first token and [0.5, 0.2, 0.3] for the second
+    let logits = Tensor::new(
+        &[[ln(0.1), ln(0.6), ln(0.3)], [ln(0.5), ln(0.2), ln(0.3)]],
+        &Device::Cpu,
+    )?;
+    let assert_weights = |actual: &[Vec<f32>], expected: &[&[f32]]| {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert_eq!(actual.len(), expected.len());
+            for (actual, expected) in actual.iter().zip(*expected) {
+                assert!((actual - expected).abs() < 1e-5, "{actual} != {expected}");
+            }
+        }
+    };
+
+    let (tokens, weights) = route_tokens(&logits, 2, false)?;
+    assert_eq!(tokens, [vec![1], vec![0], vec![0, 1]]);
+    assert_weights(&weights, &[&[0.5], &[0.6], &[0.3, 0.3]]);
+
+    // Mixtral renormalizes the weights of the selected experts so they sum to one
+    let (tokens, weights) = route_tokens(&logits, 2, true)?;
+    assert_eq!(tokens, [vec![1], vec![0], vec![0, 1]]);
+    assert_weights(&weights, &[&[0.625], &[0.6 / 0.9], &[0.3 / 0.9, 0.375]]);
+
+    let (tokens, weights) = route_tokens(&logits, 1, true)?;
+    assert_eq!(tokens, [vec![1], vec![0], vec![]]);
+    assert_weights(&weights, &[&[1.], &[1.], &[]]);
+    Ok(())
+}
+
+#[test]
+fn soft_cap_limits_values() -> candle_core::Result<()> {
+    let x = Tensor::new(&[0f32, 1., -1., 1000., -1000.], &Device::Cpu)?;
+    let capped = soft_cap(&x, 30.)?.to_vec1::<f32>()?;
+    let expected = [
+        0.,
+        30. * (1f32 / 30.).tanh(),
+        -30. * (1f32 / 30.).tanh(),
+        30.,
+        -30.,
+    ];
+    for (capped, expected) in capped.iter().zip(expected) {
+        assert!((capped - expected).abs() < 1e-4, "{capped} != {expected}");
+    }
+    Ok(())
+}
@@ -1,6 +1,5 @@
 use candle_core::{Device, Tensor};
-use candle_nn::kv_cache::Cache;
-use kalosm_common::KvCache;
+use kalosm_common::{KvCache, KvCacheQuantization};
 use std::collections::HashMap;
 
 use super::LlamaConfig;
@@ -22,7 +21,11 @@ impl LlamaCache {
         let max_seq_len = config.context_length;
         let mut blocks = Vec::with_capacity(config.n_layer);
         for _ in 0..config.n_layer {
-            blocks.push(KvCache::new(CONCAT_DIMENSION, max_seq_len))
+            blocks.push(KvCache::new_quantized(
+                CONCAT_DIMENSION,
+                max_seq_len,
+                config.kv_cache_quantization,
+            ))
         }
         Self {
             max_seq_len,
@@ -38,13 +41,23 @@ impl LlamaCache {
         }
     }
 
+    /// Get the quantization of the keys and values in the cache.
+    pub fn quantization(&self) -> KvCacheQuantization {
+        self.blocks
+            .first()
+            .map(|block| block.quantization())
+            .unwrap_or_default()
+    }
+
     /// Get the tensor map for this cache. This can be used to save the cache to disk.
+    ///
+    /// Quantized caches save the quantized keys and values, so they are as small on disk as they are in memory.
     pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
         let mut map = HashMap::with_capacity(self.blocks.len());
         for (i, kv_cache) in self.blocks.iter().enumerate() {
-            if let (Ok(Some(k)), Ok(Some(v))) = (kv_cache.cache().k(), kv_cache.cache().v()) {
-                map.insert(format!("llama.cache.blocks.{}.key", i), k);
-                map.insert(format!("llama.cache.blocks.{}.value", i), v);
+            if let Err(err) = kv_cache.save_tensors(&format!("llama.cache.blocks.{}", i), &mut map)
+            {
+                tracing::error!("Failed to save block {i} of the kv cache: {err}");
             }
         }
         map.insert(
@@ -55,6 +68,14 @@ impl LlamaCache {
             "llama.cache.max_seq_len".to_string(),
             Tensor::new(self.max_seq_len as u32, device).unwrap(),
         );
+        map.insert(
+            "llama.cache.block_count".to_string(),
+            Tensor::new(self.blocks.len() as u32, device).unwrap(),
+        );
+        map.insert(
+            "llama.cache.quantization".to_string(),
+            Tensor::new(quantization_id(self.quantization()), device).unwrap(),
+        );
         map
     }
 
@@ -68,52 +89,32 @@ impl LlamaCache {
             .get("llama.cache.max_seq_len")
             .and_then(|max_seq_len| max_seq_len.to_scalar::<u32>().ok())
             .unwrap_or(2048) as usize;
-        let mut blocks = Vec::with_capacity(24);
-        for (k, v) in map {
-            if let Some(i) = k.strip_prefix("llama.cache.blocks.") {
-                let i = i
-                    .strip_suffix(".key")
-                    .unwrap_or_else(|| i.strip_suffix(".value").unwrap());
-                let i = i.parse::<usize>().unwrap_or(0);
-                if i >= blocks.len() {
-                    blocks.resize(i + 1, KvCache::new(CONCAT_DIMENSION, max_seq_len));
-                }
-                if k.ends_with(".key") {
-                    match blocks.get_mut(i) {
-                        Some(cache) => {
-                            let key_cache = cache.cache_mut().k_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *key_cache = Cache::new(CONCAT_DIMENSION, len);
-                            key_cache.append(&v)?;
-                        }
-                        _ => {
-                            let mut cache = KvCache::new(CONCAT_DIMENSION, max_seq_len);
-                            let key_cache = cache.cache_mut().k_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *key_cache = Cache::new(CONCAT_DIMENSION, len);
-                            key_cache.append(&v)?;
-                            blocks[i] = cache;
-                        }
-                    }
-                } else if k.ends_with(".value") {
-                    match blocks.get_mut(i) {
-                        Some(cache) => {
-                            let value_cache = cache.cache_mut().v_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *value_cache = Cache::new(CONCAT_DIMENSION, len);
-                            value_cache.append(&v)?;
-                        }
-                        _ => {
-                            let mut cache = KvCache::new(CONCAT_DIMENSION, max_seq_len);
-                            let value_cache = cache.cache_mut().v_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *value_cache = Cache::new(CONCAT_DIMENSION, len);
-                            value_cache.append(&v)?;
-                            blocks[i] = cache;
-                        }
-                    }
-                }
-            }
+        let quantization = map
+            .get("llama.cache.quantization")
+            .and_then(|quantization| quantization.to_scalar::<u32>().ok())
+            .map(quantization_from_id)
+            .transpose()?
+            .unwrap_or_default();
+        // Older caches don't store the block count. Empty blocks are not saved, so we use the last saved block instead
+        let block_count = match map
+            .get("llama.cache.block_count")
+            .and_then(|block_count| block_count.to_scalar::<u32>().ok())
+        {
+            Some(block_count) => block_count as usize,
+            None => map
+                .keys()
+                .filter_map(|key| key.strip_prefix("llama.cache.blocks."))
+                .filter_map(|key| key.split_once('.'))
+                .filter_map(|(i, _)| i.parse::<usize>().ok())
+                .map(|i| i + 1)
+                .max()
+                .unwrap_or_default(),
+        };
+        let mut blocks = Vec::with_capacity(block_count);
+        for i in 0..block_count {
+            let mut cache = KvCache::new_quantized(CONCAT_DIMENSION, max_seq_len, quantization);
+            cache.load_tensors(&format!("llama.cache.blocks.{}", i), &map)?;
+            blocks.push(cache);
         }
         Ok(Self {
             tokens,
@@ -122,3 +123,20 @@ impl LlamaCache {
         })
     }
 }
+
+fn quantization_id(quantization: KvCacheQuantization) -> u32 {
+    match quantization {
+        KvCacheQuantization::None => 0,
+        KvCacheQuantization::Q8_0 => 1,
+        KvCacheQuantization::Q4_0 => 2,
+    }
+}
+
+fn quantization_from_id(id: u32) -> candle_core::Result<KvCacheQuantization> {
+    Ok(match id {
+        0 => KvCacheQuantization::None,
+        1 => KvCacheQuantization::Q8_0,
+        2 => KvCacheQuantization::Q4_0,
+        _ => candle_core::bail!("unknown kv cache quantization {id}"),
+    })
+}
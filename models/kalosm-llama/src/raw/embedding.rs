@@ -0,0 +1,243 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use candle_core::quantized::{ggml_file, gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Result, Tensor};
+
+/// A model file that is mapped into memory. Processes that map the same file share the same pages in the page cache.
+#[derive(Debug, Clone)]
+pub(crate) struct MappedFile(Arc<memmap2::Mmap>);
+
+impl MappedFile {
+    /// Map a file into memory. The file must not be modified while it is mapped.
+    pub(crate) fn new(file: &std::fs::File) -> std::io::Result<Self> {
+        // Safety: Model files are never written to while a model is loaded. This is the same assumption candle makes for mmaped safetensors
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        Ok(Self(Arc::new(mmap)))
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The raw bytes of a tensor in a model file.
+#[derive(Debug, Clone)]
+pub(crate) enum WeightBytes {
+    /// Bytes that are read lazily from a memory mapped file
+    Mapped {
+        file: MappedFile,
+        range: Range<usize>,
+    },
+    /// Bytes that were read into memory
+    Owned(Arc<[u8]>),
+}
+
+impl Deref for WeightBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            WeightBytes::Mapped { file, range } => &file.as_ref()[range.clone()],
+            WeightBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// A reader for GGUF files that can return the raw bytes of a tensor.
+pub(crate) trait GgufReader: Read + Seek {
+    /// Get the bytes in the range of the file. By default, this reads the bytes into memory.
+    fn bytes(&mut self, range: Range<usize>) -> std::io::Result<WeightBytes> {
+        let mut bytes = vec![0; range.len()];
+        self.seek(SeekFrom::Start(range.start as u64))?;
+        self.read_exact(&mut bytes)?;
+        Ok(WeightBytes::Owned(bytes.into()))
+    }
+
+    /// Get the bytes of a tensor in the GGUF file.
+    fn tensor_bytes<'a>(
+        &mut self,
+        content: &'a gguf_file::Content,
+        name: &str,
+    ) -> Result<(&'a gguf_file::TensorInfo, WeightBytes)> {
+        let Some(info) = content.tensor_infos.get(name) else {
+            candle_core::bail!("cannot find tensor info for {name}")
+        };
+        let dtype = info.ggml_dtype;
+        let len = info.shape.elem_count() / dtype.block_size() * dtype.type_size();
+        let start = (content.tensor_data_offset + info.offset) as usize;
+        let bytes = self.bytes(start..start + len)?;
+        Ok((info, bytes))
+    }
+
+    /// Load a quantized tensor from the GGUF file. Tensors in mapped files are copied straight from the mapped pages instead of a temporary buffer.
+    fn qtensor(
+        &mut self,
+        content: &gguf_file::Content,
+        name: &str,
+        device: &Device,
+    ) -> Result<QTensor> {
+        let (info, bytes) = self.tensor_bytes(content, name)?;
+        ggml_file::qtensor_from_ggml(info.ggml_dtype, &bytes, info.shape.dims().to_vec(), device)
+    }
+}
+
+impl GgufReader for std::fs::File {}
+
+impl GgufReader for std::io::Cursor<MappedFile> {
+    fn bytes(&mut self, range: Range<usize>) -> std::io::Result<WeightBytes> {
+        let file = self.get_ref();
+        if range.end > file.as_ref().len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "tensor data is out of bounds of the model file",
+            ));
+        }
+        Ok(WeightBytes::Mapped {
+            file: file.clone(),
+            range,
+        })
+    }
+}
+
+/// Token embeddings that stay quantized. Only the rows for the tokens in the input are dequantized.
+#[derive(Debug, Clone)]
+pub(crate) struct QuantizedEmbedding {
+    bytes: WeightBytes,
+    dtype: GgmlDType,
+    vocab_size: usize,
+    hidden_size: usize,
+}
+
+impl QuantizedEmbedding {
+    /// Create embeddings from the raw bytes of a quantized tensor with the shape (vocab_size, hidden_size)
+    pub(crate) fn new(
+        bytes: WeightBytes,
+        dtype: GgmlDType,
+        vocab_size: usize,
+        hidden_size: usize,
+    ) -> Result<Self> {
+        let embedding = Self {
+            bytes,
+            dtype,
+            vocab_size,
+            hidden_size,
+        };
+        if embedding.bytes.len() != vocab_size * embedding.row_bytes() {
+            candle_core::bail!(
+                "expected {} bytes of token embeddings, found {}",
+                vocab_size * embedding.row_bytes(),
+                embedding.bytes.len()
+            );
+        }
+        Ok(embedding)
+    }
+
+    /// Create embeddings from a quantized tensor with the shape (vocab_size, hidden_size)
+    pub(crate) fn from_qtensor(tensor: &QTensor) -> Result<Self> {
+        let (vocab_size, hidden_size) = tensor.shape().dims2()?;
+        let bytes = WeightBytes::Owned(tensor.data()?.into_owned().into());
+        Self::new(bytes, tensor.dtype(), vocab_size, hidden_size)
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.hidden_size / self.dtype.block_size() * self.dtype.type_size()
+    }
+
+    /// Look up the embeddings for a tensor of token ids. The embeddings are returned on the given device.
+    pub(crate) fn forward(&self, ids: &Tensor, device: &Device) -> Result<Tensor> {
+        let token_ids = ids.flatten_all()?.to_vec1::<u32>()?;
+        let row_bytes = self.row_bytes();
+        let mut rows = Vec::with_capacity(token_ids.len() * row_bytes);
+        for &id in &token_ids {
+            let id = id as usize;
+            if id >= self.vocab_size {
+                candle_core::bail!(
+                    "token {id} is out of range for a vocab of {}",
+                    self.vocab_size
+                );
+            }
+            rows.extend_from_slice(&self.bytes[id * row_bytes..(id + 1) * row_bytes]);
+        }
+        let rows = ggml_file::qtensor_from_ggml(
+            self.dtype,
+            &rows,
+            vec![token_ids.len(), self.hidden_size],
+            &Device::Cpu,
+        )?;
+        let mut dims = ids.dims().to_vec();
+        dims.push(self.hidden_size);
+        rows.dequantize(&Device::Cpu)?
+            .to_device(device)?
+            .reshape(dims)
+    }
+}
+
+#[test]
+fn quantized_embedding_lookup() -> Result<()> {
+    let device = Device::Cpu;
+    let weights = Tensor::randn(0f32, 1., (8, 64), &device)?;
+    let quantized = QTensor::quantize(&weights, GgmlDType::Q8_0)?;
+    let embedding = QuantizedEmbedding::from_qtensor(&quantized)?;
+
+    let ids = Tensor::new(&[[5u32, 0, 5]], &device)?;
+    let embedded = embedding.forward(&ids, &device)?;
+    assert_eq!(embedded.dims(), &[1, 3, 64]);
+
+    let expected = quantized
+        .dequantize(&device)?
+        .embedding(&ids.flatten_all()?)?;
+    let difference = (embedded.squeeze(0)? - expected)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    assert_eq!(difference, 0.);
+    assert!(embedding
+        .forward(&Tensor::new(&[8u32], &device)?, &device)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn mapped_tensor_bytes_match_file() -> Result<()> {
+    let device = Device::Cpu;
+    let weights = Tensor::randn(0f32, 1., (4, 32), &device)?;
+    let embeddings = QTensor::quantize(&weights, GgmlDType::Q4_0)?;
+    let norm = QTensor::quantize(
+        &Tensor::ones(32, candle_core::DType::F32, &device)?,
+        GgmlDType::F32,
+    )?;
+    let path = std::env::temp_dir().join(format!("kalosm-llama-mmap-{}.gguf", std::process::id()));
+    let mut file = std::fs::File::create(&path)?;
+    gguf_file::write(
+        &mut file,
+        &[],
+        &[
+            ("output_norm.weight", &norm),
+            ("token_embd.weight", &embeddings),
+        ],
+    )?;
+    drop(file);
+
+    let mut file = std::fs::File::open(&path)?;
+    let content = gguf_file::Content::read(&mut file)?;
+    let (_, read) = file.tensor_bytes(&content, "token_embd.weight")?;
+    let mut mapped = std::io::Cursor::new(MappedFile::new(&file)?);
+    let (info, mapped) = mapped.tensor_bytes(&content, "token_embd.weight")?;
+    assert!(matches!(mapped, WeightBytes::Mapped { .. }));
+    assert_eq!(&*read, &*mapped);
+    assert_eq!(&*mapped, &*embeddings.data()?);
+    assert_eq!(info.shape.dims(), &[4, 32]);
+
+    let mut mapped = std::io::Cursor::new(MappedFile::new(&file)?);
+    let tensor = mapped.qtensor(&content, "token_embd.weight", &device)?;
+    assert_eq!(tensor.shape().dims(), &[4, 32]);
+    assert_eq!(&*tensor.data()?, &*embeddings.data()?);
+
+    std::fs::remove_file(path)?;
+    Ok(())
+}
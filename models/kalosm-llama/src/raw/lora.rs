@@ -0,0 +1,339 @@
+use std::path::Path;
+
+use candle_core::quantized::{gguf_file, QMatMul};
+use candle_core::{DType, Device, Module, Result, Tensor};
+
+use super::LlamaConfig;
+
+/// The low rank weights for a single projection in a LoRA adapter.
+#[derive(Debug, Clone)]
+pub(crate) struct LoraWeights {
+    // The transposed A matrix with the shape (in, rank)
+    a_t: Tensor,
+    // The transposed B matrix with the shape (rank, out)
+    b_t: Tensor,
+    scale: f64,
+}
+
+impl LoraWeights {
+    fn new(a: &Tensor, b: &Tensor, scale: f64) -> Result<Self> {
+        Ok(Self {
+            a_t: a.to_dtype(DType::F32)?.t()?.contiguous()?,
+            b_t: b.to_dtype(DType::F32)?.t()?.contiguous()?,
+            scale,
+        })
+    }
+
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let x = x.broadcast_matmul(&self.a_t)?;
+        x.broadcast_matmul(&self.b_t)? * self.scale
+    }
+}
+
+/// Run a quantized projection with an optional LoRA adapter applied on top of it.
+pub(crate) fn forward_with_lora(
+    matmul: &QMatMul,
+    lora: Option<&LoraWeights>,
+    x: &Tensor,
+) -> Result<Tensor> {
+    let output = matmul.forward(x)?;
+    match lora {
+        Some(lora) => output + lora.forward(x)?,
+        None => Ok(output),
+    }
+}
+
+/// The LoRA weights for a single layer of the model.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoraLayer {
+    pub(crate) query: Option<LoraWeights>,
+    pub(crate) key: Option<LoraWeights>,
+    pub(crate) value: Option<LoraWeights>,
+    pub(crate) query_key_value: Option<LoraWeights>,
+    pub(crate) output: Option<LoraWeights>,
+    pub(crate) gate: Option<LoraWeights>,
+    pub(crate) up: Option<LoraWeights>,
+    pub(crate) down: Option<LoraWeights>,
+}
+
+impl LoraLayer {
+    fn projection_mut(&mut self, name: &str) -> Result<&mut Option<LoraWeights>> {
+        Ok(match name {
+            "q_proj" | "attn_q" => &mut self.query,
+            "k_proj" | "attn_k" => &mut self.key,
+            "v_proj" | "attn_v" => &mut self.value,
+            "qkv_proj" | "attn_qkv" => &mut self.query_key_value,
+            "o_proj" | "attn_output" => &mut self.output,
+            "gate_proj" | "ffn_gate" => &mut self.gate,
+            // Phi-3 stores the gate and up projections in the ffn_up tensor
+            "up_proj" | "gate_up_proj" | "ffn_up" => &mut self.up,
+            "down_proj" | "ffn_down" => &mut self.down,
+            _ if name.contains("exps") || name.contains("shexp") => {
+                candle_core::bail!(
+                    "LoRA adapters for mixture of experts layers are not supported ({name})"
+                )
+            }
+            _ => candle_core::bail!("unsupported LoRA target module {name}"),
+        })
+    }
+}
+
+/// The LoRA adapters loaded for a model and the adapter new sessions use.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoraAdapters {
+    pub(crate) adapters: Vec<LoraAdapter>,
+    pub(crate) default: Option<usize>,
+}
+
+/// A LoRA adapter that is applied on top of the quantized weights of a model at inference time.
+#[derive(Debug, Clone)]
+pub struct LoraAdapter {
+    pub(crate) layers: Vec<LoraLayer>,
+}
+
+impl LoraAdapter {
+    /// Load an adapter from a PEFT safetensors file or a llama.cpp GGUF LoRA file.
+    ///
+    /// The low rank update is multiplied by `scale`. GGUF adapters store `alpha`, so their update is also multiplied by `alpha / rank`. PEFT adapters store `alpha` in a separate config file, so the scale should include `alpha / rank` for those adapters.
+    pub(crate) fn load(
+        path: &Path,
+        config: &LlamaConfig,
+        scale: f64,
+        device: &Device,
+    ) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gguf") => {
+                let mut file = std::fs::File::open(path)?;
+                let content = gguf_file::Content::read(&mut file)?;
+                Self::from_gguf(content, &mut file, config, scale, device)
+            }
+            _ => {
+                let tensors = candle_core::safetensors::load(path, device)?;
+                Self::from_safetensors(tensors, config, scale)
+            }
+        }
+    }
+
+    fn from_gguf<R: std::io::Seek + std::io::Read>(
+        content: gguf_file::Content,
+        reader: &mut R,
+        config: &LlamaConfig,
+        scale: f64,
+        device: &Device,
+    ) -> Result<Self> {
+        if let Some(adapter_type) = content.metadata.get("adapter.type") {
+            let adapter_type = adapter_type.to_string()?;
+            if adapter_type != "lora" {
+                candle_core::bail!("unsupported adapter type {adapter_type}, expected lora");
+            }
+        }
+        let alpha = match content.metadata.get("adapter.lora.alpha") {
+            Some(alpha) => Some(alpha.to_f32()? as f64),
+            None => None,
+        };
+
+        let mut adapter = Self::empty(config);
+        for name in content.tensor_infos.keys() {
+            // Tensors are named like blk.0.attn_q.weight.lora_a
+            let Some(base) = name.strip_suffix(".weight.lora_a") else {
+                continue;
+            };
+            let a = content.tensor(reader, name, device)?.dequantize(device)?;
+            let b = content
+                .tensor(reader, &format!("{base}.weight.lora_b"), device)?
+                .dequantize(device)?;
+            let Some((layer, projection)) = base
+                .strip_prefix("blk.")
+                .and_then(|base| base.split_once('.'))
+            else {
+                candle_core::bail!("unsupported LoRA tensor {name}");
+            };
+            let scale = match alpha {
+                Some(alpha) => scale * alpha / a.dim(0)? as f64,
+                None => scale,
+            };
+            adapter.insert(layer, projection, LoraWeights::new(&a, &b, scale)?)?;
+        }
+
+        Ok(adapter)
+    }
+
+    fn from_safetensors(
+        tensors: std::collections::HashMap<String, Tensor>,
+        config: &LlamaConfig,
+        scale: f64,
+    ) -> Result<Self> {
+        let mut adapter = Self::empty(config);
+        for (name, a) in &tensors {
+            // Tensors are named like base_model.model.model.layers.0.self_attn.q_proj.lora_A.weight
+            let Some(base) = name.strip_suffix(".lora_A.weight") else {
+                continue;
+            };
+            let Some(b) = tensors.get(&format!("{base}.lora_B.weight")) else {
+                candle_core::bail!("cannot find the lora_B tensor for {name}");
+            };
+            let Some((layer, module)) = base
+                .split_once("layers.")
+                .and_then(|(_, base)| base.split_once('.'))
+            else {
+                candle_core::bail!("unsupported LoRA tensor {name}");
+            };
+            // Experts share projection names with dense layers (mlp.experts.0.gate_proj), so they need to be rejected before the projection is looked up
+            if module.contains("expert") {
+                candle_core::bail!(
+                    "LoRA adapters for mixture of experts layers are not supported ({name})"
+                );
+            }
+            let projection = module.rsplit('.').next().unwrap_or(module);
+            // The GGUF query and key weights are permuted for interleaved rope. The LoRA update needs the same permutation
+            let b = match projection {
+                "q_proj" if config.interleaved_rope => permute_for_rope(b, config.n_head)?,
+                "k_proj" if config.interleaved_rope => permute_for_rope(b, config.n_kv_head)?,
+                _ => b.clone(),
+            };
+            adapter.insert(layer, projection, LoraWeights::new(a, &b, scale)?)?;
+        }
+
+        Ok(adapter)
+    }
+
+    fn empty(config: &LlamaConfig) -> Self {
+        Self {
+            layers: vec![LoraLayer::default(); config.n_layer],
+        }
+    }
+
+    fn insert(&mut self, layer: &str, projection: &str, weights: LoraWeights) -> Result<()> {
+        let layer_count = self.layers.len();
+        let Some(layer) = layer
+            .parse::<usize>()
+            .ok()
+            .and_then(|layer| self.layers.get_mut(layer))
+        else {
+            candle_core::bail!(
+                "LoRA layer {layer} does not exist in a model with {layer_count} layers"
+            );
+        };
+        *layer.projection_mut(projection)? = Some(weights);
+        Ok(())
+    }
+}
+
+fn permute_for_rope(weight: &Tensor, n_head: usize) -> Result<Tensor> {
+    let (out, rank) = weight.dims2()?;
+    weight
+        .reshape((n_head, 2, out / n_head / 2, rank))?
+        .transpose(1, 2)?
+        .reshape((out, rank))
+}
+
+#[cfg(test)]
+fn test_config(n_layer: usize, n_head: usize, interleaved_rope: bool) -> LlamaConfig {
+    LlamaConfig {
+        rope_freq_weight: None,
+        rope_theta: 10000.,
+        rope_scaling: Default::default(),
+        context_length: 16,
+        head_dimension: 2,
+        n_head,
+        n_kv_head: n_head,
+        n_layer,
+        interleaved_rope,
+        embedding_scale: None,
+        final_logit_softcapping: None,
+        kv_cache_quantization: Default::default(),
+        embedding_pooling: Default::default(),
+        normalize_embeddings: true,
+    }
+}
+
+#[test]
+fn permute_lora_for_rope() -> Result<()> {
+    let weight = Tensor::arange(0f32, 8., &Device::Cpu)?.reshape((8, 1))?;
+    // The first and second half of each head are interleaved
+    let permuted = permute_for_rope(&weight, 2)?
+        .flatten_all()?
+        .to_vec1::<f32>()?;
+    assert_eq!(permuted, [0., 2., 1., 3., 4., 6., 5., 7.]);
+    let permuted = permute_for_rope(&weight, 1)?
+        .flatten_all()?
+        .to_vec1::<f32>()?;
+    assert_eq!(permuted, [0., 4., 1., 5., 2., 6., 3., 7.]);
+    Ok(())
+}
+
+#[test]
+fn parse_lora_tensor_names() -> Result<()> {
+    let device = Device::Cpu;
+    let a = Tensor::ones((1, 4), DType::F32, &device)?;
+    let b = Tensor::arange(0f32, 4., &device)?.reshape((4, 1))?;
+    let tensors = |names: &[&str]| {
+        names
+            .iter()
+            .flat_map(|name| {
+                [
+                    (format!("{name}.lora_A.weight"), a.clone()),
+                    (format!("{name}.lora_B.weight"), b.clone()),
+                ]
+            })
+            .collect::<std::collections::HashMap<_, _>>()
+    };
+
+    let adapter = LoraAdapter::from_safetensors(
+        tensors(&[
+            "base_model.model.model.layers.1.self_attn.q_proj",
+            "base_model.model.model.layers.0.mlp.down_proj",
+        ]),
+        &test_config(2, 1, true),
+        1.,
+    )?;
+    assert!(adapter.layers[0].down.is_some());
+    assert!(adapter.layers[0].query.is_none());
+    let query = adapter.layers[1].query.as_ref().unwrap();
+    assert!(adapter.layers[1].down.is_none());
+    // The query update is permuted to match the interleaved rope layout of the GGUF weights
+    let b_t = query.b_t.flatten_all()?.to_vec1::<f32>()?;
+    assert_eq!(b_t, [0., 2., 1., 3.]);
+
+    let out_of_range = LoraAdapter::from_safetensors(
+        tensors(&["base_model.model.model.layers.2.self_attn.q_proj"]),
+        &test_config(2, 1, true),
+        1.,
+    );
+    assert!(out_of_range.is_err());
+    let experts = LoraAdapter::from_safetensors(
+        tensors(&["base_model.model.model.layers.0.mlp.experts.0.gate_proj"]),
+        &test_config(2, 1, true),
+        1.,
+    );
+    assert!(experts.is_err());
+    Ok(())
+}
+
+#[test]
+fn merge_lora_with_quantized_weights() -> Result<()> {
+    use candle_core::quantized::{GgmlDType, QTensor};
+
+    let device = Device::Cpu;
+    let weight = Tensor::arange(0f32, 12., &device)?.reshape((3, 4))?;
+    let matmul = QMatMul::from_qtensor(QTensor::quantize(&weight, GgmlDType::F32)?)?;
+    let a = Tensor::new(&[[1f32, 0., -1., 2.], [0., 1., 1., 0.]], &device)?;
+    let b = Tensor::new(&[[1f32, 2.], [0., -1.], [3., 0.]], &device)?;
+    let lora = LoraWeights::new(&a, &b, 0.5)?;
+    let x = Tensor::new(&[[[1f32, 2., 3., 4.], [-1., 0., 1., 0.]]], &device)?;
+
+    let max_difference = |a: Tensor, b: Tensor| -> Result<f32> {
+        (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+    };
+
+    let without_lora = forward_with_lora(&matmul, None, &x)?;
+    let expected = x.broadcast_matmul(&weight.t()?)?;
+    assert!(max_difference(without_lora, expected)? < 1e-5);
+
+    // Applying the adapter at inference time is the same as merging B * A * scale into the weights
+    let output = forward_with_lora(&matmul, Some(&lora), &x)?;
+    let merged = (weight + (b.matmul(&a)? * 0.5)?)?;
+    let expected = x.broadcast_matmul(&merged.t()?)?;
+    assert!(max_difference(output, expected)? < 1e-5);
+    Ok(())
+}
@@ -1,39 +1,111 @@
 use crate::raw::attention_layer::LlamaAttention;
 use crate::raw::rope::RopeCache;
+pub use crate::raw::rope::RopeScaling;
+use attention_layer::soft_cap;
 use attention_layer::AttentionBias;
 use attention_layer::AttentionVariant;
 use attention_layer::FeedForwardVariant;
+use attention_layer::GemmaFeedForward;
 use attention_layer::GroupedAttention;
 use attention_layer::LlamaFeedForward;
+use attention_layer::MixtureOfExpertsFeedForward;
 use attention_layer::PhiFeedForward;
 use attention_layer::SeparateAttention;
+use attention_layer::SharedExpert;
 use candle_core::quantized::*;
 use candle_core::IndexOp;
 use candle_core::Module;
-use candle_core::{DType, Device, Result, Tensor};
-use candle_nn::Embedding;
+use candle_core::{DType, Device, Result, Tensor, D};
 use candle_transformers::quantized_nn::RmsNorm;
-use kalosm_common::MaskCache;
+use kalosm_common::{KvCacheQuantization, MaskCache};
 
 mod attention_layer;
 pub mod cache;
+mod embedding;
+mod lora;
 mod rope;
 mod silu;
 
 use cache::LlamaCache;
+use embedding::QuantizedEmbedding;
+pub(crate) use embedding::{GgufReader, MappedFile};
+pub use lora::LoraAdapter;
+pub(crate) use lora::LoraAdapters;
+
+/// The number of positions [`Model::forward_log_probs`] runs through the output head at once.
+const LOG_PROB_CHUNK_SIZE: usize = 128;
 
 fn decode_norm(tensor: QTensor, eps: f64) -> candle_core::Result<RmsNorm> {
     RmsNorm::from_qtensor(tensor, eps)
 }
 
+/// The model architectures the GGUF loader supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Architecture {
+    Llama,
+    Phi3,
+    Qwen2,
+    Qwen2Moe,
+    Gemma,
+    Gemma2,
+}
+
+impl Architecture {
+    fn from_gguf_name(name: &str) -> Self {
+        match name {
+            "llama" => Self::Llama,
+            "phi3" => Self::Phi3,
+            "qwen2" => Self::Qwen2,
+            "qwen2moe" => Self::Qwen2Moe,
+            "gemma" => Self::Gemma,
+            "gemma2" => Self::Gemma2,
+            // Many architectures only differ from llama in the tensors they include, so try to load unknown architectures as llama
+            _ => {
+                tracing::warn!("unknown model architecture {name}, loading it as llama");
+                Self::Llama
+            }
+        }
+    }
+
+    fn is_gemma(self) -> bool {
+        matches!(self, Self::Gemma | Self::Gemma2)
+    }
+
+    fn interleaved_rope(self) -> bool {
+        self == Self::Llama
+    }
+}
+
+/// Split a tensor with the weights of every expert stacked in the first dimension into one tensor per expert
+fn split_experts(experts: QTensor, expert_count: usize, device: &Device) -> Result<Vec<QTensor>> {
+    let (n_expert, rows, columns) = experts.shape().dims3()?;
+    if n_expert != expert_count {
+        candle_core::bail!("expected {expert_count} experts, but found {n_expert}");
+    }
+    let dtype = experts.dtype();
+    let data = experts.data()?;
+    let expert_size = data.len() / n_expert;
+    data.chunks_exact(expert_size)
+        .map(|expert| ggml_file::qtensor_from_ggml(dtype, expert, vec![rows, columns], device))
+        .collect()
+}
+
 /// The configuration of a Llama model.
 pub struct LlamaConfig {
     rope_freq_weight: Option<Tensor>,
     rope_theta: f32,
+    rope_scaling: RopeScaling,
     pub(crate) context_length: usize,
     head_dimension: usize,
     n_head: usize,
+    n_kv_head: usize,
     pub(crate) n_layer: usize,
+    interleaved_rope: bool,
+    embedding_scale: Option<f64>,
+    final_logit_softcapping: Option<f64>,
+    kv_cache_quantization: KvCacheQuantization,
+    embedding_pooling: LlamaPooling,
+    normalize_embeddings: bool,
 }
 
 impl LlamaConfig {
@@ -42,9 +114,52 @@ impl LlamaConfig {
     }
 }
 
+/// Settings that override the values stored in the model file.
+#[derive(Debug, Clone, Default)]
+pub struct LlamaConfigOverrides {
+    pub(crate) context_length: Option<usize>,
+    pub(crate) rope_scaling: Option<RopeScaling>,
+    pub(crate) kv_cache_quantization: KvCacheQuantization,
+    pub(crate) embedding_pooling: Option<LlamaPooling>,
+    pub(crate) normalize_embeddings: Option<bool>,
+}
+
+/// How the hidden states of the tokens in a sequence are combined into a single embedding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LlamaPooling {
+    /// Use the hidden state of the last token. Most decoder embedding models are trained with this pooling.
+    #[default]
+    LastToken,
+    /// Take the mean of the hidden states of every token.
+    Mean,
+    /// Use the hidden state of the first token.
+    FirstToken,
+}
+
+impl LlamaPooling {
+    /// Read the pooling from the `pooling_type` metadata llama.cpp writes for embedding models
+    fn from_gguf(pooling_type: u32) -> Option<Self> {
+        match pooling_type {
+            1 => Some(Self::Mean),
+            2 => Some(Self::FirstToken),
+            3 => Some(Self::LastToken),
+            _ => None,
+        }
+    }
+
+    /// Pool hidden states with the shape (seq_len, hidden_size) into a single embedding with the shape (hidden_size)
+    fn pool(&self, hidden_states: &Tensor) -> Result<Tensor> {
+        match self {
+            Self::LastToken => hidden_states.i(hidden_states.dim(0)? - 1),
+            Self::Mean => hidden_states.mean(0),
+            Self::FirstToken => hidden_states.i(0),
+        }
+    }
+}
+
 pub struct Model {
     pub(crate) config: LlamaConfig,
-    tok_embeddings: Embedding,
+    tok_embeddings: QuantizedEmbedding,
     layers: Vec<LlamaAttention>,
     norm: RmsNorm,
     output: QMatMul,
@@ -56,20 +171,29 @@ impl Model {
         mut ct: ggml_file::Content,
         gqa: usize,
         device: &Device,
+        overrides: &LlamaConfigOverrides,
     ) -> anyhow::Result<Self> {
         let head_dim = (ct.hparams.n_embd / ct.hparams.n_head) as usize;
         let n_layer = ct.hparams.n_layer as usize;
         let config = LlamaConfig {
             rope_freq_weight: None,
             rope_theta: 10000.,
+            rope_scaling: overrides.rope_scaling.unwrap_or_default(),
             head_dimension: head_dim,
             n_head: ct.hparams.n_head as usize,
+            n_kv_head: ct.hparams.n_head as usize / gqa,
             n_layer,
-            context_length: 4096,
+            interleaved_rope: true,
+            context_length: overrides.context_length.unwrap_or(4096),
+            embedding_scale: None,
+            final_logit_softcapping: None,
+            kv_cache_quantization: overrides.kv_cache_quantization,
+            embedding_pooling: overrides.embedding_pooling.unwrap_or_default(),
+            normalize_embeddings: overrides.normalize_embeddings.unwrap_or(true),
         };
         let rope = RopeCache::new(&config, DType::F32, device)?;
         let tok_embeddings_q = ct.remove("tok_embeddings.weight")?;
-        let tok_embeddings = tok_embeddings_q.dequantize(device)?;
+        let tok_embeddings = QuantizedEmbedding::from_qtensor(&tok_embeddings_q)?;
         let output = if let Ok(output) = ct.remove("output.weight") {
             QMatMul::from_qtensor(output)?
         } else {
@@ -106,16 +230,21 @@ impl Model {
                 attention_norm: decode_norm(attention_norm, 1e-5)?,
                 feed_forward_variant,
                 ffn_norm: decode_norm(ffn_norm, 1e-5)?,
+                post_attention_norm: None,
+                post_ffn_norm: None,
                 n_head: ct.hparams.n_head as usize,
                 n_kv_head: ct.hparams.n_head as usize / gqa,
                 head_dim: (ct.hparams.n_embd / ct.hparams.n_head) as usize,
                 hidden_size: config.hidden_size(),
+                attention_scale: 1. / (head_dim as f64).sqrt(),
+                attention_logit_softcapping: None,
+                sliding_window: None,
                 rope_cache: rope.clone(),
             })
         }
         Ok(Self {
             config,
-            tok_embeddings: Embedding::new(tok_embeddings, ct.hparams.n_embd as usize),
+            tok_embeddings,
             layers,
             norm: decode_norm(ct.remove("norm.weight")?, 1e-5)?,
             output,
@@ -123,16 +252,20 @@ impl Model {
         })
     }
 
-    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+    pub(crate) fn from_gguf<R: GgufReader>(
         ct: gguf_file::Content,
         reader: &mut R,
         device: &Device,
+        overrides: &LlamaConfigOverrides,
     ) -> Result<Self> {
+        let architecture_name = match ct.metadata.get("general.architecture") {
+            Some(architecture) => architecture.to_string()?.clone(),
+            None => candle_core::bail!("cannot find general.architecture in metadata"),
+        };
+        let architecture = Architecture::from_gguf_name(&architecture_name);
         let md_get = |s: &str| {
             let value = if s.starts_with('.') {
-                ct.metadata
-                    .iter()
-                    .find_map(|(k, value)| k.ends_with(s).then_some(value))
+                ct.metadata.get(&format!("{architecture_name}{s}"))
             } else {
                 ct.metadata.get(s)
             };
@@ -141,6 +274,13 @@ impl Model {
                 Some(v) => Ok(v),
             }
         };
+        let tensor = |reader: &mut R, name: &str| {
+            reader.qtensor(&ct, name, device).map_err(|err| {
+                candle_core::Error::Msg(format!(
+                    "failed to load tensor {name} for the {architecture_name} architecture: {err}"
+                ))
+            })
+        };
 
         // Parameter extraction from metadata.
         let head_count = md_get(".attention.head_count")?.to_u32()? as usize;
@@ -154,90 +294,257 @@ impl Model {
             .and_then(|m| m.to_f32())
             .unwrap_or(10_000f32);
 
-        let context_length = md_get(".context_length")?.to_u32()? as usize;
-        let head_dim = embedding_length / head_count;
+        let mut context_length = md_get(".context_length")?.to_u32()? as usize;
+        let rope_scaling = match overrides.rope_scaling {
+            Some(rope_scaling) => rope_scaling,
+            None => {
+                let factor = md_get(".rope.scaling.factor")
+                    .or_else(|_| md_get(".rope.scale_linear"))
+                    .and_then(|m| m.to_f32());
+                let scaling_type = match md_get(".rope.scaling.type") {
+                    Ok(scaling_type) => Some(scaling_type.to_string()?.as_str()),
+                    Err(_) => None,
+                };
+                match (scaling_type, factor) {
+                    (Some("none"), _) | (_, Err(_)) => RopeScaling::None,
+                    (Some("linear") | None, Ok(factor)) => RopeScaling::Linear { factor },
+                    (Some("yarn"), Ok(factor)) => {
+                        let original_context_length =
+                            match md_get(".rope.scaling.original_context_length") {
+                                Ok(length) => length.to_u32()? as usize,
+                                Err(_) => context_length,
+                            };
+                        // The context length in the file is the context length the model was trained with
+                        context_length =
+                            context_length.max((original_context_length as f32 * factor) as usize);
+                        RopeScaling::Yarn {
+                            factor,
+                            original_context_length,
+                            attention_factor: md_get(".rope.scaling.attn_factor")
+                                .and_then(|m| m.to_f32())
+                                .unwrap_or(1.),
+                            beta_fast: 32.,
+                            beta_slow: 1.,
+                        }
+                    }
+                    (Some(scaling_type), _) => {
+                        candle_core::bail!("unsupported rope scaling type {scaling_type}")
+                    }
+                }
+            }
+        };
+        if let Some(context_length_override) = overrides.context_length {
+            context_length = context_length_override;
+        }
+        // Gemma models have a head dimension that is not the embedding length divided by the head count
+        let head_dim = match md_get(".attention.key_length") {
+            Ok(key_length) => key_length.to_u32()? as usize,
+            Err(_) => embedding_length / head_count,
+        };
+        let expert_count = md_get(".expert_count")
+            .and_then(|m| m.to_u32())
+            .unwrap_or_default() as usize;
+        let expert_used_count = md_get(".expert_used_count")
+            .and_then(|m| m.to_u32())
+            .unwrap_or_default() as usize;
+
+        let mut attention_logit_softcapping = None;
+        let mut final_logit_softcapping = None;
+        let mut attention_scale = 1. / (head_dim as f64).sqrt();
+        let mut sliding_window = None;
+        if architecture == Architecture::Gemma2 {
+            attention_logit_softcapping = Some(
+                md_get(".attn_logit_softcapping")
+                    .and_then(|m| m.to_f32())
+                    .unwrap_or(50.) as f64,
+            );
+            final_logit_softcapping = Some(
+                md_get(".final_logit_softcapping")
+                    .and_then(|m| m.to_f32())
+                    .unwrap_or(30.) as f64,
+            );
+            // Gemma-2 27b scales the queries by the hidden size per head instead of the head dimension
+            if let Ok(scalar) = md_get(".attention.query_pre_attn_scalar") {
+                attention_scale = 1. / (scalar.to_u32()? as f64).sqrt();
+            } else if let Ok(size_label) = md_get("general.size_label") {
+                if size_label.to_string()? == "27B" {
+                    attention_scale = 1. / ((embedding_length / head_count) as f64).sqrt();
+                }
+            }
+            sliding_window = Some(
+                md_get(".attention.sliding_window")
+                    .and_then(|m| m.to_u32())
+                    .unwrap_or(4096) as usize,
+            );
+        }
 
         let config = LlamaConfig {
-            rope_freq_weight: match ct.tensor(reader, "rope_freqs.weight", device).ok() {
+            rope_freq_weight: match reader.qtensor(&ct, "rope_freqs.weight", device).ok() {
                 Some(rope_freq_weight) => Some(rope_freq_weight.dequantize(device)?),
                 None => None,
             },
             rope_theta: rope_freq_base,
+            rope_scaling,
             context_length,
             head_dimension: head_dim,
             n_head: head_count,
+            n_kv_head: head_count_kv,
             n_layer: block_count,
+            interleaved_rope: architecture.interleaved_rope(),
+            embedding_scale: architecture
+                .is_gemma()
+                .then(|| (embedding_length as f64).sqrt()),
+            final_logit_softcapping,
+            kv_cache_quantization: overrides.kv_cache_quantization,
+            embedding_pooling: overrides
+                .embedding_pooling
+                .or_else(|| {
+                    md_get(".pooling_type")
+                        .and_then(|pooling| pooling.to_u32())
+                        .ok()
+                        .and_then(LlamaPooling::from_gguf)
+                })
+                .unwrap_or_default(),
+            normalize_embeddings: overrides.normalize_embeddings.unwrap_or(true),
         };
 
         let rope = RopeCache::new(&config, DType::F32, device)?;
 
-        let tok_embeddings_q = ct.tensor(reader, "token_embd.weight", device)?;
-        let tok_embeddings = tok_embeddings_q.dequantize(device)?;
+        // The token embeddings are looked up from the quantized weights instead of dequantizing the whole table
+        let (embedding_info, embedding_bytes) = reader.tensor_bytes(&ct, "token_embd.weight")?;
+        let (vocab_size, _) = embedding_info.shape.dims2()?;
 
-        let norm = ct.tensor(reader, "output_norm.weight", device)?;
+        let norm = tensor(reader, "output_norm.weight")?;
         let norm = decode_norm(norm, rms_norm_eps)?;
-        let output = if let Ok(output) = ct.tensor(reader, "output.weight", device) {
+        let output = if let Ok(output) = reader.qtensor(&ct, "output.weight", device) {
             QMatMul::from_qtensor(output)?
         } else {
-            // If there is no output layer, assume the word embeddings are tied to the output
-            QMatMul::from_qtensor(tok_embeddings_q)?
+            // If there is no output layer, assume the word embeddings are tied to the output. Reuse the embedding bytes instead of reading them again
+            QMatMul::from_qtensor(ggml_file::qtensor_from_ggml(
+                embedding_info.ggml_dtype,
+                &embedding_bytes,
+                embedding_info.shape.dims().to_vec(),
+                device,
+            )?)?
         };
+        let tok_embeddings = QuantizedEmbedding::new(
+            embedding_bytes,
+            embedding_info.ggml_dtype,
+            vocab_size,
+            embedding_length,
+        )?;
         let mut layers = Vec::with_capacity(block_count);
         for layer_idx in 0..block_count {
             let prefix = format!("blk.{layer_idx}");
-            let attention_variant =
-                if let Ok(qkv) = ct.tensor(reader, &format!("{prefix}.attn_qkv.weight"), device) {
-                    AttentionVariant::Grouped(GroupedAttention {
-                        attention_qkv: QMatMul::from_qtensor(qkv)?,
+            let attention_variant = if let Ok(qkv) =
+                reader.qtensor(&ct, &format!("{prefix}.attn_qkv.weight"), device)
+            {
+                AttentionVariant::Grouped(GroupedAttention {
+                    attention_qkv: QMatMul::from_qtensor(qkv)?,
+                })
+            } else {
+                let q = tensor(reader, &format!("{prefix}.attn_q.weight"))?;
+                let k = tensor(reader, &format!("{prefix}.attn_k.weight"))?;
+                let v = tensor(reader, &format!("{prefix}.attn_v.weight"))?;
+                let bias = if let (Ok(bias_q), Ok(bias_k), Ok(bias_v)) = (
+                    reader.qtensor(&ct, &format!("{prefix}.attn_q.bias"), device),
+                    reader.qtensor(&ct, &format!("{prefix}.attn_k.bias"), device),
+                    reader.qtensor(&ct, &format!("{prefix}.attn_v.bias"), device),
+                ) {
+                    Some(AttentionBias {
+                        bias_q: bias_q.dequantize(device)?,
+                        bias_k: bias_k.dequantize(device)?,
+                        bias_v: bias_v.dequantize(device)?,
                     })
                 } else {
-                    let q = ct.tensor(reader, &format!("{prefix}.attn_q.weight"), device)?;
-                    let k = ct.tensor(reader, &format!("{prefix}.attn_k.weight"), device)?;
-                    let v = ct.tensor(reader, &format!("{prefix}.attn_v.weight"), device)?;
-                    let bias = if let (Ok(bias_q), Ok(bias_k), Ok(bias_v)) = (
-                        ct.tensor(reader, &format!("{prefix}.attn_q.bias"), device),
-                        ct.tensor(reader, &format!("{prefix}.attn_k.bias"), device),
-                        ct.tensor(reader, &format!("{prefix}.attn_v.bias"), device),
-                    ) {
-                        Some(AttentionBias {
-                            bias_q: bias_q.dequantize(device)?,
-                            bias_k: bias_k.dequantize(device)?,
-                            bias_v: bias_v.dequantize(device)?,
+                    None
+                };
+                let separate = SeparateAttention {
+                    attention_wq: QMatMul::from_qtensor(q)?,
+                    attention_wk: QMatMul::from_qtensor(k)?,
+                    attention_wv: QMatMul::from_qtensor(v)?,
+                    interleaved_rope: architecture.interleaved_rope(),
+                    bias,
+                };
+                AttentionVariant::Separate(separate)
+            };
+            let attention_wo = tensor(reader, &format!("{prefix}.attn_output.weight"))?;
+            let feed_forward_variant = if expert_count > 0 {
+                let load_experts = |reader: &mut R, name: &str| {
+                    // Older files store each expert in a separate tensor. Newer files stack the experts into one tensor
+                    match reader.qtensor(&ct, &format!("{prefix}.{name}_exps.weight"), device) {
+                        Ok(experts) => split_experts(experts, expert_count, device),
+                        Err(_) => (0..expert_count)
+                            .map(|i| tensor(reader, &format!("{prefix}.{name}.{i}.weight")))
+                            .collect(),
+                    }
+                };
+                let gate = load_experts(reader, "ffn_gate")?;
+                let down = load_experts(reader, "ffn_down")?;
+                let up = load_experts(reader, "ffn_up")?;
+                let experts = gate
+                    .into_iter()
+                    .zip(down)
+                    .zip(up)
+                    .map(|((gate, down), up)| {
+                        Ok(LlamaFeedForward {
+                            feed_forward_w1: QMatMul::from_qtensor(gate)?,
+                            feed_forward_w2: QMatMul::from_qtensor(down)?,
+                            feed_forward_w3: QMatMul::from_qtensor(up)?,
                         })
-                    } else {
-                        None
-                    };
-                    let architecture = ct.metadata["general.architecture"].to_string().unwrap();
-                    let separate = SeparateAttention {
-                        attention_wq: QMatMul::from_qtensor(q)?,
-                        attention_wk: QMatMul::from_qtensor(k)?,
-                        attention_wv: QMatMul::from_qtensor(v)?,
-                        interleaved_rope: architecture != "qwen2",
-                        bias,
-                    };
-                    AttentionVariant::Separate(separate)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let shared_expert = if architecture == Architecture::Qwen2Moe {
+                    let gate = tensor(reader, &format!("{prefix}.ffn_gate_shexp.weight"))?;
+                    let down = tensor(reader, &format!("{prefix}.ffn_down_shexp.weight"))?;
+                    let up = tensor(reader, &format!("{prefix}.ffn_up_shexp.weight"))?;
+                    // The shared expert gate is a single row of weights stored as a 1D tensor
+                    let gate_inp = tensor(reader, &format!("{prefix}.ffn_gate_inp_shexp.weight"))?
+                        .dequantize(device)?;
+                    let gate_inp = gate_inp.reshape((1, gate_inp.elem_count()))?;
+                    Some(SharedExpert {
+                        expert: LlamaFeedForward {
+                            feed_forward_w1: QMatMul::from_qtensor(gate)?,
+                            feed_forward_w2: QMatMul::from_qtensor(down)?,
+                            feed_forward_w3: QMatMul::from_qtensor(up)?,
+                        },
+                        gate: gate_inp,
+                    })
+                } else {
+                    None
                 };
-            let attention_wo =
-                ct.tensor(reader, &format!("{prefix}.attn_output.weight"), device)?;
-            // Try to read from the up, down and gate weights
-            let feed_forward_variant = if let Ok(ffn_gate) =
-                ct.tensor(reader, &format!("{prefix}.ffn_gate.weight"), device)
+                let gate_inp = tensor(reader, &format!("{prefix}.ffn_gate_inp.weight"))?;
+                FeedForwardVariant::MixtureOfExperts(MixtureOfExpertsFeedForward {
+                    gate_inp: QMatMul::from_qtensor(gate_inp)?,
+                    experts,
+                    n_expert_used: expert_used_count,
+                    // Mixtral renormalizes the weights of the selected experts, Qwen2-MoE does not
+                    normalize_expert_weights: architecture != Architecture::Qwen2Moe,
+                    shared_expert,
+                })
+            } else if architecture.is_gemma() {
+                let gate = tensor(reader, &format!("{prefix}.ffn_gate.weight"))?;
+                let down = tensor(reader, &format!("{prefix}.ffn_down.weight"))?;
+                let up = tensor(reader, &format!("{prefix}.ffn_up.weight"))?;
+                FeedForwardVariant::Gemma(GemmaFeedForward {
+                    gate: QMatMul::from_qtensor(gate)?,
+                    up: QMatMul::from_qtensor(up)?,
+                    down: QMatMul::from_qtensor(down)?,
+                })
+            } else if let Ok(ffn_gate) =
+                reader.qtensor(&ct, &format!("{prefix}.ffn_gate.weight"), device)
             {
-                let feed_forward_w1 = ffn_gate;
-                let feed_forward_w2 =
-                    ct.tensor(reader, &format!("{prefix}.ffn_down.weight"), device)?;
-                let feed_forward_w3 =
-                    ct.tensor(reader, &format!("{prefix}.ffn_up.weight"), device)?;
+                let down = tensor(reader, &format!("{prefix}.ffn_down.weight"))?;
+                let up = tensor(reader, &format!("{prefix}.ffn_up.weight"))?;
                 FeedForwardVariant::Llama(LlamaFeedForward {
-                    feed_forward_w1: QMatMul::from_qtensor(feed_forward_w1)?,
-                    feed_forward_w2: QMatMul::from_qtensor(feed_forward_w2)?,
-                    feed_forward_w3: QMatMul::from_qtensor(feed_forward_w3)?,
+                    feed_forward_w1: QMatMul::from_qtensor(ffn_gate)?,
+                    feed_forward_w2: QMatMul::from_qtensor(down)?,
+                    feed_forward_w3: QMatMul::from_qtensor(up)?,
                 })
             } else {
-                // Otherwise, try to read from the up, and down weights
-                let up = ct.tensor(reader, &format!("{prefix}.ffn_up.weight"), device)?;
-                // Transpose the down tensor
-                let down = ct.tensor(reader, &format!("{prefix}.ffn_down.weight"), device)?;
+                // Otherwise, the gate and up weights are stored in a single tensor
+                let up = tensor(reader, &format!("{prefix}.ffn_up.weight"))?;
+                let down = tensor(reader, &format!("{prefix}.ffn_down.weight"))?;
                 let feed_forward_length = md_get(".feed_forward_length")?.to_u32()? as usize;
 
                 FeedForwardVariant::Phi(PhiFeedForward {
@@ -246,25 +553,42 @@ impl Model {
                     feed_forward_length,
                 })
             };
-            let attention_norm =
-                ct.tensor(reader, &format!("{prefix}.attn_norm.weight"), device)?;
-            let ffn_norm = ct.tensor(reader, &format!("{prefix}.ffn_norm.weight"), device)?;
+            let attention_norm = tensor(reader, &format!("{prefix}.attn_norm.weight"))?;
+            let ffn_norm = tensor(reader, &format!("{prefix}.ffn_norm.weight"))?;
+            // Gemma-2 normalizes the output of the attention and feed forward blocks before the residual connection
+            let (post_attention_norm, post_ffn_norm) = if architecture == Architecture::Gemma2 {
+                let post_attention_norm =
+                    tensor(reader, &format!("{prefix}.post_attention_norm.weight"))?;
+                let post_ffn_norm = tensor(reader, &format!("{prefix}.post_ffw_norm.weight"))?;
+                (
+                    Some(decode_norm(post_attention_norm, rms_norm_eps)?),
+                    Some(decode_norm(post_ffn_norm, rms_norm_eps)?),
+                )
+            } else {
+                (None, None)
+            };
             layers.push(LlamaAttention {
                 attention_variant,
                 attention_wo: QMatMul::from_qtensor(attention_wo)?,
                 attention_norm: decode_norm(attention_norm, rms_norm_eps)?,
                 feed_forward_variant,
                 ffn_norm: decode_norm(ffn_norm, rms_norm_eps)?,
+                post_attention_norm,
+                post_ffn_norm,
                 n_head: head_count,
                 n_kv_head: head_count_kv,
                 head_dim,
                 hidden_size: config.hidden_size(),
+                attention_scale,
+                attention_logit_softcapping,
+                // Every other layer in Gemma-2 uses sliding window attention, starting with the first layer
+                sliding_window: sliding_window.filter(|_| layer_idx % 2 == 0),
                 rope_cache: rope.clone(),
             })
         }
         Ok(Self {
             config,
-            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            tok_embeddings,
             layers,
             norm,
             output,
@@ -273,10 +597,88 @@ impl Model {
     }
 
     pub fn forward(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+        lora: Option<&LoraAdapter>,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.len();
+        let hidden_states = self.hidden_states(tokens, device, cache, lora)?;
+        let x = hidden_states.i((.., seq_len - 1, ..))?;
+        let logits = self.output.forward(&x)?;
+        match self.config.final_logit_softcapping {
+            Some(softcapping) => soft_cap(&logits, softcapping),
+            None => Ok(logits),
+        }
+    }
+
+    /// Run the model and return the natural log probability of each target token. The targets are predicted by the last `targets.len()` positions in the sequence.
+    ///
+    /// The log probabilities are computed on the device in chunks of [`LOG_PROB_CHUNK_SIZE`] positions, so the logits for the whole sequence are never materialized at once.
+    pub fn forward_log_probs(
+        &self,
+        tokens: &[u32],
+        targets: &[u32],
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+        lora: Option<&LoraAdapter>,
+    ) -> Result<Vec<f32>> {
+        let hidden_states = self
+            .hidden_states(tokens, device, cache, lora)?
+            .squeeze(0)?;
+        let seq_len = hidden_states.dim(0)?;
+        if targets.len() > seq_len {
+            candle_core::bail!(
+                "cannot score {} targets with {seq_len} positions",
+                targets.len()
+            );
+        }
+        let first_scored = seq_len - targets.len();
+        let mut log_probs = Vec::with_capacity(targets.len());
+        for (index, targets) in targets.chunks(LOG_PROB_CHUNK_SIZE).enumerate() {
+            let start = first_scored + index * LOG_PROB_CHUNK_SIZE;
+            let x = hidden_states.narrow(0, start, targets.len())?;
+            let mut logits = self.output.forward(&x)?;
+            if let Some(softcapping) = self.config.final_logit_softcapping {
+                logits = soft_cap(&logits, softcapping)?;
+            }
+            let logits = logits.to_dtype(DType::F32)?;
+            let targets = Tensor::new(targets, device)?.unsqueeze(1)?;
+            let chunk = candle_nn::ops::log_softmax(&logits, D::Minus1)?
+                .gather(&targets, 1)?
+                .squeeze(1)?
+                .to_vec1::<f32>()?;
+            log_probs.extend(chunk);
+        }
+        Ok(log_probs)
+    }
+
+    /// Embed a sequence of tokens by pooling the final hidden states of the model. Returns a tensor with the shape (hidden_size)
+    pub fn embed(&self, tokens: &[u32], device: &Device) -> Result<Tensor> {
+        if tokens.is_empty() {
+            candle_core::bail!("cannot embed an empty sequence of tokens");
+        }
+        let hidden_states = self
+            .hidden_states(tokens, device, None, None)?
+            .squeeze(0)?
+            .to_dtype(DType::F32)?;
+        let embedding = self.config.embedding_pooling.pool(&hidden_states)?;
+        if self.config.normalize_embeddings {
+            let norm = embedding.sqr()?.sum_all()?.sqrt()?;
+            embedding.broadcast_div(&norm)
+        } else {
+            Ok(embedding)
+        }
+    }
+
+    /// Run the model and return the hidden states after the final norm with the shape (1, seq_len, hidden_size)
+    pub fn hidden_states(
         &self,
         tokens: &[u32],
         device: &Device,
         mut cache: Option<&mut LlamaCache>,
+        lora: Option<&LoraAdapter>,
     ) -> Result<Tensor> {
         let seq_len = tokens.len();
         let cached_tokens = cache.as_ref().map(|c| c.tokens.len()).unwrap_or_default();
@@ -305,28 +707,83 @@ impl Model {
             (Tensor::new(tokens, device)?.unsqueeze(0)?, index_pos)
         };
         let mask = self.masks.get_mask(seq_len, index_pos, device)?;
+        let sliding_window_mask = match self.layers.iter().find_map(|layer| layer.sliding_window) {
+            Some(window) => Some(
+                self.masks
+                    .get_sliding_window_mask(seq_len, index_pos, window, device)?,
+            ),
+            None => None,
+        };
 
-        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        let mut layer_in = self.tok_embeddings.forward(&x, device)?;
+        if let Some(embedding_scale) = self.config.embedding_scale {
+            layer_in = (layer_in * embedding_scale)?;
+        }
         for (i, layer) in self.layers.iter().enumerate() {
+            let layer_lora = lora.and_then(|lora| lora.layers.get(i));
             let x = layer_in;
             let residual = &x;
             let x = layer.attention_norm.forward(&x)?;
+            let mask = match (layer.sliding_window, &sliding_window_mask) {
+                (Some(_), Some(sliding_window_mask)) => sliding_window_mask,
+                _ => &mask,
+            };
             let attn = layer.forward(
                 &x,
-                Some(&mask),
+                Some(mask),
                 index_pos,
                 cache.as_mut().map(|c| &mut c.blocks[i]),
+                layer_lora,
             )?;
+            let attn = match &layer.post_attention_norm {
+                Some(norm) => norm.forward(&attn)?,
+                None => attn,
+            };
             let x = (attn + residual)?;
 
             // MLP
             let residual = &x;
             let x = layer.ffn_norm.forward(&x)?;
+            let x = layer.feed_forward_variant.forward(&x, layer_lora)?;
+            let x = match &layer.post_ffn_norm {
+                Some(norm) => norm.forward(&x)?,
+                None => x,
+            };
 
-            layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
+            layer_in = (&x + residual)?;
         }
-        let x = self.norm.forward(&layer_in)?;
-        let x = x.i((.., seq_len - 1, ..))?;
-        self.output.forward(&x)
+        self.norm.forward(&layer_in)
     }
 }
+
+#[test]
+fn pool_hidden_states() -> Result<()> {
+    let hidden_states = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 12.]], &Device::Cpu)?;
+    let pool = |pooling: LlamaPooling| pooling.pool(&hidden_states)?.to_vec1::<f32>();
+    assert_eq!(pool(LlamaPooling::LastToken)?, [5., 12.]);
+    assert_eq!(pool(LlamaPooling::FirstToken)?, [1., 2.]);
+    assert_eq!(pool(LlamaPooling::Mean)?, [3., 6.]);
+    assert_eq!(LlamaPooling::from_gguf(1), Some(LlamaPooling::Mean));
+    assert_eq!(LlamaPooling::from_gguf(0), None);
+    Ok(())
+}
+
+#[test]
+fn architecture_dispatch() {
+    assert_eq!(Architecture::from_gguf_name("llama"), Architecture::Llama);
+    assert_eq!(Architecture::from_gguf_name("phi3"), Architecture::Phi3);
+    assert_eq!(Architecture::from_gguf_name("qwen2"), Architecture::Qwen2);
+    assert_eq!(
+        Architecture::from_gguf_name("qwen2moe"),
+        Architecture::Qwen2Moe
+    );
+    assert_eq!(Architecture::from_gguf_name("gemma"), Architecture::Gemma);
+    assert_eq!(Architecture::from_gguf_name("gemma2"), Architecture::Gemma2);
+    // Unknown architectures fall back to llama
+    assert_eq!(Architecture::from_gguf_name("mistral"), Architecture::Llama);
+
+    assert!(Architecture::Gemma2.is_gemma());
+    assert!(!Architecture::Qwen2.is_gemma());
+    assert!(Architecture::Llama.interleaved_rope());
+    assert!(!Architecture::Phi3.interleaved_rope());
+}
@@ -1,6 +1,142 @@
 use super::LlamaConfig;
 use candle_core::{DType, Device, Tensor};
 
+/// The method used to scale rotary position embeddings so a model can attend to more tokens than it was trained on.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RopeScaling {
+    /// Use the position embeddings the model was trained with.
+    #[default]
+    None,
+    /// Divide every position by the factor. This is also known as position interpolation.
+    Linear {
+        /// The factor to divide the positions by.
+        factor: f32,
+    },
+    /// Increase the rope base so low frequencies are interpolated while high frequencies are mostly preserved. This works without fine-tuning the model.
+    Ntk {
+        /// The factor to extend the context length by.
+        factor: f32,
+    },
+    /// Interpolate low frequencies, extrapolate high frequencies and scale the attention as described in the YaRN paper.
+    Yarn {
+        /// The factor to extend the context length by.
+        factor: f32,
+        /// The context length the model was trained with.
+        original_context_length: usize,
+        /// An additional factor to scale the attention by.
+        attention_factor: f32,
+        /// The number of rotations at which frequencies start being interpolated.
+        beta_fast: f32,
+        /// The number of rotations at which frequencies are fully interpolated.
+        beta_slow: f32,
+    },
+    /// The frequency dependent scaling Llama 3.1 was trained with. Most Llama 3.1 GGUF files store this scaling in the `rope_freqs.weight` tensor instead.
+    Llama3 {
+        /// The factor to divide low frequencies by.
+        factor: f32,
+        /// The context length the model was trained with.
+        original_context_length: usize,
+        /// Frequencies with wavelengths longer than the original context length divided by this factor are divided by the scaling factor.
+        low_frequency_factor: f32,
+        /// Frequencies with wavelengths shorter than the original context length divided by this factor are not scaled.
+        high_frequency_factor: f32,
+    },
+}
+
+impl RopeScaling {
+    /// Create YaRN scaling with the default attention factor and betas.
+    pub fn yarn(factor: f32, original_context_length: usize) -> Self {
+        Self::Yarn {
+            factor,
+            original_context_length,
+            attention_factor: 1.,
+            beta_fast: 32.,
+            beta_slow: 1.,
+        }
+    }
+
+    /// Create Llama 3.1 scaling with the default low and high frequency factors.
+    pub fn llama3(factor: f32, original_context_length: usize) -> Self {
+        Self::Llama3 {
+            factor,
+            original_context_length,
+            low_frequency_factor: 1.,
+            high_frequency_factor: 4.,
+        }
+    }
+
+    /// Scale the inverse frequencies of each dimension pair and return the factor the sin and cos tables are multiplied by
+    fn scale(&self, inverse_frequency: &mut [f32], rope_theta: f32, head_dimension: usize) -> f32 {
+        match *self {
+            RopeScaling::None => 1.,
+            RopeScaling::Linear { factor } => {
+                for frequency in inverse_frequency {
+                    *frequency /= factor;
+                }
+                1.
+            }
+            RopeScaling::Ntk { factor } => {
+                let dim = head_dimension as f32;
+                let rope_theta = rope_theta * factor.powf(dim / (dim - 2.));
+                for (i, frequency) in inverse_frequency.iter_mut().enumerate() {
+                    *frequency = 1. / rope_theta.powf((2 * i) as f32 / dim);
+                }
+                1.
+            }
+            RopeScaling::Yarn {
+                factor,
+                original_context_length,
+                attention_factor,
+                beta_fast,
+                beta_slow,
+            } => {
+                let dim = head_dimension as f32;
+                // The dimension at which the frequency rotates `rotations` times over the original context length
+                let correction_dimension = |rotations: f32| {
+                    dim * (original_context_length as f32 / (rotations * 2. * std::f32::consts::PI))
+                        .ln()
+                        / (2. * rope_theta.ln())
+                };
+                let low = correction_dimension(beta_fast).floor().max(0.);
+                let high = correction_dimension(beta_slow).ceil().min(dim - 1.);
+                let high = if low == high { high + 0.001 } else { high };
+                for (i, frequency) in inverse_frequency.iter_mut().enumerate() {
+                    let interpolation = ((i as f32 - low) / (high - low)).clamp(0., 1.);
+                    *frequency =
+                        *frequency / factor * interpolation + *frequency * (1. - interpolation);
+                }
+                let magnitude_scale = if factor <= 1. {
+                    1.
+                } else {
+                    0.1 * factor.ln() + 1.
+                };
+                magnitude_scale * attention_factor
+            }
+            RopeScaling::Llama3 {
+                factor,
+                original_context_length,
+                low_frequency_factor,
+                high_frequency_factor,
+            } => {
+                let original_context_length = original_context_length as f32;
+                let low_frequency_wavelength = original_context_length / low_frequency_factor;
+                let high_frequency_wavelength = original_context_length / high_frequency_factor;
+                for frequency in inverse_frequency {
+                    let wavelength = 2. * std::f32::consts::PI / *frequency;
+                    if wavelength > low_frequency_wavelength {
+                        *frequency /= factor;
+                    } else if wavelength >= high_frequency_wavelength {
+                        let smooth = (original_context_length / wavelength - low_frequency_factor)
+                            / (high_frequency_factor - low_frequency_factor);
+                        *frequency = (1. - smooth) * *frequency / factor + smooth * *frequency;
+                    }
+                }
+                1.
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RopeCache {
     sin: Tensor,
@@ -9,7 +145,7 @@ pub struct RopeCache {
 
 impl RopeCache {
     pub fn new(config: &LlamaConfig, dtype: DType, device: &Device) -> candle_core::Result<Self> {
-        let inverse_frequency = (0..config.head_dimension)
+        let mut inverse_frequency = (0..config.head_dimension)
             .step_by(2)
             .map(|i| {
                 1. / (config
@@ -17,6 +153,11 @@ impl RopeCache {
                     .powf(i as f32 / config.head_dimension as f32))
             })
             .collect::<Vec<_>>();
+        let magnitude_scale = config.rope_scaling.scale(
+            &mut inverse_frequency,
+            config.rope_theta,
+            config.head_dimension,
+        );
         let inverse_frequency_len = inverse_frequency.len();
         let mut inverse_frequency =
             Tensor::from_vec(inverse_frequency, (1, inverse_frequency_len), device)?
@@ -32,8 +173,12 @@ impl RopeCache {
 
         let outer_product = llama_context_length_indices.matmul(&inverse_frequency)?;
 
-        let sin = outer_product.sin()?;
-        let cos = outer_product.cos()?;
+        let mut sin = outer_product.sin()?;
+        let mut cos = outer_product.cos()?;
+        if magnitude_scale != 1. {
+            sin = (sin * magnitude_scale as f64)?;
+            cos = (cos * magnitude_scale as f64)?;
+        }
 
         Ok(Self { sin, cos })
     }
@@ -53,15 +198,11 @@ impl RopeCache {
         };
         let device = q.device();
         let (q, k) = if matches!(device, Device::Cpu) {
-            std::thread::scope(|s| {
-                let q = s.spawn(|| apply_rotary_emb(&self.sin, &self.cos, q, start_pos));
-                let k = apply_rotary_emb(&self.sin, &self.cos, k, start_pos)?;
-                candle_core::Result::Ok((
-                    q.join()
-                        .map_err(|e| candle_core::Error::Msg(format!("Error in q: {:?}", e)))??,
-                    k,
-                ))
-            })?
+            let (q, k) = rayon::join(
+                || apply_rotary_emb(&self.sin, &self.cos, q, start_pos),
+                || apply_rotary_emb(&self.sin, &self.cos, k, start_pos),
+            );
+            (q?, k?)
         } else {
             let q = apply_rotary_emb(&self.sin, &self.cos, q, start_pos)?;
             let k = apply_rotary_emb(&self.sin, &self.cos, k, start_pos)?;
@@ -95,10 +236,18 @@ fn test_rope_cache() {
     let config = LlamaConfig {
         rope_freq_weight: None,
         rope_theta: 5000.,
+        rope_scaling: RopeScaling::None,
         context_length: 6,
         head_dimension: 2,
         n_head: 0,
+        n_kv_head: 0,
         n_layer: 0,
+        interleaved_rope: true,
+        embedding_scale: None,
+        final_logit_softcapping: None,
+        kv_cache_quantization: Default::default(),
+        embedding_pooling: Default::default(),
+        normalize_embeddings: true,
     };
     let device = Device::cuda_if_available(0).unwrap();
     let cache = RopeCache::new(&config, DType::F32, &device).unwrap();
@@ -147,3 +296,43 @@ fn test_rope_cache() {
         .unwrap();
     assert!(sin_error < 1e-2);
 }
+
+#[test]
+fn test_rope_scaling() {
+    let head_dimension = 128;
+    let rope_theta = 10000f32;
+    let inverse_frequency = (0..head_dimension)
+        .step_by(2)
+        .map(|i| 1. / rope_theta.powf(i as f32 / head_dimension as f32))
+        .collect::<Vec<_>>();
+    let scaled = |scaling: RopeScaling| {
+        let mut scaled = inverse_frequency.clone();
+        let magnitude_scale = scaling.scale(&mut scaled, rope_theta, head_dimension);
+        (scaled, magnitude_scale)
+    };
+
+    let (none, magnitude_scale) = scaled(RopeScaling::None);
+    assert_eq!(none, inverse_frequency);
+    assert_eq!(magnitude_scale, 1.);
+
+    let (linear, _) = scaled(RopeScaling::Linear { factor: 4. });
+    for (scaled, original) in linear.iter().zip(&inverse_frequency) {
+        assert!((scaled * 4. - original).abs() < 1e-6);
+    }
+
+    // NTK, YaRN and Llama 3 scaling keep the highest frequency and interpolate the lowest frequency
+    for scaling in [
+        RopeScaling::Ntk { factor: 4. },
+        RopeScaling::yarn(4., 4096),
+        RopeScaling::llama3(4., 4096),
+    ] {
+        let (scaled, _) = scaled(scaling);
+        assert!((scaled[0] - inverse_frequency[0]).abs() < 1e-6);
+        let last = inverse_frequency.len() - 1;
+        assert!(scaled[last] < inverse_frequency[last]);
+        assert!(scaled.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    let (_, magnitude_scale) = scaled(RopeScaling::yarn(4., 4096));
+    assert!((magnitude_scale - (0.1 * 4f32.ln() + 1.)).abs() < 1e-6);
+}
@@ -8,12 +8,13 @@ use std::collections::HashMap;
 #[derive(Debug, Clone)]
 pub struct LlamaSession {
     pub(crate) cache: LlamaCache,
+    pub(crate) lora: Option<usize>,
 }
 
 impl Session for LlamaSession {
     fn save_to(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
         let device = accelerated_device_if_available()?;
-        let tensors = self.get_tensor_map(&device);
+        let tensors = self.get_tensor_map(&device)?;
         Ok(candle_core::safetensors::save(&tensors, path)?)
     }
 
@@ -40,21 +41,49 @@ impl Session for LlamaSession {
 }
 
 impl LlamaSession {
+    /// Get the index of the LoRA adapter this session uses. Adapters are indexed in the order they were added with [`crate::LlamaBuilder::with_lora`].
+    pub fn lora(&self) -> Option<usize> {
+        self.lora
+    }
+
+    /// Set the LoRA adapter this session uses or disable adapters for this session with `None`. Adapters are indexed in the order they were added with [`crate::LlamaBuilder::with_lora`].
+    ///
+    /// The cached state of the session depends on the adapter, so switching adapters clears the session.
+    pub fn set_lora(&mut self, lora: Option<usize>) {
+        if self.lora != lora {
+            self.lora = lora;
+            self.cache.clear();
+            self.cache.tokens.clear();
+        }
+    }
+
     /// Export the current cache tensor map.
-    pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
-        self.cache.get_tensor_map(device)
+    pub fn get_tensor_map(&self, device: &Device) -> candle_core::Result<HashMap<String, Tensor>> {
+        let mut map = self.cache.get_tensor_map(device);
+        if let Some(lora) = self.lora {
+            map.insert(
+                "llama.session.lora".to_string(),
+                Tensor::new(lora as u32, device)?,
+            );
+        }
+        Ok(map)
     }
 
     /// Import a cache tensor map.
     pub fn set_tensor_map(&mut self, map: HashMap<String, Tensor>) -> candle_core::Result<()> {
-        self.cache = LlamaCache::from_tensor_map(map)?;
+        *self = Self::from_tensor_map(map)?;
         Ok(())
     }
 
     /// Create a cache from a tensor map. This can be used to load a cache from disk.
     pub fn from_tensor_map(map: HashMap<String, Tensor>) -> candle_core::Result<Self> {
+        let lora = map
+            .get("llama.session.lora")
+            .and_then(|lora| lora.to_scalar::<u32>().ok())
+            .map(|lora| lora as usize);
         Ok(Self {
             cache: LlamaCache::from_tensor_map(map)?,
+            lora,
         })
     }
 }
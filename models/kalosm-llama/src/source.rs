@@ -74,6 +74,30 @@ impl LlamaSource {
         self
     }
 
+    /// Set the model file to use. The model must be a GGUF or GGML file.
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = model;
+
+        self
+    }
+
+    /// Set the tokenizer file to use
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = tokenizer;
+
+        self
+    }
+
+    /// Get the chat markers for the model if it is a chat model
+    pub fn chat_markers(&self) -> Option<ChatMarkers> {
+        self.markers.clone()
+    }
+
+    /// Get the model and tokenizer files this source loads
+    pub fn files(&self) -> Vec<FileSource> {
+        vec![self.model.clone(), self.tokenizer.clone()]
+    }
+
     /// Set the marker text for a user message
     pub fn with_chat_markers(mut self, markers: ChatMarkers) -> Self {
         self.markers = Some(markers);
@@ -81,6 +81,23 @@ impl OcrSource {
         Self { model, config }
     }
 
+    /// Set the model file to use. The model must be a safetensors file.
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Set the config file to use.
+    pub fn with_config(mut self, config: FileSource) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Get the model and config files this source loads.
+    pub fn files(&self) -> Vec<FileSource> {
+        vec![self.model.clone(), self.config.clone()]
+    }
+
     /// Create the base model source.
     pub fn base() -> Self {
         Self::new(
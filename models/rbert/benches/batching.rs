@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rbert::*;
+
+criterion_group!(mbenches, batch_scaling);
+criterion_main!(mbenches);
+
+fn create_model_sync() -> Bert {
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async move { Bert::builder().build_with_loading_handler(|_| {}).await })
+        .unwrap()
+}
+
+/// Sentences with a wide range of lengths so batching has to deal with padding
+fn sentences(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| "The quick brown fox jumps over the lazy dog. ".repeat(1 + i % 16))
+        .collect()
+}
+
+fn batch_scaling(c: &mut Criterion) {
+    let model = create_model_sync();
+
+    let mut group = c.benchmark_group("embed sentences");
+    for count in [1, 16, 64, 256] {
+        let sentences = sentences(count);
+        let sentences = sentences.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        group.bench_with_input(BenchmarkId::new("batched", count), &count, |b, _| {
+            b.iter(|| {
+                model
+                    .embed_batch_with_pooling(sentences.clone(), Pooling::CLS)
+                    .unwrap()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("one at a time", count), &count, |b, _| {
+            b.iter(|| {
+                for sentence in &sentences {
+                    model.embed_with_pooling(sentence, Pooling::CLS).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use candle_core::{IndexOp, Tensor};
+use candle_nn::{Linear, Module, VarBuilder};
+use kalosm_common::*;
+use tokenizers::{Encoding, PaddingParams, Tokenizer, TruncationParams, TruncationStrategy};
+
+use crate::raw::DTYPE;
+use crate::{BertModel, Config};
+
+/// The number of query and document pairs scored in one forward pass
+const BATCH_SIZE: usize = 16;
+
+/// The source of a [`BertCrossEncoder`] model
+pub struct BertCrossEncoderSource {
+    pub(crate) config: FileSource,
+    pub(crate) tokenizer: FileSource,
+    pub(crate) model: FileSource,
+}
+
+impl BertCrossEncoderSource {
+    /// Create a new [`BertCrossEncoderSource`] with the default model
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the model to use. The model must be a BERT model with a sequence classification head that outputs a single relevance score, like the models at <https://huggingface.co/cross-encoder>
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Set the tokenizer to use
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Set the config to use
+    pub fn with_config(mut self, config: FileSource) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Get the model, tokenizer and config files this source loads
+    pub fn files(&self) -> Vec<FileSource> {
+        vec![
+            self.model.clone(),
+            self.tokenizer.clone(),
+            self.config.clone(),
+        ]
+    }
+
+    /// Create a new [`BertCrossEncoderSource`] with the [ms-marco-MiniLM-L-6-v2](https://huggingface.co/cross-encoder/ms-marco-MiniLM-L-6-v2) model
+    pub fn ms_marco_mini_lm_l6_v2() -> Self {
+        Self::huggingface("cross-encoder/ms-marco-MiniLM-L-6-v2")
+    }
+
+    /// Create a new [`BertCrossEncoderSource`] with the [ms-marco-MiniLM-L-12-v2](https://huggingface.co/cross-encoder/ms-marco-MiniLM-L-12-v2) model
+    pub fn ms_marco_mini_lm_l12_v2() -> Self {
+        Self::huggingface("cross-encoder/ms-marco-MiniLM-L-12-v2")
+    }
+
+    fn huggingface(model_id: &str) -> Self {
+        let file = |file: &str| {
+            FileSource::huggingface(model_id.to_string(), "main".to_string(), file.to_string())
+        };
+        Self {
+            config: file("config.json"),
+            tokenizer: file("tokenizer.json"),
+            model: file("model.safetensors"),
+        }
+    }
+}
+
+impl Default for BertCrossEncoderSource {
+    fn default() -> Self {
+        Self::ms_marco_mini_lm_l6_v2()
+    }
+}
+
+/// A builder for a [`BertCrossEncoder`] model
+#[derive(Default)]
+pub struct BertCrossEncoderBuilder {
+    source: BertCrossEncoderSource,
+    cache: kalosm_common::Cache,
+    threads: InferenceThreads,
+}
+
+impl BertCrossEncoderBuilder {
+    /// Set the source of the model
+    pub fn with_source(mut self, source: BertCrossEncoderSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Set the cache location to use for the model (defaults DATA_DIR/kalosm/cache)
+    pub fn with_cache(mut self, cache: kalosm_common::Cache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Set the number of threads the model uses for inference on the CPU. The model gets a dedicated thread pool with this many threads. (Defaults to rayon's global thread pool)
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = InferenceThreads::Count(threads);
+        self
+    }
+
+    /// Set the thread pool the model uses for inference on the CPU. Models built with the same pool share its threads. (Defaults to rayon's global thread pool)
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.threads = InferenceThreads::Pool(pool);
+        self
+    }
+
+    /// Build the model
+    pub async fn build(self) -> anyhow::Result<BertCrossEncoder> {
+        self.build_with_loading_handler(ModelLoadingProgress::multi_bar_loading_indicator())
+            .await
+    }
+
+    /// Build the model with a loading handler
+    pub async fn build_with_loading_handler(
+        self,
+        mut progress_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<BertCrossEncoder> {
+        let Self {
+            source,
+            cache,
+            threads,
+        } = self;
+        let BertCrossEncoderSource {
+            config,
+            tokenizer,
+            model,
+        } = source;
+
+        let source = format!("Config ({})", config);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+        let config_filename = cache
+            .get(&config, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let tokenizer_source = format!("Tokenizer ({})", tokenizer);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
+        let tokenizer_filename = cache
+            .get(&tokenizer, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let model_source = format!("Model ({})", model);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+        let weights_filename = cache
+            .get(&model, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+
+        let config = std::fs::read_to_string(config_filename)?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let thread_pool = threads.build("rbert-cross-encoder")?;
+        let device = accelerated_device_if_available()?;
+        let vb =
+            unsafe { VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)? };
+        let model = BertModel::load(vb.clone(), &config)?;
+        let hidden_size = model.embedding_dim();
+        // Sequence classification models pool the CLS token before the classifier. The pooler is stored next to the encoder
+        let pooler = match optional_linear(hidden_size, vb.pp("pooler.dense"))? {
+            Some(pooler) => Some(pooler),
+            None => optional_linear(hidden_size, vb.pp("bert.pooler.dense"))?,
+        };
+        let classifier = candle_nn::linear(hidden_size, 1, vb.pp("classifier"))?;
+
+        let mut tokenizer =
+            Tokenizer::from_file(&tokenizer_filename).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(None);
+        // Only truncate the document so the query is always fully visible to the model
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: 512,
+                strategy: TruncationStrategy::OnlySecond,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+
+        Ok(BertCrossEncoder {
+            model: Arc::new(model),
+            pooler: pooler.map(Arc::new),
+            classifier: Arc::new(classifier),
+            tokenizer: Arc::new(tokenizer),
+            thread_pool,
+        })
+    }
+}
+
+/// Load a square linear layer that may not be in the weights. Only a missing tensor is treated as a missing layer, so weights with the wrong shape are still an error.
+fn optional_linear(size: usize, vb: VarBuilder) -> candle_core::Result<Option<Linear>> {
+    fn is_missing_tensor(err: &candle_core::Error) -> bool {
+        match err {
+            candle_core::Error::CannotFindTensor { .. } => true,
+            candle_core::Error::WithBacktrace { inner, .. }
+            | candle_core::Error::WithPath { inner, .. } => is_missing_tensor(inner),
+            _ => false,
+        }
+    }
+
+    match candle_nn::linear(size, size, vb) {
+        Ok(linear) => Ok(Some(linear)),
+        Err(err) if is_missing_tensor(&err) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// A BERT cross encoder that scores how relevant documents are to a query.
+///
+/// Unlike an embedding model, a cross encoder reads the query and the document together, which makes it much more accurate, but too slow to run over every document in a database. Cross encoders are typically used to rerank the top results from a faster search.
+///
+/// # Example
+/// ```rust, no_run
+/// use rbert::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let cross_encoder = BertCrossEncoder::new().await?;
+///     let scores = cross_encoder
+///         .score(
+///             "How many people live in Berlin?",
+///             vec![
+///                 "Berlin has a population of 3,520,031 registered inhabitants.".to_string(),
+///                 "Berlin is well known for its museums.".to_string(),
+///             ],
+///         )
+///         .await?;
+///     assert!(scores[0] > scores[1]);
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BertCrossEncoder {
+    model: Arc<BertModel>,
+    pooler: Option<Arc<Linear>>,
+    classifier: Arc<Linear>,
+    tokenizer: Arc<Tokenizer>,
+    thread_pool: InferenceThreadPool,
+}
+
+impl BertCrossEncoder {
+    /// Create a new [`BertCrossEncoderBuilder`]
+    pub fn builder() -> BertCrossEncoderBuilder {
+        BertCrossEncoderBuilder::default()
+    }
+
+    /// Create a new default cross encoder
+    pub async fn new() -> anyhow::Result<Self> {
+        Self::builder().build().await
+    }
+
+    /// Score how relevant each document is to the query. Higher scores are more relevant. The scores are returned in the same order as the documents.
+    pub async fn score(&self, query: &str, documents: Vec<String>) -> anyhow::Result<Vec<f32>> {
+        let query = query.to_string();
+        let self_clone = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let documents = documents.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            self_clone.score_raw(&query, documents)
+        })
+        .await?
+    }
+
+    /// Score how relevant each document is to the query synchronously.
+    pub fn score_raw(&self, query: &str, documents: Vec<&str>) -> anyhow::Result<Vec<f32>> {
+        self.thread_pool.install(|| {
+            let encodings = self
+                .tokenizer
+                .encode_batch(
+                    documents
+                        .into_iter()
+                        .map(|document| (query, document))
+                        .collect::<Vec<_>>(),
+                    true,
+                )
+                .map_err(anyhow::Error::msg)?;
+
+            let mut scores = Vec::with_capacity(encodings.len());
+            for batch in encodings.chunks(BATCH_SIZE) {
+                let batch_scores = maybe_autoreleasepool(|| self.score_batch(batch.to_vec()))?;
+                scores.extend(batch_scores);
+            }
+            Ok(scores)
+        })
+    }
+
+    fn score_batch(&self, mut encodings: Vec<Encoding>) -> anyhow::Result<Vec<f32>> {
+        let device = &self.model.device;
+        let pp = PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        };
+        tokenizers::pad_encodings(&mut encodings, &pp).map_err(anyhow::Error::msg)?;
+
+        let stack = |get: fn(&Encoding) -> &[u32]| -> anyhow::Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(get(encoding), device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let token_ids = stack(Encoding::get_ids)?;
+        // Unlike embedding, the token types are needed to tell the query apart from the document
+        let token_type_ids = stack(Encoding::get_type_ids)?;
+        let attention_mask = stack(Encoding::get_attention_mask)?;
+
+        let hidden =
+            self.model
+                .forward(&token_ids, &token_type_ids, Some(&attention_mask), false)?;
+        let cls = hidden.i((.., 0, ..))?;
+        let pooled = match &self.pooler {
+            Some(pooler) => pooler.forward(&cls)?.tanh()?,
+            None => cls,
+        };
+        let logits = self.classifier.forward(&pooled)?;
+
+        Ok(logits.squeeze(1)?.to_vec1()?)
+    }
+}
+
+#[test]
+fn optional_linear_only_ignores_missing_tensors() -> candle_core::Result<()> {
+    use candle_core::{DType, Device};
+    use std::collections::HashMap;
+
+    let device = Device::Cpu;
+    let tensors = HashMap::from([
+        (
+            "pooler.dense.weight".to_string(),
+            Tensor::zeros((4, 4), DType::F32, &device)?,
+        ),
+        (
+            "pooler.dense.bias".to_string(),
+            Tensor::zeros(4, DType::F32, &device)?,
+        ),
+    ]);
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, &device);
+    assert!(optional_linear(4, vb.pp("pooler.dense"))?.is_some());
+    assert!(optional_linear(4, vb.pp("bert.pooler.dense"))?.is_none());
+    // Weights with the wrong shape are an error instead of a missing pooler
+    assert!(optional_linear(8, vb.pp("pooler.dense")).is_err());
+    Ok(())
+}
@@ -54,12 +54,14 @@ use std::sync::{Arc, RwLock};
 
 use candle_core::{IndexOp, Tensor};
 use candle_nn::VarBuilder;
-use tokenizers::{Encoding, PaddingParams, Tokenizer};
+use tokenizers::{Encoding, PaddingParams, Tokenizer, TruncationParams};
 
+mod cross_encoder;
 mod language_model;
 mod raw;
 mod source;
 
+pub use crate::cross_encoder::*;
 pub use crate::language_model::*;
 use crate::raw::DTYPE;
 pub use crate::raw::{BertModel, Config};
@@ -70,6 +72,9 @@ pub use crate::source::*;
 pub struct BertBuilder {
     source: BertSource,
     cache: kalosm_common::Cache,
+    threads: InferenceThreads,
+    max_batch_tokens: Option<usize>,
+    max_sequence_length: Option<usize>,
 }
 
 impl BertBuilder {
@@ -92,6 +97,30 @@ impl BertBuilder {
         self
     }
 
+    /// Set the number of threads the model uses for inference on the CPU. The model gets a dedicated thread pool with this many threads. (Defaults to rayon's global thread pool)
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = InferenceThreads::Count(threads);
+        self
+    }
+
+    /// Set the thread pool the model uses for inference on the CPU. Models built with the same pool share its threads. (Defaults to rayon's global thread pool)
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.threads = InferenceThreads::Pool(pool);
+        self
+    }
+
+    /// Set the maximum number of tokens, including padding, the model runs in one batch. Larger batches are faster, but use more memory. (Defaults to 8192)
+    pub fn with_max_batch_tokens(mut self, max_batch_tokens: usize) -> Self {
+        self.max_batch_tokens = Some(max_batch_tokens);
+        self
+    }
+
+    /// Set the maximum number of tokens in each input. Longer inputs are truncated to this length and a warning is logged. (Defaults to the longest input the model supports, which is usually 512 tokens)
+    pub fn with_max_sequence_length(mut self, max_sequence_length: usize) -> Self {
+        self.max_sequence_length = Some(max_sequence_length);
+        self
+    }
+
     /// Build the model with a loading handler
     ///
     /// ```rust, no_run
@@ -179,6 +208,9 @@ pub struct Bert {
     embedding_search_prefix: Arc<Option<String>>,
     model: Arc<BertModel>,
     tokenizer: Arc<RwLock<Tokenizer>>,
+    thread_pool: InferenceThreadPool,
+    max_batch_tokens: usize,
+    max_sequence_length: usize,
 }
 
 impl Bert {
@@ -204,7 +236,13 @@ impl Bert {
         builder: BertBuilder,
         mut progress_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
     ) -> anyhow::Result<Self> {
-        let BertBuilder { source, cache } = builder;
+        let BertBuilder {
+            source,
+            cache,
+            threads,
+            max_batch_tokens,
+            max_sequence_length,
+        } = builder;
         let BertSource {
             config,
             tokenizer,
@@ -237,71 +275,92 @@ impl Bert {
         let config = std::fs::read_to_string(config_filename)?;
         let config: Config = serde_json::from_str(&config)?;
 
+        let thread_pool = threads.build("rbert")?;
         let device = accelerated_device_if_available()?;
         let vb =
             unsafe { VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)? };
         let model = BertModel::load(vb, &config)?;
+        // Inputs longer than the position embeddings of the model can't be embedded, so they are always truncated
+        let max_sequence_length = max_sequence_length
+            .unwrap_or(usize::MAX)
+            .min(config.max_position_embeddings());
         let mut tokenizer =
             Tokenizer::from_file(&tokenizer_filename).map_err(anyhow::Error::msg)?;
         tokenizer.with_padding(None);
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: max_sequence_length,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
 
         Ok(Bert {
             tokenizer: Arc::new(RwLock::new(tokenizer)),
             model: Arc::new(model),
             embedding_search_prefix: Arc::new(search_embedding_prefix),
+            thread_pool,
+            max_batch_tokens: max_batch_tokens.unwrap_or(8192).max(max_sequence_length),
+            max_sequence_length,
         })
     }
 
+    /// Get the maximum number of tokens in each input. Longer inputs are truncated to this length.
+    pub fn max_sequence_length(&self) -> usize {
+        self.max_sequence_length
+    }
+
     /// Embed a batch of sentences
     pub(crate) fn embed_batch_raw(
         &self,
         sentences: Vec<&str>,
         pooling: Pooling,
     ) -> anyhow::Result<Vec<Tensor>> {
-        let embedding_dim = self.model.embedding_dim();
-        // The batch size limit (input length * memory per token)
-        let limit = embedding_dim * 512usize.pow(2) * 2;
+        self.thread_pool
+            .install(|| self.embed_batch_raw_in_pool(sentences, pooling))
+    }
+
+    fn embed_batch_raw_in_pool(
+        &self,
+        sentences: Vec<&str>,
+        pooling: Pooling,
+    ) -> anyhow::Result<Vec<Tensor>> {
+        if sentences.is_empty() {
+            return Ok(Vec::new());
+        }
 
-        // The sentences we are embedding may have a very different length. First we sort them so that similar length sentences are grouped together in the same batch to reduce the overhead of padding.
         let encodings = {
             let tokenizer_read = self.tokenizer.read().unwrap();
             tokenizer_read.encode_batch(sentences, true)
         }
         .map_err(anyhow::Error::msg)?;
-        let mut encodings_with_indices = encodings.into_iter().enumerate().collect::<Vec<_>>();
-
-        encodings_with_indices.sort_unstable_by_key(|(_, encoding)| encoding.len());
-
-        let mut combined: Vec<Option<Tensor>> = vec![None; encodings_with_indices.len()];
-        let mut chunks = Vec::new();
-        let mut current_chunk_len = 0;
-        let mut current_chunk_max_token_len = 0;
-        let mut current_chunk_indices = Vec::new();
-        let mut current_chunk_text: Vec<Encoding> = Vec::new();
-        for (index, encoding) in encodings_with_indices {
-            let len = encoding.get_ids().len();
-            current_chunk_max_token_len = current_chunk_max_token_len.max(len);
-            current_chunk_len += 1;
-            let score = current_chunk_len
-                * (embedding_dim * 8 + embedding_dim * current_chunk_max_token_len.pow(2));
-            if score > limit {
-                chunks.push((
-                    std::mem::take(&mut current_chunk_indices),
-                    std::mem::take(&mut current_chunk_text),
-                ));
-                current_chunk_max_token_len = len;
-                current_chunk_len = 1;
-            }
-            current_chunk_indices.push(index);
-            current_chunk_text.push(encoding);
+        let truncated = encodings
+            .iter()
+            .filter(|encoding| !encoding.get_overflowing().is_empty())
+            .count();
+        if truncated > 0 {
+            tracing::warn!(
+                "{truncated} of {} inputs were longer than the maximum sequence length of {} tokens and were truncated",
+                encodings.len(),
+                self.max_sequence_length
+            );
         }
-        // Add the last chunk even if the score isn't maxed out
-        chunks.push((
-            std::mem::take(&mut current_chunk_indices),
-            std::mem::take(&mut current_chunk_text),
-        ));
 
-        for (indices, encodings) in chunks {
+        let lengths = encodings.iter().map(Encoding::len).collect::<Vec<_>>();
+        let mut encodings = encodings.into_iter().map(Some).collect::<Vec<_>>();
+        let batches = plan_batches(&lengths, self.max_batch_tokens)
+            .into_iter()
+            .map(|indices| {
+                let encodings = indices
+                    .iter()
+                    .map(|index| encodings[*index].take().unwrap())
+                    .collect::<Vec<_>>();
+                (indices, encodings)
+            })
+            .collect::<Vec<_>>();
+
+        // Run each batch and restore the original order of the sentences
+        let mut combined: Vec<Option<Tensor>> = vec![None; lengths.len()];
+        for (indices, encodings) in batches {
             let embeddings =
                 maybe_autoreleasepool(|| self.embed_batch_raw_inner(encodings, pooling))?;
             for (i, embedding) in indices.iter().zip(embeddings) {
@@ -349,18 +408,15 @@ impl Bert {
             self.model
                 .forward(&token_ids, &token_type_ids, Some(&attention_mask), false)?;
 
-        let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
-
         match pooling {
             Pooling::Mean => {
                 // Take the mean embedding value for all tokens (except padding)
-                let embeddings = embeddings.mul(
-                    &attention_mask
-                        .to_dtype(DTYPE)?
-                        .unsqueeze(2)?
-                        .broadcast_as(embeddings.shape())?,
-                )?;
-                let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
+                let mask = attention_mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+                let token_counts = mask.sum(1)?;
+                let embeddings = embeddings
+                    .broadcast_mul(&mask)?
+                    .sum(1)?
+                    .broadcast_div(&token_counts)?;
                 let embeddings = normalize_l2(&embeddings)?;
                 Ok(embeddings.chunk(n_sentences, 0)?)
             }
@@ -373,6 +429,65 @@ impl Bert {
     }
 }
 
+/// Split inputs with the given token lengths into batches, returning the indices of the inputs in each batch.
+///
+/// The sentences we are embedding may have a very different length. First we sort them so that similar length sentences are grouped together in the same batch to reduce the overhead of padding. Then we split them into batches. Each batch only contains sentences from one power of two length bucket, so no sentence is padded to more than twice its length, and the padded batch never has more than max_batch_tokens tokens unless a single input is longer than that.
+fn plan_batches(lengths: &[usize], max_batch_tokens: usize) -> Vec<Vec<usize>> {
+    let mut indices = (0..lengths.len()).collect::<Vec<_>>();
+    indices.sort_by_key(|index| lengths[*index]);
+
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_bucket = 0;
+    for index in indices {
+        let len = lengths[index];
+        let bucket = len.next_power_of_two();
+        // The lengths are sorted, so this input is the longest in the batch
+        let padded_tokens = (batch.len() + 1) * len;
+        if !batch.is_empty() && (bucket != batch_bucket || padded_tokens > max_batch_tokens) {
+            batches.push(std::mem::take(&mut batch));
+        }
+        batch_bucket = bucket;
+        batch.push(index);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
 fn normalize_l2(v: &Tensor) -> anyhow::Result<Tensor> {
     Ok(v.broadcast_div(&v.sqr()?.sum_keepdim(1)?.sqrt()?)?)
 }
+
+#[test]
+fn batches_group_similar_lengths() {
+    let lengths = [3, 100, 5, 4, 60, 7, 1, 64, 33, 2, 8, 9];
+    let max_batch_tokens = 128;
+    let batches = plan_batches(&lengths, max_batch_tokens);
+
+    // Every input is in exactly one batch, so the original order can be restored
+    let mut seen = batches.iter().flatten().copied().collect::<Vec<_>>();
+    seen.sort_unstable();
+    assert_eq!(seen, (0..lengths.len()).collect::<Vec<_>>());
+
+    for batch in &batches {
+        let longest = batch.iter().map(|index| lengths[*index]).max().unwrap();
+        // Inputs that are longer than the token limit on their own still get a batch
+        assert!(batch.len() * longest <= max_batch_tokens || batch.len() == 1);
+        let bucket = lengths[batch[0]].next_power_of_two();
+        assert!(batch
+            .iter()
+            .all(|index| lengths[*index].next_power_of_two() == bucket));
+    }
+
+    // 3 and 4 tokens share a bucket
+    assert!(batches.contains(&vec![0, 3]));
+    // 33 and 60 tokens fit in one batch, but a third input with 64 tokens would go over the limit
+    assert!(batches.contains(&vec![8, 4]));
+    assert!(batches.contains(&vec![7]));
+    assert!(batches.contains(&vec![1]));
+
+    assert!(plan_batches(&[], max_batch_tokens).is_empty());
+    assert_eq!(plan_batches(&[1000], max_batch_tokens), vec![vec![0]]);
+}
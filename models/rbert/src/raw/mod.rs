@@ -84,6 +84,19 @@ pub struct Config {
     model_type: Option<String>,
 }
 
+impl Config {
+    /// The maximum number of tokens the model can embed at once
+    pub(crate) fn max_position_embeddings(&self) -> usize {
+        match self.model_type.as_deref() {
+            // RoBERTa style models start position ids after the padding token, so the first two positions are never used for tokens
+            Some("roberta" | "xlm-roberta" | "camembert") => {
+                self.max_position_embeddings.saturating_sub(2)
+            }
+            _ => self.max_position_embeddings,
+        }
+    }
+}
+
 /// A raw synchronous Bert model. You should generally use the [`super::Bert`] instead.
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L874
 pub struct BertModel {
@@ -40,6 +40,15 @@ impl BertSource {
         self
     }
 
+    /// Get the model, tokenizer and config files this source loads
+    pub fn files(&self) -> Vec<FileSource> {
+        vec![
+            self.model.clone(),
+            self.tokenizer.clone(),
+            self.config.clone(),
+        ]
+    }
+
     /// Set the prefix to use when embedding search queries
     pub(crate) fn with_search_embedding_prefix(
         mut self,
@@ -32,8 +32,12 @@ impl Model for Phi {
     type TextStream = ChannelTextStream;
     type SyncModel = PhiModel;
 
-    fn tokenizer(&self) -> Arc<Tokenizer> {
-        self.get_tokenizer()
+    fn tokenizer(&self) -> anyhow::Result<Arc<Tokenizer>> {
+        Ok(self.get_tokenizer())
+    }
+
+    fn context_length(&self) -> Option<usize> {
+        Some(self.get_context_length())
     }
 
     fn run_sync_raw(
@@ -84,6 +84,7 @@ type SyncCallback = Box<
 pub struct Phi {
     task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
     tokenizer: Arc<Tokenizer>,
+    context_length: usize,
     chat_markers: Arc<Option<ChatMarkers>>,
 }
 
@@ -121,6 +122,7 @@ impl Phi {
         device: Device,
         cache: PhiCache,
         chat_markers: Option<ChatMarkers>,
+        context_length: usize,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
         let arc_tokenizer = Arc::new(tokenizer);
@@ -158,10 +160,16 @@ impl Phi {
         Self {
             task_sender,
             tokenizer: arc_tokenizer,
+            context_length,
             chat_markers: chat_markers.into(),
         }
     }
 
+    /// Get the maximum number of tokens the model can attend to.
+    pub(crate) fn get_context_length(&self) -> usize {
+        self.context_length
+    }
+
     /// Get the tokenizer used by this model.
     pub(crate) fn get_tokenizer(&self) -> Arc<Tokenizer> {
         self.tokenizer.clone()
@@ -268,6 +276,7 @@ impl PhiBuilder {
             device,
             cache,
             self.source.chat_markers,
+            config.n_positions,
         ))
     }
 }
@@ -121,6 +121,28 @@ impl PhiSource {
         myself
     }
 
+    /// Set the model file to use. The model must be a quantized GGUF file.
+    pub fn with_model(mut self, model: FileSource) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Set the tokenizer file to use.
+    pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Get the chat markers for the model if it is a chat model.
+    pub fn chat_markers(&self) -> Option<ChatMarkers> {
+        self.chat_markers.clone()
+    }
+
+    /// Get the model and tokenizer files this source loads.
+    pub fn files(&self) -> Vec<FileSource> {
+        vec![self.model.clone(), self.tokenizer.clone()]
+    }
+
     /// Set the phi config to use for the model.
     pub fn with_phi_config(mut self, phi_config: crate::Config) -> Self {
         self.phi_config = phi_config;
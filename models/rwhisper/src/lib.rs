@@ -36,8 +36,8 @@
 #![warn(missing_docs)]
 
 use cpal::FromSample;
-use kalosm_common::FileSource;
 pub use kalosm_common::ModelLoadingProgress;
+use kalosm_common::{FileSource, InferenceThreads, ThreadPool};
 use kalosm_language_model::ModelBuilder;
 use kalosm_streams::text_stream::ChannelTextStream;
 use model::WhisperInner;
@@ -201,6 +201,9 @@ pub struct WhisperBuilder {
 
     /// The cache location to use for the model (defaults DATA_DIR/kalosm/cache)
     cache: kalosm_common::Cache,
+
+    /// The threads the model uses for inference on the CPU.
+    threads: InferenceThreads,
 }
 
 impl Default for WhisperBuilder {
@@ -209,6 +212,7 @@ impl Default for WhisperBuilder {
             model: WhisperSource::default(),
             language: Some(WhisperLanguage::English),
             cache: kalosm_common::Cache::default(),
+            threads: InferenceThreads::default(),
         }
     }
 }
@@ -233,6 +237,12 @@ impl ModelBuilder for WhisperBuilder {
 }
 
 impl WhisperBuilder {
+    /// Get the model, tokenizer and config files the model will load.
+    pub fn files(&self) -> Vec<FileSource> {
+        let whisper = self.get_whisper_model_config();
+        vec![whisper.model, whisper.tokenizer, whisper.config]
+    }
+
     fn get_whisper_model_config(&self) -> WhisperModelConfig {
         let (model_id, revision) = self.model.model_and_revision();
         if self.model.is_quantized() {
@@ -402,6 +412,7 @@ impl WhisperBuilder {
             })
             .await?;
 
+        let thread_pool = self.threads.build("rwhisper")?;
         let (rx, tx) = std::sync::mpsc::channel();
         let thread = std::thread::spawn(move || {
             tokio::runtime::Builder::new_current_thread()
@@ -414,7 +425,7 @@ impl WhisperBuilder {
                         match message {
                             WhisperMessage::Kill => return,
                             WhisperMessage::Transcribe(input, result) => {
-                                model.transcribe(input, result);
+                                thread_pool.install(|| model.transcribe(input, result));
                             }
                         }
                     }
@@ -447,6 +458,18 @@ impl WhisperBuilder {
 
         self
     }
+
+    /// Set the number of threads the model uses for inference on the CPU. The model gets a dedicated thread pool with this many threads. (Defaults to rayon's global thread pool)
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = InferenceThreads::Count(threads);
+        self
+    }
+
+    /// Set the thread pool the model uses for inference on the CPU. Models built with the same pool share its threads. (Defaults to rayon's global thread pool)
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.threads = InferenceThreads::Pool(pool);
+        self
+    }
 }
 
 /// A language whisper can use